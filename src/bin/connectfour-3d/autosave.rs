@@ -0,0 +1,94 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use kiss3d::event::{Action, Key, WindowEvent};
+use kiss3d::nalgebra::{Point2, Point3};
+use kiss3d::text::Font;
+use kiss3d::window::Window;
+
+use connectfour::game_manager::save::SavedGame;
+
+use super::gui3d::Theme;
+
+/// Path to the autosave file, in the user's data dir (e.g.
+/// ~/.local/share/connectfour-3d/autosave.json on Linux). The GameManager
+/// keeps the unfinished local game there, so that it can be resumed if the
+/// app crashes or gets killed; see GameManager::set_autosave_path.
+pub fn path() -> Result<PathBuf> {
+    let dir = dirs::data_dir().ok_or(anyhow!("no data dir"))?;
+
+    Ok(dir.join("connectfour-3d").join("autosave.json"))
+}
+
+/// Loads the autosaved game, if any. A file which can't be loaded is of no
+/// use to anyone, so it's deleted.
+pub fn load(path: &Path) -> Option<SavedGame> {
+    if !path.exists() {
+        return None;
+    }
+
+    match SavedGame::load(path) {
+        Ok(saved) => Some(saved),
+        Err(err) => {
+            println!("ignoring the autosave: {:#}", err);
+            discard(path);
+            None
+        }
+    }
+}
+
+/// Deletes the autosaved game, e.g. when the user doesn't want to resume it.
+pub fn discard(path: &Path) {
+    if let Err(err) = fs::remove_file(path) {
+        println!("failed to delete the autosave: {}", err);
+    }
+}
+
+/// Asks the user in the given window whether to resume the autosaved game.
+/// Returns the answer, or None if the user has closed the window.
+pub fn offer_resume(w: &mut Window, theme: Theme, saved: &SavedGame) -> Option<bool> {
+    let font = Font::default();
+    let white = Point3::new(1.0, 1.0, 1.0);
+    let grey = Point3::new(0.5, 0.5, 0.5);
+    let green = Point3::new(0.0, 1.0, 0.0);
+
+    let bg = theme.background_color();
+    w.set_background_color(bg.0, bg.1, bg.2);
+
+    let details = match saved.history.len() {
+        1 => "1 move made so far.".to_string(),
+        n => format!("{} moves made so far.", n),
+    };
+
+    while w.render() {
+        for event in w.events().iter() {
+            match event.value {
+                WindowEvent::Key(Key::Y | Key::Return, Action::Press, _) => return Some(true),
+                WindowEvent::Key(Key::N | Key::Escape, Action::Press, _) => return Some(false),
+                _ => {}
+            }
+        }
+
+        w.draw_text(
+            "The last game wasn't finished.",
+            &Point2::new(50.0, 50.0),
+            80.0,
+            &font,
+            &green,
+        );
+        w.draw_text("Resume it?", &Point2::new(50.0, 200.0), 60.0, &font, &white);
+        w.draw_text(&details, &Point2::new(50.0, 300.0), 60.0, &font, &grey);
+
+        let h = w.size()[1] as f32 * 2.0;
+        w.draw_text(
+            "Y or Enter: resume, N or Esc: start a new game",
+            &Point2::new(50.0, h - 150.0),
+            50.0,
+            &font,
+            &grey,
+        );
+    }
+
+    None
+}
@@ -0,0 +1,86 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+
+use super::gui3d::Theme;
+use super::skins::TokenSkin;
+use super::OpponentKind;
+
+pub const DEFAULT_URL: &str = "ws://64.226.98.150:7248";
+pub const DEFAULT_GAME_ID: &str = "mygame1";
+
+/// User settings which persist between the runs; they're written by the
+/// first-run wizard (see wizard::run), and can be edited by hand. Every
+/// setting can also be overridden from the command line.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Name of the local player.
+    pub player_name: String,
+    pub opponent_kind: OpponentKind,
+    pub theme: Theme,
+    /// Style of the tokens; in a network game, it's also advertised to the
+    /// server as a cosmetic preference.
+    pub token_skin: TokenSkin,
+
+    /// URL of the server and game ID, for the network game.
+    pub url: String,
+    pub game_id: String,
+
+    /// Whether to check for a newer release on startup (see update::check),
+    /// and the URL of the release feed to check.
+    pub check_updates: bool,
+    pub update_url: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            player_name: String::new(),
+            opponent_kind: OpponentKind::Network,
+            theme: Theme::Classic,
+            token_skin: TokenSkin::Plain,
+            url: DEFAULT_URL.to_string(),
+            game_id: DEFAULT_GAME_ID.to_string(),
+            check_updates: false,
+            update_url: String::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Path to the config file, in the user's config dir (e.g.
+    /// ~/.config/connectfour-3d/config.json on Linux).
+    pub fn path() -> Result<PathBuf> {
+        let dir = dirs::config_dir().ok_or(anyhow!("no config dir"))?;
+
+        Ok(dir.join("connectfour-3d").join("config.json"))
+    }
+
+    /// Loads the config file; returns None if there's none yet.
+    pub fn load() -> Result<Option<Config>> {
+        let path = Config::path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let data = fs::read_to_string(&path).context(format!("reading {}", path.display()))?;
+        let config = serde_json::from_str(&data).context(format!("parsing {}", path.display()))?;
+
+        Ok(Some(config))
+    }
+
+    /// Writes the config file, creating its dir if needed.
+    pub fn save(&self) -> Result<()> {
+        let path = Config::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(format!("creating {}", dir.display()))?;
+        }
+
+        let data = serde_json::to_string_pretty(self)?;
+        fs::write(&path, data).context(format!("writing {}", path.display()))?;
+
+        Ok(())
+    }
+}
@@ -1,8 +1,13 @@
 use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
 use std::rc::Rc;
-use std::time::{Duration, Instant};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::vec::Vec;
 
+use anyhow::{anyhow, Result};
 use kiss3d::camera::{ArcBall, Camera};
 use kiss3d::event::{Action, Event, Key, MouseButton, WindowEvent};
 use kiss3d::light::Light;
@@ -11,30 +16,45 @@ use kiss3d::scene::SceneNode;
 use kiss3d::text::Font;
 use kiss3d::window::Window;
 use ordered_float::OrderedFloat;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
+use super::skins::TokenSkin;
 use super::sounds;
+#[cfg(feature = "tts")]
+use super::speech;
 use super::OpponentKind;
-use connectfour::game::{PoleCoords, Side, TokenCoords, WinRow, ROW_SIZE};
-use connectfour::game_manager::player_local::PlayerLocalToUI;
-use connectfour::game_manager::{GameManagerToUI, GameState, PlayerState};
-
-/// Constants which configure the 3D model.
+use connectfour::engine::EngineKind;
+use connectfour::game::analysis;
+use connectfour::game::eval::{self, Style};
+use connectfour::game::record::{Annotation, GameRecord, MoveMark};
+use connectfour::game::{
+    BoardDiff, BoardState, Game, PoleCoords, Rules, Side, TokenCoords, WinRow, DEAD_DRAW_REASON,
+};
+use connectfour::game_manager::clock::{self, ClockTimes};
+use connectfour::game_manager::player_ai::Difficulty;
+use connectfour::game_manager::player_local::{PlayerLocalToUI, UIToPlayerLocal};
+use connectfour::game_manager::series::{MatchScore, SessionStats};
+use connectfour::game_manager::{
+    GameManager, GameManagerToUI, GameOverReason, GameState, HintScore, PlayerConfig, PlayerState,
+    UIToGameManager,
+};
+
+/// Constants which configure the 3D model. They don't depend on the size of
+/// the board; the ones which do are in BoardLayout.
 
 const POLE_WIDTH: f32 = 1.0;
 const TOKEN_D_TO_HEIGHT: f32 = 0.8;
 const POLE_RADIUS: f32 = POLE_WIDTH / 2.0;
 const TOKEN_RADIUS: f32 = POLE_RADIUS * 2.0;
 const TOKEN_HEIGHT: f32 = TOKEN_RADIUS * 2.0 * TOKEN_D_TO_HEIGHT;
-const POLE_HEIGHT: f32 = TOKEN_HEIGHT * (ROW_SIZE as f32 + (1.0 - TOKEN_D_TO_HEIGHT));
 const POLE_SPACING: f32 = POLE_WIDTH * 3.0; // From center to center, not from edge to edge
 const MARGIN: f32 = POLE_WIDTH * 2.0;
-const FOUNDATION_WIDTH: f32 = POLE_SPACING * (ROW_SIZE as f32 - 1.0) + MARGIN * 2.0;
 const FOUNDATION_HEIGHT: f32 = POLE_WIDTH;
 const POINTER_RADIUS: f32 = POLE_RADIUS * 0.7;
 
-/// Y coord for a plane which matches tops of all poles.
-const POLES_TOP_Y: f32 = POLE_HEIGHT / 2.0;
+/// How far from the center of a pole top the mouse can point for the pole to
+/// be picked.
+const PICK_TOLERANCE: f32 = POLE_RADIUS * 1.5;
 
 /// How often to flash tokens, whenever we need to flash some (we do for the
 /// winning row).
@@ -44,23 +64,118 @@ const FLASH_DUR: Duration = Duration::from_millis(FLASH_DUR_MS as u64);
 /// How many times to flash the last token.
 const LAST_TOKEN_NUM_FLASHES: usize = 2;
 
+/// For how long the tokens are revealed when the user peeks at the board in
+/// blindfold mode.
+const PEEK_DUR: Duration = Duration::from_millis(2000);
+
+/// How long it takes to rotate the camera to the other side of the board, see
+/// WindowOptions::auto_rotate.
+const ROTATION_DUR: Duration = Duration::from_millis(800);
+
+/// For how long a notice is shown, see Window3D::notice.
+const NOTICE_DUR: Duration = Duration::from_secs(10);
+
+/// How many of the latest announcements the log panel shows, see
+/// WindowOptions::announce.
+const ANNOUNCEMENT_LOG_LEN: usize = 8;
+
+/// How many of the latest chat messages are shown, see Window3D::chat_log,
+/// and how long a message the user can type.
+const CHAT_LOG_LEN: usize = 6;
+const CHAT_MAX_LEN: usize = 200;
+
+/// Longest comment which the user can type for a move of the review.
+const COMMENT_MAX_LEN: usize = 200;
+
+/// Width of the evaluation bar, and its height relative to the window height,
+/// see Window3D::draw_evaluation_bar.
+const EVAL_BAR_WIDTH: f32 = 16.0;
+const EVAL_BAR_HEIGHT: f32 = 0.6;
+
+/// Color of the tokens whose colors are hidden in blindfold mode.
+const BLINDFOLD_COLOR: (f32, f32, f32) = (0.5, 0.5, 0.5);
+
+/// Tokens which the server hasn't confirmed yet are drawn dimmer, with their
+/// colors multiplied by this factor.
+const UNCONFIRMED_DIM: f32 = 0.6;
+
+/// Options of the window, which the user can set from the command line.
+pub struct WindowOptions {
+    pub blindfold: Blindfold,
+    /// Time penalty for every peek at the board in blindfold mode.
+    pub peek_penalty: Duration,
+    /// Rules of the game; the window only uses them to remind the user.
+    pub rules: Rules,
+    /// Whether to start in zen mode, see Window3D::zen.
+    pub zen: bool,
+    /// In a local game, whether to rotate the camera by 180 degrees between
+    /// turns, so that each player views the board from their own side.
+    pub auto_rotate: bool,
+    /// In a local game, whether it's played two against two, see
+    /// GameManager::set_teammates; then each player row stands for a team.
+    pub teams: bool,
+    /// Initial difficulty of the AI opponent.
+    pub ai_difficulty: Difficulty,
+    /// Initial playing style of the AI opponent.
+    pub ai_style: Style,
+    /// Colors of the scene.
+    pub theme: Theme,
+    /// Style of the tokens.
+    pub token_skin: TokenSkin,
+    /// Name of the local player, if known.
+    pub player_name: Option<String>,
+    /// Whether the player on this side of the board is the AI (or the
+    /// scripted player), which plays either the AI opponent or the network
+    /// one; so there is no local player at all.
+    pub ai_player: bool,
+    /// Whether to print the announcements of the moves and the game state
+    /// changes (see GameManagerToUI::Announcement) to stdout, and to show the
+    /// latest ones in the log panel.
+    pub announce: bool,
+    /// Whether to start with the evaluation bar shown, see
+    /// Window3D::evaluation.
+    pub evaluation: bool,
+    /// Engine which the GameManager evaluates the positions with; needed to
+    /// make sense of the scores.
+    pub engine: EngineKind,
+    /// If set, the announcements are also spoken aloud.
+    #[cfg(feature = "tts")]
+    pub speaker: Option<speech::Speaker>,
+}
+
+/// Camera rotation in progress, see WindowOptions::auto_rotate.
+struct CameraRotation {
+    from_yaw: f32,
+    to_yaw: f32,
+    started: Instant,
+}
+
 pub struct Window3D {
     w: Window,
     font: Rc<Font>,
     camera: ArcBall,
+    /// Sizes and positions of the board model.
+    layout: BoardLayout,
+    /// Foundation and poles of the board model, see create_3d_board.
+    board_nodes: Vec<SceneNode>,
 
     sound_player: sounds::Player,
 
-    /// A vector of currently added tokens as spheres.
-    tokens: Vec<Option<SceneNode>>,
+    /// A vector of currently added tokens.
+    tokens: Vec<Option<Token>>,
     /// A tiny sphere which shows up on top of poles when mouse hovers them (only
     /// whenever a local player has requested an input from UI, i.e. when
     /// pending_input is not None).
     pole_pointer: SceneNode,
+    /// A sphere on top of the pole suggested by the engine, shown after the
+    /// user asks for a hint with the H key, until the board changes.
+    hint_pointer: SceneNode,
+    /// Last hint from the GameManager, with its score, if still relevant.
+    hint: Option<(PoleCoords, HintScore)>,
 
     /// Whenever a PlayerLocal requests an input from UI (where to put a token),
     /// pending_input becomes Some(v). When the user picks a pole, the PoleCoords
-    /// are sent via pending_input.coord_sender, and it becomes None again.
+    /// are sent via pending_input.to_player, and it becomes None again.
     pending_input: Option<PendingInput>,
 
     /// Last mouse coords are updated whenever the user moves the mouse cursor.
@@ -85,28 +200,142 @@ pub struct Window3D {
     flash_show: bool,
 
     from_gm: mpsc::Receiver<GameManagerToUI>,
+    to_gm: mpsc::Sender<UIToGameManager>,
     from_players: mpsc::Receiver<PlayerLocalToUI>,
+    /// Human readable notices from outside of the game, e.g. about a newer
+    /// release.
+    from_notices: mpsc::Receiver<String>,
+    /// Authoritative board from the GameManager, used to resync the scene.
+    board_watch: watch::Receiver<BoardState>,
 
     players: [PlayerInfo; 2],
     opponent_kind: OpponentKind,
 
     game_state: Option<GameState>,
+    /// Number of moves made so far, see GameManagerToUI::GameStateChanged.
+    moves: usize,
+    /// In a team game, the seat to move, see GameManagerToUI::SeatToMove.
+    seat_to_move: Option<usize>,
+    /// Poles where a token can be put, as reported by the GameManager. The
+    /// pole pointer is never shown over other poles, and clicks there are
+    /// ignored.
+    legal_moves: Vec<PoleCoords>,
 
     /// If not None, it means there is a winner, and it's the winning row. We'll
     /// flash the tokens there.
     win_row: Option<WinRow>,
+
+    /// Blindfold mode, and the time penalty for every peek.
+    blindfold: Blindfold,
+    peek_penalty: Duration,
+    /// If the user is peeking at the board in blindfold mode, it's the time
+    /// until which the tokens are revealed.
+    peek_until: Option<Instant>,
+    /// How many times the user has peeked at the board in the current game,
+    /// see GameManagerToUI::Peeks.
+    num_peeks: u32,
+
+    /// Rules of the game.
+    rules: Rules,
+
+    /// Zen mode: all the text is hidden, except a small turn indicator in the
+    /// corner. Toggled with the Z key.
+    zen: bool,
+
+    /// Whether the game is paused, see GameManagerToUI::Paused. Toggled with
+    /// the space key, in local games.
+    paused: bool,
+
+    /// Whether to rotate the camera between turns in a local game, and the
+    /// side which the camera is currently facing.
+    auto_rotate: bool,
+    camera_side: Side,
+    /// Camera rotation in progress, if any.
+    camera_rotation: Option<CameraRotation>,
+
+    /// Analysis mode: hovering a pole shows some stats about it. Toggled with
+    /// the A key.
+    analysis: bool,
+    /// Review of the finished game (see GameManagerToUI::GameReview), and the
+    /// index of the move selected in it. In analysis mode, the user can pick
+    /// another move with the arrow keys, mark it with M, comment on it with C,
+    /// and save the whole annotated game with W. Dropped once the next game
+    /// starts.
+    review: Option<(GameRecord, usize)>,
+    /// While the user is typing a comment for the selected move of the
+    /// review, the text so far; all the keys go there then, like with
+    /// chat_input.
+    comment_input: Option<String>,
+
+    /// Current difficulty and playing style of the AI opponent, if any.
+    ai_difficulty: Difficulty,
+    ai_style: Style,
+
+    /// Colors of the scene.
+    theme: Theme,
+    /// Style of the tokens as configured, and the one picked for the current
+    /// game (they only differ with TokenSkin::Random).
+    skin_setting: TokenSkin,
+    skin: TokenSkin,
+    /// Whether there is no local player, see WindowOptions::ai_player.
+    ai_player: bool,
+
+    /// Notice which is currently shown, and until when, if any. It doesn't
+    /// block anything; it just goes away after a while.
+    notice: Option<(String, Instant)>,
+
+    /// Whether the evaluation bar is shown; toggled with the E key. While it's
+    /// shown, the GameManager evaluates every position (see
+    /// UIToGameManager::SetEvaluation), and the latest score is here.
+    evaluation: bool,
+    eval_score: Option<i32>,
+    /// Why the game is over, if it is, and the GameManager has told us, see
+    /// GameManagerToUI::GameOver.
+    game_over_reason: Option<GameOverReason>,
+    /// Clocks of both sides, if the game is timed, see
+    /// GameManagerToUI::ClockUpdate.
+    clock_times: Option<ClockTimes>,
+    /// Score of the match, if the games are played as one, see
+    /// GameManagerToUI::MatchScore.
+    match_score: Option<MatchScore>,
+    /// Results of the games played so far, see
+    /// GameManagerToUI::SessionStats.
+    session_stats: SessionStats,
+    engine: EngineKind,
+
+    /// Whether to print and show the announcements, see
+    /// WindowOptions::announce, and the latest ones to show in the log panel.
+    announce: bool,
+    announcement_log: VecDeque<String>,
+    #[cfg(feature = "tts")]
+    speaker: Option<speech::Speaker>,
+
+    /// Latest chat messages in a network game, as "who: what", including the
+    /// user's own ones.
+    chat_log: VecDeque<String>,
+    /// While the user is typing a chat message (Tab starts it), the text so
+    /// far; all the keys go there then.
+    chat_input: Option<String>,
 }
 
 impl Window3D {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        mut w: Window,
         sound_player: sounds::Player,
         from_gm: mpsc::Receiver<GameManagerToUI>,
+        to_gm: mpsc::Sender<UIToGameManager>,
+        board_watch: watch::Receiver<BoardState>,
         from_players: mpsc::Receiver<PlayerLocalToUI>,
+        from_notices: mpsc::Receiver<String>,
         opponent_kind: OpponentKind,
+        options: WindowOptions,
     ) -> Window3D {
-        let mut w = Window::new("ConnectFour 3D");
         w.set_light(Light::StickToCamera);
 
+        let bg = options.theme.background_color();
+        w.set_background_color(bg.0, bg.1, bg.2);
+
         // Set up camera in a meaningful position.
         let eye = Point3::new(18.0, 18.0, 18.0);
         let at = Point3::origin();
@@ -117,27 +346,72 @@ impl Window3D {
         let mut pole_pointer = w.add_sphere(POINTER_RADIUS);
         pole_pointer.set_visible(false);
 
+        let mut hint_pointer = w.add_sphere(POINTER_RADIUS);
+        hint_pointer.set_color(0.0, 1.0, 0.0);
+        hint_pointer.set_visible(false);
+
         let p0_name;
         let p1_name;
 
+        let you = match &options.player_name {
+            Some(name) => format!("{} (you)", name),
+            None => "local (you)".to_string(),
+        };
+
         match opponent_kind {
+            OpponentKind::Local if options.teams => {
+                p0_name = "team A: A1 and A2".to_string();
+                p1_name = "team B: B1 and B2".to_string();
+            }
             OpponentKind::Local => {
-                p0_name = "local";
-                p1_name = "local";
+                p0_name = "local".to_string();
+                p1_name = "local".to_string();
+            }
+            OpponentKind::Network if options.ai_player => {
+                p0_name = "network".to_string();
+                p1_name = "AI".to_string();
             }
             OpponentKind::Network => {
-                p0_name = "network";
-                p1_name = "local (you)";
+                p0_name = "network".to_string();
+                p1_name = you;
+            }
+            OpponentKind::AI if options.ai_player => {
+                p0_name = "AI".to_string();
+                p1_name = "AI".to_string();
+            }
+            OpponentKind::AI => {
+                p0_name = you;
+                p1_name = "AI".to_string();
+            }
+            OpponentKind::Random if options.ai_player => {
+                p0_name = "AI".to_string();
+                p1_name = "random".to_string();
+            }
+            OpponentKind::Random => {
+                p0_name = you;
+                p1_name = "random".to_string();
+            }
+            OpponentKind::Replay => {
+                p0_name = "replay".to_string();
+                p1_name = "replay".to_string();
             }
         }
 
+        let layout = BoardLayout::new(options.rules.row_size());
         let mut window = Window3D {
             w,
             font: Font::default(),
             camera,
+            // Everything which depends on the size of the board only goes
+            // through the layout, so that it can be rebuilt for another size,
+            // see GameManagerToUI::ResetBoard.
+            layout,
+            board_nodes: Vec::new(),
             sound_player,
-            tokens: vec![None; ROW_SIZE * ROW_SIZE * ROW_SIZE],
+            tokens: vec![None; layout.num_tokens()],
             pole_pointer,
+            hint_pointer,
+            hint: None,
             pending_input: None,
             mouse_down: false,
             rotating: false,
@@ -146,27 +420,72 @@ impl Window3D {
             last_flash_time: Instant::now(),
             flash_show: true,
             from_gm,
+            to_gm,
             from_players,
+            from_notices,
+            board_watch,
             last_mouse_coords: Point2::new(0.0f32, 0.0f32),
             players: [
                 PlayerInfo {
-                    name: p0_name.to_string(),
+                    name: p0_name,
                     state: PlayerState::NotReady("-".to_string()),
                     side: None,
+                    latency: None,
                 },
                 PlayerInfo {
-                    name: p1_name.to_string(),
+                    name: p1_name,
                     state: PlayerState::NotReady("-".to_string()),
                     side: None,
+                    latency: None,
                 },
             ],
             opponent_kind,
             game_state: None,
+            moves: 0,
+            seat_to_move: None,
+            legal_moves: Vec::new(),
             win_row: None,
+            blindfold: options.blindfold,
+            peek_penalty: options.peek_penalty,
+            peek_until: None,
+            num_peeks: 0,
+            rules: options.rules,
+            zen: options.zen,
+            paused: false,
+            auto_rotate: options.auto_rotate,
+            camera_side: Side::White,
+            camera_rotation: None,
+            analysis: false,
+            review: None,
+            comment_input: None,
+            ai_difficulty: options.ai_difficulty,
+            ai_style: options.ai_style,
+            theme: options.theme,
+            skin_setting: options.token_skin,
+            skin: options.token_skin.pick(),
+            ai_player: options.ai_player,
+            notice: None,
+            evaluation: false,
+            eval_score: None,
+            game_over_reason: None,
+            clock_times: None,
+            match_score: None,
+            session_stats: SessionStats::default(),
+            engine: options.engine,
+            announce: options.announce,
+            announcement_log: VecDeque::new(),
+            #[cfg(feature = "tts")]
+            speaker: options.speaker,
+            chat_log: VecDeque::new(),
+            chat_input: None,
         };
 
         window.create_3d_board();
 
+        if options.evaluation {
+            window.set_evaluation(true);
+        }
+
         window
     }
 
@@ -179,13 +498,46 @@ impl Window3D {
         while self.render() {
             // Handle keyboard and mouse events (apart from rotating the model,
             // zooming etc - this one is taken care of automatically).
-            for event in self.w.events().iter() {
+            for mut event in self.w.events().iter() {
+                // While the user is typing in the chat, the keys are theirs:
+                // neither our shortcuts nor kiss3d's own (e.g. Escape closing
+                // the window) apply.
+                if self.chat_input.is_some()
+                    && matches!(event.value, WindowEvent::Key(..) | WindowEvent::Char(_))
+                {
+                    event.inhibited = true;
+                    self.handle_chat_input(event.value);
+                    continue;
+                }
+                // Same for the comment on a move of the review.
+                if self.comment_input.is_some()
+                    && matches!(event.value, WindowEvent::Key(..) | WindowEvent::Char(_))
+                {
+                    event.inhibited = true;
+                    self.handle_comment_input(event.value);
+                    continue;
+                }
+
                 self.handle_user_input(&event)
             }
 
             self.handle_gm_messages();
             self.handle_player_messages();
 
+            while let Ok(notice) = self.from_notices.try_recv() {
+                self.show_notice(notice);
+            }
+
+            self.update_camera_rotation();
+
+            // Once the user has peeked enough, hide the tokens back.
+            if let Some(peek_until) = self.peek_until {
+                if Instant::now() >= peek_until {
+                    self.peek_until = None;
+                    self.apply_blindfold();
+                }
+            }
+
             // If some tokens need to be flashed, flash them every FLASH_DUR_MS ms.
             let now = Instant::now();
             let dur = now
@@ -214,38 +566,115 @@ impl Window3D {
                 }
             }
         }
+
+        // The window is closed, so let the GameManager wind everything down.
+        // We're not in the tokio runtime, so blocking is fine.
+        if let Err(err) = self.to_gm.blocking_send(UIToGameManager::Shutdown) {
+            println!("failed to shut down the game: {}", err);
+        }
     }
 
     /// Create a 3D model of an empty game board.
     fn create_3d_board(&mut self) {
-        let mut foundation = self
-            .w
-            .add_cube(FOUNDATION_WIDTH, FOUNDATION_HEIGHT, FOUNDATION_WIDTH);
-        foundation.set_color(1.0, 0.8, 0.0);
+        let layout = self.layout;
+
+        let mut foundation = self.w.add_cube(
+            layout.foundation_width,
+            FOUNDATION_HEIGHT,
+            layout.foundation_width,
+        );
+        let c = self.theme.foundation_color();
+        foundation.set_color(c.0, c.1, c.2);
         foundation.set_local_translation(Translation3::new(
             0.0,
-            -(POLE_HEIGHT + FOUNDATION_HEIGHT) / 2.0,
+            -(layout.pole_height + FOUNDATION_HEIGHT) / 2.0,
             0.0,
         ));
+        self.board_nodes.push(foundation);
 
-        for x in 0..ROW_SIZE {
-            for z in 0..ROW_SIZE {
-                let mut pole = self.w.add_cylinder(POLE_RADIUS, POLE_HEIGHT);
+        for x in 0..layout.row_size {
+            for z in 0..layout.row_size {
+                let mut pole = self.w.add_cylinder(POLE_RADIUS, layout.pole_height);
 
-                pole.set_local_translation(Self::pole_translation(PoleCoords::new(x, z)));
-                pole.set_color(1.0, 1.0, 0.0);
+                pole.set_local_translation(layout.pole_translation(PoleCoords::new(x, z)));
+
+                let c = self.theme.pole_color();
+                pole.set_color(c.0, c.1, c.2);
+                self.board_nodes.push(pole);
             }
         }
     }
 
+    /// Replaces the 3D model of the board with an empty one of the given
+    /// size.
+    fn rebuild_3d_board(&mut self, row_size: usize) {
+        for mut node in self.board_nodes.drain(..) {
+            node.unlink();
+        }
+        for mut token in self.tokens.drain(..).flatten() {
+            token.node.unlink();
+        }
+
+        self.layout = BoardLayout::new(row_size);
+        self.tokens = vec![None; self.layout.num_tokens()];
+        self.create_3d_board();
+    }
+
     /// Set the token with the given coords visible or not.
     fn set_token_visible(&mut self, tcoords: TokenCoords, visible: bool) {
-        self.tokens[Self::token_coords_to_idx(tcoords)]
+        self.tokens[self.layout.token_idx(tcoords)]
             .as_mut()
             .unwrap()
+            .node
             .set_visible(visible);
     }
 
+    /// Update colors and visibility of all tokens according to the blindfold
+    /// mode. Unless the board is revealed (blindfold is off, the user is
+    /// peeking, or the game is over), all tokens except the last one have
+    /// their colors, or themselves altogether, hidden. Tokens not confirmed by
+    /// the server yet are dimmed.
+    fn apply_blindfold(&mut self) {
+        let blindfold = self.blindfold;
+        let skin = self.skin;
+        let reveal = blindfold == Blindfold::Off
+            || self.peek_until.is_some()
+            || matches!(
+                self.game_state,
+                Some(GameState::WonBy(_, _) | GameState::Draw(_))
+            );
+        let last_token_idx = self
+            .last_token
+            .map(|tcoords| self.layout.token_idx(tcoords));
+
+        for (idx, maybe_token) in self.tokens.iter_mut().enumerate() {
+            let token = match maybe_token {
+                Some(token) => token,
+                None => continue,
+            };
+
+            let hide = !reveal && last_token_idx != Some(idx);
+
+            let mut c = skin.tint(if hide && blindfold == Blindfold::Colors {
+                BLINDFOLD_COLOR
+            } else {
+                Self::color_by_side(token.side)
+            });
+            if token.unconfirmed {
+                c = (
+                    c.0 * UNCONFIRMED_DIM,
+                    c.1 * UNCONFIRMED_DIM,
+                    c.2 * UNCONFIRMED_DIM,
+                );
+            }
+            token.node.set_color(c.0, c.1, c.2);
+
+            if blindfold == Blindfold::All {
+                token.node.set_visible(!hide);
+            }
+        }
+    }
+
     fn handle_user_input(&mut self, event: &Event<'_>) {
         match event.value {
             WindowEvent::MouseButton(_btn, Action::Press, _modif) => {
@@ -260,7 +689,11 @@ impl Window3D {
 
                 // If it wasn't the left button, or if were rotating scene, then
                 // don't add a token on release.
-                if btn != MouseButton::Button1 || was_rotating || !self.waiting_for_input() {
+                if btn != MouseButton::Button1
+                    || was_rotating
+                    || !self.waiting_for_input()
+                    || self.paused
+                {
                     // When we release after the rotation, the mouse might again
                     // be pointing at a pole, so update the pole pointer if
                     // that's the case.
@@ -271,16 +704,16 @@ impl Window3D {
                 // Going to try to add a token.
 
                 let pcoords = match self.mouse_coords_to_pole_coords(self.last_mouse_coords) {
-                    Some(pcoords) => pcoords,
-                    None => return,
+                    Some(pcoords) if self.legal_moves.contains(&pcoords) => pcoords,
+                    _ => return,
                 };
 
                 match self
                     .pending_input
                     .as_ref()
                     .expect("no pending_input")
-                    .coord_sender
-                    .try_send(pcoords)
+                    .to_player
+                    .try_send(UIToPlayerLocal::PutToken(pcoords))
                 {
                     Ok(_) => {
                         self.pending_input = None;
@@ -313,25 +746,444 @@ impl Window3D {
                 }
             }
 
+            // Peek at the board in blindfold mode, at the cost of a time
+            // penalty.
+            WindowEvent::Key(Key::P, Action::Press, _modif)
+                if self.blindfold != Blindfold::Off && self.peek_until.is_none() =>
+            {
+                self.peek();
+            }
+
+            WindowEvent::Key(Key::Z, Action::Press, _modif) => {
+                self.zen = !self.zen;
+            }
+
+            WindowEvent::Key(Key::A, Action::Press, _modif) => {
+                self.analysis = !self.analysis;
+            }
+
+            // Annotating the review, see Window3D::review.
+            WindowEvent::Key(key @ (Key::Left | Key::Right), Action::Press, _modif)
+                if self.analysis =>
+            {
+                if let Some((record, idx)) = &mut self.review {
+                    *idx = match key {
+                        Key::Left => idx.saturating_sub(1),
+                        _ => (*idx + 1).min(record.moves.len() - 1),
+                    };
+                }
+            }
+            WindowEvent::Key(Key::M, Action::Press, _modif) if self.analysis => {
+                self.cycle_review_mark();
+            }
+            WindowEvent::Key(Key::C, Action::Press, _modif)
+                if self.analysis && self.review.is_some() =>
+            {
+                self.comment_input = Some(String::new());
+            }
+            WindowEvent::Key(Key::W, Action::Press, _modif) if self.analysis => {
+                self.save_review();
+            }
+
+            WindowEvent::Key(Key::R, Action::Press, _modif) => {
+                self.resync_scene();
+            }
+
+            WindowEvent::Key(Key::H, Action::Press, _modif) => {
+                self.request_hint();
+            }
+
+            WindowEvent::Key(Key::E, Action::Press, _modif) => {
+                self.set_evaluation(!self.evaluation);
+            }
+
+            WindowEvent::Key(Key::N, Action::Press, _modif) => {
+                self.request_new_game();
+            }
+
+            WindowEvent::Key(Key::D, Action::Press, _modif) => {
+                self.decline_new_game();
+            }
+
+            WindowEvent::Key(Key::U, Action::Press, _modif) => {
+                self.request_undo();
+            }
+
+            // There's only someone to chat with in a network game.
+            WindowEvent::Key(Key::Tab, Action::Press, _modif)
+                if matches!(self.opponent_kind, OpponentKind::Network) =>
+            {
+                self.chat_input = Some(String::new());
+            }
+
+            // The remote user's game can't be paused.
+            WindowEvent::Key(Key::Space, Action::Press, _modif)
+                if !matches!(self.opponent_kind, OpponentKind::Network) =>
+            {
+                self.toggle_pause();
+            }
+
+            WindowEvent::Key(key @ (Key::Key1 | Key::Key2 | Key::Key3), Action::Press, _modif)
+                if matches!(self.opponent_kind, OpponentKind::AI) =>
+            {
+                let difficulty = match key {
+                    Key::Key1 => Difficulty::EASY,
+                    Key::Key2 => Difficulty::MEDIUM,
+                    _ => Difficulty::HARD,
+                };
+                self.set_ai_difficulty(difficulty);
+            }
+
+            WindowEvent::Key(Key::S, Action::Press, _modif)
+                if matches!(self.opponent_kind, OpponentKind::AI) =>
+            {
+                let idx = Style::ALL.iter().position(|&s| s == self.ai_style);
+                let style = Style::ALL[idx.map_or(0, |i| (i + 1) % Style::ALL.len())];
+                self.set_ai_style(style);
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Handles a key while the user is typing a chat message: Enter sends it,
+    /// Escape drops it.
+    fn handle_chat_input(&mut self, event: WindowEvent) {
+        let text = match &mut self.chat_input {
+            Some(text) => text,
+            None => return,
+        };
+
+        match event {
+            WindowEvent::Char(c) if !c.is_control() && text.chars().count() < CHAT_MAX_LEN => {
+                text.push(c);
+            }
+            WindowEvent::Key(Key::Back, Action::Press, _modif) => {
+                text.pop();
+            }
+            WindowEvent::Key(Key::Return, Action::Press, _modif) => {
+                let text = self.chat_input.take().unwrap_or_default();
+                let text = text.trim();
+                if text.is_empty() {
+                    return;
+                }
+
+                match self.to_gm.try_send(UIToGameManager::Chat(text.to_string())) {
+                    Ok(_) => self.add_chat_message("You", text),
+                    Err(err) => println!("failed to send a chat message: {}", err),
+                }
+            }
+            WindowEvent::Key(Key::Escape, Action::Press, _modif) => {
+                self.chat_input = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Handles a key while the user is typing a comment for the selected move
+    /// of the review: Enter sets it (an empty one removes the comment),
+    /// Escape leaves the old one.
+    fn handle_comment_input(&mut self, event: WindowEvent) {
+        let text = match &mut self.comment_input {
+            Some(text) => text,
+            None => return,
+        };
+
+        match event {
+            WindowEvent::Char(c) if !c.is_control() && text.chars().count() < COMMENT_MAX_LEN => {
+                text.push(c);
+            }
+            WindowEvent::Key(Key::Back, Action::Press, _modif) => {
+                text.pop();
+            }
+            WindowEvent::Key(Key::Return, Action::Press, _modif) => {
+                let text = self.comment_input.take().unwrap_or_default();
+                let text = text.trim();
+                let comment = (!text.is_empty()).then(|| text.to_string());
+
+                self.annotate_review(|annotation| annotation.comment = comment);
+            }
+            WindowEvent::Key(Key::Escape, Action::Press, _modif) => {
+                self.comment_input = None;
+            }
             _ => {}
         }
     }
 
+    /// Changes the annotation of the selected move of the review, if any.
+    fn annotate_review(&mut self, f: impl FnOnce(&mut Annotation)) {
+        let (record, idx) = match &mut self.review {
+            Some(v) => v,
+            None => return,
+        };
+
+        let mut annotation = record.moves[*idx].annotation.clone();
+        f(&mut annotation);
+        if let Err(err) = record.annotate(*idx, annotation) {
+            println!("failed to annotate the review: {}", err);
+        }
+    }
+
+    /// Sets the next mark (see MoveMark::ALL) on the selected move of the
+    /// review; after the last one, the mark is removed.
+    fn cycle_review_mark(&mut self) {
+        self.annotate_review(|annotation| {
+            annotation.mark = match annotation.mark {
+                None => Some(MoveMark::ALL[0]),
+                Some(mark) => {
+                    let idx = MoveMark::ALL.iter().position(|&m| m == mark);
+                    idx.and_then(|i| MoveMark::ALL.get(i + 1)).copied()
+                }
+            };
+        });
+    }
+
+    /// Saves the annotated review as a transcript (see
+    /// GameRecord::to_transcript) to a new file in the current dir, so that it
+    /// can be shared, or watched later with "--opponent replay".
+    fn save_review(&mut self) {
+        let record = match &self.review {
+            Some((record, _)) => record,
+            None => return,
+        };
+
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = format!("connectfour-review-{}.txt", secs);
+
+        match fs::write(&path, record.to_transcript()) {
+            Ok(()) => self.show_notice(format!("Saved the review to {}", path)),
+            Err(err) => self.show_notice(format!("Failed to save the review: {}", err)),
+        }
+    }
+
+    /// Adds the message to the chat log, pushing the oldest one out if needed.
+    fn add_chat_message(&mut self, from: &str, text: &str) {
+        println!("chat: {}: {}", from, text);
+
+        if self.chat_log.len() == CHAT_LOG_LEN {
+            self.chat_log.pop_front();
+        }
+        self.chat_log.push_back(format!("{}: {}", from, text));
+    }
+
+    /// Ask the GameManager to change the AI difficulty.
+    fn set_ai_difficulty(&mut self, difficulty: Difficulty) {
+        let msg = UIToGameManager::ConfigurePlayers(PlayerConfig {
+            ai_difficulty: Some(difficulty),
+            ..PlayerConfig::default()
+        });
+
+        match self.to_gm.try_send(msg) {
+            Ok(_) => {
+                self.ai_difficulty = difficulty;
+            }
+            Err(err) => {
+                println!("failed to change AI difficulty: {}", err);
+            }
+        }
+    }
+
+    /// Ask the GameManager to change the AI playing style.
+    fn set_ai_style(&mut self, style: Style) {
+        let msg = UIToGameManager::ConfigurePlayers(PlayerConfig {
+            ai_style: Some(style),
+            ..PlayerConfig::default()
+        });
+
+        match self.to_gm.try_send(msg) {
+            Ok(_) => {
+                self.ai_style = style;
+            }
+            Err(err) => {
+                println!("failed to change AI style: {}", err);
+            }
+        }
+    }
+
+    /// If auto-rotation is enabled in a local game, start rotating the camera
+    /// to the other side of the board, unless it's facing the given side
+    /// already.
+    fn face_side(&mut self, side: Side) {
+        if !self.auto_rotate
+            || !matches!(self.opponent_kind, OpponentKind::Local)
+            || self.camera_side == side
+        {
+            return;
+        }
+
+        // If the previous rotation is still in progress, continue from its
+        // target, so that the camera ends up exactly on the other side.
+        let base_yaw = match self.camera_rotation.take() {
+            Some(rotation) => rotation.to_yaw,
+            None => self.camera.yaw(),
+        };
+
+        self.camera_side = side;
+        self.camera_rotation = Some(CameraRotation {
+            from_yaw: self.camera.yaw(),
+            to_yaw: base_yaw + std::f32::consts::PI,
+            started: Instant::now(),
+        });
+    }
+
+    /// Ask the GameManager to suggest a move; it'll come back as
+    /// GameManagerToUI::Hint. Only makes sense when it's the user's turn.
+    fn request_hint(&mut self) {
+        if !self.waiting_for_input() {
+            return;
+        }
+
+        if let Err(err) = self.to_gm.try_send(UIToGameManager::RequestHint) {
+            println!("failed requesting a hint: {}", err);
+        }
+    }
+
+    /// Ask the GameManager to start a new game; it starts once the opponent
+    /// agrees, see UIToGameManager::NewGame.
+    fn request_new_game(&mut self) {
+        if let Err(err) = self.to_gm.try_send(UIToGameManager::NewGame) {
+            println!("failed requesting a new game: {}", err);
+        }
+    }
+
+    /// Call off the new game which the opponent (or the user) has asked for,
+    /// see UIToGameManager::DeclineNewGame.
+    fn decline_new_game(&mut self) {
+        if let Err(err) = self.to_gm.try_send(UIToGameManager::DeclineNewGame) {
+            println!("failed declining a new game: {}", err);
+        }
+    }
+
+    /// Ask the local player whose turn it is to take back its last move (and
+    /// the opponent's reply), see UIToPlayerLocal::RequestUndo. It goes
+    /// through the player, since it's the player who asks the opponent; so
+    /// it's only possible while the input is pending.
+    fn request_undo(&mut self) {
+        let pending_input = match &self.pending_input {
+            Some(v) => v,
+            None => {
+                self.show_notice("Can only undo on your turn".to_string());
+                return;
+            }
+        };
+
+        if let Err(err) = pending_input
+            .to_player
+            .try_send(UIToPlayerLocal::RequestUndo)
+        {
+            println!("failed requesting an undo: {}", err);
+        }
+    }
+
+    /// Reveal the board for a while in blindfold mode, and let the GameManager
+    /// charge the penalty to the clock of the side to play, see
+    /// UIToGameManager::Peek. Since the clock only runs on the user's turn,
+    /// that's when peeking is possible.
+    fn peek(&mut self) {
+        let side = match &self.pending_input {
+            Some(v) => v.side,
+            None => {
+                self.show_notice("Can only peek on your turn".to_string());
+                return;
+            }
+        };
+
+        if let Err(err) = self.to_gm.try_send(UIToGameManager::Peek(side)) {
+            println!("failed to peek: {}", err);
+            return;
+        }
+
+        self.peek_until = Some(Instant::now() + PEEK_DUR);
+        self.apply_blindfold();
+    }
+
+    /// Ask the GameManager to pause the game, or to resume it if it's paused;
+    /// the overlay is shown once it says it's paused, see
+    /// GameManagerToUI::Paused.
+    fn toggle_pause(&mut self) {
+        let msg = if self.paused {
+            UIToGameManager::Resume
+        } else {
+            UIToGameManager::Pause
+        };
+
+        if let Err(err) = self.to_gm.try_send(msg) {
+            println!("failed to pause or resume: {}", err);
+        }
+    }
+
+    /// Ask the GameManager to turn the evaluation on or off, and show or hide
+    /// the evaluation bar accordingly.
+    fn set_evaluation(&mut self, evaluation: bool) {
+        match self
+            .to_gm
+            .try_send(UIToGameManager::SetEvaluation(evaluation))
+        {
+            Ok(_) => {
+                self.evaluation = evaluation;
+                self.eval_score = None;
+            }
+            Err(err) => {
+                println!("failed to toggle the evaluation: {}", err);
+            }
+        }
+    }
+
+    /// Show the hint pointer on top of the given pole, or hide it.
+    fn set_hint(&mut self, hint: Option<(PoleCoords, HintScore)>) {
+        self.hint = hint;
+
+        match hint {
+            Some((pcoords, _score)) => {
+                let mut t = self.layout.pole_translation(pcoords);
+                t.y = self.layout.poles_top_y + POINTER_RADIUS * 2.0;
+
+                self.hint_pointer.set_local_translation(t);
+                self.hint_pointer.set_visible(true);
+            }
+            None => self.hint_pointer.set_visible(false),
+        }
+    }
+
+    /// Move the camera according to the rotation in progress, if any.
+    fn update_camera_rotation(&mut self) {
+        let rotation = match &self.camera_rotation {
+            Some(rotation) => rotation,
+            None => return,
+        };
+
+        let t = rotation.started.elapsed().as_secs_f32() / ROTATION_DUR.as_secs_f32();
+        if t >= 1.0 {
+            self.camera.set_yaw(rotation.to_yaw);
+            self.camera_rotation = None;
+            return;
+        }
+
+        // Ease in and out, so that the rotation doesn't start and stop
+        // abruptly.
+        let k = t * t * (3.0 - 2.0 * t);
+        let yaw = rotation.from_yaw + (rotation.to_yaw - rotation.from_yaw) * k;
+        self.camera.set_yaw(yaw);
+    }
+
     /// Depending on the current mouse coords and internal state, either hide
     /// the pole pointer, or hide it. We show it when all of those are true:
     ///
     /// - PlayerLocal requested an input from the UI
-    /// - The mouse hovers some pole top
+    /// - The mouse hovers some pole top, and a token can be put there
     /// - We aren't in the process of rotating or moving 3D view
     fn update_pole_pointer(&mut self) {
-        if self.rotating || !self.waiting_for_input() {
+        if self.rotating || !self.waiting_for_input() || self.paused {
             self.pole_pointer.set_visible(false);
             return;
         }
 
         let pcoords = match self.mouse_coords_to_pole_coords(self.last_mouse_coords) {
-            Some(pcoords) => pcoords,
-            None => {
+            Some(pcoords) if self.legal_moves.contains(&pcoords) => pcoords,
+            _ => {
                 self.pole_pointer.set_visible(false);
                 return;
             }
@@ -339,8 +1191,8 @@ impl Window3D {
 
         // We need to show the pointer, so figure the exact coords, and show it.
 
-        let mut pole_top_t = Self::pole_translation(pcoords);
-        pole_top_t.y += POLE_HEIGHT / 2.0;
+        let mut pole_top_t = self.layout.pole_translation(pcoords);
+        pole_top_t.y = self.layout.poles_top_y;
 
         self.pole_pointer.set_local_translation(pole_top_t);
         self.pole_pointer.set_visible(true);
@@ -357,46 +1209,70 @@ impl Window3D {
             //println!("hey received from GM: {:?}", &msg);
 
             match msg {
-                GameManagerToUI::SetToken(side, tcoords) => {
-                    self.add_token(side, tcoords);
-                    self.set_last_token(tcoords);
-
-                    // Also play sound effect. TODO: make it optional.
-                    self.sound_player
-                        .play(sounds::Sound::PutToken(side))
-                        .unwrap();
-                }
-                GameManagerToUI::ResetBoard(board) => {
-                    for maybe_token in &mut self.tokens {
-                        if let Some(token) = maybe_token {
-                            token.unlink();
-                            *maybe_token = None;
-                        }
+                GameManagerToUI::SetToken(placed) => {
+                    self.set_hint(None);
+                    self.add_token(placed.side, placed.tcoords);
+                    self.set_last_token(placed.tcoords);
+                    self.apply_blindfold();
+                }
+                GameManagerToUI::ResetBoard(rules, diff) => {
+                    // Tokens of the win row and the last token might be still
+                    // there, but they aren't flashing anymore, so make sure
+                    // they're not left invisible.
+                    let mut flashing = Vec::new();
+                    if let Some(win_row) = self.win_row.take() {
+                        flashing.extend(win_row.row);
                     }
+                    flashing.extend(self.last_token.take());
+                    self.set_hint(None);
 
-                    self.win_row = None;
-                    self.last_token = None;
-
-                    // TODO: reimplement as an iterator exposed by the board.
-                    for x in 0..ROW_SIZE {
-                        for y in 0..ROW_SIZE {
-                            for z in 0..ROW_SIZE {
-                                let tcoords = TokenCoords::new(x, y, z);
-                                if let Some(side) = board.get(tcoords) {
-                                    self.add_token(side, tcoords);
-                                }
-                            }
+                    for tcoords in flashing {
+                        if self.tokens[self.layout.token_idx(tcoords)].is_some() {
+                            self.set_token_visible(tcoords, true);
                         }
                     }
+
+                    // If the board comes from the server, whatever tokens are
+                    // left on it are confirmed; otherwise, nothing was pending
+                    // anyway.
+                    for token in self.tokens.iter_mut().flatten() {
+                        token.unconfirmed = false;
+                    }
+
+                    // The rules only change along with the GameManager, i.e.
+                    // before the first reset, so the diff is then from the
+                    // empty board anyway.
+                    if rules.row_size() != self.layout.row_size {
+                        self.rebuild_3d_board(rules.row_size());
+                    }
+                    self.rules = rules;
+
+                    self.apply_board_diff(&diff);
+
+                    // A new game gets a new random skin, if it's random.
+                    if self.tokens.iter().all(Option::is_none) {
+                        self.skin = self.skin_setting.pick();
+                    }
                 }
 
                 GameManagerToUI::PlayerStateChanged(i, state) => {
-                    if i >= 2 {
-                        // TODO: create a separate enum for player indices
-                        panic!("invalid player idx: {}", i)
+                    // In a team game, there are also the teammates; they're
+                    // local and always ready, so there's nothing to show.
+                    if i < self.players.len() {
+                        // Whatever was measured is stale once the player
+                        // is not ready, e.g. has lost the connection.
+                        if !matches!(state, PlayerState::Ready) {
+                            self.players[i].latency = None;
+                        }
+                        self.players[i].state = state;
                     }
+                }
 
-                    self.players[i].state = state;
+                GameManagerToUI::PlayerNameChanged(i, name) => {
+                    // Same for the teammates.
+                    if i < self.players.len() {
+                        self.players[i].name = name;
+                    }
                 }
 
                 GameManagerToUI::PlayerSidesChanged(pri_side, sec_side) => {
@@ -404,58 +1280,224 @@ impl Window3D {
                     self.players[1].side = Some(sec_side);
                 }
 
-                GameManagerToUI::GameStateChanged(game_state) => {
+                GameManagerToUI::GameStateChanged(game_state, moves) => {
                     self.game_state = Some(game_state);
+                    self.moves = moves;
+                    self.seat_to_move = None;
+
+                    if let GameState::WaitingFor(side, _) = game_state {
+                        self.face_side(side);
+                        self.game_over_reason = None;
+                        self.review = None;
+                        self.comment_input = None;
+                    }
+
+                    // The board is revealed once the game is over.
+                    self.apply_blindfold();
+                }
+
+                GameManagerToUI::SeatToMove(i) => {
+                    self.seat_to_move = Some(i);
+                }
+
+                GameManagerToUI::LegalMovesChanged(legal_moves) => {
+                    self.legal_moves = legal_moves;
+                    self.update_pole_pointer();
                 }
 
                 GameManagerToUI::WinRow(win_row) => {
                     self.win_row = Some(win_row);
                 }
-            }
-        }
-    }
 
-    /// Handle all pending messages from both players.
-    fn handle_player_messages(&mut self) {
-        loop {
-            let msg = match self.from_players.try_recv() {
-                Ok(msg) => msg,
-                Err(_) => return,
-            };
+                GameManagerToUI::Hint(pcoords, score) => {
+                    self.set_hint(Some((pcoords, score)));
+                }
 
-            //println!("hey received from a player {:?}", &msg);
+                // The GUI doesn't request the analysis or the move history yet.
+                GameManagerToUI::Analysis(_) | GameManagerToUI::MoveHistory(_) => {}
 
-            match msg {
-                PlayerLocalToUI::RequestInput(side, coord_sender) => {
-                    // Sanity check that the UI is not busy serving another player.
-                    if let Some(v) = &self.pending_input {
-                        if v.side != side {
-                            panic!("coord_sender already existed for {:?} when a new one for {:?} came in", v.side, side);
-                        }
-                    }
+                GameManagerToUI::Evaluation(score) => {
+                    self.eval_score = Some(score);
+                }
 
-                    // Remember the channel to send the resulting coords to.
-                    self.pending_input = Some(PendingInput { coord_sender, side });
+                GameManagerToUI::GameOver(reason) => {
+                    self.game_over_reason = Some(reason);
+                }
 
-                    // Update the color of the pole pointer to reflect the side.
-                    let c = Self::color_by_side(side);
-                    self.pole_pointer.set_color(c.0, c.1, c.2);
+                GameManagerToUI::ClockUpdate(times) => {
+                    self.clock_times = Some(times);
                 }
-            }
-        }
-    }
 
-    /// Call the 3D rendering routine, and put all the overlay texts.
-    fn render(&mut self) -> bool {
-        if !self.w.render_with_camera(&mut self.camera) {
-            return false;
-        }
+                GameManagerToUI::MatchScore(score) => {
+                    self.match_score = Some(score);
+                }
 
-        // Write details about both players.
+                GameManagerToUI::SessionStats(stats) => {
+                    self.session_stats = stats;
+                }
 
-        self.w.draw_text(
-            &self.player_str(0),
-            &Point2::new(10.0, 0.0),
+                GameManagerToUI::Paused(paused) => {
+                    self.paused = paused;
+                    self.update_pole_pointer();
+                }
+
+                GameManagerToUI::Peeks(num_peeks) => {
+                    self.num_peeks = num_peeks;
+                }
+
+                // Mention the blunders right away; the rest of the review is
+                // in analysis mode.
+                GameManagerToUI::GameReview(record) => {
+                    let blunders: Vec<String> = record
+                        .moves
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, m)| m.annotation.mark == Some(MoveMark::Blunder))
+                        .map(|(i, m)| format!("{}. {:?} {}", i + 1, m.side, m.pole))
+                        .collect();
+
+                    if blunders.is_empty() {
+                        self.show_notice("Review: no blunders".to_string());
+                    } else {
+                        self.show_notice(format!("Review: blunders {}", blunders.join(", ")));
+                    }
+
+                    if !record.moves.is_empty() {
+                        let idx = record.moves.len() - 1;
+                        self.review = Some((record, idx));
+                    }
+                }
+
+                GameManagerToUI::Notice(notice) => {
+                    self.show_notice(notice);
+                }
+
+                GameManagerToUI::TokenPending(tcoords) => {
+                    self.set_token_unconfirmed(tcoords, true);
+                }
+                GameManagerToUI::TokenConfirmed(tcoords) => {
+                    self.set_token_unconfirmed(tcoords, false);
+                }
+
+                GameManagerToUI::SoundCue(cue) => {
+                    // TODO: make sounds optional.
+                    if let Some(sound) = sounds::Sound::for_cue(cue) {
+                        self.sound_player.play(sound).unwrap();
+                    }
+                }
+
+                GameManagerToUI::Announcement(text) => {
+                    self.handle_announcement(text);
+                }
+
+                GameManagerToUI::Chat { from, text } => {
+                    self.add_chat_message(&from, &text);
+                }
+
+                GameManagerToUI::PlayerLatency(i, rtt) => {
+                    if i < self.players.len() {
+                        self.players[i].latency = Some(rtt);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Pass the announcement on to wherever the user wants it: stdout, the
+    /// log panel, and the speaker.
+    fn handle_announcement(&mut self, text: String) {
+        #[cfg(feature = "tts")]
+        if let Some(speaker) = &self.speaker {
+            speaker.say(&text);
+        }
+
+        if !self.announce {
+            return;
+        }
+
+        println!("{}", text);
+
+        if self.announcement_log.len() == ANNOUNCEMENT_LOG_LEN {
+            self.announcement_log.pop_front();
+        }
+        self.announcement_log.push_back(text);
+    }
+
+    /// Show the given notice for a while, replacing the current one, if any.
+    fn show_notice(&mut self, notice: String) {
+        println!("notice: {}", notice);
+        self.notice = Some((notice, Instant::now() + NOTICE_DUR));
+    }
+
+    /// Handle all pending messages from both players.
+    fn handle_player_messages(&mut self) {
+        loop {
+            let msg = match self.from_players.try_recv() {
+                Ok(msg) => msg,
+                Err(_) => return,
+            };
+
+            //println!("hey received from a player {:?}", &msg);
+
+            match msg {
+                PlayerLocalToUI::RequestInput(side, to_player) => {
+                    // The UI can only serve one player at a time. Normally
+                    // the previous one cancels its request first (see
+                    // CancelInput), but the players are separate tasks, so
+                    // the new request can come in before that.
+                    if let Some(v) = &self.pending_input {
+                        if !v.to_player.same_channel(&to_player) {
+                            println!(
+                                "input for {:?} is requested while {:?} is still pending",
+                                side, v.side
+                            );
+                        }
+                    }
+
+                    // Remember the channel to send the resulting coords to.
+                    self.pending_input = Some(PendingInput { to_player, side });
+
+                    // Update the color of the pole pointer to reflect the side.
+                    let c = Self::color_by_side(side);
+                    self.pole_pointer.set_color(c.0, c.1, c.2);
+                }
+
+                PlayerLocalToUI::MoveRejected(pcoords, reason) => {
+                    self.show_notice(format!("Can't move {}: {}", pcoords, reason));
+                }
+
+                // Only drop the request if it's still the one being
+                // cancelled, and not already a new one of another player.
+                PlayerLocalToUI::CancelInput(to_player) => {
+                    let pending = self.pending_input.as_ref();
+                    if pending.is_some_and(|v| v.to_player.same_channel(&to_player)) {
+                        self.pending_input = None;
+                        self.update_pole_pointer();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Call the 3D rendering routine, and put all the overlay texts.
+    fn render(&mut self) -> bool {
+        if !self.w.render_with_camera(&mut self.camera) {
+            return false;
+        }
+
+        self.draw_turn_indicator();
+        self.draw_pause_overlay();
+        // Even in zen mode, the user has to see what they're typing.
+        self.draw_chat();
+        if self.zen {
+            return true;
+        }
+
+        // Write details about both players.
+
+        self.w.draw_text(
+            &self.player_str(0),
+            &Point2::new(10.0, 0.0),
             40.0,
             &self.font,
             &Point3::new(0.0, 1.0, 0.0),
@@ -481,13 +1523,23 @@ impl Window3D {
                 );
             }
 
-            Some(GameState::WaitingFor(waiting_for_side)) => {
-                match self.opponent_kind {
-                    OpponentKind::Local => {
-                        // Nothing special to write here in local mode.
+            Some(GameState::WaitingFor(waiting_for_side, _)) => {
+                match self.local_player_idx() {
+                    None => {
+                        // Nothing special to write here in local mode, unless
+                        // it's a team game: then, whose turn it is in the team.
+                        if let Some(i) = self.seat_to_move {
+                            self.w.draw_text(
+                                &format!("{}'s turn", GameManager::seat_name(i)),
+                                &Point2::new(10.0, 100.0),
+                                60.0,
+                                &self.font,
+                                &Point3::new(1.0, 1.0, 1.0),
+                            );
+                        }
                     }
-                    OpponentKind::Network => {
-                        let player_local = &self.players[1];
+                    Some(i) => {
+                        let player_local = &self.players[i];
                         let text;
                         let color;
 
@@ -505,21 +1557,21 @@ impl Window3D {
                 }
             }
 
-            Some(GameState::WonBy(winning_side)) => {
+            Some(GameState::WonBy(winning_side, _)) => {
                 let text;
 
-                // Depending on the opponent kind (local or network), we construct the text
-                // differently.
-                match self.opponent_kind {
-                    OpponentKind::Local => {
+                // Depending on whether both players are local, we construct the
+                // text differently.
+                match self.local_player_idx() {
+                    None => {
                         if self.players[0].side == Some(winning_side) {
                             text = "player #1 won";
                         } else {
                             text = "player #2 won";
                         }
                     }
-                    OpponentKind::Network => {
-                        let player_local = &self.players[1];
+                    Some(i) => {
+                        let player_local = &self.players[i];
                         if player_local.side == Some(winning_side) {
                             text = "you won!";
                         } else {
@@ -535,12 +1587,182 @@ impl Window3D {
                     &self.font,
                     &Point3::new(1.0, 1.0, 1.0),
                 );
+                self.draw_game_over_reason();
+            }
+
+            Some(GameState::Draw(_)) => {
+                self.w.draw_text(
+                    "draw",
+                    &Point2::new(10.0, 100.0),
+                    100.0,
+                    &self.font,
+                    &Point3::new(1.0, 1.0, 1.0),
+                );
+                self.draw_game_over_reason();
+            }
+        }
+
+        if matches!(self.opponent_kind, OpponentKind::AI) {
+            self.w.draw_text(
+                &format!("AI difficulty: {} (1, 2, 3 to change)", self.ai_difficulty),
+                &Point2::new(10.0, 250.0),
+                35.0,
+                &self.font,
+                &Point3::new(0.5, 0.5, 0.5),
+            );
+            self.w.draw_text(
+                &format!("AI style: {} (S to change)", self.ai_style),
+                &Point2::new(10.0, 300.0),
+                35.0,
+                &self.font,
+                &Point3::new(0.5, 0.5, 0.5),
+            );
+        }
+
+        // In a match, the score is per player, in the same order as above.
+        if let Some(score) = self.match_score {
+            let mut text = format!(
+                "Match (best of {}): {}-{}",
+                score.best_of, score.wins[0], score.wins[1]
+            );
+            if score.draws > 0 {
+                text += &format!(", {} drawn", score.draws);
+            }
+            self.w.draw_text(
+                &text,
+                &Point2::new(10.0, 400.0),
+                35.0,
+                &self.font,
+                &Point3::new(1.0, 1.0, 1.0),
+            );
+        }
+
+        // Same for the running tally of the whole session, once there is one.
+        if self.session_stats.games() > 0 {
+            let stats = self.session_stats;
+            let mut text = format!("Session: {}-{}", stats.wins[0], stats.wins[1]);
+            if stats.draws > 0 {
+                text += &format!(", {} drawn", stats.draws);
+            }
+            self.w.draw_text(
+                &text,
+                &Point2::new(10.0, 440.0),
+                35.0,
+                &self.font,
+                &Point3::new(0.5, 0.5, 0.5),
+            );
+        }
+
+        // In the scoring variant, show how many rows each side has completed.
+        if let (true, Some(game_state)) = (self.rules.scoring, self.game_state) {
+            let scores = game_state.scores();
+            self.w.draw_text(
+                &format!("Rows: white {}, black {}", scores.white, scores.black),
+                &Point2::new(10.0, 200.0),
+                40.0,
+                &self.font,
+                &Point3::new(1.0, 1.0, 1.0),
+            );
+        }
+
+        // Remind about the unusual rules, if needed.
+        let rules_reminder = match (self.rules.scoring, self.rules.misere) {
+            (false, false) => None,
+            (false, true) => Some("Misère: whoever completes a row, loses"),
+            (true, false) => Some("Scoring: whoever completes more rows, wins"),
+            (true, true) => Some("Misère scoring: whoever completes fewer rows, wins"),
+        };
+        if let Some(text) = rules_reminder {
+            self.w.draw_text(
+                text,
+                &Point2::new(10.0, self.w.size()[1] as f32 * 2.0 - 150.0),
+                35.0,
+                &self.font,
+                &Point3::new(1.0, 1.0, 1.0),
+            );
+        }
+
+        // In blindfold mode, show how much peeking has cost so far.
+        if self.blindfold != Blindfold::Off {
+            let penalty = self.peek_penalty * self.num_peeks;
+            self.w.draw_text(
+                &format!(
+                    "Blindfold: {} peeks, penalty {}s",
+                    self.num_peeks,
+                    penalty.as_secs()
+                ),
+                &Point2::new(10.0, self.w.size()[1] as f32 * 2.0 - 100.0),
+                35.0,
+                &self.font,
+                &Point3::new(1.0, 1.0, 1.0),
+            );
+        }
+
+        if let Some((pcoords, score)) = self.hint {
+            let score = match score {
+                HintScore::ForcedWin(1) => "wins right away".to_string(),
+                // Only count the moves of the side which gets the hint.
+                HintScore::ForcedWin(plies) => format!("wins in {} moves", plies.div_ceil(2)),
+                HintScore::Score(score) => format!("score {:+}", score),
+            };
+            self.w.draw_text(
+                &format!("Hint: {} ({})", pcoords, score),
+                &Point2::new(10.0, 350.0),
+                35.0,
+                &self.font,
+                &Point3::new(0.0, 1.0, 0.0),
+            );
+        }
+
+        if self.analysis {
+            self.draw_pole_stats();
+            self.draw_review();
+        }
+
+        if self.evaluation {
+            self.draw_evaluation_bar();
+        }
+
+        // The log panel with the latest announcements, at the top right.
+        let log_x = self.w.size()[0] as f32 * 2.0 - 650.0;
+        for (i, text) in self.announcement_log.iter().enumerate() {
+            self.w.draw_text(
+                text,
+                &Point2::new(log_x, i as f32 * 35.0),
+                30.0,
+                &self.font,
+                &Point3::new(0.8, 0.8, 0.8),
+            );
+        }
+
+        if let Some((notice, until)) = &self.notice {
+            if Instant::now() < *until {
+                self.w.draw_text(
+                    notice,
+                    &Point2::new(10.0, self.w.size()[1] as f32 * 2.0 - 200.0),
+                    35.0,
+                    &self.font,
+                    &Point3::new(1.0, 1.0, 0.0),
+                );
+            } else {
+                self.notice = None;
             }
         }
 
         // Write some hint about the controls, at the bottom.
-        self.w.draw_text(
+        let mut hint = String::from(
             "Left mouse btn: rotate, Right mouse btn: move, Enter: center, L: flash last token",
+        );
+        if self.blindfold != Blindfold::Off {
+            hint.push_str(", P: peek");
+        }
+        hint.push_str(", Z: zen mode, A: analysis, E: evaluation, R: resync, H: hint");
+        if matches!(self.opponent_kind, OpponentKind::Network) {
+            hint.push_str(", Tab: chat");
+        }
+
+        self.w.draw_text(
+            &hint,
             &Point2::new(10.0, self.w.size()[1] as f32 * 2.0 - 50.0),
             35.0,
             &self.font,
@@ -550,56 +1772,280 @@ impl Window3D {
         true
     }
 
-    /// Return whether we are currently waiting for the user's input where to
-    /// put the token.
-    fn waiting_for_input(&self) -> bool {
-        self.pending_input.is_some()
+    /// Draw the chat message being typed, if any, at the bottom left, and
+    /// unless in zen mode, the latest chat messages above it.
+    fn draw_chat(&mut self) {
+        let input_y = self.w.size()[1] as f32 * 2.0 - 250.0;
+
+        if let Some(text) = &self.chat_input {
+            self.w.draw_text(
+                &format!("Say: {}_", text),
+                &Point2::new(10.0, input_y),
+                35.0,
+                &self.font,
+                &Point3::new(1.0, 1.0, 1.0),
+            );
+        }
+
+        if self.zen {
+            return;
+        }
+
+        let n = self.chat_log.len();
+        for (i, text) in self.chat_log.iter().enumerate() {
+            self.w.draw_text(
+                text,
+                &Point2::new(10.0, input_y - (n - i) as f32 * 35.0),
+                30.0,
+                &self.font,
+                &Point3::new(0.6, 0.8, 1.0),
+            );
+        }
     }
 
-    /// Return 3D coords (translation) of the given pole.
-    fn pole_translation(pcoords: PoleCoords) -> Translation3<f32> {
-        let xcoord = MARGIN + pcoords.x as f32 * POLE_SPACING - FOUNDATION_WIDTH / 2.0;
-        let zcoord = MARGIN + pcoords.z as f32 * POLE_SPACING - FOUNDATION_WIDTH / 2.0;
+    /// In analysis mode, draw the selected move of the review, if any, with its
+    /// annotation, and the comment being typed for it.
+    fn draw_review(&mut self) {
+        let (record, idx) = match &self.review {
+            Some(v) => v,
+            None => return,
+        };
 
-        Translation3::new(xcoord, 0.0, zcoord)
+        let m = &record.moves[*idx];
+        let mut text = format!("Review: {}. {:?} {}", idx + 1, m.side, m.pole);
+        if let Some(mark) = m.annotation.mark {
+            text.push_str(&format!(" {}", mark));
+        }
+        if let Some(eval) = m.annotation.eval {
+            text.push_str(&format!(" (score {:+})", eval));
+        }
+        if let Some(comment) = &m.annotation.comment {
+            text.push_str(&format!(": {}", comment));
+        }
+
+        self.w.draw_text(
+            &text,
+            &Point2::new(10.0, 400.0),
+            35.0,
+            &self.font,
+            &Point3::new(1.0, 1.0, 1.0),
+        );
+
+        let text = match &self.comment_input {
+            Some(comment) => format!("Comment: {}_", comment),
+            None => "Left/Right: pick a move, M: mark, C: comment, W: save".to_string(),
+        };
+        self.w.draw_text(
+            &text,
+            &Point2::new(10.0, 450.0),
+            30.0,
+            &self.font,
+            &Point3::new(0.8, 0.8, 0.8),
+        );
     }
 
-    /// Return 3D coords (translation) of the given token.
-    fn token_translation(tcoords: TokenCoords) -> Translation3<f32> {
-        let mut t = Self::pole_translation(tcoords.pole_coords());
-        t.y = -POLE_HEIGHT / 2.0 + TOKEN_HEIGHT / 2.0 + TOKEN_HEIGHT * (tcoords.y as f32);
+    /// Draw the evaluation bar at the right edge of the window: the bottom part
+    /// is White's share, the top part is Black's. Until the first evaluation
+    /// comes in, it's split evenly; once the game is over, it shows the
+    /// result.
+    fn draw_evaluation_bar(&mut self) {
+        let white_share = match self.game_state {
+            Some(GameState::WonBy(Side::White, _)) => 1.0,
+            Some(GameState::WonBy(Side::Black, _)) => 0.0,
+            Some(GameState::Draw(_)) | None => 0.5,
+            Some(GameState::WaitingFor(_, _)) => match self.eval_score {
+                Some(score) => (self.engine.advantage(score) as f32 + 1.0) / 2.0,
+                None => 0.5,
+            },
+        };
 
-        t
+        // The planar coordinates have the origin in the center of the window.
+        let size = self.w.size();
+        let right = size[0] as f32 / 2.0 - EVAL_BAR_WIDTH;
+        let half_height = size[1] as f32 * EVAL_BAR_HEIGHT / 2.0;
+        let split = -half_height + half_height * 2.0 * white_share;
+
+        let white = Self::color_by_side(Side::White);
+        let black = Self::color_by_side(Side::Black);
+        let mut x = right - EVAL_BAR_WIDTH;
+        while x < right {
+            self.w.draw_planar_line(
+                &Point2::new(x, -half_height),
+                &Point2::new(x, split),
+                &Point3::new(white.0, white.1, white.2),
+            );
+            self.w.draw_planar_line(
+                &Point2::new(x, split),
+                &Point2::new(x, half_height),
+                &Point3::new(black.0, black.1, black.2),
+            );
+            x += 1.0;
+        }
     }
 
-    /// returns approximate point where the given ray intersects with the plane
-    /// which matches the top of the poles.
-    ///
-    /// TODO: it's written in extremely dumb way, figure the proper way to do it.
-    fn top_plane_intersect(p: &Point3<f32>, v: &Vector3<f32>) -> Option<Point3<f32>> {
-        // If the ray doesn't intersect with the plane, cut it short.
-        let ord_p = Self::cmpf32(POLES_TOP_Y, p.y);
-        let ord_v = Self::cmpf32(0.0, v.y);
-        if ord_p == ord_v {
-            return None;
+    /// In analysis mode, show stats about the pole which the mouse hovers, next
+    /// to the mouse pointer: how many rows through that pole can still be
+    /// completed by each side, and the evaluation after the side to move puts
+    /// a token there.
+    fn draw_pole_stats(&mut self) {
+        if self.rotating {
+            return;
         }
 
-        let mut pcur = *p;
-        let vcur = Vector3::new(v.x / 10.0, v.y / 10.0, v.z / 10.0);
+        let pcoords = match self.mouse_coords_to_pole_coords(self.last_mouse_coords) {
+            Some(pcoords) => pcoords,
+            None => return,
+        };
+
+        let mut game = Game::with_rules(self.rules);
+        game.reset_board(&self.board());
+
+        let lines = analysis::open_lines_through_pole(game.get_board(), pcoords);
+        let mut texts = vec![
+            pcoords.to_string(),
+            format!("Open rows: white {}, black {}", lines.white, lines.black),
+        ];
+
+        if let Some(GameState::WaitingFor(side, _)) = self.game_state {
+            match eval::evaluate_move(&game, side, pcoords) {
+                Some(score) => {
+                    // The score is from White's point of view, so flip it for
+                    // Black.
+                    let score = if side == Side::White { score } else { -score };
+                    texts.push(format!("Score for {:?}: {:+}", side, score));
+                }
+                None => texts.push("The pole is full".to_string()),
+            }
+        }
 
-        let ord_orig = Self::cmpf32(POLES_TOP_Y, pcur.y);
+        for (i, text) in texts.iter().enumerate() {
+            self.w.draw_text(
+                text,
+                &Point2::new(
+                    self.last_mouse_coords.x * 2.0 + 40.0,
+                    self.last_mouse_coords.y * 2.0 + i as f32 * 35.0,
+                ),
+                30.0,
+                &self.font,
+                &Point3::new(1.0, 1.0, 0.0),
+            );
+        }
+    }
 
-        loop {
-            pcur.x += vcur.x;
-            pcur.y += vcur.y;
-            pcur.z += vcur.z;
+    /// Returns the board with the tokens which are currently shown.
+    fn board(&self) -> BoardState {
+        let mut board = BoardState::new();
 
-            if Self::cmpf32(POLES_TOP_Y, pcur.y) != ord_orig {
-                break;
+        for x in 0..self.layout.row_size {
+            for y in 0..self.layout.row_size {
+                for z in 0..self.layout.row_size {
+                    let tcoords = TokenCoords::new(x, y, z);
+                    if let Some(token) = &self.tokens[self.layout.token_idx(tcoords)] {
+                        board.set(token.side, tcoords);
+                    }
+                }
             }
         }
 
-        Some(pcur)
+        board
+    }
+
+    /// Under the result of the game, write why it's over, unless it's just the
+    /// last move which has ended it.
+    fn draw_game_over_reason(&mut self) {
+        let text = match self.game_over_reason {
+            None | Some(GameOverReason::Move) => return,
+            Some(GameOverReason::DeadDraw) => DEAD_DRAW_REASON.to_string(),
+            Some(GameOverReason::Resignation(side)) => format!("{:?} resigned", side),
+            Some(GameOverReason::DrawAgreed) => "agreed by both sides".to_string(),
+            Some(GameOverReason::Timeout(side)) => format!("{:?} ran out of time", side),
+        };
+
+        self.w.draw_text(
+            &text,
+            &Point2::new(10.0, 200.0),
+            40.0,
+            &self.font,
+            &Point3::new(0.5, 0.5, 0.5),
+        );
+    }
+
+    /// Whose turn it is and the move number, or how the game ended, in the
+    /// color of the relevant side, at the bottom right. In zen mode, it's the
+    /// only text shown.
+    fn draw_turn_indicator(&mut self) {
+        let (text, color) = match self.game_state {
+            None => return,
+            Some(GameState::WaitingFor(side, _)) => {
+                (format!("{:?} to move", side), Self::color_by_side(side))
+            }
+            Some(GameState::WonBy(side, _)) => {
+                (format!("{:?} won", side), Self::color_by_side(side))
+            }
+            Some(GameState::Draw(_)) => ("Draw".to_string(), (0.5, 0.5, 0.5)),
+        };
+
+        let size = self.w.size();
+        self.w.draw_text(
+            &text,
+            &Point2::new(size[0] as f32 * 2.0 - 300.0, size[1] as f32 * 2.0 - 50.0),
+            35.0,
+            &self.font,
+            &Point3::new(color.0, color.1, color.2),
+        );
+
+        if let Some(GameState::WaitingFor(_, _)) = self.game_state {
+            self.w.draw_text(
+                &format!("Move {}", self.moves + 1),
+                &Point2::new(size[0] as f32 * 2.0 - 300.0, size[1] as f32 * 2.0 - 100.0),
+                35.0,
+                &self.font,
+                &Point3::new(0.5, 0.5, 0.5),
+            );
+        }
+    }
+
+    /// While the game is paused, says so across the middle of the window, even
+    /// in zen mode, since nothing else shows why the game doesn't go on.
+    fn draw_pause_overlay(&mut self) {
+        if !self.paused {
+            return;
+        }
+
+        let size = self.w.size();
+        self.w.draw_text(
+            "Paused",
+            &Point2::new(size[0] as f32 - 200.0, size[1] as f32 - 100.0),
+            120.0,
+            &self.font,
+            &Point3::new(1.0, 1.0, 1.0),
+        );
+        self.w.draw_text(
+            "press space to resume",
+            &Point2::new(size[0] as f32 - 210.0, size[1] as f32 + 40.0),
+            40.0,
+            &self.font,
+            &Point3::new(0.5, 0.5, 0.5),
+        );
+    }
+
+    /// If there is only one local player (the opponent is either network, AI
+    /// or random), returns its index. In self-play and in a replay, there are
+    /// no local players at all, so it's None just like when both are local.
+    fn local_player_idx(&self) -> Option<usize> {
+        match self.opponent_kind {
+            OpponentKind::Local | OpponentKind::Replay => None,
+            OpponentKind::Network if self.ai_player => None,
+            OpponentKind::Network => Some(1),
+            OpponentKind::AI | OpponentKind::Random if self.ai_player => None,
+            OpponentKind::AI | OpponentKind::Random => Some(0),
+        }
+    }
+
+    /// Return whether we are currently waiting for the user's input where to
+    /// put the token.
+    fn waiting_for_input(&self) -> bool {
+        self.pending_input.is_some()
     }
 
     /// Just a shortcut to compare two f32-s.
@@ -614,29 +2060,7 @@ impl Window3D {
         let window_size = Vector2::new(self.w.size()[0] as f32, self.w.size()[1] as f32);
         let ray = self.camera.unproject(&mouse_pt, &window_size);
 
-        Self::top_plane_intersect(&ray.0, &ray.1)
-    }
-
-    /// Try to convert pole top 3D coords (translation) to the game PoleCoords.
-    /// If the given coords don't seem to be pointing to a particular plane,
-    /// returns None.
-    fn pole_translation_to_pole_coords(t: Point3<f32>) -> Option<PoleCoords> {
-        const TOLERANCE: f32 = POLE_RADIUS * 1.5;
-
-        for x in 0..ROW_SIZE {
-            for z in 0..ROW_SIZE {
-                let cur_t = Self::pole_translation(PoleCoords::new(x, z));
-                if t.x >= cur_t.x - TOLERANCE
-                    && t.x <= cur_t.x + TOLERANCE
-                    && t.z >= cur_t.z - TOLERANCE
-                    && t.z <= cur_t.z + TOLERANCE
-                {
-                    return Some(PoleCoords::new(x, z));
-                }
-            }
-        }
-
-        None
+        self.layout.top_plane_intersect(&ray.0, &ray.1)
     }
 
     /// Try to convert mouse pointer coords into game coords of a pole
@@ -644,17 +2068,95 @@ impl Window3D {
     /// top, returns None.
     fn mouse_coords_to_pole_coords(&self, mouse_pt: Point2<f32>) -> Option<PoleCoords> {
         let v = self.mouse_coords_to_pole_translation(mouse_pt)?;
-        Self::pole_translation_to_pole_coords(v)
+        self.layout.pole_at(v)
     }
 
     /// Add a new token with the given side and coords.
     fn add_token(&mut self, side: Side, tcoords: TokenCoords) {
         let mut s = self.w.add_sphere(TOKEN_RADIUS);
-        let c = Self::color_by_side(side);
+        let c = self.skin.tint(Self::color_by_side(side));
         s.set_color(c.0, c.1, c.2);
-        s.set_local_translation(Self::token_translation(tcoords));
+        self.skin.apply_texture(&mut s);
+        s.set_local_translation(self.layout.token_translation(tcoords));
+
+        self.tokens[self.layout.token_idx(tcoords)] = Some(Token {
+            node: s,
+            side,
+            unconfirmed: false,
+        });
+    }
+
+    /// Mark the token with the given coords as (un)confirmed by the server, if
+    /// it's there.
+    fn set_token_unconfirmed(&mut self, tcoords: TokenCoords, unconfirmed: bool) {
+        if let Some(token) = &mut self.tokens[self.layout.token_idx(tcoords)] {
+            token.unconfirmed = unconfirmed;
+            self.apply_blindfold();
+        }
+    }
+
+    /// Replace all the tokens in the scene with the ones on the given board.
+    fn set_board(&mut self, board: &BoardState) {
+        for maybe_token in &mut self.tokens {
+            if let Some(token) = maybe_token {
+                token.node.unlink();
+                *maybe_token = None;
+            }
+        }
+
+        // TODO: reimplement as an iterator exposed by the board.
+        for x in 0..self.layout.row_size {
+            for y in 0..self.layout.row_size {
+                for z in 0..self.layout.row_size {
+                    let tcoords = TokenCoords::new(x, y, z);
+                    if let Some(side) = board.get(tcoords) {
+                        self.add_token(side, tcoords);
+                    }
+                }
+            }
+        }
+
+        self.apply_blindfold();
+    }
 
-        self.tokens[Self::token_coords_to_idx(tcoords)] = Some(s);
+    /// Update only the tokens which have changed, see BoardState::diff.
+    fn apply_board_diff(&mut self, diff: &BoardDiff) {
+        for &tcoords in &diff.removed {
+            self.remove_token(tcoords);
+        }
+
+        for &(side, tcoords) in &diff.set {
+            self.remove_token(tcoords);
+            self.add_token(side, tcoords);
+        }
+
+        self.apply_blindfold();
+    }
+
+    /// Remove the token with the given coords from the scene, if it's there.
+    fn remove_token(&mut self, tcoords: TokenCoords) {
+        if let Some(mut token) = self.tokens[self.layout.token_idx(tcoords)].take() {
+            token.node.unlink();
+        }
+    }
+
+    /// Rebuild the scene from the authoritative board, in case it has diverged
+    /// for whatever reason.
+    fn resync_scene(&mut self) {
+        let board = self.board_watch.borrow().clone();
+        if board != self.board() {
+            println!("the scene has diverged from the board, resyncing");
+        }
+
+        // If the last token is not there anymore, forget about it, so that we
+        // don't try to flash it.
+        if let Some(last_token) = self.last_token {
+            if board.get(last_token).is_none() {
+                self.last_token = None;
+            }
+        }
+
+        self.set_board(&board);
     }
 
     /// Remember which token was set last. Needed because we need to flash it a
@@ -674,11 +2176,6 @@ impl Window3D {
         self.flash_show = true;
     }
 
-    /// Convert game token coords to the index in the self.tokens vector.
-    fn token_coords_to_idx(tcoords: TokenCoords) -> usize {
-        tcoords.x + tcoords.y * ROW_SIZE + tcoords.z * ROW_SIZE * ROW_SIZE
-    }
-
     /// Return RGB floats for the given game side.
     fn color_by_side(side: Side) -> (f32, f32, f32) {
         match side {
@@ -698,6 +2195,10 @@ impl Window3D {
 
         if let Some(side) = self.players[i].side {
             s.push_str(&format!(" ({:?})", side));
+
+            if let Some(times) = &self.clock_times {
+                s.push_str(&format!(" [{}]", clock::format_time(times.remaining(side))));
+            }
         }
 
         match &self.players[i].state {
@@ -706,6 +2207,13 @@ impl Window3D {
             }
             PlayerState::Ready => {
                 s.push_str(": ready");
+
+                if let Some(rtt) = self.players[i].latency {
+                    s.push_str(&format!(", ping: {} ms", rtt.as_millis()));
+                }
+            }
+            PlayerState::Gone => {
+                s.push_str(": gone");
             }
         }
 
@@ -719,10 +2227,126 @@ impl Window3D {
     }
 }
 
+/// Sizes and positions of the 3D model of the board, which depend on the
+/// size of the board, see BoardLayout::new.
+#[derive(Debug, Clone, Copy)]
+struct BoardLayout {
+    /// Number of poles in a row, and of tokens on a pole.
+    row_size: usize,
+    pole_height: f32,
+    /// The foundation is square, this is its side.
+    foundation_width: f32,
+    /// Y coord for a plane which matches tops of all poles.
+    poles_top_y: f32,
+}
+
+impl BoardLayout {
+    /// Computes the layout for a board with the given number of poles in a
+    /// row (and tokens on a pole).
+    fn new(row_size: usize) -> BoardLayout {
+        let pole_height = TOKEN_HEIGHT * (row_size as f32 + (1.0 - TOKEN_D_TO_HEIGHT));
+
+        BoardLayout {
+            row_size,
+            pole_height,
+            foundation_width: POLE_SPACING * (row_size as f32 - 1.0) + MARGIN * 2.0,
+            poles_top_y: pole_height / 2.0,
+        }
+    }
+
+    /// Number of tokens on a full board.
+    fn num_tokens(&self) -> usize {
+        self.row_size.pow(3)
+    }
+
+    /// Convert game token coords to the index in Window3D::tokens.
+    fn token_idx(&self, tcoords: TokenCoords) -> usize {
+        tcoords.x + tcoords.y * self.row_size + tcoords.z * self.row_size * self.row_size
+    }
+
+    /// Return 3D coords (translation) of the given pole.
+    fn pole_translation(&self, pcoords: PoleCoords) -> Translation3<f32> {
+        let xcoord = MARGIN + pcoords.x as f32 * POLE_SPACING - self.foundation_width / 2.0;
+        let zcoord = MARGIN + pcoords.z as f32 * POLE_SPACING - self.foundation_width / 2.0;
+
+        Translation3::new(xcoord, 0.0, zcoord)
+    }
+
+    /// Return 3D coords (translation) of the given token.
+    fn token_translation(&self, tcoords: TokenCoords) -> Translation3<f32> {
+        let mut t = self.pole_translation(tcoords.pole_coords());
+        t.y = -self.pole_height / 2.0 + TOKEN_HEIGHT / 2.0 + TOKEN_HEIGHT * (tcoords.y as f32);
+
+        t
+    }
+
+    /// returns approximate point where the given ray intersects with the plane
+    /// which matches the top of the poles.
+    ///
+    /// TODO: it's written in extremely dumb way, figure the proper way to do it.
+    fn top_plane_intersect(&self, p: &Point3<f32>, v: &Vector3<f32>) -> Option<Point3<f32>> {
+        // If the ray doesn't intersect with the plane, cut it short.
+        let ord_p = Window3D::cmpf32(self.poles_top_y, p.y);
+        let ord_v = Window3D::cmpf32(0.0, v.y);
+        if ord_p == ord_v {
+            return None;
+        }
+
+        let mut pcur = *p;
+        let vcur = Vector3::new(v.x / 10.0, v.y / 10.0, v.z / 10.0);
+
+        let ord_orig = Window3D::cmpf32(self.poles_top_y, pcur.y);
+
+        loop {
+            pcur.x += vcur.x;
+            pcur.y += vcur.y;
+            pcur.z += vcur.z;
+
+            if Window3D::cmpf32(self.poles_top_y, pcur.y) != ord_orig {
+                break;
+            }
+        }
+
+        Some(pcur)
+    }
+
+    /// Try to convert pole top 3D coords (translation) to the game PoleCoords.
+    /// If the given coords don't seem to be pointing to a particular plane,
+    /// returns None.
+    fn pole_at(&self, t: Point3<f32>) -> Option<PoleCoords> {
+        for x in 0..self.row_size {
+            for z in 0..self.row_size {
+                let cur_t = self.pole_translation(PoleCoords::new(x, z));
+                if t.x >= cur_t.x - PICK_TOLERANCE
+                    && t.x <= cur_t.x + PICK_TOLERANCE
+                    && t.z >= cur_t.z - PICK_TOLERANCE
+                    && t.z <= cur_t.z + PICK_TOLERANCE
+                {
+                    return Some(PoleCoords::new(x, z));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// A token on the board.
+#[derive(Clone)]
+struct Token {
+    /// The sphere in the 3D scene.
+    node: SceneNode,
+    side: Side,
+    /// Whether the move is not confirmed by the server yet, see
+    /// GameManagerToUI::TokenPending.
+    unconfirmed: bool,
+}
+
 /// Context for the input requested from UI by PlayerLocal.
 struct PendingInput {
-    /// Where to send the resulting pole coords to.
-    coord_sender: mpsc::Sender<PoleCoords>,
+    /// Where to send the resulting pole coords to, or the undo request, see
+    /// Window3D::request_undo.
+    to_player: mpsc::Sender<UIToPlayerLocal>,
     /// Side of the token to request. Only affects the color of the pole
     /// pointer.
     side: Side,
@@ -736,4 +2360,108 @@ struct PlayerInfo {
 
     state: PlayerState,
     side: Option<Side>,
+    /// Round-trip time to the player, if it's measured, see
+    /// GameManagerToUI::PlayerLatency.
+    latency: Option<Duration>,
+}
+
+/// Blindfold mode: whether and how to hide the tokens once they are placed.
+/// The last placed token is always shown, so that the player can see the
+/// opponent's move.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Blindfold {
+    /// Normal game, nothing is hidden.
+    Off,
+    /// Tokens are shown, but all of the same color.
+    Colors,
+    /// Tokens are not shown at all.
+    All,
+}
+
+impl FromStr for Blindfold {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "off" => Ok(Blindfold::Off),
+            "colors" => Ok(Blindfold::Colors),
+            "all" => Ok(Blindfold::All),
+            _ => Err(anyhow!(
+                "invalid blindfold mode; try 'off', 'colors' or 'all'"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Blindfold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Blindfold::Off => write!(f, "off"),
+            Blindfold::Colors => write!(f, "colors"),
+            Blindfold::All => write!(f, "all"),
+        }
+    }
+}
+
+/// Color theme of the scene.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    /// Yellow board on black.
+    Classic,
+    /// Bluish grey board on dark blue.
+    Night,
+    /// Wooden board on dark brown.
+    Wood,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::Classic, Theme::Night, Theme::Wood];
+
+    pub fn background_color(&self) -> (f32, f32, f32) {
+        match self {
+            Theme::Classic => (0.0, 0.0, 0.0),
+            Theme::Night => (0.05, 0.05, 0.15),
+            Theme::Wood => (0.15, 0.1, 0.05),
+        }
+    }
+
+    pub fn foundation_color(&self) -> (f32, f32, f32) {
+        match self {
+            Theme::Classic => (1.0, 0.8, 0.0),
+            Theme::Night => (0.3, 0.3, 0.45),
+            Theme::Wood => (0.55, 0.35, 0.15),
+        }
+    }
+
+    pub fn pole_color(&self) -> (f32, f32, f32) {
+        match self {
+            Theme::Classic => (1.0, 1.0, 0.0),
+            Theme::Night => (0.6, 0.6, 0.75),
+            Theme::Wood => (0.75, 0.55, 0.3),
+        }
+    }
+}
+
+impl FromStr for Theme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "classic" => Ok(Theme::Classic),
+            "night" => Ok(Theme::Night),
+            "wood" => Ok(Theme::Wood),
+            _ => Err(anyhow!("invalid theme; try 'classic', 'night' or 'wood'")),
+        }
+    }
+}
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Theme::Classic => write!(f, "classic"),
+            Theme::Night => write!(f, "night"),
+            Theme::Wood => write!(f, "wood"),
+        }
+    }
 }
@@ -0,0 +1,140 @@
+use anyhow::{anyhow, Result};
+use kiss3d::event::{Action, Key, WindowEvent};
+use kiss3d::nalgebra::{Point2, Point3};
+use kiss3d::text::Font;
+use kiss3d::window::Window;
+
+use connectfour::game_manager::player_ws_client;
+use connectfour::protocol::WSOpenGame;
+
+use super::gui3d::Theme;
+
+/// Max number of the open games to offer; each is picked with a number key.
+const MAX_GAMES: usize = 9;
+
+/// Asks the server at the given URL for the games which wait for the second
+/// player, see player_ws_client::list_open_games. It's called before the
+/// tokio runtime of the game is up, so it runs its own one.
+pub fn fetch(url: &str) -> Result<Vec<WSOpenGame>> {
+    let url = url::Url::parse(url).map_err(|err| anyhow!("invalid URL {}: {}", url, err))?;
+    let rt = tokio::runtime::Runtime::new()?;
+
+    rt.block_on(player_ws_client::list_open_games(&url))
+}
+
+/// Offers the user in the given window to join one of the open games. Returns
+/// the ID of the picked one, or Some(None) if the user would rather go with
+/// the configured game (default_game_id), or None if the user has closed the
+/// window.
+pub fn pick_game(
+    w: &mut Window,
+    theme: Theme,
+    open_games: &[WSOpenGame],
+    default_game_id: &str,
+) -> Option<Option<String>> {
+    let font = Font::default();
+    let white = Point3::new(1.0, 1.0, 1.0);
+    let grey = Point3::new(0.5, 0.5, 0.5);
+    let green = Point3::new(0.0, 1.0, 0.0);
+
+    let bg = theme.background_color();
+    w.set_background_color(bg.0, bg.1, bg.2);
+
+    let open_games = &open_games[..open_games.len().min(MAX_GAMES)];
+    let lines: Vec<String> = open_games
+        .iter()
+        .enumerate()
+        .map(|(i, og)| {
+            let host = if og.host_name.is_empty() {
+                "someone"
+            } else {
+                &og.host_name
+            };
+            format!(
+                "{}: {} ({} waits for {})",
+                i + 1,
+                og.game_id,
+                host,
+                waiting_time(og.waiting_secs)
+            )
+        })
+        .collect();
+
+    while w.render() {
+        for event in w.events().iter() {
+            if let WindowEvent::Key(key, Action::Press, _) = event.value {
+                if let Some(n) = key_number(key) {
+                    if let Some(og) = open_games.get(n - 1) {
+                        return Some(Some(og.game_id.clone()));
+                    }
+                }
+                if matches!(key, Key::Return | Key::Escape) {
+                    return Some(None);
+                }
+            }
+        }
+
+        if lines.is_empty() {
+            w.draw_text(
+                "Nobody is waiting for an opponent.",
+                &Point2::new(50.0, 50.0),
+                80.0,
+                &font,
+                &green,
+            );
+        } else {
+            w.draw_text(
+                "Join an open game?",
+                &Point2::new(50.0, 50.0),
+                80.0,
+                &font,
+                &green,
+            );
+        }
+        for (i, line) in lines.iter().enumerate() {
+            let y = 200.0 + i as f32 * 80.0;
+            w.draw_text(line, &Point2::new(50.0, y), 60.0, &font, &white);
+        }
+
+        let h = w.size()[1] as f32 * 2.0;
+        let hint = if lines.is_empty() {
+            format!("Enter or Esc: start the game {}", default_game_id)
+        } else {
+            format!(
+                "1-{}: join, Enter or Esc: start the game {} instead",
+                lines.len(),
+                default_game_id
+            )
+        };
+        w.draw_text(&hint, &Point2::new(50.0, h - 150.0), 50.0, &font, &grey);
+    }
+
+    None
+}
+
+/// Formats the waiting time for the picker, e.g. "42s" or "3m".
+fn waiting_time(secs: u64) -> String {
+    if secs < 60 {
+        format!("{}s", secs)
+    } else {
+        format!("{}m", secs / 60)
+    }
+}
+
+/// Returns the number on the given number key, if it's one.
+fn key_number(key: Key) -> Option<usize> {
+    let n = match key {
+        Key::Key1 => 1,
+        Key::Key2 => 2,
+        Key::Key3 => 3,
+        Key::Key4 => 4,
+        Key::Key5 => 5,
+        Key::Key6 => 6,
+        Key::Key7 => 7,
+        Key::Key8 => 8,
+        Key::Key9 => 9,
+        _ => return None,
+    };
+
+    Some(n)
+}
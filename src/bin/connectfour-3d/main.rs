@@ -1,75 +1,661 @@
+mod autosave;
+mod config;
 mod gui3d;
+mod lobby;
+mod skins;
 mod sounds;
+#[cfg(feature = "tts")]
+mod speech;
+mod update;
+mod wizard;
 
 use std::fmt;
+use std::io;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::mpsc as std_mpsc;
 use std::thread;
+use std::time::Duration;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use clap::Parser;
-use tokio::sync::mpsc;
+use kiss3d::window::Window;
+use tokio::sync::{mpsc, watch};
 use tokio::task;
 
-use connectfour::game::Side;
-use connectfour::game_manager::player_local::{PlayerLocal, PlayerLocalToUI};
+use connectfour::engine::EngineKind;
+use connectfour::game::eval::Style;
+use connectfour::game::handicap::Handicap;
+use connectfour::game::record::GameRecord;
+use connectfour::game::{BoardState, Rules, Side};
+use connectfour::game_manager::clock::TimeControl;
+use connectfour::game_manager::player_ai::{Difficulty, PlayerAI};
+use connectfour::game_manager::player_external::PlayerExternalEngine;
+use connectfour::game_manager::player_local::{AutoMove, PlayerLocal, PlayerLocalToUI};
+use connectfour::game_manager::player_random::PlayerRandom;
+use connectfour::game_manager::player_replay::PlayerReplay;
+use connectfour::game_manager::player_scripted::{PlayerScripted, Script};
 use connectfour::game_manager::player_ws_client::PlayerWSClient;
+use connectfour::game_manager::save::SavedGame;
 use connectfour::game_manager::{
-    GameManager, GameManagerToPlayer, GameManagerToUI, PlayerToGameManager,
+    FullGameState, GameManager, GameManagerConfig, GameManagerToPlayer, GameManagerToUI,
+    PlayerToGameManager, SideChoice, UIToGameManager,
 };
+use connectfour::protocol::WireFormat;
+
+use config::Config;
+
+/// Once the window is closed, how long to wait for the GameManager and the
+/// players to wrap up before exiting anyway.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(Debug, clap::Parser)]
 struct CliArgs {
-    #[clap(short = 'o', long = "opponent", default_value_t = OpponentKind::Network)]
-    opponent_kind: OpponentKind,
+    /// Kind of the opponent: local, network, ai, random (which just puts its
+    /// tokens on random poles; it's meant for testing) or replay (both sides
+    /// replay a recorded game, see --replay-file). This one, as well as the
+    /// URL, the game name and the theme, default to the config file (which the
+    /// first-run wizard creates), or to network if there's none.
+    #[clap(short = 'o', long = "opponent")]
+    opponent_kind: Option<OpponentKind>,
+
+    /// Kind of the player on this side of the board: local, ai or scripted
+    /// (which plays the --script). With "--opponent ai --player ai", the AI
+    /// plays itself, which is useful for testing, or just to watch; with
+    /// "--opponent network --player ai", the AI plays over the network.
+    #[clap(short = 'p', long = "player", default_value_t = PlayerKind::Local)]
+    player_kind: PlayerKind,
+
+    /// Name to show to the opponent in a network game, and in the window.
+    /// Defaults to the config file, or to the OS username.
+    #[clap(long = "name")]
+    name: Option<String>,
 
     /// URL to use for the network game.
-    #[clap(short = 'u', long = "url", default_value_t = String::from("ws://64.226.98.150:7248"))]
-    url: String,
+    #[clap(short = 'u', long = "url")]
+    url: Option<String>,
 
     /// Game name to use for the network game.
-    #[clap(short = 'g', long = "game", default_value_t = String::from("mygame1"))]
-    game_id: String,
+    #[clap(short = 'g', long = "game")]
+    game_id: Option<String>,
+
+    /// Encoding of the messages in the network game: json, or msgpack (a
+    /// binary one, which takes less bandwidth). The older servers only speak
+    /// JSON, so they stick to it regardless.
+    #[clap(long = "wire-format", default_value_t = WireFormat::Json)]
+    wire_format: WireFormat,
+
+    /// Don't ask the server to compress the messages in the network game; by
+    /// default, they're compressed if the server can do it.
+    #[clap(long = "no-deflate")]
+    no_deflate: bool,
+
+    /// Only watch the network game, without playing it: both sides are the
+    /// players on the other machines. Needs a server which lets anyone watch.
+    #[clap(long = "watch")]
+    watch: bool,
+
+    /// Before the network game, ask the server for the games which wait for
+    /// an opponent, and offer to join one of them instead of the --game.
+    #[clap(long = "lobby")]
+    lobby: bool,
+
+    /// Color theme: classic, night or wood.
+    #[clap(long = "theme")]
+    theme: Option<gui3d::Theme>,
+
+    /// Style of the tokens: plain, marble, wood, glass, or random (a new one
+    /// for every game). Defaults to the config file, or to plain.
+    #[clap(long = "skin")]
+    token_skin: Option<skins::TokenSkin>,
+
+    /// Check for a newer release on startup. It can also be turned on
+    /// permanently in the config file.
+    #[clap(long = "check-updates")]
+    check_updates: bool,
+
+    /// URL of the release feed to check for updates.
+    #[clap(long = "update-url")]
+    update_url: Option<String>,
+
+    /// Blindfold mode: hide either the colors of the placed tokens, or the
+    /// tokens altogether. Possible values: off, colors, all.
+    #[clap(long = "blindfold", default_value_t = gui3d::Blindfold::Off)]
+    blindfold: gui3d::Blindfold,
+
+    /// Time penalty, in seconds, for every peek at the board in blindfold mode;
+    /// in a timed game (see --time), it's taken off the clock.
+    #[clap(long = "peek-penalty", default_value_t = 10)]
+    peek_penalty: u64,
+
+    /// Play the misère variant: whoever completes a row, loses. Only supported
+    /// in local games.
+    #[clap(long = "misere")]
+    misere: bool,
+
+    /// Play the scoring variant: the game goes on until the board is full, and
+    /// whoever completes more rows wins. Only supported in local games.
+    #[clap(long = "scoring")]
+    scoring: bool,
+
+    /// Start in zen mode: no text on the screen except a small turn
+    /// indicator. It can also be toggled with the Z key.
+    #[clap(long = "zen")]
+    zen: bool,
+
+    /// In a local game, rotate the camera to the other side of the board
+    /// between turns, so that each player views it from their own side.
+    #[clap(long = "auto-rotate")]
+    auto_rotate: bool,
+
+    /// Start with the evaluation bar shown; it can also be toggled with the E
+    /// key. The position is evaluated by the --ai-engine after every move.
+    #[clap(long = "eval-bar")]
+    eval_bar: bool,
+
+    /// Announce every move, game state change and result as a line of plain
+    /// text, e.g. "White plays B3, stack height 2": print it to stdout, and
+    /// show the latest ones in a log panel.
+    #[clap(long = "announce")]
+    announce: bool,
+
+    /// Speak the announcements aloud, with the OS text-to-speech command
+    /// ("say" on macOS, "espeak" elsewhere).
+    #[cfg(feature = "tts")]
+    #[clap(long = "speak")]
+    speak: bool,
+
+    /// Text-to-speech command to use with --speak instead of the default one,
+    /// e.g. "espeak -s 200"; it's split on whitespace, and the text is passed
+    /// as the last argument.
+    #[cfg(feature = "tts")]
+    #[clap(long = "speak-cmd")]
+    speak_cmd: Option<String>,
+
+    /// Handicap: the given side gets a few tokens pre-placed on the central
+    /// poles, and the other side moves first. Format: side and the number of
+    /// tokens, e.g. "black:2"; with ":free" (e.g. "black:2:free"), the side
+    /// places up to 3 tokens wherever it likes before the game starts
+    /// instead. Only supported in local games.
+    #[clap(long = "handicap")]
+    handicap: Option<Handicap>,
+
+    /// Start every game with the given number of random moves, for the
+    /// variety, e.g. against the AI; the players take over from there. Only
+    /// supported in local games.
+    #[clap(long = "random-opening")]
+    random_opening: Option<usize>,
+
+    /// Time control: main time in minutes and the increment in seconds, e.g.
+    /// "5+3"; a side which runs out of time loses. In a network game, both
+    /// players should pass the same one.
+    #[clap(long = "time")]
+    time_control: Option<TimeControl>,
+
+    /// Resume the game saved in the given file, if it exists, and keep saving
+    /// the game there as it goes on, so that it can be resumed again after
+    /// the app is closed. The rules and the time control aren't saved, so
+    /// pass the same ones again. Only supported in local games.
+    #[clap(long = "resume")]
+    resume: Option<PathBuf>,
+
+    /// Side to play: white, black or random (picked anew for every game).
+    /// White moves first, unless there's a handicap. Against the AI, the AI
+    /// plays the other side; with "--player ai", it's the side of that AI.
+    /// Only supported in local games.
+    #[clap(long = "side", default_value_t = SideChoice::default())]
+    side: SideChoice,
+
+    /// Swap the sides for every new game (see the N key), like people usually
+    /// play a series; --side then only picks the side for the first one. Only
+    /// supported in local games.
+    #[clap(long = "alternate-sides")]
+    alternate_sides: bool,
+
+    /// Play a match of the given number of games: whoever wins more than half
+    /// of them wins the match (draws don't count). The next game starts by
+    /// itself a few seconds after the previous one is over. Only supported in
+    /// local games.
+    #[clap(long = "best-of")]
+    best_of: Option<u32>,
+
+    /// Name of player #1 in a local game, shown instead of "local"; in a team
+    /// game, it's the name of team A.
+    #[clap(long = "p1")]
+    p1: Option<String>,
+
+    /// Name of player #2 in a local game, like --p1; in a team game, it's the
+    /// name of team B.
+    #[clap(long = "p2")]
+    p2: Option<String>,
+
+    /// Play two against two: each side is played by a team of two, whose
+    /// players take turns at their side's moves, so the seats go A1, B1, A2,
+    /// B2. Only supported in local games.
+    #[clap(long = "teams")]
+    teams: bool,
+
+    /// Time limit, in seconds, for every move of a local player: once it
+    /// passes, the move is made on the player's behalf, see --auto-move.
+    /// Useful with --time, so that a player who has stepped away doesn't lose
+    /// on time.
+    #[clap(long = "move-timeout")]
+    move_timeout: Option<u64>,
+
+    /// What to play once the --move-timeout passes: engine (the move the
+    /// --ai-engine suggests, like a hint) or random (a random legal move).
+    #[clap(long = "auto-move", default_value_t = AutoMove::default())]
+    auto_move: AutoMove,
+
+    /// Moves for "--player scripted" to play, of both sides, e.g. "B3 A1 B3";
+    /// "*" stands for any move of the opponent. With "-", the script is read
+    /// from stdin. As soon as the game goes differently, the player stops and
+    /// prints the error, see PlayerScripted.
+    #[clap(long = "script")]
+    script: Option<String>,
+
+    /// Recorded game to watch with "--opponent replay": either a JSON game
+    /// record, or a transcript (see GameRecord::to_transcript). Press N to
+    /// watch it again from the start.
+    #[clap(long = "replay-file")]
+    replay_file: Option<PathBuf>,
+
+    /// Pause before every move of the replay, in milliseconds.
+    #[clap(long = "replay-delay", default_value_t = 1000)]
+    replay_delay_ms: u64,
+
+    /// Difficulty of the AI opponent: easy, medium or hard. It can also be
+    /// changed during the game with the 1, 2, 3 keys.
+    #[clap(long = "ai-difficulty", default_value_t = Difficulty::default())]
+    ai_difficulty: Difficulty,
+
+    /// Playing style of the AI opponent: balanced, aggressive (builds up its
+    /// own rows), defensive (blocks the opponent's rows) or casual (a bit
+    /// random). It can also be changed during the game with the S key.
+    #[clap(long = "ai-style", default_value_t = Style::default())]
+    ai_style: Style,
+
+    /// Search engine of the AI opponent: minimax or mcts (Monte Carlo Tree
+    /// Search).
+    #[clap(long = "ai-engine", default_value_t = EngineKind::Minimax)]
+    ai_engine: EngineKind,
+
+    /// If given, the AI thinks about every move for this many milliseconds,
+    /// searching as deep as it manages to, instead of using the depth of the
+    /// difficulty. The difficulty still defines how often the AI blunders.
+    #[clap(long = "ai-time")]
+    ai_time_ms: Option<u64>,
+
+    /// Number of threads the AI searches with.
+    #[clap(long = "ai-threads", default_value_t = 1)]
+    ai_threads: usize,
+
+    /// If given, every AI move takes at least this many milliseconds, so that
+    /// it's easier to follow the game, e.g. when the AI plays itself. The
+    /// random opponent and the scripted player wait for this long before every
+    /// move, too.
+    #[clap(long = "ai-delay")]
+    ai_delay_ms: Option<u64>,
+
+    /// Command to run an external engine, which then plays instead of the
+    /// built-in AI, e.g. "./mybot --fast"; it's split on whitespace. See
+    /// PlayerExternalEngine for the protocol. The engine gets the --ai-time to
+    /// think about every move, or the time limit of the difficulty.
+    #[clap(long = "ai-cmd")]
+    ai_cmd: Option<String>,
+
+    /// Record all the GameManager traffic to the given file, so that it can be
+    /// replayed later, see game_manager::record.
+    #[cfg(feature = "record")]
+    #[clap(long = "record")]
+    record: Option<std::path::PathBuf>,
+}
+
+impl CliArgs {
+    /// Overrides the settings from the config file with the ones given on the
+    /// command line, if any.
+    fn override_config(&self, config: &mut Config) {
+        if let Some(v) = &self.name {
+            config.player_name = v.clone();
+        }
+        if let Some(v) = self.opponent_kind {
+            config.opponent_kind = v;
+        }
+        if let Some(v) = &self.url {
+            config.url = v.clone();
+        }
+        if let Some(v) = &self.game_id {
+            config.game_id = v.clone();
+        }
+        if let Some(v) = self.theme {
+            config.theme = v;
+        }
+        if let Some(v) = self.token_skin {
+            config.token_skin = v;
+        }
+        if self.check_updates {
+            config.check_updates = true;
+        }
+        if let Some(v) = &self.update_url {
+            config.update_url = v.clone();
+        }
+    }
+
+    /// Returns the rules of the game as given on the command line.
+    fn rules(&self) -> Rules {
+        Rules {
+            misere: self.misere,
+            scoring: self.scoring,
+        }
+    }
 }
 
 fn main() -> Result<()> {
     let cli_args = CliArgs::parse();
-    let opponent_kind = cli_args.opponent_kind;
+    let mut w = Window::new("ConnectFour 3D");
+
+    // On the very first run there's no config file yet, so unless the user
+    // already knows what they want (has given the opponent kind on the command
+    // line), ask them with the wizard.
+    let mut config = match Config::load() {
+        Ok(Some(config)) => config,
+        Ok(None) if cli_args.opponent_kind.is_none() => {
+            let config = match wizard::run(&mut w, Config::default()) {
+                Some(config) => config,
+                // The window was closed.
+                None => return Ok(()),
+            };
+
+            if let Err(err) = config.save() {
+                println!("failed to save the config: {:#}", err);
+            }
+
+            config
+        }
+        Ok(None) => Config::default(),
+        Err(err) => {
+            println!("ignoring the config: {:#}", err);
+            Config::default()
+        }
+    };
+    cli_args.override_config(&mut config);
+
+    // Nobody has given a name, so the OS username will do; it's not saved to
+    // the config, so it's only the default.
+    if config.player_name.is_empty() {
+        config.player_name = os_username().unwrap_or_default();
+    }
+
+    let opponent_kind = config.opponent_kind;
+
+    // The server doesn't know anything about the rules, so in a network game
+    // both players would have to agree on them out of band; not supported.
+    if matches!(opponent_kind, OpponentKind::Network) {
+        if cli_args.rules() != Rules::default() {
+            return Err(anyhow!(
+                "misère and scoring are only supported in local games"
+            ));
+        }
+
+        if cli_args.handicap.is_some() {
+            return Err(anyhow!("handicap is only supported in local games"));
+        }
+
+        if cli_args.random_opening.is_some() {
+            return Err(anyhow!("random openings are only supported in local games"));
+        }
+
+        // The server has the game already.
+        if cli_args.resume.is_some() {
+            return Err(anyhow!("resuming is only supported in local games"));
+        }
+
+        // The server assigns the sides too.
+        if cli_args.side != SideChoice::default() || cli_args.alternate_sides {
+            return Err(anyhow!("picking the side is only supported in local games"));
+        }
+
+        // The server decides when the next game starts.
+        if cli_args.best_of.is_some() {
+            return Err(anyhow!("matches are only supported in local games"));
+        }
+    } else if cli_args.lobby {
+        return Err(anyhow!("--lobby is only supported with --opponent network"));
+    }
+
+    // Failing to get the open games doesn't prevent the configured one.
+    if cli_args.lobby {
+        match lobby::fetch(&config.url) {
+            Ok(open_games) => {
+                match lobby::pick_game(&mut w, config.theme, &open_games, &config.game_id) {
+                    Some(Some(game_id)) => config.game_id = game_id,
+                    Some(None) => {}
+                    // The window was closed.
+                    None => return Ok(()),
+                }
+            }
+            Err(err) => println!("failed to get the open games: {:#}", err),
+        }
+    }
+
+    // The replay plays both sides from an empty board, so nothing may get in
+    // the way of the recorded moves.
+    let replay = match (opponent_kind, &cli_args.replay_file) {
+        (OpponentKind::Replay, Some(path)) => {
+            if cli_args.handicap.is_some() || cli_args.random_opening.is_some() {
+                return Err(anyhow!(
+                    "handicap and random openings can't be used with a replay"
+                ));
+            }
+            if cli_args.resume.is_some() {
+                return Err(anyhow!("a replay can't be resumed"));
+            }
+
+            let record = GameRecord::load_file(path)?;
+            record
+                .replay()
+                .with_context(|| format!("replaying {:?}", path))?;
+            if record.moves.first().is_some_and(|m| m.side != Side::White) {
+                return Err(anyhow!("the replay has to start with White's move"));
+            }
+
+            Some(record)
+        }
+        (OpponentKind::Replay, None) => {
+            return Err(anyhow!("--opponent replay needs a --replay-file"));
+        }
+        (_, Some(_)) => {
+            return Err(anyhow!(
+                "--replay-file is only supported with --opponent replay"
+            ));
+        }
+        (_, None) => None,
+    };
+
+    if cli_args.best_of == Some(0) {
+        return Err(anyhow!("a match has to be best of at least 1 game"));
+    }
+
+    // Nothing to resume the first time, the file is only created once the
+    // game starts.
+    let mut saved = match &cli_args.resume {
+        Some(path) if path.exists() => Some(SavedGame::load(path)?),
+        _ => None,
+    };
+
+    // Unless the game is saved to a file given explicitly, keep it in the
+    // autosave file while it goes on, and if the app didn't get to finish the
+    // last one, offer to resume it. Network games are kept by the server, and
+    // a replay is in its file already.
+    let autosave_path = if cli_args.resume.is_some()
+        || matches!(opponent_kind, OpponentKind::Network | OpponentKind::Replay)
+    {
+        None
+    } else {
+        match autosave::path() {
+            Ok(path) => Some(path),
+            Err(err) => {
+                println!("not autosaving: {:#}", err);
+                None
+            }
+        }
+    };
+    if let Some(path) = &autosave_path {
+        if let Some(autosaved) = autosave::load(path) {
+            match autosave::offer_resume(&mut w, config.theme, &autosaved) {
+                Some(true) => saved = Some(autosaved),
+                Some(false) => autosave::discard(path),
+                // The window was closed.
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // The AI can play the AI, the random or the network player, but it makes
+    // no sense for it to play a local player: that's just --opponent ai. The
+    // same goes for the scripted player.
+    let ai_player = cli_args.player_kind != PlayerKind::Local;
+    if ai_player && matches!(opponent_kind, OpponentKind::Local | OpponentKind::Replay) {
+        return Err(anyhow!(
+            "--player ai and scripted are only supported with --opponent ai, random or network"
+        ));
+    }
+
+    if cli_args.watch && (opponent_kind != OpponentKind::Network || ai_player) {
+        return Err(anyhow!(
+            "--watch is only supported with --opponent network and --player local"
+        ));
+    }
+
+    let script = match (cli_args.player_kind, &cli_args.script) {
+        (PlayerKind::Scripted, Some(script)) => Some(load_script(script)?),
+        (PlayerKind::Scripted, None) => {
+            return Err(anyhow!("--player scripted needs a --script"));
+        }
+        (_, Some(_)) => {
+            return Err(anyhow!("--script is only supported with --player scripted"));
+        }
+        (_, None) => None,
+    };
+
+    if cli_args.teams && opponent_kind != OpponentKind::Local {
+        return Err(anyhow!("team games are only supported in local games"));
+    }
+
+    // Otherwise, the user is "you", see Config::player_name.
+    if (cli_args.p1.is_some() || cli_args.p2.is_some()) && opponent_kind != OpponentKind::Local {
+        return Err(anyhow!("--p1 and --p2 are only supported in local games"));
+    }
+
+    let window_options = gui3d::WindowOptions {
+        blindfold: cli_args.blindfold,
+        peek_penalty: Duration::from_secs(cli_args.peek_penalty),
+        rules: cli_args.rules(),
+        zen: cli_args.zen,
+        auto_rotate: cli_args.auto_rotate,
+        teams: cli_args.teams,
+        ai_difficulty: cli_args.ai_difficulty,
+        ai_style: cli_args.ai_style,
+        theme: config.theme,
+        token_skin: config.token_skin,
+        player_name: Some(config.player_name.clone()).filter(|name| !name.is_empty()),
+        ai_player,
+        evaluation: cli_args.eval_bar,
+        engine: cli_args.ai_engine,
+        announce: cli_args.announce,
+        #[cfg(feature = "tts")]
+        speaker: cli_args
+            .speak
+            .then(|| speech::Speaker::new(cli_args.speak_cmd.clone())),
+    };
 
     let (gm_to_ui_sender, gm_to_ui_receiver) = mpsc::channel::<GameManagerToUI>(16);
+    let (ui_to_gm_sender, ui_to_gm_receiver) = mpsc::channel::<UIToGameManager>(16);
     let (player_to_ui_tx, player_to_ui_rx) = mpsc::channel::<PlayerLocalToUI>(1);
+    let (board_watch_tx, board_watch_rx) = watch::channel(BoardState::new());
+    let (notices_tx, notices_rx) = mpsc::channel::<String>(1);
 
-    // Setup tokio runtime in another thread.
-    thread::spawn(move || async_runtime(gm_to_ui_sender, player_to_ui_tx, cli_args));
+    // Check for updates in the background, so that the game doesn't have to
+    // wait for the network.
+    if config.check_updates {
+        if config.update_url.is_empty() {
+            println!("not checking for updates: no update_url in the config");
+        } else {
+            let update_url = config.update_url.clone();
+            thread::spawn(move || match update::check(&update_url) {
+                Ok(Some(release)) => {
+                    let _ = notices_tx.try_send(update::notice(&release));
+                }
+                Ok(None) => {}
+                Err(err) => println!("failed to check for updates: {:#}", err),
+            });
+        }
+    }
+
+    // Setup tokio runtime in another thread; it lets us know once all the
+    // tasks are done, see below.
+    let (runtime_done_tx, runtime_done_rx) = std_mpsc::channel::<()>();
+    thread::spawn(move || {
+        async_runtime(
+            gm_to_ui_sender,
+            ui_to_gm_receiver,
+            board_watch_tx,
+            player_to_ui_tx,
+            cli_args,
+            config,
+            saved,
+            autosave_path,
+            replay,
+            script,
+        );
+        let _ = runtime_done_tx.send(());
+    });
 
     let sound_player = sounds::Player::new()?;
 
-    // Run GUI in the main thread. It's easier since when the user closes the
-    // window, the whole thing gets killed (albeit not yet gracefully).
+    // Run GUI in the main thread. When the user closes the window, it tells
+    // the GameManager to shut down, see UIToGameManager::Shutdown.
     let mut w = gui3d::Window3D::new(
+        w,
         sound_player,
         gm_to_ui_receiver,
+        ui_to_gm_sender,
+        board_watch_rx,
         player_to_ui_rx,
+        notices_rx,
         opponent_kind,
+        window_options,
     );
     w.run();
 
-    // GUI window was closed by the user.
-    //
-    // TODO: would be nice to implement some graceful shutdown, but not
-    // bothering for now.
+    // GUI window was closed by the user. Give the tasks some time to wrap up
+    // (e.g. for the network player to disconnect from the server), but don't
+    // hang if some of them doesn't; e.g. the network player only gets the
+    // message once it's connected.
+    if runtime_done_rx.recv_timeout(SHUTDOWN_TIMEOUT).is_err() {
+        println!("not all tasks have stopped in time, exiting anyway");
+    }
 
     Ok(())
 }
 
 /// Should be called in a separate OS thread, it'll handle all the tokio runtime.
+#[allow(clippy::too_many_arguments)]
 fn async_runtime(
     gm_to_ui_sender: mpsc::Sender<GameManagerToUI>,
+    ui_to_gm_receiver: mpsc::Receiver<UIToGameManager>,
+    board_watch_tx: watch::Sender<BoardState>,
     player_to_ui_tx: mpsc::Sender<PlayerLocalToUI>,
     cli_args: CliArgs,
+    config: Config,
+    saved: Option<SavedGame>,
+    autosave_path: Option<PathBuf>,
+    replay: Option<GameRecord>,
+    script: Option<Script>,
 ) {
     // Every player will need a copy of the sender, so clone it.
     let pwhite_to_ui_tx = player_to_ui_tx.clone();
-    let pblack_to_ui_tx = player_to_ui_tx;
+    let pblack_to_ui_tx = player_to_ui_tx.clone();
 
     // For both players, create channels for bidirectional communication with
     // the GameManager.
@@ -79,32 +665,166 @@ fn async_runtime(
     let (gm_to_pblack_tx, gm_to_pblack_rx) = mpsc::channel::<GameManagerToPlayer>(16);
     let (pblack_to_gm_tx, pblack_to_gm_rx) = mpsc::channel::<PlayerToGameManager>(16);
 
+    // Just like failing to record later on, failing to start recording doesn't
+    // prevent the game.
+    #[cfg(feature = "record")]
+    let recorder = cli_args.record.as_ref().and_then(|path| {
+        match connectfour::game_manager::record::Recorder::create(path) {
+            Ok(v) => Some(v),
+            Err(err) => {
+                println!("not recording: {:#}", err);
+                None
+            }
+        }
+    });
+
     let rt = tokio::runtime::Runtime::new().unwrap();
     rt.block_on(async {
         let mut set = task::JoinSet::new();
 
+        // Whatever is needed from cli_args has to be prepared before they are
+        // moved into the player task below.
+        let opponent_kind = config.opponent_kind;
+        let rules = cli_args.rules();
+        let ai = AIOptions {
+            rules,
+            difficulty: cli_args.ai_difficulty,
+            style: cli_args.ai_style,
+            engine: cli_args.ai_engine,
+            threads: cli_args.ai_threads,
+            think_time: cli_args.ai_time_ms.map(Duration::from_millis),
+            delay: cli_args.ai_delay_ms.map(Duration::from_millis),
+            cmd: cli_args.ai_cmd.clone(),
+        };
+        let p0_ai = ai.clone();
+        let ai_player = cli_args.player_kind == PlayerKind::AI;
+        let p0_script = script.clone();
+        let gm_config = GameManagerConfig {
+            rules,
+            engine: cli_args.ai_engine,
+            seed: None,
+        };
+        let handicap = cli_args.handicap;
+        let random_opening = cli_args.random_opening;
+        let p1_name = cli_args.p1.clone();
+        let p2_name = cli_args.p2.clone();
+        // Every local player gets the same time limit for its moves.
+        let move_timeout = cli_args.move_timeout.map(|secs| {
+            (
+                Duration::from_secs(secs),
+                cli_args.auto_move,
+                cli_args.ai_engine,
+            )
+        });
+        let time_control = cli_args.time_control;
+        let peek_penalty = Duration::from_secs(cli_args.peek_penalty);
+        let save_path = cli_args.resume.clone();
+        let side_choice = cli_args.side;
+        let alternate_sides = cli_args.alternate_sides;
+        let best_of = cli_args.best_of;
+        let saved_state = saved.as_ref().map(|saved| saved.state.clone());
+        let replay_delay = Duration::from_millis(cli_args.replay_delay_ms);
+        let p0_replay = replay.clone();
+        let watch = cli_args.watch;
+
+        // In a team game, the teammates of both players are local too; they
+        // only ever get the input when it's their seat's turn.
+        let teammates = if cli_args.teams {
+            let (gm_to_p2_tx, gm_to_p2_rx) = mpsc::channel::<GameManagerToPlayer>(16);
+            let (p2_to_gm_tx, p2_to_gm_rx) = mpsc::channel::<PlayerToGameManager>(16);
+            let (gm_to_p3_tx, gm_to_p3_rx) = mpsc::channel::<GameManagerToPlayer>(16);
+            let (p3_to_gm_tx, p3_to_gm_rx) = mpsc::channel::<PlayerToGameManager>(16);
+
+            for (from_gm, to_gm) in [(gm_to_p2_rx, p2_to_gm_tx), (gm_to_p3_rx, p3_to_gm_tx)] {
+                let to_ui = player_to_ui_tx.clone();
+                set.spawn(async move {
+                    let mut p = PlayerLocal::new(None, from_gm, to_gm, to_ui);
+                    if let Some((timeout, auto_move, engine)) = move_timeout {
+                        p.set_move_timeout(timeout, auto_move, engine);
+                    }
+                    p.run().await?;
+
+                    Ok::<(), anyhow::Error>(())
+                });
+            }
+
+            Some((gm_to_p2_tx, p2_to_gm_rx, gm_to_p3_tx, p3_to_gm_rx))
+        } else {
+            None
+        };
+
         // Create the primary player, depending on the opponent_kind: either the
-        // network or local player. Network player *has* to be the primary one,
-        // since it will receive info from the server which has the big picture.
+        // network or local player (or AI, if it plays itself). Network player
+        // *has* to be the primary one, since it will receive info from the
+        // server which has the big picture.
         set.spawn(async move {
-            match cli_args.opponent_kind {
-                OpponentKind::Local => {
+            let side = side_choice.pick(&mut rand::thread_rng());
+            let initial_state = match (saved_state, &cli_args.handicap) {
+                (Some(state), _) => state,
+                (None, Some(handicap)) => FullGameState::from_handicap(handicap, side)?,
+                (None, None) => FullGameState::new(side),
+            };
+
+            match config.opponent_kind {
+                OpponentKind::AI | OpponentKind::Random if p0_script.is_some() => {
+                    run_scripted(
+                        p0_script.unwrap_or_default(),
+                        p0_ai.delay,
+                        Some(initial_state),
+                        gm_to_pwhite_rx,
+                        pwhite_to_gm_tx,
+                    )
+                    .await?;
+                }
+                OpponentKind::AI | OpponentKind::Random if ai_player => {
+                    run_ai(
+                        &p0_ai,
+                        Some(initial_state),
+                        gm_to_pwhite_rx,
+                        pwhite_to_gm_tx,
+                    )
+                    .await?;
+                }
+                OpponentKind::Replay => {
+                    let mut p0 = PlayerReplay::new(
+                        p0_replay.unwrap_or_default(),
+                        gm_to_pwhite_rx,
+                        pwhite_to_gm_tx,
+                    );
+                    p0.set_initial_state(initial_state);
+                    p0.set_move_delay(replay_delay);
+                    p0.run().await?;
+                }
+                OpponentKind::Local | OpponentKind::AI | OpponentKind::Random => {
                     let mut p0 = PlayerLocal::new(
-                        Some(Side::White),
+                        Some(initial_state),
                         gm_to_pwhite_rx,
                         pwhite_to_gm_tx,
                         pwhite_to_ui_tx,
                     );
+                    if let Some(name) = p1_name {
+                        p0.set_name(name);
+                    }
+                    if let Some((timeout, auto_move, engine)) = move_timeout {
+                        p0.set_move_timeout(timeout, auto_move, engine);
+                    }
                     p0.run().await?;
                 }
                 OpponentKind::Network => {
-                    let conn_url = url::Url::parse(&cli_args.url).unwrap();
+                    let conn_url = url::Url::parse(&config.url).unwrap();
                     let mut p0 = PlayerWSClient::new(
                         conn_url,
-                        cli_args.game_id,
+                        config.game_id,
                         gm_to_pwhite_rx,
                         pwhite_to_gm_tx,
                     );
+                    p0.set_token_skin(config.token_skin.to_string());
+                    if !config.player_name.is_empty() {
+                        p0.set_player_name(config.player_name.clone());
+                    }
+                    p0.set_wire_format(cli_args.wire_format);
+                    p0.set_deflate(!cli_args.no_deflate);
+                    p0.set_spectate(watch);
                     p0.run().await?;
                 }
             }
@@ -112,23 +832,110 @@ fn async_runtime(
             Ok::<(), anyhow::Error>(())
         });
 
-        // Create the secondary player, always local.
-        set.spawn(async {
-            let mut p1 = PlayerLocal::new(None, gm_to_pblack_rx, pblack_to_gm_tx, pblack_to_ui_tx);
-            p1.run().await?;
+        // Create the secondary player: either AI, random, replay or local.
+        set.spawn(async move {
+            match opponent_kind {
+                OpponentKind::Replay => {
+                    let mut p1 = PlayerReplay::new(
+                        replay.unwrap_or_default(),
+                        gm_to_pblack_rx,
+                        pblack_to_gm_tx,
+                    );
+                    p1.set_move_delay(replay_delay);
+                    p1.run().await?;
+                }
+                OpponentKind::AI => {
+                    run_ai(&ai, None, gm_to_pblack_rx, pblack_to_gm_tx).await?;
+                }
+                OpponentKind::Random => {
+                    let mut p1 = PlayerRandom::new(ai.rules, gm_to_pblack_rx, pblack_to_gm_tx);
+                    if let Some(delay) = ai.delay {
+                        p1.set_move_delay(delay);
+                    }
+                    p1.run().await?;
+                }
+                OpponentKind::Network if watch => {
+                    // The network player feeds the whole game to the
+                    // GameManager, and nobody moves here; a replay of an empty
+                    // record never does.
+                    let mut p1 =
+                        PlayerReplay::new(GameRecord::default(), gm_to_pblack_rx, pblack_to_gm_tx);
+                    p1.run().await?;
+                }
+                OpponentKind::Network if script.is_some() => {
+                    let script = script.unwrap_or_default();
+                    run_scripted(script, ai.delay, None, gm_to_pblack_rx, pblack_to_gm_tx).await?;
+                }
+                OpponentKind::Network if ai_player => {
+                    run_ai(&ai, None, gm_to_pblack_rx, pblack_to_gm_tx).await?;
+                }
+                OpponentKind::Local | OpponentKind::Network => {
+                    let mut p1 =
+                        PlayerLocal::new(None, gm_to_pblack_rx, pblack_to_gm_tx, pblack_to_ui_tx);
+                    if let Some(name) = p2_name {
+                        p1.set_name(name);
+                    }
+                    if let Some((timeout, auto_move, engine)) = move_timeout {
+                        p1.set_move_timeout(timeout, auto_move, engine);
+                    }
+                    p1.run().await?;
+                }
+            }
 
             Ok::<(), anyhow::Error>(())
         });
 
         // Create the GameManager.
-        set.spawn(async {
+        set.spawn(async move {
             let mut gm = GameManager::new(
                 gm_to_ui_sender,
+                ui_to_gm_receiver,
+                board_watch_tx,
                 gm_to_pwhite_tx,
                 pwhite_to_gm_rx,
                 gm_to_pblack_tx,
                 pblack_to_gm_rx,
+                gm_config,
             );
+            if let Some((to_p2, from_p2, to_p3, from_p3)) = teammates {
+                gm.set_teammates(to_p2, from_p2, to_p3, from_p3);
+            }
+
+            // New games start just like the first one.
+            if let Some(handicap) = handicap {
+                gm.set_handicap(handicap);
+            }
+            if let Some(random_opening) = random_opening {
+                gm.set_random_opening(random_opening);
+            }
+            if matches!(opponent_kind, OpponentKind::Network) {
+                gm.set_network_game();
+            }
+            gm.set_side_choice(side_choice);
+            gm.set_alternate_sides(alternate_sides);
+            if let Some(time_control) = time_control {
+                gm.set_time_control(time_control);
+            }
+            gm.set_peek_penalty(peek_penalty);
+            if let Some(best_of) = best_of {
+                gm.set_match(best_of);
+            }
+
+            if let Some(saved) = saved {
+                gm.resume(saved);
+            }
+            if let Some(save_path) = save_path {
+                gm.set_save_path(save_path);
+            }
+            if let Some(autosave_path) = autosave_path {
+                gm.set_autosave_path(autosave_path);
+            }
+
+            #[cfg(feature = "record")]
+            if let Some(recorder) = recorder {
+                gm.set_recorder(recorder);
+            }
+
             gm.run().await?;
 
             Ok::<(), anyhow::Error>(())
@@ -157,12 +964,140 @@ fn async_runtime(
     })
 }
 
-/// Kind of the opponent: local or network.
-#[derive(Debug, Copy, Clone)]
+/// Everything needed to create an AI player, see run_ai.
+#[derive(Debug, Clone)]
+struct AIOptions {
+    rules: Rules,
+    difficulty: Difficulty,
+    style: Style,
+    engine: EngineKind,
+    threads: usize,
+    think_time: Option<Duration>,
+    delay: Option<Duration>,
+    /// Command to run the external engine, if any, see CliArgs::ai_cmd.
+    cmd: Option<String>,
+}
+
+/// Creates and runs the AI player: either the built-in one, or the external
+/// engine, if its command is given. If the initial state is given, the player
+/// is the primary one.
+async fn run_ai(
+    ai: &AIOptions,
+    initial_state: Option<FullGameState>,
+    from_gm: mpsc::Receiver<GameManagerToPlayer>,
+    to_gm: mpsc::Sender<PlayerToGameManager>,
+) -> Result<()> {
+    if let Some(cmd) = &ai.cmd {
+        let command = cmd.split_whitespace().map(str::to_string).collect();
+        let think_time = ai.think_time.unwrap_or(ai.difficulty.time_limit);
+        let mut p = PlayerExternalEngine::new(command, ai.rules, think_time, from_gm, to_gm);
+        if let Some(initial_state) = initial_state {
+            p.set_initial_state(initial_state);
+        }
+        if let Some(delay) = ai.delay {
+            p.set_move_delay(delay);
+        }
+        return p.run().await;
+    }
+
+    let mut p = PlayerAI::new(
+        ai.rules,
+        ai.engine.new_engine(ai.threads),
+        ai.difficulty,
+        ai.think_time,
+        from_gm,
+        to_gm,
+    );
+    p.set_style(ai.style);
+    if let Some(initial_state) = initial_state {
+        p.set_initial_state(initial_state);
+    }
+    if let Some(delay) = ai.delay {
+        p.set_move_delay(delay);
+    }
+    p.run().await
+}
+
+/// Runs the scripted player, see --script.
+async fn run_scripted(
+    script: Script,
+    delay: Option<Duration>,
+    initial_state: Option<FullGameState>,
+    from_gm: mpsc::Receiver<GameManagerToPlayer>,
+    to_gm: mpsc::Sender<PlayerToGameManager>,
+) -> Result<()> {
+    let mut p = PlayerScripted::new(script, from_gm, to_gm);
+    if let Some(initial_state) = initial_state {
+        p.set_initial_state(initial_state);
+    }
+    if let Some(delay) = delay {
+        p.set_move_delay(delay);
+    }
+    p.run().await
+}
+
+/// Returns the name of the user logged into the OS, if it's known.
+fn os_username() -> Option<String> {
+    ["USER", "USERNAME"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|name| !name.is_empty())
+}
+
+/// Parses the --script, or if it's "-", the one from stdin.
+fn load_script(script: &str) -> Result<Script> {
+    if script == "-" {
+        let script = io::read_to_string(io::stdin()).context("reading the script from stdin")?;
+        return script.parse().context("parsing the script from stdin");
+    }
+
+    script.parse().context("parsing the script")
+}
+
+/// Kind of the player on this side of the board: local, AI or scripted (see
+/// PlayerScripted).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlayerKind {
+    Local,
+    AI,
+    Scripted,
+}
+
+impl FromStr for PlayerKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "local" => Ok(PlayerKind::Local),
+            "ai" => Ok(PlayerKind::AI),
+            "scripted" => Ok(PlayerKind::Scripted),
+            _ => Err(anyhow!(
+                "invalid player kind; try 'local', 'ai' or 'scripted'"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PlayerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayerKind::Local => write!(f, "local"),
+            PlayerKind::AI => write!(f, "ai"),
+            PlayerKind::Scripted => write!(f, "scripted"),
+        }
+    }
+}
+
+/// Kind of the opponent: local, network, AI, random (see PlayerRandom) or
+/// replay (see PlayerReplay).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OpponentKind {
     Local,
     Network,
-    // TODO: AI
+    AI,
+    Random,
+    Replay,
 }
 
 impl FromStr for OpponentKind {
@@ -173,7 +1108,12 @@ impl FromStr for OpponentKind {
             "" => Ok(OpponentKind::Local),
             "local" => Ok(OpponentKind::Local),
             "network" => Ok(OpponentKind::Network),
-            _ => Err(anyhow!("invalid opponent kind; try 'local' or 'network'")),
+            "ai" => Ok(OpponentKind::AI),
+            "random" => Ok(OpponentKind::Random),
+            "replay" => Ok(OpponentKind::Replay),
+            _ => Err(anyhow!(
+                "invalid opponent kind; try 'local', 'network', 'ai', 'random' or 'replay'"
+            )),
         }
     }
 }
@@ -183,6 +1123,9 @@ impl fmt::Display for OpponentKind {
         match self {
             OpponentKind::Local => write!(f, "local"),
             OpponentKind::Network => write!(f, "network"),
+            OpponentKind::AI => write!(f, "ai"),
+            OpponentKind::Random => write!(f, "random"),
+            OpponentKind::Replay => write!(f, "replay"),
         }
     }
 }
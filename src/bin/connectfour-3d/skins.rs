@@ -0,0 +1,113 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use kiss3d::scene::SceneNode;
+use rand::seq::SliceRandom;
+
+/// Cosmetic style of the tokens: a texture, and a slight tint of the side
+/// colors. It doesn't affect the game in any way.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenSkin {
+    /// Plain colored tokens, no texture.
+    Plain,
+    /// White veins.
+    Marble,
+    /// Wood grain, a bit warmer colors.
+    Wood,
+    /// Smooth, with a highlight, and bluish colors.
+    Glass,
+    /// One of the above, picked anew for every game, see TokenSkin::pick.
+    Random,
+}
+
+impl TokenSkin {
+    pub const ALL: [TokenSkin; 5] = [
+        TokenSkin::Plain,
+        TokenSkin::Marble,
+        TokenSkin::Wood,
+        TokenSkin::Glass,
+        TokenSkin::Random,
+    ];
+
+    /// Returns the skin to use for a new game: Random picks one of the actual
+    /// skins, and the others are returned as is.
+    pub fn pick(self) -> TokenSkin {
+        match self {
+            TokenSkin::Random => *TokenSkin::ALL[..TokenSkin::ALL.len() - 1]
+                .choose(&mut rand::thread_rng())
+                .unwrap(),
+            skin => skin,
+        }
+    }
+
+    /// Returns the given token color, tinted according to the skin. The
+    /// texture, if any, is then multiplied by it.
+    pub fn tint(self, c: (f32, f32, f32)) -> (f32, f32, f32) {
+        match self {
+            TokenSkin::Plain | TokenSkin::Random => c,
+            TokenSkin::Marble => (c.0 * 0.95, c.1 * 0.95, c.2 * 0.95),
+            TokenSkin::Wood => (c.0, c.1 * 0.9, c.2 * 0.75),
+            TokenSkin::Glass => (c.0 * 0.7 + 0.24, c.1 * 0.7 + 0.27, c.2 * 0.7 + 0.3),
+        }
+    }
+
+    /// Sets the texture of the skin, if any, on the given token node. The
+    /// textures are embedded in the binary, and kiss3d caches them by the
+    /// name, so every one is only uploaded to the GPU once.
+    pub fn apply_texture(self, node: &mut SceneNode) {
+        if let Some((name, data)) = self.texture() {
+            node.set_texture_from_memory(data, name);
+        }
+    }
+
+    /// Returns the name and the PNG data of the skin's texture, if any. The
+    /// textures are greyscale, so that the sides still have their colors.
+    fn texture(self) -> Option<(&'static str, &'static [u8])> {
+        match self {
+            TokenSkin::Plain | TokenSkin::Random => None,
+            TokenSkin::Marble => Some((
+                "skin_marble",
+                include_bytes!("../../../res/skins/marble.png").as_slice(),
+            )),
+            TokenSkin::Wood => Some((
+                "skin_wood",
+                include_bytes!("../../../res/skins/wood.png").as_slice(),
+            )),
+            TokenSkin::Glass => Some((
+                "skin_glass",
+                include_bytes!("../../../res/skins/glass.png").as_slice(),
+            )),
+        }
+    }
+}
+
+impl FromStr for TokenSkin {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "plain" => Ok(TokenSkin::Plain),
+            "marble" => Ok(TokenSkin::Marble),
+            "wood" => Ok(TokenSkin::Wood),
+            "glass" => Ok(TokenSkin::Glass),
+            "random" => Ok(TokenSkin::Random),
+            _ => Err(anyhow!(
+                "invalid token skin; try 'plain', 'marble', 'wood', 'glass' or 'random'"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TokenSkin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenSkin::Plain => write!(f, "plain"),
+            TokenSkin::Marble => write!(f, "marble"),
+            TokenSkin::Wood => write!(f, "wood"),
+            TokenSkin::Glass => write!(f, "glass"),
+            TokenSkin::Random => write!(f, "random"),
+        }
+    }
+}
@@ -5,6 +5,7 @@ use anyhow::Result;
 use rodio::{source::Source, Decoder, OutputStream, OutputStreamHandle};
 
 use connectfour::game::Side;
+use connectfour::game_manager::SoundCue;
 
 /// Describes which sound effect to play.
 #[derive(Eq, PartialEq, Hash)]
@@ -13,6 +14,19 @@ pub enum Sound {
     PutToken(Side),
 }
 
+impl Sound {
+    /// Returns the sound effect for the given cue, if there is one; so far,
+    /// only the tokens have sounds.
+    pub fn for_cue(cue: SoundCue) -> Option<Sound> {
+        match cue {
+            SoundCue::TokenPlaced(side) => Some(Sound::PutToken(side)),
+            SoundCue::TurnStart(_) | SoundCue::Win(_) | SoundCue::Draw | SoundCue::InvalidMove => {
+                None
+            }
+        }
+    }
+}
+
 /// Sound effects player. It embeds all the sound data in memory.
 pub struct Player {
     /// Stream and its handle created by Rodio's try_default. NOTE: even though
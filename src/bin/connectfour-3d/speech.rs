@@ -0,0 +1,54 @@
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+
+/// Text-to-speech command used by default: the one which comes with the OS on
+/// macOS, and the most common one elsewhere.
+#[cfg(target_os = "macos")]
+const DEFAULT_COMMAND: &str = "say";
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_COMMAND: &str = "espeak";
+
+/// Speaks the announcements (see GameManagerToUI::Announcement) aloud, by
+/// running the OS text-to-speech command with the text as the last argument.
+/// The texts are spoken one by one, in a separate thread, so that the window
+/// doesn't have to wait.
+pub struct Speaker {
+    to_thread: mpsc::Sender<String>,
+}
+
+impl Speaker {
+    /// Creates a speaker with the given command, split on whitespace, e.g.
+    /// "espeak -s 200"; if None, the default one for the OS is used.
+    pub fn new(command: Option<String>) -> Speaker {
+        let command = command.unwrap_or_else(|| DEFAULT_COMMAND.to_string());
+        let (to_thread, from_window) = mpsc::channel::<String>();
+
+        thread::spawn(move || {
+            let mut args = command.split_whitespace();
+            let program = match args.next() {
+                Some(program) => program,
+                None => return,
+            };
+            let args: Vec<&str> = args.collect();
+
+            for text in from_window {
+                let res = Command::new(program).args(&args).arg(&text).status();
+                if let Err(err) = res {
+                    // Most likely, there's no such command, so don't bother
+                    // trying again.
+                    println!("failed to run {}, not speaking: {}", program, err);
+                    return;
+                }
+            }
+        });
+
+        Speaker { to_thread }
+    }
+
+    /// Queues the text to be spoken.
+    pub fn say(&self, text: &str) {
+        // The thread only quits if speaking is hopeless anyway.
+        let _ = self.to_thread.send(text.to_string());
+    }
+}
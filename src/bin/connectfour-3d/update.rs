@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+/// How long to wait for the release feed; the check runs in the background,
+/// but there's no point keeping a connection around for long.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Latest release, as described by the release feed: a JSON document like
+/// `{"version": "0.2.0", "url": "https://..."}`, where the url (where to get
+/// the release from) is optional.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Release {
+    pub version: String,
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+/// Fetches the latest release from the feed at the given URL, and returns it
+/// if it's newer than this client. Blocks until the feed is fetched, so it's
+/// supposed to be called in a separate thread.
+pub fn check(feed_url: &str) -> Result<Option<Release>> {
+    let body = ureq::get(feed_url)
+        .timeout(TIMEOUT)
+        .call()
+        .context(format!("fetching {}", feed_url))?
+        .into_string()?;
+    let release: Release = serde_json::from_str(&body).context("parsing the release feed")?;
+
+    let latest = semver::Version::parse(release.version.trim_start_matches('v'))
+        .context(format!("parsing the version {:?}", release.version))?;
+    let current = semver::Version::parse(env!("CARGO_PKG_VERSION"))?;
+
+    Ok(Some(release).filter(|_| latest > current))
+}
+
+/// Human readable notice about the given newer release.
+pub fn notice(release: &Release) -> String {
+    let mut notice = format!(
+        "ConnectFour 3D {} is available (this is {})",
+        release.version,
+        env!("CARGO_PKG_VERSION")
+    );
+    if let Some(url) = &release.url {
+        notice.push_str(&format!(", get it at {}", url));
+    }
+
+    notice
+}
@@ -0,0 +1,258 @@
+use std::rc::Rc;
+
+use kiss3d::event::{Action, Key, WindowEvent};
+use kiss3d::nalgebra::{Point2, Point3};
+use kiss3d::text::Font;
+use kiss3d::window::Window;
+
+use super::config::Config;
+use super::gui3d::Theme;
+use super::OpponentKind;
+
+/// Opponent kinds to choose from, with the descriptions.
+const OPPONENT_KINDS: [(OpponentKind, &str); 3] = [
+    (OpponentKind::Local, "two players at this computer"),
+    (OpponentKind::AI, "play against the computer"),
+    (
+        OpponentKind::Network,
+        "play with someone else over the network",
+    ),
+];
+
+/// Steps of the wizard, in order.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Step {
+    PlayerName,
+    OpponentKind,
+    Theme,
+    /// Only for the network game.
+    Url,
+    /// Only for the network game.
+    GameId,
+}
+
+/// State of the wizard: the config being filled in, the current step, and
+/// the input of that step: either the text being typed, or the index of the
+/// selected option.
+struct Wizard {
+    config: Config,
+    step: Step,
+    text: String,
+    selected: usize,
+}
+
+/// Runs the first-run wizard in the given window, which asks the user for the
+/// basic settings, starting with the given ones. Returns the resulting
+/// config, or None if the user has closed the window.
+pub fn run(w: &mut Window, config: Config) -> Option<Config> {
+    let font = Font::default();
+    let mut wizard = Wizard::new(config);
+
+    while w.render() {
+        for event in w.events().iter() {
+            if wizard.handle_event(event.value) {
+                return Some(wizard.config);
+            }
+        }
+
+        let bg = wizard.current_theme().background_color();
+        w.set_background_color(bg.0, bg.1, bg.2);
+
+        wizard.draw(w, &font);
+    }
+
+    None
+}
+
+impl Wizard {
+    fn new(config: Config) -> Wizard {
+        let mut wizard = Wizard {
+            config,
+            step: Step::PlayerName,
+            text: String::new(),
+            selected: 0,
+        };
+        wizard.enter_step(Step::PlayerName);
+
+        wizard
+    }
+
+    /// Handles a single event; returns true once the wizard is done.
+    fn handle_event(&mut self, event: WindowEvent) -> bool {
+        match event {
+            WindowEvent::Char(c) if self.is_text_step() && !c.is_control() => {
+                self.text.push(c);
+            }
+            WindowEvent::Key(Key::Back, Action::Press, _) if self.is_text_step() => {
+                self.text.pop();
+            }
+            WindowEvent::Key(Key::Left | Key::Up, Action::Press, _) if !self.is_text_step() => {
+                let n = self.num_options();
+                self.selected = (self.selected + n - 1) % n;
+            }
+            WindowEvent::Key(Key::Right | Key::Down, Action::Press, _) if !self.is_text_step() => {
+                self.selected = (self.selected + 1) % self.num_options();
+            }
+            WindowEvent::Key(Key::Return, Action::Press, _) => {
+                self.commit_step();
+
+                match self.next_step() {
+                    Some(step) => self.enter_step(step),
+                    None => return true,
+                }
+            }
+            WindowEvent::Key(Key::Escape, Action::Press, _) => {
+                if let Some(step) = self.prev_step() {
+                    self.enter_step(step);
+                }
+            }
+            _ => {}
+        }
+
+        false
+    }
+
+    /// Prepares the input of the given step, with the current setting.
+    fn enter_step(&mut self, step: Step) {
+        self.step = step;
+
+        match step {
+            Step::PlayerName => self.text = self.config.player_name.clone(),
+            Step::Url => self.text = self.config.url.clone(),
+            Step::GameId => self.text = self.config.game_id.clone(),
+            Step::OpponentKind => {
+                self.selected = OPPONENT_KINDS
+                    .iter()
+                    .position(|(kind, _)| *kind == self.config.opponent_kind)
+                    .unwrap_or(0);
+            }
+            Step::Theme => {
+                self.selected = Theme::ALL
+                    .iter()
+                    .position(|theme| *theme == self.config.theme)
+                    .unwrap_or(0);
+            }
+        }
+    }
+
+    /// Saves the input of the current step into the config.
+    fn commit_step(&mut self) {
+        let text = self.text.trim().to_string();
+
+        match self.step {
+            Step::PlayerName => self.config.player_name = text,
+            Step::Url => self.config.url = text,
+            Step::GameId => self.config.game_id = text,
+            Step::OpponentKind => self.config.opponent_kind = OPPONENT_KINDS[self.selected].0,
+            Step::Theme => self.config.theme = Theme::ALL[self.selected],
+        }
+    }
+
+    fn next_step(&self) -> Option<Step> {
+        let network = self.config.opponent_kind == OpponentKind::Network;
+
+        match self.step {
+            Step::PlayerName => Some(Step::OpponentKind),
+            Step::OpponentKind => Some(Step::Theme),
+            Step::Theme if network => Some(Step::Url),
+            Step::Theme => None,
+            Step::Url => Some(Step::GameId),
+            Step::GameId => None,
+        }
+    }
+
+    fn prev_step(&self) -> Option<Step> {
+        match self.step {
+            Step::PlayerName => None,
+            Step::OpponentKind => Some(Step::PlayerName),
+            Step::Theme => Some(Step::OpponentKind),
+            Step::Url => Some(Step::Theme),
+            Step::GameId => Some(Step::Url),
+        }
+    }
+
+    fn is_text_step(&self) -> bool {
+        matches!(self.step, Step::PlayerName | Step::Url | Step::GameId)
+    }
+
+    fn num_options(&self) -> usize {
+        match self.step {
+            Step::OpponentKind => OPPONENT_KINDS.len(),
+            Step::Theme => Theme::ALL.len(),
+            _ => 0,
+        }
+    }
+
+    /// Theme to show: while choosing it, it's the selected one, so that the
+    /// user can see it right away.
+    fn current_theme(&self) -> Theme {
+        match self.step {
+            Step::Theme => Theme::ALL[self.selected],
+            _ => self.config.theme,
+        }
+    }
+
+    fn draw(&self, w: &mut Window, font: &Rc<Font>) {
+        let white = Point3::new(1.0, 1.0, 1.0);
+        let grey = Point3::new(0.5, 0.5, 0.5);
+        let green = Point3::new(0.0, 1.0, 0.0);
+
+        w.draw_text(
+            "Welcome to ConnectFour 3D! Let's set things up.",
+            &Point2::new(50.0, 50.0),
+            80.0,
+            font,
+            &green,
+        );
+
+        let prompt = match self.step {
+            Step::PlayerName => "Your name:",
+            Step::OpponentKind => "Who do you want to play with?",
+            Step::Theme => "Color theme:",
+            Step::Url => "Server URL:",
+            Step::GameId => "Game code (your opponent has to enter the same one):",
+        };
+        w.draw_text(prompt, &Point2::new(50.0, 200.0), 60.0, font, &white);
+
+        let mut y = 300.0;
+
+        if self.is_text_step() {
+            let text = format!("> {}_", self.text);
+            w.draw_text(&text, &Point2::new(50.0, y), 60.0, font, &white);
+        } else {
+            let options: Vec<String> = match self.step {
+                Step::OpponentKind => OPPONENT_KINDS
+                    .iter()
+                    .map(|(kind, descr)| format!("{}: {}", kind, descr))
+                    .collect(),
+                _ => Theme::ALL.iter().map(|theme| theme.to_string()).collect(),
+            };
+
+            for (i, option) in options.iter().enumerate() {
+                let (text, color) = if i == self.selected {
+                    (format!("> {}", option), &white)
+                } else {
+                    (format!("  {}", option), &grey)
+                };
+
+                w.draw_text(&text, &Point2::new(50.0, y), 60.0, font, color);
+                y += 70.0;
+            }
+        }
+
+        let hint = if self.is_text_step() {
+            "Enter: next, Esc: back"
+        } else {
+            "Arrows: choose, Enter: next, Esc: back"
+        };
+        let h = w.size()[1] as f32 * 2.0;
+        w.draw_text(hint, &Point2::new(50.0, h - 150.0), 50.0, font, &grey);
+        w.draw_text(
+            "The answers are saved in the config file; you can edit it later.",
+            &Point2::new(50.0, h - 100.0),
+            50.0,
+            font,
+            &grey,
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+
+use connectfour::engine::{mcts, minimax, EngineKind, SearchLimits};
+use connectfour::game::analysis;
+use connectfour::game::eval::Style;
+use connectfour::game::{Game, PoleCoords, Side};
+
+/// Positions to benchmark on: a name, and the moves leading to it, with White
+/// moving first. They're fixed, so that the numbers are comparable between
+/// the runs.
+const POSITIONS: [(&str, &str); 3] = [
+    ("empty", ""),
+    ("opening", "A1 A4 D1 B1"),
+    (
+        "middlegame",
+        "A1 A4 D1 B1 D4 B2 A1 B3 B4 B2 B2 B3 B3 B1 B4 B1 B1 D2 B4 B4 A2 A1 A1 A2",
+    ),
+];
+
+/// Measures the performance of the move generation and the engines: runs
+/// perft and fixed-depth searches on a few standard positions, and prints the
+/// nodes per second. Build it in release mode, otherwise the numbers mean
+/// little.
+#[derive(Debug, clap::Parser)]
+struct CliArgs {
+    /// Depth of perft, see analysis::perft.
+    #[clap(long = "perft-depth", default_value_t = 5)]
+    perft_depth: usize,
+
+    /// Depth of the minimax search.
+    #[clap(long = "minimax-depth", default_value_t = 6)]
+    minimax_depth: usize,
+
+    /// Depth of the MCTS search; it runs this many times
+    /// mcts::PLAYOUTS_PER_DEPTH playouts.
+    #[clap(long = "mcts-depth", default_value_t = 5)]
+    mcts_depth: usize,
+
+    /// Only benchmark the given position: empty, opening or middlegame.
+    #[clap(long = "position")]
+    position: Option<String>,
+}
+
+fn main() -> Result<()> {
+    let cli_args = CliArgs::parse();
+
+    let mut found = false;
+    for (name, moves) in POSITIONS {
+        if cli_args.position.as_ref().is_some_and(|p| p != name) {
+            continue;
+        }
+        found = true;
+
+        let (game, side) = setup(moves).with_context(|| format!("position {}", name))?;
+        println!("{} ({:?} to move):", name, side);
+
+        bench_perft(&game, side, cli_args.perft_depth);
+        bench_minimax(&game, side, cli_args.minimax_depth);
+        bench_mcts(&game, side, cli_args.mcts_depth);
+    }
+
+    if !found {
+        return Err(anyhow!(
+            "no such position; try 'empty', 'opening' or 'middlegame'"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Plays the given moves, in the pole notation and separated by whitespace,
+/// on an empty board; returns the resulting game and the side to move.
+fn setup(moves: &str) -> Result<(Game, Side)> {
+    let mut game = Game::new();
+    let mut side = Side::White;
+
+    for m in moves.split_whitespace() {
+        let pcoords: PoleCoords = m.parse()?;
+        let res = game
+            .put_token(side, pcoords)
+            .with_context(|| format!("move {}", m))?;
+        if res.winner.is_some() || res.draw {
+            return Err(anyhow!("the game is over after {}", m));
+        }
+        side = side.opposite();
+    }
+
+    Ok((game, side))
+}
+
+fn bench_perft(game: &Game, side: Side, depth: usize) {
+    let started = Instant::now();
+    let nodes = analysis::perft(game, side, depth);
+    let elapsed = started.elapsed();
+
+    println!(
+        "  perft depth {}: {} nodes in {:.3}s, {}",
+        depth,
+        nodes,
+        elapsed.as_secs_f64(),
+        per_second(nodes, elapsed, "nodes")
+    );
+}
+
+fn bench_minimax(game: &Game, side: Side, depth: usize) {
+    let nodes_before = minimax::nodes_searched();
+    let started = Instant::now();
+    // A single thread, so that all the nodes are counted, see
+    // minimax::nodes_searched.
+    let res = minimax::search(game, side, depth, None, Style::Balanced, 1);
+    let elapsed = started.elapsed();
+    let nodes = minimax::nodes_searched() - nodes_before;
+
+    let best = res.map_or("none".to_string(), |r| {
+        format!("{} ({:+})", r.best_move, r.score)
+    });
+    println!(
+        "  minimax depth {}: best {}, {} nodes in {:.3}s, {}",
+        depth,
+        best,
+        nodes,
+        elapsed.as_secs_f64(),
+        per_second(nodes, elapsed, "nodes")
+    );
+}
+
+fn bench_mcts(game: &Game, side: Side, depth: usize) {
+    let engine = EngineKind::Mcts.new_engine(1);
+    let limits = SearchLimits {
+        depth,
+        deadline: None,
+        style: Style::Balanced,
+    };
+
+    let started = Instant::now();
+    let res = engine.best_move(game, side, &limits);
+    let elapsed = started.elapsed();
+    let playouts = (depth.max(1) * mcts::PLAYOUTS_PER_DEPTH) as u64;
+
+    let best = res.map_or("none".to_string(), |r| {
+        format!("{} ({:+})", r.best_move, r.score)
+    });
+    println!(
+        "  mcts depth {}: best {}, {} playouts in {:.3}s, {}",
+        depth,
+        best,
+        playouts,
+        elapsed.as_secs_f64(),
+        per_second(playouts, elapsed, "playouts")
+    );
+}
+
+/// Formats the rate, e.g. "1234k nodes/s".
+fn per_second(count: u64, elapsed: Duration, what: &str) -> String {
+    let secs = elapsed.as_secs_f64().max(1e-9);
+    format!("{:.0}k {}/s", count as f64 / secs / 1000.0, what)
+}
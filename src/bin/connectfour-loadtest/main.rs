@@ -0,0 +1,274 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use rand::seq::SliceRandom;
+use tokio::net::TcpStream;
+use tokio::task;
+use tokio::time;
+use tokio_tungstenite::{connect_async, tungstenite, MaybeTlsStream, WebSocketStream};
+
+use connectfour::game::GameState;
+use connectfour::game::{self, BoardState, Game, PoleCoords, Side};
+use connectfour::protocol::{
+    WSClientInfo, WSClientToServer, WSFullGameState, WSServerToClient, WireFormat, PROTOCOL_VERSION,
+};
+
+type WSStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Stress-tests the server: opens a number of simulated client pairs, which
+/// play random legal games against each other, and reports the latency of
+/// relaying the moves, and the errors.
+#[derive(Debug, clap::Parser)]
+struct CliArgs {
+    /// URL of the server to test.
+    #[clap(short = 'u', long = "url", default_value_t = String::from("ws://127.0.0.1:7248"))]
+    url: String,
+
+    /// Number of client pairs playing simultaneously.
+    #[clap(short = 'n', long = "pairs", default_value_t = 10)]
+    num_pairs: usize,
+
+    /// Number of games every pair plays, one after another.
+    #[clap(short = 'g', long = "games", default_value_t = 1)]
+    num_games: usize,
+
+    /// Moves per second within each pair.
+    #[clap(short = 'r', long = "rate", default_value_t = 2.0)]
+    rate: f64,
+
+    /// How long to wait for a single server message, in milliseconds, before
+    /// considering it an error.
+    #[clap(long = "timeout", default_value_t = 5000)]
+    timeout_ms: u64,
+}
+
+/// Stats collected by all the pairs.
+#[derive(Default)]
+struct Stats {
+    /// Time between one client sending a move and the other one receiving it.
+    latencies: Mutex<Vec<Duration>>,
+    /// Number of games played to the end.
+    num_games: AtomicUsize,
+    /// Number of errors; every error aborts the game.
+    num_errors: AtomicUsize,
+}
+
+/// Both connections of a client pair: the one playing white, and the one
+/// playing black.
+struct Client {
+    to_ws: SplitSink<WSStream, tungstenite::Message>,
+    from_ws: SplitStream<WSStream>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli_args = Arc::new(CliArgs::parse());
+    let stats = Arc::new(Stats::default());
+
+    println!(
+        "Running {} pairs, {} games each, against {}",
+        cli_args.num_pairs, cli_args.num_games, cli_args.url
+    );
+
+    let started = Instant::now();
+    let mut set = task::JoinSet::new();
+
+    for pair_idx in 0..cli_args.num_pairs {
+        let cli_args = cli_args.clone();
+        let stats = stats.clone();
+
+        set.spawn(async move {
+            for game_idx in 0..cli_args.num_games {
+                let game_id = format!("loadtest-{}-{}-{}", std::process::id(), pair_idx, game_idx);
+
+                match play_game(&cli_args, &stats, &game_id).await {
+                    Ok(()) => {
+                        stats.num_games.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(err) => {
+                        println!("game {}: {:#}", game_id, err);
+                        stats.num_errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        });
+    }
+
+    while let Some(res) = set.join_next().await {
+        if let Err(err) = res {
+            println!("task panicked {:?}", err);
+        }
+    }
+
+    print_report(&stats, started.elapsed());
+
+    Ok(())
+}
+
+/// Connects both clients of a pair to the given game, and plays random moves
+/// until the game is over.
+async fn play_game(cli_args: &CliArgs, stats: &Stats, game_id: &str) -> Result<()> {
+    let timeout = Duration::from_millis(cli_args.timeout_ms);
+    let move_interval = Duration::from_secs_f64(1.0 / cli_args.rate);
+
+    let mut c0 = connect(cli_args, game_id).await.context("client 0")?;
+    let mut c1 = connect(cli_args, game_id).await.context("client 1")?;
+
+    // Once both are connected, the server sends the game reset to both; find
+    // out which one is white.
+    let c0_state = wait_for_reset(&mut c0, timeout).await.context("client 0")?;
+    wait_for_reset(&mut c1, timeout).await.context("client 1")?;
+
+    let mut side = match c0_state.game_state {
+        GameState::WaitingFor(side, _) => side,
+        v => return Err(anyhow!("unexpected game state {:?}", v)),
+    };
+
+    // The ws player side is the side of the client's opponent.
+    let mut clients = if c0_state.ws_player_side == Side::Black {
+        [c0, c1]
+    } else {
+        [c1, c0]
+    };
+
+    let mut game = Game::new();
+    game.reset_board(&c0_state.board);
+
+    let mut interval = time::interval(move_interval);
+
+    while let Some(pcoords) = random_move(&game) {
+        interval.tick().await;
+
+        let (mover, opponent) = match side {
+            Side::White => (0, 1),
+            Side::Black => (1, 0),
+        };
+
+        let sent = Instant::now();
+        send(&mut clients[mover], &WSClientToServer::PutToken(pcoords)).await?;
+
+        // Wait for the opponent to receive the move.
+        loop {
+            match recv(&mut clients[opponent], timeout).await? {
+                WSServerToClient::PutToken(v) if v == pcoords => break,
+                // The ack of the opponent's own previous move might still be
+                // there.
+                WSServerToClient::Ping | WSServerToClient::MoveAccepted(_) => {}
+                v => return Err(anyhow!("expected move {}, got {:?}", pcoords, v)),
+            }
+        }
+        stats.latencies.lock().unwrap().push(sent.elapsed());
+
+        let res = game.put_token(side, pcoords)?;
+        // The server adjudicates dead draws, so stop there as well.
+        if res.winner.is_some() || res.draw || game.is_dead_draw() {
+            break;
+        }
+
+        side = side.opposite();
+    }
+
+    for c in &mut clients {
+        let _ = c.to_ws.close().await;
+    }
+
+    Ok(())
+}
+
+fn random_move(game: &Game) -> Option<PoleCoords> {
+    game.legal_moves().choose(&mut rand::thread_rng()).copied()
+}
+
+/// Connects a single client and says hello.
+async fn connect(cli_args: &CliArgs, game_id: &str) -> Result<Client> {
+    let url = url::Url::parse(&cli_args.url)?;
+    let (ws_stream, _) = connect_async(url).await?;
+    let (to_ws, from_ws) = ws_stream.split();
+    let mut client = Client { to_ws, from_ws };
+
+    let hello = WSClientToServer::Hello(WSClientInfo {
+        game_id: game_id.to_string(),
+        player_name: "loadtest".to_string(),
+        game_state: WSFullGameState {
+            game_state: GameState::WaitingFor(game::Side::White, game::Scores::default()),
+            ws_player_side: game::Side::White,
+            board: BoardState::new(),
+        },
+        protocol_version: PROTOCOL_VERSION,
+        token_skin: None,
+        wire_format: WireFormat::Json,
+        deflate: false,
+        session_token: None,
+        spectate: false,
+    });
+    send(&mut client, &hello).await?;
+
+    Ok(client)
+}
+
+/// Waits for the game reset, skipping pings and messages.
+async fn wait_for_reset(client: &mut Client, timeout: Duration) -> Result<WSFullGameState> {
+    loop {
+        match recv(client, timeout).await? {
+            WSServerToClient::GameReset(v) => return Ok(v.game_state),
+            WSServerToClient::Ping
+            | WSServerToClient::Msg(_)
+            | WSServerToClient::Welcome(_)
+            | WSServerToClient::MoveAccepted(_) => {}
+            v => return Err(anyhow!("expected game reset, got {:?}", v)),
+        }
+    }
+}
+
+async fn send(client: &mut Client, msg: &WSClientToServer) -> Result<()> {
+    let j = serde_json::to_string(msg)?;
+    client.to_ws.send(tungstenite::Message::Text(j)).await?;
+
+    Ok(())
+}
+
+async fn recv(client: &mut Client, timeout: Duration) -> Result<WSServerToClient> {
+    let v = time::timeout(timeout, client.from_ws.next())
+        .await
+        .context("timed out waiting for the server")?
+        .ok_or(anyhow!("connection closed"))??;
+
+    let msg =
+        serde_json::from_str(&v.to_string()).with_context(|| format!("failed to parse {:?}", v))?;
+
+    Ok(msg)
+}
+
+/// Print the latency percentiles and the error counts.
+fn print_report(stats: &Stats, elapsed: Duration) {
+    let mut latencies = stats.latencies.lock().unwrap().clone();
+    latencies.sort();
+
+    println!("Done in {:.1}s", elapsed.as_secs_f64());
+    println!(
+        "Games: {} played, {} errors",
+        stats.num_games.load(Ordering::Relaxed),
+        stats.num_errors.load(Ordering::Relaxed)
+    );
+
+    if latencies.is_empty() {
+        println!("No moves were relayed");
+        return;
+    }
+
+    let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+
+    println!("Moves relayed: {}", latencies.len());
+    println!(
+        "Latency: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        latencies[latencies.len() - 1]
+    );
+}
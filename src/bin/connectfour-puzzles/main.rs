@@ -0,0 +1,94 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+
+use connectfour::game::analysis;
+use connectfour::game::puzzle::Puzzle;
+use connectfour::game::record::GameRecord;
+use connectfour::game::Game;
+
+/// Scans recorded games for positions where the side to move could force a
+/// win, and saves them as puzzle files with solutions.
+#[derive(Debug, clap::Parser)]
+struct CliArgs {
+    /// Recorded games to scan: either JSON files (with the .json extension),
+    /// or transcripts.
+    #[clap(required = true)]
+    files: Vec<PathBuf>,
+
+    /// Max number of moves in which the win has to be forced.
+    #[clap(short = 'm', long = "max-moves", default_value_t = 3)]
+    max_moves: usize,
+
+    /// Directory to save the puzzles to.
+    #[clap(short = 'o', long = "out-dir", default_value = ".")]
+    out_dir: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let cli_args = CliArgs::parse();
+
+    let mut num_puzzles = 0;
+    for file in &cli_args.files {
+        let record = GameRecord::load_file(file)?;
+        num_puzzles += scan_record(file, &record, &cli_args)?;
+    }
+
+    println!("Saved {} puzzles", num_puzzles);
+
+    Ok(())
+}
+
+/// Replay the given record move by move, and save a puzzle for every position
+/// where the side to move could force a win. Returns the number of saved
+/// puzzles.
+fn scan_record(path: &Path, record: &GameRecord, cli_args: &CliArgs) -> Result<usize> {
+    let stem = path
+        .file_stem()
+        .map_or("game".into(), |v| v.to_string_lossy());
+
+    let mut game = Game::new();
+    let mut num_puzzles = 0;
+
+    // Whether each side (by the parity of the move index) had a forced win at
+    // its previous move. If it did, then the current forced win is most likely
+    // just a continuation of the same one, so it's not worth a separate puzzle.
+    let mut had_win = [false, false];
+
+    for (i, m) in record.moves.iter().enumerate() {
+        let forced_win = analysis::find_forced_win(&game, m.side, cli_args.max_moves);
+
+        if let Some(forced_win) = &forced_win {
+            if !had_win[i % 2] {
+                let puzzle = Puzzle {
+                    board: game.get_board().clone(),
+                    side_to_move: m.side,
+                    num_moves: forced_win.num_moves,
+                    solution: forced_win.line.clone(),
+                    source: Some(format!("{:?}, move {}", path, i + 1)),
+                };
+
+                let out_path = cli_args.out_dir.join(format!("{}-{}.json", stem, i + 1));
+                puzzle.save(&out_path)?;
+
+                println!(
+                    "{:?}, move {}: {:?} wins in {}, saved to {:?}",
+                    path,
+                    i + 1,
+                    m.side,
+                    forced_win.num_moves,
+                    out_path
+                );
+                num_puzzles += 1;
+            }
+        }
+
+        had_win[i % 2] = forced_win.is_some();
+
+        game.put_token(m.side, m.pole)
+            .with_context(|| format!("{:?}, move {}", path, i + 1))?;
+    }
+
+    Ok(num_puzzles)
+}
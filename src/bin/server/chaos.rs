@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Max delay of a relayed message in chaos mode.
+const MAX_DELAY: Duration = Duration::from_millis(3000);
+
+/// Chaos mode, for development only: with the given probability, every message
+/// relayed from one player to another is messed with in some way: delayed,
+/// dropped, duplicated, or the connection is forcibly closed instead of
+/// delivering it. This is useful to see how clients cope with realistic
+/// network failures, without having to actually break the network.
+///
+/// With the probability of 0, which is the default, chaos mode is off.
+#[derive(Debug, Clone, Copy)]
+pub struct Chaos {
+    probability: f64,
+}
+
+/// What to do with a relayed message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChaosAction {
+    /// Deliver the message as usual.
+    Deliver,
+    /// Deliver the message after the given delay.
+    Delay(Duration),
+    /// Don't deliver the message at all.
+    Drop,
+    /// Deliver the message twice.
+    Duplicate,
+    /// Close the connection instead of delivering the message.
+    Disconnect,
+}
+
+impl Chaos {
+    pub fn new(probability: f64) -> Chaos {
+        Chaos { probability }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.probability > 0.0
+    }
+
+    /// Decides what to do with the next relayed message.
+    pub fn next_action(&self) -> ChaosAction {
+        let mut rng = rand::thread_rng();
+
+        if !self.is_enabled() || !rng.gen_bool(self.probability.min(1.0)) {
+            return ChaosAction::Deliver;
+        }
+
+        match rng.gen_range(0..4) {
+            0 => ChaosAction::Delay(MAX_DELAY.mul_f64(rng.gen())),
+            1 => ChaosAction::Drop,
+            2 => ChaosAction::Duplicate,
+            _ => ChaosAction::Disconnect,
+        }
+    }
+}
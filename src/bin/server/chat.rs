@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use tokio::time::Instant;
+
+/// Longer chat messages are refused, so that nobody floods the opponent's
+/// screen with a single one. The GUI doesn't let the user type more anyway.
+const MAX_LEN: usize = 200;
+
+/// How many chat messages a player can send in a row, see ChatLimiter.
+const BURST: u32 = 5;
+
+/// How often a player gets to send one more chat message, once the burst is
+/// used up, see ChatLimiter.
+const REFILL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Tidies up the chat message before it's relayed: trims the whitespace.
+/// Returns an error if nothing is left, or if it's over MAX_LEN characters.
+pub fn clean_message(text: &str) -> Result<String> {
+    let text = text.trim();
+
+    if text.is_empty() {
+        return Err(anyhow!("the message is empty"));
+    }
+
+    if text.chars().count() > MAX_LEN {
+        return Err(anyhow!("the message is over {} characters", MAX_LEN));
+    }
+
+    Ok(text.to_string())
+}
+
+/// Limits how fast a single player can chat: up to BURST messages in a row,
+/// and then one every REFILL_INTERVAL; the messages over the limit are
+/// dropped.
+pub struct ChatLimiter {
+    /// How many messages the player can send right now.
+    tokens: u32,
+    /// When the last token was added, or the first one was taken.
+    refilled_at: Instant,
+}
+
+impl ChatLimiter {
+    pub fn new() -> ChatLimiter {
+        ChatLimiter {
+            tokens: BURST,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Should be called for every chat message of the player; returns whether
+    /// it's within the limit, so it can be relayed.
+    pub fn allow(&mut self) -> bool {
+        if self.tokens == BURST {
+            self.refilled_at = Instant::now();
+        }
+
+        let earned = (self.refilled_at.elapsed().as_millis() / REFILL_INTERVAL.as_millis()) as u32;
+        if earned > 0 {
+            self.tokens = (self.tokens + earned).min(BURST);
+            self.refilled_at += REFILL_INTERVAL * earned;
+        }
+
+        if self.tokens == 0 {
+            return false;
+        }
+
+        self.tokens -= 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trims_the_messages() {
+        assert_eq!(clean_message("  hi there\n").unwrap(), "hi there");
+        assert!(clean_message("").is_err());
+        assert!(clean_message(" \n\t ").is_err());
+    }
+
+    #[test]
+    fn refuses_over_length_messages() {
+        // The limit is in characters, not bytes.
+        let longest = "ü".repeat(MAX_LEN);
+        assert_eq!(clean_message(&longest).unwrap(), longest);
+        assert_eq!(clean_message(&format!("  {}  ", longest)).unwrap(), longest);
+
+        assert!(clean_message(&"ü".repeat(MAX_LEN + 1)).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn throttles_bursts() {
+        let mut limiter = ChatLimiter::new();
+        for _ in 0..BURST {
+            assert!(limiter.allow());
+        }
+        assert!(!limiter.allow());
+
+        // One more message per interval.
+        tokio::time::advance(REFILL_INTERVAL).await;
+        assert!(limiter.allow());
+        assert!(!limiter.allow());
+
+        // After a long pause, it's the whole burst again, but no more.
+        tokio::time::advance(REFILL_INTERVAL * BURST * 10).await;
+        for _ in 0..BURST {
+            assert!(limiter.allow());
+        }
+        assert!(!limiter.allow());
+    }
+}
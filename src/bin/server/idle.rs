@@ -0,0 +1,70 @@
+use std::time::{Duration, Instant};
+
+/// How long a player may take to move before the server does something about
+/// it: first nudges the player (and lets the opponent know), and eventually
+/// abandons the game, so that the opponent doesn't wait forever. None disables
+/// the respective step.
+#[derive(Debug, Clone, Copy)]
+pub struct IdleLimits {
+    pub nudge_after: Option<Duration>,
+    pub abandon_after: Option<Duration>,
+}
+
+/// What to do about an idle player, see IdleTimer::check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleAction {
+    /// Nudge the player, who has been idle for the given time; the second
+    /// value is the time left until the game is abandoned, if it's going to.
+    Nudge(Duration, Option<Duration>),
+    /// Abandon the game, it counts as a loss of the idle player.
+    Abandon,
+}
+
+/// Keeps track of how long a single player takes to move: the timer runs
+/// while it's the player's turn and the opponent is there waiting.
+pub struct IdleTimer {
+    limits: IdleLimits,
+    waiting_since: Option<Instant>,
+    nudged: bool,
+}
+
+impl IdleTimer {
+    pub fn new(limits: IdleLimits) -> IdleTimer {
+        IdleTimer {
+            limits,
+            waiting_since: None,
+            nudged: false,
+        }
+    }
+
+    /// It's the player's turn now, and the opponent is waiting.
+    pub fn start(&mut self) {
+        self.waiting_since = Some(Instant::now());
+        self.nudged = false;
+    }
+
+    /// The player has moved, or the opponent is gone, so nobody is waiting.
+    pub fn stop(&mut self) {
+        self.waiting_since = None;
+    }
+
+    /// Should be called periodically; returns what to do about the player, if
+    /// anything. Every action is returned only once per turn, and after
+    /// Abandon, the timer is stopped.
+    pub fn check(&mut self) -> Option<IdleAction> {
+        let idle = self.waiting_since?.elapsed();
+
+        if self.limits.abandon_after.is_some_and(|v| idle >= v) {
+            self.stop();
+            return Some(IdleAction::Abandon);
+        }
+
+        if !self.nudged && self.limits.nudge_after.is_some_and(|v| idle >= v) {
+            self.nudged = true;
+            let abandon_in = self.limits.abandon_after.map(|v| v - idle);
+            return Some(IdleAction::Nudge(idle, abandon_in));
+        }
+
+        None
+    }
+}
@@ -1,44 +1,135 @@
+mod chaos;
+mod chat;
+mod idle;
 mod registry;
+mod storage;
 
-use std::{env, io::Error, sync::Arc, time::Duration};
+use std::{io::Error, path::PathBuf, sync::Arc, time::Duration};
 
 use anyhow::{anyhow, Result};
+use chaos::{Chaos, ChaosAction};
+use chat::ChatLimiter;
+use clap::Parser;
 use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
-use registry::{GameCtx, PlayerToPlayer, Registry};
+use idle::{IdleAction, IdleLimits, IdleTimer};
+use registry::{GameCtx, GameData, PlayerToPlayer, Registry, ToSpectator};
+use storage::Storage;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc;
 use tokio::time;
-use tokio_tungstenite::{tungstenite, tungstenite::protocol::Message, WebSocketStream};
+use tokio_tungstenite::{tungstenite::protocol::Message, WebSocketStream};
 
 use connectfour::game;
-use connectfour::game_manager::GameState;
-use connectfour::{WSClientToServer, WSFullGameState, WSGameReset, WSServerToClient};
+use connectfour::game::GameState;
+use connectfour::protocol::{
+    WSClientToServer, WSFullGameState, WSGameReset, WSIdle, WSServerInfo, WSServerToClient,
+    WireCodec, WireFormat, HEARTBEAT_INTERVAL, PROTOCOL_VERSION,
+};
+
+/// Oldest protocol version which the server supports, see
+/// connectfour::protocol::PROTOCOL_VERSION. The game state can be
+/// GameState::Draw (the board is full, or see WSServerToClient::GameDrawn),
+/// which the clients older than 4 can't decode.
+const MIN_PROTOCOL_VERSION: u32 = 4;
+
+/// How many heartbeats in a row the client may miss before it's considered
+/// gone, see WSClientToServer::Heartbeat.
+const MAX_MISSED_HEARTBEATS: u32 = 3;
+
+/// Longer player names are cut, see WSClientInfo::player_name.
+const MAX_PLAYER_NAME_LEN: usize = 32;
+
+#[derive(Debug, clap::Parser)]
+struct CliArgs {
+    /// Address to listen on.
+    #[clap(default_value_t = String::from("0.0.0.0:7248"))]
+    addr: String,
+
+    /// For development only: probability (from 0 to 1) of messing with every
+    /// relayed move: delaying, dropping or duplicating it, or disconnecting
+    /// the receiving player instead. Useful to test how clients cope with
+    /// network failures.
+    #[clap(long = "chaos", default_value_t = 0.0)]
+    chaos: f64,
+
+    /// If the player whose turn it is hasn't moved for this many seconds, nudge
+    /// them, and let the opponent know; 0 disables it.
+    #[clap(long = "nudge-after", default_value_t = 60)]
+    nudge_after: u64,
+
+    /// If the player whose turn it is hasn't moved for this many seconds,
+    /// abandon the game, which counts as their loss; 0 disables it.
+    #[clap(long = "abandon-after", default_value_t = 300)]
+    abandon_after: u64,
+
+    /// SQLite database to keep the games in, so that they survive a restart;
+    /// it's created if it doesn't exist. Without it, the games are only kept
+    /// in memory.
+    #[clap(long = "db")]
+    db: Option<PathBuf>,
+
+    /// Never compress the messages, even if the client asks for it, see
+    /// WSClientInfo::deflate; e.g. to read the traffic in a network sniffer.
+    #[clap(long = "no-deflate")]
+    no_deflate: bool,
+}
+
+/// How often to check whether the player is idle, see IdleTimer.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Options which apply to every connection.
+#[derive(Debug, Clone, Copy)]
+struct ConnOptions {
+    chaos: Chaos,
+    idle_limits: IdleLimits,
+    /// Whether to compress the messages for the clients which ask for it.
+    deflate: bool,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    // By default, listen on 0.0.0.0:7248.
-    let addr = env::args()
-        .nth(1)
-        .unwrap_or_else(|| "0.0.0.0:7248".to_string());
+    let cli_args = CliArgs::parse();
+    let addr = cli_args.addr;
+    let chaos = Chaos::new(cli_args.chaos);
+    let secs = |v| Some(Duration::from_secs(v)).filter(|v| !v.is_zero());
+    let opts = ConnOptions {
+        chaos,
+        idle_limits: IdleLimits {
+            nudge_after: secs(cli_args.nudge_after),
+            abandon_after: secs(cli_args.abandon_after),
+        },
+        deflate: !cli_args.no_deflate,
+    };
 
     let try_socket = TcpListener::bind(&addr).await;
     let listener = try_socket.expect("failed to bind");
     println!("Listening on: {}", addr);
 
+    if chaos.is_enabled() {
+        println!("Chaos mode is on, with probability {}", cli_args.chaos);
+    }
+
     // Create registry to keep all active game data in.
-    let r = Arc::new(Registry::new());
+    let mut r = Registry::new();
+    if let Some(path) = &cli_args.db {
+        let storage = Storage::open(path)
+            .map_err(|err| Error::other(format!("opening {:?}: {:#}", path, err)))?;
+        r.set_storage(storage);
+        println!("Keeping the games in {:?}", path);
+    }
+    let r = Arc::new(r);
 
     // Listen forever, accepting incoming connections.
     while let Ok((stream, _)) = listener.accept().await {
-        tokio::spawn(handle_conn(r.clone(), stream));
+        tokio::spawn(handle_conn(r.clone(), opts, stream));
     }
 
     Ok(())
 }
 
 /// Takes care of a single connection, until it is broken. Never returns Ok.
-async fn handle_conn(r: Arc<Registry>, stream: TcpStream) -> Result<()> {
+async fn handle_conn(r: Arc<Registry>, opts: ConnOptions, stream: TcpStream) -> Result<()> {
     let addr = stream
         .peer_addr()
         .expect("connected streams should have a peer address");
@@ -56,21 +147,123 @@ async fn handle_conn(r: Arc<Registry>, stream: TcpStream) -> Result<()> {
 
     let (mut write, mut read) = ws_stream.split();
 
-    // Wait for the hello message first.
-    let recv = read
-        .next()
+    // Wait for the hello message first; until then, the client can look at
+    // the games it could join, see WSClientToServer::ListGames.
+    let player_info = loop {
+        let recv = read
+            .next()
+            .await
+            .ok_or(anyhow!("failed to read from ws"))??;
+        let msg: WSClientToServer = parse_ws_message(WireCodec::default(), &recv)?;
+
+        match msg {
+            WSClientToServer::Hello(msg) => break msg,
+            WSClientToServer::ListGames => {
+                let msg = WSServerToClient::OpenGames(r.open_games().await);
+                write.send(ws_message(WireCodec::default(), &msg)?).await?;
+            }
+            v => {
+                let msg = WSServerToClient::Msg("expected hello".to_string());
+                let _ = write.send(ws_message(WireCodec::default(), &msg)?).await;
+
+                return Err(anyhow!("expected hello, got {:?}", v));
+            }
+        }
+    };
+
+    if player_info.protocol_version < MIN_PROTOCOL_VERSION {
+        let msg = WSServerToClient::Msg(format!(
+            "the client is too old (protocol version {}, while the server needs at least {}), please update it",
+            player_info.protocol_version, MIN_PROTOCOL_VERSION,
+        ));
+        let _ = write.send(ws_message(WireCodec::default(), &msg)?).await;
+
+        return Err(anyhow!(
+            "too old protocol version {}",
+            player_info.protocol_version
+        ));
+    }
+
+    // Let the client know which versions we support, so that it can suggest
+    // the user to update before it's too late.
+    let codec = WireCodec {
+        format: player_info.wire_format,
+        deflate: opts.deflate && player_info.deflate,
+    };
+    let welcome = WSServerToClient::Welcome(WSServerInfo {
+        protocol_version: PROTOCOL_VERSION,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        wire_format: codec.format,
+        deflate: codec.deflate,
+    });
+    write
+        .send(ws_message(WireCodec::default(), &welcome)?)
+        .await?;
+
+    // The welcome has confirmed the client's wire format and compression, so
+    // they're in use from now on; the older clients never ask for anything
+    // but uncompressed JSON.
+    if codec.format != WireFormat::Json {
+        println!("{}: speaks {}", addr, codec.format);
+    }
+    if codec.deflate {
+        println!("{}: compresses the messages", addr);
+    }
+
+    // Spectators only need the game, if it's there; the rest of the hello is
+    // about playing it.
+    if player_info.spectate {
+        let (game_ctx, from_game) = match r.watch_game(&player_info.game_id).await {
+            Ok(v) => v,
+            Err(err) => {
+                let msg = WSServerToClient::Msg(err.to_string());
+                let _ = write.send(ws_message(codec, &msg)?).await;
+                return Err(err);
+            }
+        };
+
+        let err = match handle_spectator(
+            game_ctx,
+            &addr.to_string(),
+            player_info.protocol_version,
+            codec,
+            from_game,
+            write,
+            read,
+        )
         .await
-        .ok_or(anyhow!("failed to read from ws"))??;
-    let msg: WSClientToServer = serde_json::from_str(&recv.to_string())?;
+        {
+            Ok(()) => panic!("should never happen"),
+            Err(err) => err,
+        };
 
-    let player_info = match msg {
-        WSClientToServer::Hello(msg) => msg,
-        v => {
-            let j = serde_json::to_string(&WSServerToClient::Msg("expected hello".to_string()))?;
-            let _ = write.send(tungstenite::Message::Text(j)).await;
+        return Err(anyhow!("stopped watching: {}", err));
+    }
 
-            return Err(anyhow!("expected hello, got {:?}", v));
-        }
+    // The board that the client sends will be used to resume the game if
+    // there's no such game on the server yet, so make sure it makes sense.
+    if !player_info.game_state.board.is_reachable() {
+        let msg = WSServerToClient::Msg("the board is not reachable by legal play".to_string());
+        let _ = write.send(ws_message(codec, &msg)?).await;
+
+        return Err(anyhow!("unreachable board in hello"));
+    }
+
+    if let Some(token_skin) = &player_info.token_skin {
+        println!("{}: prefers token skin {:?}", addr, token_skin);
+    }
+
+    // The clients which predate the names send "me", which is no use to the
+    // opponent. Names are shown as is, so keep them reasonably short.
+    let player_name: String = if player_info.protocol_version >= 9 {
+        player_info
+            .player_name
+            .trim()
+            .chars()
+            .take(MAX_PLAYER_NAME_LEN)
+            .collect()
+    } else {
+        String::new()
     };
 
     let (to_player_tx, to_player_rx) = mpsc::channel::<PlayerToPlayer>(8);
@@ -83,6 +276,8 @@ async fn handle_conn(r: Arc<Registry>, stream: TcpStream) -> Result<()> {
         .join_or_create_game(
             &player_info.game_id,
             &player_id,
+            &player_name,
+            player_info.session_token.as_deref(),
             to_player_tx.clone(),
             player_info.game_state,
         )
@@ -90,21 +285,32 @@ async fn handle_conn(r: Arc<Registry>, stream: TcpStream) -> Result<()> {
     {
         Ok(v) => v,
         Err(err) => {
-            let j = serde_json::to_string(&WSServerToClient::Msg(err.to_string()))?;
-            let _ = write.send(tungstenite::Message::Text(j)).await;
+            let _ = write
+                .send(ws_message(codec, &WSServerToClient::Msg(err.to_string()))?)
+                .await;
             return Err(err);
         }
     };
 
     // Now that the player is authenticated and added to the game, defer all the
     // rest of the work on behalf of this player to handle_player.
-    let leave_msg =
-        match handle_player(game_ctx.clone(), &player_id, to_player_rx, write, read).await {
-            Ok(()) => {
-                panic!("should never happen");
-            }
-            Err(err) => format!("err: {}", err),
-        };
+    let leave_msg = match handle_player(
+        game_ctx.clone(),
+        &player_id,
+        player_info.protocol_version,
+        codec,
+        opts,
+        to_player_rx,
+        write,
+        read,
+    )
+    .await
+    {
+        Ok(()) => {
+            panic!("should never happen");
+        }
+        Err(err) => format!("err: {}", err),
+    };
 
     // The client has disconnected, remove it from the game (and potentially
     // destroy the game).
@@ -114,9 +320,18 @@ async fn handle_conn(r: Arc<Registry>, stream: TcpStream) -> Result<()> {
 }
 
 /// Take care of a single player, until the connection is broken. Never returns Ok.
+///
+/// Protocol version is the one which the client speaks; the messages which it
+/// wouldn't understand are not sent to it. The codec is the one which the
+/// welcome has confirmed, see WSServerInfo::wire_format and
+/// WSServerInfo::deflate.
+#[allow(clippy::too_many_arguments)]
 async fn handle_player(
     game_ctx: Arc<GameCtx>,
     player_id: &str,
+    protocol_version: u32,
+    codec: WireCodec,
+    opts: ConnOptions,
     mut from_opponent: mpsc::Receiver<PlayerToPlayer>,
     mut to_ws: SplitSink<WebSocketStream<tokio::net::TcpStream>, Message>,
     mut from_ws: SplitStream<WebSocketStream<tokio::net::TcpStream>>,
@@ -125,7 +340,24 @@ async fn handle_player(
 
     let mut ping_interval = time::interval(Duration::from_millis(5000));
     let mut maybe_to_opponent: Option<mpsc::Sender<PlayerToPlayer>> = None;
+
+    // Side of the websocket player, as the client sees it: that's the
+    // opponent's side, and our client plays the opposite one.
     let mut side = game::Side::White;
+    // Name of the opponent, once they're here.
+    let mut opponent_name = String::new();
+
+    // Keeps track of how long our client takes to move.
+    let mut idle_timer = IdleTimer::new(opts.idle_limits);
+    let mut idle_interval = time::interval(IDLE_CHECK_INTERVAL);
+
+    // Keeps our client from flooding the chat.
+    let mut chat_limiter = ChatLimiter::new();
+
+    // When we last heard from the client; the clients which send heartbeats
+    // are dropped once they're silent for too long.
+    let mut last_recv = time::Instant::now();
+    let heartbeats = protocol_version >= 16;
 
     loop {
         tokio::select! {
@@ -133,19 +365,226 @@ async fn handle_player(
             // behalf of which we're working here.
             Some(v) = from_ws.next() => {
                 let recv = v?;
+                last_recv = time::Instant::now();
 
-                let msg: WSClientToServer = serde_json::from_str(&recv.to_string())?;
+                let msg: WSClientToServer = parse_ws_message(codec, &recv)?;
                 match msg {
                     WSClientToServer::Hello(_) => { return Err(anyhow!("did not expect hello")); }
+                    WSClientToServer::ListGames => { return Err(anyhow!("did not expect list games")); }
                     WSClientToServer::PutToken(tcoords) => {
                         let mut gd = game_ctx.data.lock().await;
 
-                        gd.game.put_token(side.opposite(), tcoords)?;
-                        gd.game_state = GameState::WaitingFor(side);
+                        let res = if gd.is_waiting_for(side.opposite()) {
+                            gd.game.put_token(side.opposite(), tcoords).map(|_| ())
+                        } else {
+                            Err(anyhow!("not your turn"))
+                        };
+
+                        if let Err(err) = res {
+                            // The client can roll the move back, so let it
+                            // know, and send it the actual state.
+                            println!("player {}: rejecting move {}: {}", player_id, tcoords, err);
+                            let game_reset = game_reset(&gd, side, &opponent_name);
+                            drop(gd);
+
+                            let rejected = WSServerToClient::MoveRejected(tcoords, err.to_string());
+                            to_ws.send(ws_message(codec, &rejected)?).await?;
+
+                            to_ws.send(ws_message(codec, &game_reset)?).await?;
+                        } else {
+                            // If nobody can win anymore, adjudicate a draw
+                            // right away, instead of letting the players fill
+                            // the rest of the board.
+                            gd.moves.push((side.opposite(), tcoords));
+                            gd.undo_request = None;
+
+                            let drawn = gd.game.is_dead_draw();
+                            let scores = gd.game.get_scores();
+                            gd.game_state = if drawn {
+                                GameState::Draw(scores)
+                            } else {
+                                GameState::WaitingFor(side, scores)
+                            };
+                            gd.tell_spectators(ToSpectator::PutToken(tcoords));
+                            if drawn {
+                                gd.tell_spectators(ToSpectator::GameReset);
+                            }
+                            game_ctx.save(&gd);
+                            let game_reset = game_reset(&gd, side, &opponent_name);
+                            drop(gd);
+
+                            idle_timer.stop();
+
+                            let accepted = WSServerToClient::MoveAccepted(tcoords);
+                            to_ws.send(ws_message(codec, &accepted)?).await?;
+
+                            if let Some(to_opponent) = &maybe_to_opponent {
+                                to_opponent.send(PlayerToPlayer::PutToken(tcoords)).await?;
+                            }
+
+                            if drawn {
+                                println!("game {}: draw, {}", game_ctx.id, game::DEAD_DRAW_REASON);
+                                send_game_drawn(&mut to_ws, codec, game_reset).await?;
+                            }
+                        }
+                    },
+                    WSClientToServer::RequestUndo(num) => {
+                        // Our client plays the side opposite to the websocket
+                        // player's one, see above.
+                        let mut gd = game_ctx.data.lock().await;
+                        gd.undo_request = Some((side.opposite(), num));
+                        drop(gd);
+
+                        if let Some(to_opponent) = &maybe_to_opponent {
+                            to_opponent.send(PlayerToPlayer::UndoRequested(num)).await?;
+                        }
+                    },
+                    WSClientToServer::AcceptUndo => {
+                        // The undo was requested by the opponent, which plays
+                        // the websocket player's side. Our client has taken
+                        // the moves back already, so even if it fails here,
+                        // it needs the actual state.
+                        let mut gd = game_ctx.data.lock().await;
+                        let res = gd.undo(side);
+                        if res.is_ok() {
+                            gd.tell_spectators(ToSpectator::GameReset);
+                            game_ctx.save(&gd);
+                        }
+                        let game_reset = game_reset(&gd, side, &opponent_name);
+                        drop(gd);
+
+                        match res {
+                            Ok(()) => {
+                                // Now it's the opponent's turn.
+                                idle_timer.stop();
+
+                                if let Some(to_opponent) = &maybe_to_opponent {
+                                    to_opponent.send(PlayerToPlayer::UndoApplied).await?;
+                                }
+                            }
+                            Err(err) => {
+                                println!("player {}: failed to undo: {}", player_id, err);
+                            }
+                        }
+
+                        to_ws.send(ws_message(codec, &game_reset)?).await?;
+                    },
+                    WSClientToServer::Resign => {
+                        // The opponent wins, unless the game is over already.
+                        let mut gd = game_ctx.data.lock().await;
+                        let resigned = !gd.is_over();
+                        if resigned {
+                            gd.game_state = GameState::WonBy(side, gd.game.get_scores());
+                            gd.tell_spectators(ToSpectator::GameReset);
+                            game_ctx.save(&gd);
+                        }
+                        let game_reset = game_reset(&gd, side, &opponent_name);
+                        drop(gd);
+
+                        if resigned {
+                            println!("game {}: {:?} resigns", game_ctx.id, side.opposite());
+                            idle_timer.stop();
+
+                            if let Some(to_opponent) = &maybe_to_opponent {
+                                to_opponent.send(PlayerToPlayer::OpponentResigned).await?;
+                            }
+                            send_resigned(&mut to_ws, protocol_version, codec, side.opposite()).await?;
+                        }
+
+                        to_ws.send(ws_message(codec, &game_reset)?).await?;
+                    },
+                    WSClientToServer::OfferRematch => {
+                        // If the opponent has asked for a new game already,
+                        // start it; otherwise, pass the offer on.
+                        let mut gd = game_ctx.data.lock().await;
+                        if gd.rematch_offer == Some(side) {
+                            gd.new_game();
+                            gd.tell_spectators(ToSpectator::GameReset);
+                            game_ctx.save(&gd);
+                            let our_turn = gd.is_waiting_for(side.opposite());
+                            let game_reset = game_reset(&gd, side, &opponent_name);
+                            drop(gd);
+
+                            println!("game {}: new game", game_ctx.id);
+
+                            if our_turn {
+                                idle_timer.start();
+                            } else {
+                                idle_timer.stop();
+                            }
+
+                            to_ws.send(ws_message(codec, &game_reset)?).await?;
+
+                            if let Some(to_opponent) = &maybe_to_opponent {
+                                to_opponent.send(PlayerToPlayer::NewGame).await?;
+                            }
+                        } else {
+                            gd.rematch_offer = Some(side.opposite());
+                            drop(gd);
+
+                            if let Some(to_opponent) = &maybe_to_opponent {
+                                to_opponent.send(PlayerToPlayer::RematchOffered).await?;
+                            }
+                        }
+                    },
+                    WSClientToServer::DeclineRematch => {
+                        // Whoever has asked for the new game, it's off.
+                        let mut gd = game_ctx.data.lock().await;
+                        let declined = gd.rematch_offer.take().is_some();
+                        drop(gd);
+
+                        if declined {
+                            if let Some(to_opponent) = &maybe_to_opponent {
+                                to_opponent.send(PlayerToPlayer::RematchDeclined).await?;
+                            }
+                        }
+                    },
+                    WSClientToServer::Clock(ms) => {
+                        // The server doesn't keep the time, so just pass it
+                        // on.
+                        if let Some(to_opponent) = &maybe_to_opponent {
+                            to_opponent.send(PlayerToPlayer::OpponentClock(ms)).await?;
+                        }
+                    },
+                    WSClientToServer::Ping(nonce) => {
+                        to_ws.send(ws_message(codec, &WSServerToClient::Pong(nonce))?).await?;
+                    },
+                    WSClientToServer::Heartbeat => {},
+                    WSClientToServer::RequestFullState => {
+                        if maybe_to_opponent.is_some() {
+                            let gd = game_ctx.data.lock().await;
+                            let game_reset = game_reset(&gd, side, &opponent_name);
+                            drop(gd);
+
+                            to_ws.send(ws_message(codec, &game_reset)?).await?;
+                        }
+                    },
+                    WSClientToServer::Chat(text) => {
+                        let text = match chat::clean_message(&text) {
+                            Ok(text) => text,
+                            Err(err) => {
+                                println!("player {}: dropping the chat message: {}", player_id, err);
+                                continue;
+                            }
+                        };
+                        if !chat_limiter.allow() {
+                            println!("player {}: chatting too fast, dropping the message", player_id);
+                            continue;
+                        }
+
+                        // The spectators read along, under our user's name,
+                        // or the side if there's none.
+                        let mut gd = game_ctx.data.lock().await;
+                        let mut from = gd.player_name(side.opposite());
+                        if from.is_empty() {
+                            from = format!("{:?}", side.opposite());
+                        }
+                        gd.tell_spectators(ToSpectator::Chat { from, text: text.clone() });
                         drop(gd);
 
+                        // Nobody is there to read it, so it's just dropped.
                         if let Some(to_opponent) = &maybe_to_opponent {
-                            to_opponent.send(PlayerToPlayer::PutToken(tcoords)).await?;
+                            to_opponent.send(PlayerToPlayer::Chat(text)).await?;
                         }
                     },
                 }
@@ -154,47 +593,384 @@ async fn handle_player(
             // Handle messages from the opponent, so another player connected to
             // the same server.
             Some(val) = from_opponent.recv() => {
-                println!("player {}: received from another player: {:?}", player_id, val);
+                // The clocks come every couple of seconds, which would drown
+                // out everything else; and the chat is none of our business.
+                if !matches!(val, PlayerToPlayer::OpponentClock(_) | PlayerToPlayer::Chat(_)) {
+                    println!("player {}: received from another player: {:?}", player_id, val);
+                }
 
                 match val {
                     PlayerToPlayer::OpponentIsHere(v) => {
                         maybe_to_opponent = Some(v.to_opponent);
                         side = v.my_side;
+                        opponent_name = v.opponent_name;
 
                         let gd = game_ctx.data.lock().await;
-                        let game_reset = WSServerToClient::GameReset(WSGameReset{
-                            opponent_name: "my opponent".to_string(), // TODO: actual name
-                            game_state: WSFullGameState{
-                                game_state: gd.game_state,
-                                ws_player_side: side,
-                                board: gd.game.get_board().clone(),
-                            },
-                        });
-
+                        let game_reset = game_reset(&gd, side, &opponent_name);
+                        let our_turn = gd.is_waiting_for(side.opposite());
                         drop(gd);
 
-                        let j = serde_json::to_string(&game_reset)?;
-                        to_ws.send(tungstenite::Message::Text(j)).await?;
+                        to_ws.send(ws_message(codec, &game_reset)?).await?;
+
+                        if our_turn {
+                            idle_timer.start();
+                        }
                     },
                     PlayerToPlayer::OpponentIsGone => {
                         maybe_to_opponent = None;
+                        idle_timer.stop();
+
+                        to_ws.send(ws_message(codec, &WSServerToClient::OpponentIsGone)?).await?;
+                    }
+
+                    PlayerToPlayer::OpponentIsIdle(idle) => {
+                        to_ws.send(ws_message(codec, &WSServerToClient::Idle(idle))?).await?;
+                    }
+                    PlayerToPlayer::OpponentAbandoned => {
+                        idle_timer.stop();
 
-                        let j = serde_json::to_string(&WSServerToClient::OpponentIsGone)?;
-                        to_ws.send(tungstenite::Message::Text(j)).await?;
+                        let gd = game_ctx.data.lock().await;
+                        let game_reset = game_reset(&gd, side, &opponent_name);
+                        drop(gd);
+
+                        to_ws.send(ws_message(codec, &WSServerToClient::GameAbandoned(side))?).await?;
+
+                        to_ws.send(ws_message(codec, &game_reset)?).await?;
                     }
 
                     PlayerToPlayer::PutToken(tcoords) => {
+                        // Now it's our turn, unless the move has ended the
+                        // game.
+                        let gd = game_ctx.data.lock().await;
+                        if gd.is_waiting_for(side.opposite()) {
+                            idle_timer.start();
+                        }
+                        let drawn = matches!(gd.game_state, GameState::Draw(_));
+                        let game_reset = game_reset(&gd, side, &opponent_name);
+                        drop(gd);
+
                         let put_token = WSServerToClient::PutToken(tcoords);
-                        let j = serde_json::to_string(&put_token)?;
-                        to_ws.send(tungstenite::Message::Text(j)).await?;
+                        let msg = ws_message(codec, &put_token)?;
+
+                        let action = opts.chaos.next_action();
+                        if action != ChaosAction::Deliver {
+                            println!("player {}: chaos: {:?}", player_id, action);
+                        }
+
+                        match action {
+                            ChaosAction::Deliver => {
+                                to_ws.send(msg).await?;
+                            }
+                            ChaosAction::Delay(d) => {
+                                time::sleep(d).await;
+                                to_ws.send(msg).await?;
+                            }
+                            ChaosAction::Drop => {}
+                            ChaosAction::Duplicate => {
+                                to_ws.send(msg.clone()).await?;
+                                to_ws.send(msg).await?;
+                            }
+                            ChaosAction::Disconnect => {
+                                return Err(anyhow!("chaos: forced disconnect"));
+                            }
+                        }
+
+                        if drawn {
+                            send_game_drawn(&mut to_ws, codec, game_reset).await?;
+                        }
                     },
+
+                    PlayerToPlayer::UndoRequested(num) => {
+                        if protocol_version >= 5 {
+                            to_ws.send(ws_message(codec, &WSServerToClient::UndoRequested(num))?).await?;
+                        }
+                    }
+                    PlayerToPlayer::UndoApplied => {
+                        // Now it's our turn again.
+                        let gd = game_ctx.data.lock().await;
+                        if gd.is_waiting_for(side.opposite()) {
+                            idle_timer.start();
+                        }
+                        let game_reset = game_reset(&gd, side, &opponent_name);
+                        drop(gd);
+
+                        to_ws.send(ws_message(codec, &game_reset)?).await?;
+                    }
+
+                    PlayerToPlayer::OpponentResigned => {
+                        idle_timer.stop();
+
+                        let gd = game_ctx.data.lock().await;
+                        let game_reset = game_reset(&gd, side, &opponent_name);
+                        drop(gd);
+
+                        send_resigned(&mut to_ws, protocol_version, codec, side).await?;
+
+                        to_ws.send(ws_message(codec, &game_reset)?).await?;
+                    }
+
+                    PlayerToPlayer::RematchOffered => {
+                        if protocol_version >= 6 {
+                            to_ws.send(ws_message(codec, &WSServerToClient::RematchOffered)?).await?;
+                        }
+                    }
+                    PlayerToPlayer::RematchDeclined => {
+                        if protocol_version >= 18 {
+                            to_ws.send(ws_message(codec, &WSServerToClient::RematchDeclined)?).await?;
+                        }
+                    }
+                    PlayerToPlayer::NewGame => {
+                        let gd = game_ctx.data.lock().await;
+                        let our_turn = gd.is_waiting_for(side.opposite());
+                        let game_reset = game_reset(&gd, side, &opponent_name);
+                        drop(gd);
+
+                        if our_turn {
+                            idle_timer.start();
+                        } else {
+                            idle_timer.stop();
+                        }
+
+                        to_ws.send(ws_message(codec, &game_reset)?).await?;
+                    }
+
+                    PlayerToPlayer::OpponentClock(ms) => {
+                        if protocol_version >= 8 {
+                            to_ws.send(ws_message(codec, &WSServerToClient::OpponentClock(ms))?).await?;
+                        }
+                    }
+
+                    PlayerToPlayer::Chat(text) => {
+                        if protocol_version >= 11 {
+                            let chat = WSServerToClient::Chat {
+                                from: opponent_name.clone(),
+                                text,
+                            };
+                            to_ws.send(ws_message(codec, &chat)?).await?;
+                        }
+                    }
+
+                    PlayerToPlayer::Replaced => {
+                        return Err(anyhow!("the seat is taken over by another connection"));
+                    }
                 }
             }
 
             _ = ping_interval.tick() => {
-                let j = serde_json::to_string(&WSServerToClient::Ping)?;
-                to_ws.send(tungstenite::Message::Text(j)).await?;
+                to_ws.send(ws_message(codec, &WSServerToClient::Ping)?).await?;
+            }
+
+            _ = time::sleep_until(last_recv + HEARTBEAT_INTERVAL * MAX_MISSED_HEARTBEATS), if heartbeats => {
+                return Err(anyhow!(
+                    "missed {} heartbeats in a row, the client must be gone",
+                    MAX_MISSED_HEARTBEATS
+                ));
+            }
+
+            _ = idle_interval.tick() => {
+                match idle_timer.check() {
+                    None => {}
+
+                    Some(IdleAction::Nudge(idle, abandon_in)) => {
+                        println!("player {}: idle for {:?}, nudging", player_id, idle);
+
+                        let idle = WSIdle {
+                            side: side.opposite(),
+                            idle_secs: idle.as_secs(),
+                            abandon_in_secs: abandon_in.map(|v| v.as_secs()),
+                        };
+
+                        to_ws.send(ws_message(codec, &WSServerToClient::Idle(idle.clone()))?).await?;
+
+                        if let Some(to_opponent) = &maybe_to_opponent {
+                            to_opponent.send(PlayerToPlayer::OpponentIsIdle(idle)).await?;
+                        }
+                    }
+
+                    Some(IdleAction::Abandon) => {
+                        println!("player {}: idle for too long, abandoning the game", player_id);
+
+                        // The opponent wins.
+                        let mut gd = game_ctx.data.lock().await;
+                        gd.game_state = GameState::WonBy(side, gd.game.get_scores());
+                        gd.tell_spectators(ToSpectator::GameReset);
+                        game_ctx.save(&gd);
+                        let game_reset = game_reset(&gd, side, &opponent_name);
+                        drop(gd);
+
+                        let abandoned = WSServerToClient::GameAbandoned(side.opposite());
+                        to_ws.send(ws_message(codec, &abandoned)?).await?;
+
+                        to_ws.send(ws_message(codec, &game_reset)?).await?;
+
+                        if let Some(to_opponent) = &maybe_to_opponent {
+                            to_opponent.send(PlayerToPlayer::OpponentAbandoned).await?;
+                        }
+                    }
+                }
             }
         }
     }
 }
+
+/// Take care of a spectator (see WSClientInfo::spectate) of the given game,
+/// until the connection is broken, or the game is destroyed: it gets the whole
+/// game right away, and then every change of it. Never returns Ok.
+async fn handle_spectator(
+    game_ctx: Arc<GameCtx>,
+    spectator_id: &str,
+    protocol_version: u32,
+    codec: WireCodec,
+    mut from_game: mpsc::Receiver<ToSpectator>,
+    mut to_ws: SplitSink<WebSocketStream<tokio::net::TcpStream>, Message>,
+    mut from_ws: SplitStream<WebSocketStream<tokio::net::TcpStream>>,
+) -> Result<()> {
+    println!("spectator {} watches game {}", spectator_id, game_ctx.id);
+
+    let gd = game_ctx.data.lock().await;
+    let game_reset = spectator_game_reset(&gd);
+    drop(gd);
+    to_ws.send(ws_message(codec, &game_reset)?).await?;
+
+    let mut ping_interval = time::interval(Duration::from_millis(5000));
+    let mut last_recv = time::Instant::now();
+    let heartbeats = protocol_version >= 16;
+
+    loop {
+        tokio::select! {
+            Some(v) = from_ws.next() => {
+                let recv = v?;
+                last_recv = time::Instant::now();
+
+                let msg: WSClientToServer = parse_ws_message(codec, &recv)?;
+                match msg {
+                    WSClientToServer::Ping(nonce) => {
+                        to_ws.send(ws_message(codec, &WSServerToClient::Pong(nonce))?).await?;
+                    }
+                    WSClientToServer::RequestFullState => {
+                        let gd = game_ctx.data.lock().await;
+                        let game_reset = spectator_game_reset(&gd);
+                        drop(gd);
+
+                        to_ws.send(ws_message(codec, &game_reset)?).await?;
+                    }
+                    // Spectators only watch, so anything else is ignored.
+                    _ => {}
+                }
+            }
+
+            msg = from_game.recv() => {
+                let msg = match msg {
+                    Some(ToSpectator::PutToken(pcoords)) => WSServerToClient::PutToken(pcoords),
+                    Some(ToSpectator::Chat { from, text }) => WSServerToClient::Chat { from, text },
+                    Some(ToSpectator::GameReset) => {
+                        let gd = game_ctx.data.lock().await;
+                        spectator_game_reset(&gd)
+                    }
+                    None => {
+                        let msg = WSServerToClient::Msg("everyone has left the game".to_string());
+                        let _ = to_ws.send(ws_message(codec, &msg)?).await;
+
+                        return Err(anyhow!("the game is gone"));
+                    }
+                };
+
+                to_ws.send(ws_message(codec, &msg)?).await?;
+            }
+
+            _ = ping_interval.tick() => {
+                to_ws.send(ws_message(codec, &WSServerToClient::Ping)?).await?;
+            }
+
+            _ = time::sleep_until(last_recv + HEARTBEAT_INTERVAL * MAX_MISSED_HEARTBEATS), if heartbeats => {
+                return Err(anyhow!(
+                    "missed {} heartbeats in a row, the client must be gone",
+                    MAX_MISSED_HEARTBEATS
+                ));
+            }
+        }
+    }
+}
+
+/// Returns the game reset message with the current game data, for a
+/// spectator: it's the game as the primary player's client sees it, so the
+/// opponent is the secondary player; but without the session token, which is
+/// the player's secret.
+fn spectator_game_reset(gd: &GameData) -> WSServerToClient {
+    WSServerToClient::GameReset(WSGameReset {
+        opponent_name: gd.player_name(gd.player_pri_side),
+        game_state: WSFullGameState {
+            game_state: gd.game_state,
+            ws_player_side: gd.player_pri_side,
+            board: gd.game.get_board().clone(),
+        },
+        session_token: None,
+    })
+}
+
+/// Returns the game reset message with the current game data, for the client
+/// whose websocket player has the given side, and whose opponent has the given
+/// name (see handle_player).
+fn game_reset(gd: &GameData, side: game::Side, opponent_name: &str) -> WSServerToClient {
+    WSServerToClient::GameReset(WSGameReset {
+        opponent_name: opponent_name.to_string(),
+        game_state: WSFullGameState {
+            game_state: gd.game_state,
+            ws_player_side: side,
+            board: gd.game.get_board().clone(),
+        },
+        session_token: gd.session_token(side),
+    })
+}
+
+/// Lets the client know that the game is adjudicated as a dead draw: sends
+/// the GameDrawn, followed by the given game reset with the final state.
+async fn send_game_drawn(
+    to_ws: &mut SplitSink<WebSocketStream<tokio::net::TcpStream>, Message>,
+    codec: WireCodec,
+    game_reset: WSServerToClient,
+) -> Result<()> {
+    let drawn = WSServerToClient::GameDrawn(game::DEAD_DRAW_REASON.to_string());
+    to_ws.send(ws_message(codec, &drawn)?).await?;
+
+    to_ws.send(ws_message(codec, &game_reset)?).await?;
+
+    Ok(())
+}
+
+/// Lets the client know that the given side has resigned, if the client
+/// understands it; the game reset with the final state should follow.
+async fn send_resigned(
+    to_ws: &mut SplitSink<WebSocketStream<tokio::net::TcpStream>, Message>,
+    protocol_version: u32,
+    codec: WireCodec,
+    side: game::Side,
+) -> Result<()> {
+    if protocol_version >= 7 {
+        to_ws
+            .send(ws_message(codec, &WSServerToClient::Resigned(side))?)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Encodes the message to the client in the given format, compressed if the
+/// welcome has said so, see WireCodec.
+fn ws_message(codec: WireCodec, msg: &WSServerToClient) -> Result<Message> {
+    let data = codec.encode(msg)?;
+    if codec.is_binary() {
+        Ok(Message::Binary(data))
+    } else {
+        Ok(Message::Text(String::from_utf8(data)?))
+    }
+}
+
+/// Decodes the message from the client; the text messages are always JSON,
+/// and the binary ones are decoded as the codec says.
+fn parse_ws_message(codec: WireCodec, msg: &Message) -> Result<WSClientToServer> {
+    match msg {
+        Message::Binary(data) => codec.decode_binary(data),
+        _ => WireFormat::Json.decode(msg.to_string().as_bytes()),
+    }
+}
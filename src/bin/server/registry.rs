@@ -1,13 +1,17 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::{anyhow, Result};
+use rand::Rng;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
 
 use connectfour::game;
-use connectfour::game_manager::GameState;
-use connectfour::WSFullGameState;
+use connectfour::game::GameState;
+use connectfour::protocol::{WSFullGameState, WSIdle, WSOpenGame};
+
+use super::storage::{Storage, StoredGame};
 
 /// Game registry, to match players by game IDs.
 ///
@@ -16,9 +20,15 @@ use connectfour::WSFullGameState;
 /// issues or whatever else), the game state is kept for now, and when that
 /// player reconnects later on, the game resumes from where it left off.
 ///
-/// When the last player leaves the game, the game is destroyed as well.
+/// When the last player leaves the game, the game is destroyed as well. The
+/// spectators (see watch_game) don't count: they're just told to go away.
+///
+/// With the storage (see set_storage), the games are also kept in the
+/// database, so that when the server restarts, a game is resumed as soon as
+/// either player joins it again.
 pub struct Registry {
     game_by_name: Mutex<HashMap<String, Arc<GameCtx>>>,
+    storage: Option<Arc<Storage>>,
 }
 
 pub struct GameCtx {
@@ -26,6 +36,9 @@ pub struct GameCtx {
     pub id: String,
 
     pub data: Mutex<GameData>,
+
+    /// Where to save the game whenever it changes, see GameCtx::save.
+    storage: Option<Arc<Storage>>,
 }
 
 pub struct GameData {
@@ -39,12 +52,55 @@ pub struct GameData {
     pub game_state: GameState,
     pub player_pri_side: game::Side,
     pub game: game::Game,
+
+    /// Moves made on the server, and the sides which made them, so that they
+    /// can be taken back, see undo. The moves made before the game was
+    /// created (e.g. before the server restarted) are not there.
+    pub moves: Vec<(game::Side, game::PoleCoords)>,
+    /// Side which has asked to take back the given number of moves, if the
+    /// request still stands; it's cleared by every move.
+    pub undo_request: Option<(game::Side, usize)>,
+    /// Side which has asked for a new game, if any, see new_game.
+    pub rematch_offer: Option<game::Side>,
+
+    /// Whoever watches the game, see Registry::watch_game.
+    spectators: Vec<Spectator>,
+
+    /// Since when the only player waits for the second one, see
+    /// Registry::open_games; it's only meaningful with a single player.
+    waiting_since: Instant,
+}
+
+/// Message to a spectator, see Registry::watch_game.
+#[derive(Debug, Clone)]
+pub enum ToSpectator {
+    /// A player has put token on the given pole.
+    PutToken(game::PoleCoords),
+    /// The game has changed other than by a move, e.g. moves were taken back,
+    /// or a new game has started; the spectator needs the whole game again.
+    GameReset,
+    /// A player says the given text in the chat, see
+    /// WSClientToServer::Chat; from is their name, which can be empty.
+    Chat { from: String, text: String },
+}
+
+/// Spectator context, see Registry::watch_game.
+struct Spectator {
+    to: mpsc::Sender<ToSpectator>,
+    /// Whether the spectator hasn't kept up, and has missed some message; so
+    /// the next one it gets is the game reset.
+    behind: bool,
 }
 
-/// Player context; contains just the ID and a sender to send messages to this
-/// player.
+/// Player context; contains just the ID, the name, the session token and a
+/// sender to send messages to this player.
 struct Player {
     id: String,
+    /// Name to show to the opponent, see WSClientInfo::player_name.
+    name: String,
+    /// Secret which lets the player reclaim the seat, see
+    /// WSGameReset::session_token.
+    session_token: String,
 
     /// Sender to send messages to this player.
     to: mpsc::Sender<PlayerToPlayer>,
@@ -60,6 +116,39 @@ pub enum PlayerToPlayer {
 
     /// Opponent has put token on the given pole.
     PutToken(game::PoleCoords),
+
+    /// Opponent hasn't moved for a while, see idle::IdleTimer.
+    OpponentIsIdle(WSIdle),
+    /// Opponent hasn't moved for too long, so the game is abandoned, and we
+    /// won.
+    OpponentAbandoned,
+
+    /// Opponent asks to take back the given number of moves.
+    UndoRequested(usize),
+    /// Opponent has accepted our undo request, and the moves are taken back.
+    UndoApplied,
+
+    /// Opponent has resigned, so we won.
+    OpponentResigned,
+
+    /// Opponent asks for a new game.
+    RematchOffered,
+    /// Opponent has called off the new game, see
+    /// WSClientToServer::DeclineRematch.
+    RematchDeclined,
+    /// Both players have asked for a new game, and it has started.
+    NewGame,
+
+    /// Time remaining on the opponent's clock, in milliseconds, see
+    /// WSClientToServer::Clock.
+    OpponentClock(u64),
+
+    /// Sent to a player whose seat is reclaimed by a new connection with its
+    /// session token; the old connection should just go away.
+    Replaced,
+
+    /// Opponent says the given text in the chat.
+    Chat(String),
 }
 
 /// Sent to both players whenever the second player arrives.
@@ -69,6 +158,8 @@ pub struct GameStartOrResume {
     pub to_opponent: mpsc::Sender<PlayerToPlayer>,
     /// Side of the receiving player.
     pub my_side: game::Side,
+    /// Name of the opponent, see WSGameReset::opponent_name.
+    pub opponent_name: String,
 }
 
 impl Registry {
@@ -78,12 +169,27 @@ impl Registry {
 
         Registry {
             game_by_name: Mutex::<HashMap<String, Arc<GameCtx>>>::new(m),
+            storage: None,
         }
     }
 
+    /// Makes the registry keep the games in the given storage as well, so
+    /// that they survive a restart. Must be called before any game is
+    /// created.
+    pub fn set_storage(&mut self, storage: Storage) {
+        self.storage = Some(Arc::new(storage));
+    }
+
     /// Either join existing game by game_id, or if it doesn't exist, then
     /// create a new one, and in either case, return the game context. If the
-    /// game already has both players, an error is returned.
+    /// game already has both players, an error is returned, unless the given
+    /// session token is the one of either seat (see
+    /// WSClientInfo::session_token): then the player takes that seat over.
+    ///
+    /// A new game is created from the player's game state, unless the storage
+    /// has the game from before the restart: then it's resumed from there, and
+    /// with the session token of either seat, the player gets that seat's
+    /// side back.
     ///
     /// Player ID must only be unique for a particular game ID, but in practice,
     /// client code just passes remote addr as player ID, so they end up being
@@ -92,6 +198,8 @@ impl Registry {
         &self,
         game_id: &str,
         player_id: &str,
+        player_name: &str,
+        session_token: Option<&str>,
         to_player: mpsc::Sender<PlayerToPlayer>,
         game_state: WSFullGameState,
     ) -> Result<Arc<GameCtx>> {
@@ -99,7 +207,14 @@ impl Registry {
 
         // Try to join existing game, if any.
         if let Some(res) = self
-            .try_join_game(&mut m, game_id, player_id, &to_player)
+            .try_join_game(
+                &mut m,
+                game_id,
+                player_id,
+                player_name,
+                session_token,
+                &to_player,
+            )
             .await
         {
             return res;
@@ -113,7 +228,19 @@ impl Registry {
 
         let sname = game_id.to_string();
 
-        let gc = GameCtx::new(sname.clone(), player_id.to_string(), to_player, game_state);
+        let mut gc = GameCtx::new(
+            sname.clone(),
+            player_id.to_string(),
+            player_name.to_string(),
+            to_player,
+            game_state,
+            self.storage.clone(),
+        );
+        if let Some(stored) = self.load_stored(game_id).await {
+            println!("game {}: resuming from the storage", game_id);
+            gc.data.get_mut().restore(stored, session_token);
+        }
+        gc.save(&*gc.data.lock().await);
         let a = Arc::new(gc);
 
         m.insert(sname, a.clone());
@@ -121,6 +248,60 @@ impl Registry {
         Ok(a)
     }
 
+    /// Starts watching the game with the given ID, if it exists: the returned
+    /// receiver gets every move and every game reset (see
+    /// GameData::tell_spectators), and once the game is destroyed, it's
+    /// closed. Unlike the players, spectators don't have to leave: once the
+    /// receiver is dropped, they're forgotten.
+    pub async fn watch_game(
+        &self,
+        game_id: &str,
+    ) -> Result<(Arc<GameCtx>, mpsc::Receiver<ToSpectator>)> {
+        let m = self.game_by_name.lock().await;
+        let gc = match m.get(game_id) {
+            Some(gc) => gc.clone(),
+            None => return Err(anyhow!("game {} doesn't exist", game_id)),
+        };
+
+        // With the moves, it's 2 messages per move at most; someone who
+        // can't keep up with that gets the whole game, see
+        // GameData::tell_spectators.
+        let (tx, rx) = mpsc::channel(8);
+        gc.data.lock().await.spectators.push(Spectator {
+            to: tx,
+            behind: false,
+        });
+
+        Ok((gc, rx))
+    }
+
+    /// Returns the games which can be joined: the ones which are still on,
+    /// and only have a single player, who has waited the longest first; see
+    /// WSClientToServer::ListGames.
+    pub async fn open_games(&self) -> Vec<WSOpenGame> {
+        let m = self.game_by_name.lock().await;
+
+        let mut open = Vec::new();
+        for gc in m.values() {
+            let gd = gc.data.lock().await;
+            if gd.num_players() != 1 || gd.is_over() {
+                continue;
+            }
+
+            open.push((
+                gd.waiting_since,
+                WSOpenGame {
+                    game_id: gc.id.clone(),
+                    host_name: gd.player_name(gd.player_pri_side.opposite()),
+                    waiting_secs: gd.waiting_since.elapsed().as_secs(),
+                },
+            ));
+        }
+
+        open.sort_by_key(|(waiting_since, _)| *waiting_since);
+        open.into_iter().map(|(_, og)| og).collect()
+    }
+
     /// Try joining existing game, if any. Returned None means there is no
     /// suitable existing game for us to join, so the caller should create a new
     /// one; Some(res) means we either found a game, or ran into an error;
@@ -130,6 +311,8 @@ impl Registry {
         m: &mut HashMap<String, Arc<GameCtx>>,
         game_id: &str,
         player_id: &str,
+        player_name: &str,
+        session_token: Option<&str>,
         to_player: &mpsc::Sender<PlayerToPlayer>,
     ) -> Option<Result<Arc<GameCtx>>> {
         match m.get(game_id) {
@@ -138,7 +321,8 @@ impl Registry {
                 let gc = v.clone();
                 let mut gd = gc.data.lock().await;
 
-                // If the game is over already (there's a winner), delete it and
+                // If the game is over already (there's a winner, or it was
+                // abandoned), delete it and
                 // pretend it didn't exist. This is needed to make it easier to
                 // start a new game: if we kept returning the old one, then
                 // after the game has ended, to to restart a game players would
@@ -150,55 +334,63 @@ impl Registry {
                 // disconnects/reconnects independently, so we're accommodating
                 // this case here.
                 //
-                // Obviously it would be even more convenient to just have a way
-                // to restart the game without quitting the app, but it's a much
-                // bigger change than this simple hack (We'd need to implement
-                // some UI for collecting the user's willingness to start a new
-                // game, and some coordination via the server, so that the game
-                // only restarts when both players agreed to. All in all, it's a
-                // TODO).
-                if !gd.game.get_win_row().is_none() {
+                // The players which stay connected don't need this: they can
+                // start a new game once both have asked for it, see
+                // GameData::new_game. This is for the clients which can't.
+                if gd.is_over() {
+                    gd.spectators.clear();
                     m.remove(game_id);
+                    self.delete_stored(game_id);
                     return None;
                 }
 
                 // The game already exists and has not ended yet, check how many
                 // players are there.  If both are there, error out; otherwise,
                 // add the new player and return the game.
+                //
+                // Unless the player is reconnecting (e.g. from another
+                // network) before we've noticed that its old connection is
+                // dead: with the seat's session token, it takes the seat over.
                 if gd.player_sec.is_some() {
-                    println!("game {} already has both players", game_id);
-                    return Some(Err(anyhow!("game {} already has both players", game_id)));
+                    let seat = match session_token {
+                        Some(token) => gd.seat_by_token(token),
+                        None => None,
+                    };
+                    let player = match seat {
+                        Some(player) => player,
+                        None => {
+                            println!("game {} already has both players", game_id);
+                            return Some(Err(anyhow!("game {} already has both players", game_id)));
+                        }
+                    };
+
+                    let old_id = std::mem::replace(&mut player.id, player_id.to_string());
+                    let old_to = std::mem::replace(&mut player.to, to_player.clone());
+                    player.name = player_name.to_string();
+                    drop(gd);
+
+                    println!(
+                        "game {}: player {} takes over the seat of {}",
+                        game_id, player_id, old_id
+                    );
+                    let _ = old_to.send(PlayerToPlayer::Replaced).await;
+
+                    Self::introduce_players(&gc).await;
+
+                    return Some(Ok(gc));
                 }
 
                 // The game only had a single player, so adding this one as the secondary.
                 gd.player_sec = Some(Player {
                     id: player_id.to_string(),
+                    name: player_name.to_string(),
+                    session_token: new_session_token(),
                     to: to_player.clone(),
                 });
-
-                let to_pri = gd.player_pri.as_ref().unwrap().to.clone();
-                let to_sec = to_player;
-                let pri_side = gd.player_pri_side;
+                gc.save(&gd);
                 drop(gd);
 
-                // Introduce both players to each other, so they can take it
-                // from there. If sending fails, which would mean that some
-                // player is gone, we ignore it here, since it'll be handled in
-                // the individual connection's loop.
-
-                let _ = to_pri
-                    .send(PlayerToPlayer::OpponentIsHere(GameStartOrResume {
-                        to_opponent: to_sec.clone(),
-                        my_side: pri_side,
-                    }))
-                    .await;
-
-                let _ = to_sec
-                    .send(PlayerToPlayer::OpponentIsHere(GameStartOrResume {
-                        to_opponent: to_pri,
-                        my_side: pri_side.opposite(),
-                    }))
-                    .await;
+                Self::introduce_players(&gc).await;
 
                 println!("game {}: added new player {}", game_id, player_id);
 
@@ -210,13 +402,69 @@ impl Registry {
         }
     }
 
+    /// Introduces both players of the game to each other, so they can take it
+    /// from there. If sending fails, which would mean that some player is
+    /// gone, we ignore it here, since it'll be handled in the individual
+    /// connection's loop.
+    async fn introduce_players(gc: &GameCtx) {
+        let mut gd = gc.data.lock().await;
+        let (player_pri, player_sec) = match (&gd.player_pri, &gd.player_sec) {
+            (Some(pri), Some(sec)) => (pri, sec),
+            _ => return,
+        };
+
+        let to_pri = player_pri.to.clone();
+        let pri_name = player_pri.name.clone();
+        let to_sec = player_sec.to.clone();
+        let sec_name = player_sec.name.clone();
+        let pri_side = gd.player_pri_side;
+
+        // The spectators need the names too, see GameData::player_name.
+        gd.tell_spectators(ToSpectator::GameReset);
+        drop(gd);
+
+        let _ = to_pri
+            .send(PlayerToPlayer::OpponentIsHere(GameStartOrResume {
+                to_opponent: to_sec.clone(),
+                my_side: pri_side,
+                opponent_name: sec_name,
+            }))
+            .await;
+
+        let _ = to_sec
+            .send(PlayerToPlayer::OpponentIsHere(GameStartOrResume {
+                to_opponent: to_pri,
+                my_side: pri_side.opposite(),
+                opponent_name: pri_name,
+            }))
+            .await;
+    }
+
     /// Leave the game with the given ID. If it was the last player on this game, the game is
     /// destroyed.
+    ///
+    /// If the player's seat was taken over by another connection (see
+    /// join_or_create_game), it's not in the game anymore, and it's a no-op.
     pub async fn leave_game(&self, game_id: &str, player_id: &str) {
         let mut m = self.game_by_name.lock().await;
-        let gc = m[game_id].clone();
+        let gc = match m.get(game_id) {
+            Some(gc) => gc.clone(),
+            None => return,
+        };
 
         let mut gd = gc.data.lock().await;
+        let is_joined = [&gd.player_pri, &gd.player_sec]
+            .into_iter()
+            .flatten()
+            .any(|p| p.id == player_id);
+        if !is_joined {
+            println!(
+                "game {}: player {} is gone, but its seat is taken over already",
+                game_id, player_id
+            );
+            return;
+        }
+
         match gd.num_players() {
             1 => {
                 // With one player, we just destroy the game, since there are no more players.
@@ -226,7 +474,9 @@ impl Registry {
                 );
                 assert_eq!(gd.player_pri.as_ref().unwrap().id, player_id);
 
+                gd.spectators.clear();
                 m.remove(game_id);
+                self.delete_stored(game_id);
             }
 
             2 => {
@@ -243,6 +493,7 @@ impl Registry {
                     );
                     let _ = player_sec.to.send(PlayerToPlayer::OpponentIsGone).await;
                     gd.sec_to_pri();
+                    gd.waiting_since = Instant::now();
                     return;
                 }
 
@@ -251,6 +502,7 @@ impl Registry {
                 assert_eq!(gd.player_sec.as_ref().unwrap().id, player_id);
                 let _ = player_pri.to.send(PlayerToPlayer::OpponentIsGone).await;
                 gd.player_sec = None;
+                gd.waiting_since = Instant::now();
             }
 
             _ => {
@@ -258,17 +510,44 @@ impl Registry {
             }
         }
     }
+
+    /// Loads the game with the given ID from the storage, if it's there and
+    /// still on. Failing to load it just means a new game, so the errors are
+    /// only printed.
+    async fn load_stored(&self, game_id: &str) -> Option<StoredGame> {
+        let storage = self.storage.as_ref()?;
+
+        match storage.load(game_id).await {
+            Ok(Some(stored)) if !stored.is_over() => Some(stored),
+            Ok(_) => None,
+            Err(err) => {
+                println!("game {}: failed to load: {:#}", game_id, err);
+                None
+            }
+        }
+    }
+
+    /// Deletes the game with the given ID from the storage, if any.
+    fn delete_stored(&self, game_id: &str) {
+        if let Some(storage) = &self.storage {
+            storage.delete(game_id);
+        }
+    }
 }
 
 impl GameCtx {
     fn new(
         game_id: String,
         player_id: String,
+        player_name: String,
         to_player: mpsc::Sender<PlayerToPlayer>,
         game_state: WSFullGameState,
+        storage: Option<Arc<Storage>>,
     ) -> GameCtx {
         let player_pri = Player {
             id: player_id,
+            name: player_name,
+            session_token: new_session_token(),
             to: to_player,
         };
 
@@ -284,12 +563,177 @@ impl GameCtx {
                 game_state: game_state.game_state,
                 player_pri_side: game_state.ws_player_side,
                 game: g,
+                moves: Vec::new(),
+                undo_request: None,
+                rematch_offer: None,
+                spectators: Vec::new(),
+                waiting_since: Instant::now(),
             }),
+            storage,
+        }
+    }
+
+    /// Saves the game to the storage, if any; should be called whenever the
+    /// game changes. It's only queued, see Storage::save.
+    pub fn save(&self, gd: &GameData) {
+        if let Some(storage) = &self.storage {
+            storage.save(&self.id, gd.to_stored());
         }
     }
 }
 
 impl GameData {
+    /// Whether the game is over: either there's a winner, or it was abandoned.
+    pub fn is_over(&self) -> bool {
+        self.game.get_win_row().is_some()
+            || matches!(self.game_state, GameState::WonBy(_, _) | GameState::Draw(_))
+    }
+
+    /// Whether the game is still on, and it's the given side's turn.
+    pub fn is_waiting_for(&self, side: game::Side) -> bool {
+        !self.is_over() && self.game_state.is_waiting_for(side)
+    }
+
+    /// Takes back the moves which the given side has asked to take back (see
+    /// undo_request), so that it's its turn again.
+    pub fn undo(&mut self, side: game::Side) -> Result<()> {
+        let num = match self.undo_request.take() {
+            Some((s, num)) if s == side => num,
+            _ => return Err(anyhow!("{:?} hasn't asked to undo", side)),
+        };
+
+        if num == 0 || num > self.moves.len() {
+            return Err(anyhow!(
+                "can't take back {} moves out of {}",
+                num,
+                self.moves.len()
+            ));
+        }
+
+        let first = self.moves.len() - num;
+        if self.moves[first].0 != side {
+            return Err(anyhow!("{:?} can only take back its own move", side));
+        }
+
+        // Take the moves back on a copy, so that if something is off, the
+        // game stays as it was.
+        let mut game = self.game.clone();
+        for &(s, pcoords) in self.moves[first..].iter().rev() {
+            game.take_back(s, pcoords)?;
+        }
+
+        self.game = game;
+        self.moves.truncate(first);
+        self.game_state = GameState::WaitingFor(side, self.game.get_scores());
+
+        Ok(())
+    }
+
+    /// Starts a new game in place of the current one, on the empty board,
+    /// with the same sides; White moves first.
+    pub fn new_game(&mut self) {
+        self.game.reset_board(&game::BoardState::new());
+        self.game_state = GameState::WaitingFor(game::Side::White, self.game.get_scores());
+        self.moves.clear();
+        self.undo_request = None;
+        self.rematch_offer = None;
+    }
+
+    /// Passes the given message on to the spectators of the game, forgetting
+    /// the ones which are gone. A spectator which can't keep up loses the
+    /// message, and gets the game reset with the next change of the game
+    /// instead; so it has to be called for every change.
+    pub fn tell_spectators(&mut self, msg: ToSpectator) {
+        self.spectators.retain_mut(|s| {
+            // The game reset has everything the spectator has missed.
+            let msg = if s.behind {
+                ToSpectator::GameReset
+            } else {
+                msg.clone()
+            };
+
+            match s.to.try_send(msg) {
+                Ok(()) => {
+                    s.behind = false;
+                    true
+                }
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    s.behind = true;
+                    true
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+
+    /// Name of the player who plays the given side (as opposed to its
+    /// client's WS player, see WSFullGameState::ws_player_side); empty if
+    /// nobody is there, or the player hasn't given one.
+    pub fn player_name(&self, side: game::Side) -> String {
+        let player = if side == self.player_pri_side {
+            &self.player_sec
+        } else {
+            &self.player_pri
+        };
+
+        player.as_ref().map(|p| p.name.clone()).unwrap_or_default()
+    }
+
+    /// Session token of the seat of the player whose WS player (i.e. the
+    /// opponent, as the player's client sees it) plays the given side, see
+    /// WSGameReset::session_token.
+    pub fn session_token(&self, ws_player_side: game::Side) -> Option<String> {
+        let player = if ws_player_side == self.player_pri_side {
+            &self.player_pri
+        } else {
+            &self.player_sec
+        };
+
+        player.as_ref().map(|p| p.session_token.clone())
+    }
+
+    /// Returns the game as it's kept in the storage, see GameCtx::save.
+    fn to_stored(&self) -> StoredGame {
+        let mut session_tokens = Vec::new();
+        for side in [self.player_pri_side, self.player_pri_side.opposite()] {
+            if let Some(token) = self.session_token(side) {
+                session_tokens.push((side, token));
+            }
+        }
+
+        StoredGame {
+            game_state: self.game_state,
+            board: self.game.get_board().clone(),
+            moves: self.moves.clone(),
+            session_tokens,
+        }
+    }
+
+    /// Resumes the stored game, with the only player who has just joined; if
+    /// the player has the session token of either seat, it gets that seat.
+    fn restore(&mut self, stored: StoredGame, session_token: Option<&str>) {
+        let seat = stored
+            .session_tokens
+            .into_iter()
+            .find(|(_, token)| Some(token.as_str()) == session_token);
+        if let (Some((side, token)), Some(player)) = (seat, self.player_pri.as_mut()) {
+            player.session_token = token;
+            self.player_pri_side = side;
+        }
+
+        self.game.reset_board(&stored.board);
+        self.game_state = stored.game_state;
+        self.moves = stored.moves;
+    }
+
+    /// Player whose seat has the given session token, if any.
+    fn seat_by_token(&mut self, token: &str) -> Option<&mut Player> {
+        [&mut self.player_pri, &mut self.player_sec]
+            .into_iter()
+            .flatten()
+            .find(|p| p.session_token == token)
+    }
+
     /// Number of players currently joined the game. Can either be 1 or 2.
     fn num_players(&self) -> usize {
         let mut ret = 0;
@@ -312,3 +756,233 @@ impl GameData {
         self.player_sec = None;
     }
 }
+
+/// Generates a new random session token, see WSGameReset::session_token.
+fn new_session_token() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_game_state() -> WSFullGameState {
+        WSFullGameState {
+            game_state: GameState::WaitingFor(game::Side::White, game::Scores::default()),
+            ws_player_side: game::Side::Black,
+            board: game::BoardState::new(),
+        }
+    }
+
+    /// Joins the game as the given player, and returns the game along with
+    /// the player's receiver.
+    async fn join(
+        r: &Registry,
+        game_id: &str,
+        player_id: &str,
+        session_token: Option<&str>,
+    ) -> Result<(Arc<GameCtx>, mpsc::Receiver<PlayerToPlayer>)> {
+        let (tx, rx) = mpsc::channel(16);
+        let name = format!("{} name", player_id);
+        let gc = r
+            .join_or_create_game(
+                game_id,
+                player_id,
+                &name,
+                session_token,
+                tx,
+                new_game_state(),
+            )
+            .await?;
+
+        Ok((gc, rx))
+    }
+
+    /// Receives the OpponentIsHere message, and returns the side and the
+    /// opponent name from it.
+    async fn opponent_is_here(rx: &mut mpsc::Receiver<PlayerToPlayer>) -> (game::Side, String) {
+        match rx.recv().await {
+            Some(PlayerToPlayer::OpponentIsHere(gsr)) => (gsr.my_side, gsr.opponent_name),
+            other => panic!("expected OpponentIsHere, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn pairs_two_players_and_refuses_the_third() {
+        let r = Registry::new();
+        let (gc1, mut rx1) = join(&r, "game", "p1", None).await.unwrap();
+        assert!(rx1.try_recv().is_err());
+
+        let (gc2, mut rx2) = join(&r, "game", "p2", None).await.unwrap();
+        assert!(Arc::ptr_eq(&gc1, &gc2));
+
+        let (side1, name1) = opponent_is_here(&mut rx1).await;
+        let (side2, name2) = opponent_is_here(&mut rx2).await;
+        assert_eq!(side1, side2.opposite());
+        assert_eq!(name1, "p2 name");
+        assert_eq!(name2, "p1 name");
+
+        assert!(join(&r, "game", "p3", None).await.is_err());
+        assert!(join(&r, "game", "p3", Some("bogus")).await.is_err());
+
+        // Other games are unaffected.
+        let (gc3, _rx3) = join(&r, "other game", "p3", None).await.unwrap();
+        assert!(!Arc::ptr_eq(&gc1, &gc3));
+    }
+
+    #[tokio::test]
+    async fn takes_the_seat_over_with_the_session_token() {
+        let r = Registry::new();
+        let (gc, mut rx1) = join(&r, "game", "p1", None).await.unwrap();
+        let (_, mut rx2) = join(&r, "game", "p2", None).await.unwrap();
+        let (side1, _) = opponent_is_here(&mut rx1).await;
+        opponent_is_here(&mut rx2).await;
+
+        // The token of p1's seat is the one sent in its game reset, see
+        // game_reset in main.rs.
+        let token = gc.data.lock().await.session_token(side1).unwrap();
+        let (_, mut rx3) = join(&r, "game", "p3", Some(&token)).await.unwrap();
+
+        assert!(matches!(rx1.recv().await, Some(PlayerToPlayer::Replaced)));
+        let (side3, name3) = opponent_is_here(&mut rx3).await;
+        assert_eq!(side3, side1);
+        assert_eq!(name3, "p2 name");
+        let (_, name2) = opponent_is_here(&mut rx2).await;
+        assert_eq!(name2, "p3 name");
+
+        // The old connection going away doesn't affect the game.
+        r.leave_game("game", "p1").await;
+        assert!(rx2.try_recv().is_err());
+        assert!(r.watch_game("game").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn keeps_the_game_until_the_last_player_leaves() {
+        let r = Registry::new();
+        let (_, mut rx1) = join(&r, "game", "p1", None).await.unwrap();
+        let (_, mut rx2) = join(&r, "game", "p2", None).await.unwrap();
+        opponent_is_here(&mut rx1).await;
+        let (side2, _) = opponent_is_here(&mut rx2).await;
+        let (_, mut spectator) = r.watch_game("game").await.unwrap();
+
+        r.leave_game("game", "p1").await;
+        assert!(matches!(
+            rx2.recv().await,
+            Some(PlayerToPlayer::OpponentIsGone)
+        ));
+
+        // The game waits for the second player again, and whoever joins
+        // takes p1's side.
+        let open = r.open_games().await;
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].game_id, "game");
+        assert_eq!(open[0].host_name, "p2 name");
+
+        let (_, mut rx3) = join(&r, "game", "p3", None).await.unwrap();
+        let (side3, _) = opponent_is_here(&mut rx3).await;
+        assert_eq!(side3, side2.opposite());
+        opponent_is_here(&mut rx2).await;
+
+        r.leave_game("game", "p2").await;
+        r.leave_game("game", "p3").await;
+        assert!(r.watch_game("game").await.is_err());
+        assert!(r.open_games().await.is_empty());
+
+        // The spectator is told to go away.
+        while spectator.recv().await.is_some() {}
+    }
+
+    #[tokio::test]
+    async fn lists_the_open_games() {
+        let r = Registry::new();
+        let _p1 = join(&r, "open", "p1", None).await.unwrap();
+        let _p2 = join(&r, "full", "p2", None).await.unwrap();
+        let _p3 = join(&r, "full", "p3", None).await.unwrap();
+
+        let (gc, _p4) = join(&r, "over", "p4", None).await.unwrap();
+        gc.data.lock().await.game_state =
+            GameState::WonBy(game::Side::White, game::Scores::default());
+
+        let open = r.open_games().await;
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].game_id, "open");
+        assert_eq!(open[0].host_name, "p1 name");
+    }
+
+    #[tokio::test]
+    async fn spectators_receive_chat() {
+        let r = Registry::new();
+        let _p1 = join(&r, "game", "p1", None).await.unwrap();
+        assert!(r.watch_game("no such game").await.is_err());
+
+        let (gc, mut spectator1) = r.watch_game("game").await.unwrap();
+        let (_, mut spectator2) = r.watch_game("game").await.unwrap();
+
+        gc.data.lock().await.tell_spectators(ToSpectator::Chat {
+            from: "p1 name".to_string(),
+            text: "hi".to_string(),
+        });
+
+        for spectator in [&mut spectator1, &mut spectator2] {
+            match spectator.recv().await {
+                Some(ToSpectator::Chat { from, text }) => {
+                    assert_eq!(from, "p1 name");
+                    assert_eq!(text, "hi");
+                }
+                other => panic!("expected Chat, got {:?}", other),
+            }
+        }
+
+        // A spectator which is gone is forgotten, and the rest still get it.
+        drop(spectator1);
+        let mut gd = gc.data.lock().await;
+        gd.tell_spectators(ToSpectator::PutToken("A1".parse().unwrap()));
+        assert_eq!(gd.spectators.len(), 1);
+        drop(gd);
+        assert!(matches!(
+            spectator2.recv().await,
+            Some(ToSpectator::PutToken(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn lagging_spectators_get_the_game_reset() {
+        let r = Registry::new();
+        let _p1 = join(&r, "game", "p1", None).await.unwrap();
+        let (gc, mut spectator) = r.watch_game("game").await.unwrap();
+
+        // The channel holds 8 messages, so the 9th one is missed.
+        let mut gd = gc.data.lock().await;
+        for _ in 0..9 {
+            gd.tell_spectators(ToSpectator::PutToken("A1".parse().unwrap()));
+        }
+        drop(gd);
+        for _ in 0..8 {
+            assert!(matches!(
+                spectator.recv().await,
+                Some(ToSpectator::PutToken(_))
+            ));
+        }
+        assert!(spectator.try_recv().is_err());
+
+        // The chat comes after the missed move, so the reset comes instead.
+        gc.data.lock().await.tell_spectators(ToSpectator::Chat {
+            from: String::new(),
+            text: "hi".to_string(),
+        });
+        assert!(matches!(
+            spectator.recv().await,
+            Some(ToSpectator::GameReset)
+        ));
+
+        gc.data.lock().await.tell_spectators(ToSpectator::Chat {
+            from: String::new(),
+            text: "hi again".to_string(),
+        });
+        assert!(matches!(
+            spectator.recv().await,
+            Some(ToSpectator::Chat { .. })
+        ));
+    }
+}
@@ -0,0 +1,233 @@
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use tokio::sync::oneshot;
+
+use connectfour::game;
+use connectfour::game::GameState;
+
+/// Games which haven't changed for this long are not resumed anymore: most
+/// likely, the players are long gone, and whoever comes with the same game ID
+/// wants a new game.
+const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// SQLite database to keep the games in, so that they survive a server
+/// restart, see Registry::set_storage. Every game is a single row with its
+/// data in JSON, saved after every change, and deleted once the game is
+/// destroyed.
+///
+/// The queries are blocking, so they're made one by one in a separate thread,
+/// and the connection tasks only queue them. Being queued in order, a load
+/// always sees the saves and deletes made before it.
+pub struct Storage {
+    to_thread: mpsc::Sender<Query>,
+}
+
+/// Query for the storage thread, see Storage.
+enum Query {
+    Save(String, StoredGame),
+    Load(String, oneshot::Sender<Result<Option<StoredGame>>>),
+    Delete(String),
+}
+
+/// Game as it's stored, see Storage.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct StoredGame {
+    pub game_state: GameState,
+    pub board: game::BoardState,
+    /// See GameData::moves.
+    pub moves: Vec<(game::Side, game::PoleCoords)>,
+    /// Session tokens of the seats, each with the side of its WS player, so
+    /// that after a restart the player who presents a token gets the same side
+    /// as before; see GameData::session_token.
+    pub session_tokens: Vec<(game::Side, String)>,
+}
+
+impl Storage {
+    /// Opens the database at the given path, creating it if needed, and
+    /// deletes the games which are too old to be resumed (see MAX_AGE).
+    pub fn open(path: &Path) -> Result<Storage> {
+        let conn = Connection::open(path)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS games (
+                id TEXT PRIMARY KEY,
+                data TEXT NOT NULL,
+                updated_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        let expired = conn.execute(
+            "DELETE FROM games WHERE updated_at < ?1",
+            params![oldest_updated_at()],
+        )?;
+        if expired > 0 {
+            println!("Deleted {} expired games", expired);
+        }
+
+        let (to_thread, from_tasks) = mpsc::channel::<Query>();
+        thread::spawn(move || {
+            for query in from_tasks {
+                match query {
+                    Query::Save(game_id, game) => {
+                        if let Err(err) = save_game(&conn, &game_id, &game) {
+                            println!("game {}: failed to save: {:#}", game_id, err);
+                        }
+                    }
+                    Query::Load(game_id, reply) => {
+                        let _ = reply.send(load_game(&conn, &game_id));
+                    }
+                    Query::Delete(game_id) => {
+                        if let Err(err) = delete_game(&conn, &game_id) {
+                            println!("game {}: failed to delete: {:#}", game_id, err);
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Storage { to_thread })
+    }
+
+    /// Queues the game with the given ID to be saved, replacing the previous
+    /// version. Failing to save it doesn't stop the game, so the errors are
+    /// only printed.
+    pub fn save(&self, game_id: &str, game: StoredGame) {
+        // The thread only quits along with the Storage.
+        let _ = self.to_thread.send(Query::Save(game_id.to_string(), game));
+    }
+
+    /// Loads the game with the given ID, if it's there and not too old (see
+    /// MAX_AGE).
+    pub async fn load(&self, game_id: &str) -> Result<Option<StoredGame>> {
+        let (reply, res) = oneshot::channel();
+        self.to_thread
+            .send(Query::Load(game_id.to_string(), reply))
+            .map_err(|_| anyhow!("the storage thread is gone"))?;
+
+        res.await?
+    }
+
+    /// Queues the game with the given ID to be deleted, if it's there. The
+    /// errors are only printed, just like for save.
+    pub fn delete(&self, game_id: &str) {
+        let _ = self.to_thread.send(Query::Delete(game_id.to_string()));
+    }
+}
+
+/// Makes the query for Storage::save, in the storage thread.
+fn save_game(conn: &Connection, game_id: &str, game: &StoredGame) -> Result<()> {
+    let data = serde_json::to_string(game)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO games (id, data, updated_at) VALUES (?1, ?2, ?3)",
+        params![game_id, data, unix_time()],
+    )?;
+
+    Ok(())
+}
+
+/// Makes the query for Storage::load, in the storage thread.
+fn load_game(conn: &Connection, game_id: &str) -> Result<Option<StoredGame>> {
+    let data: Option<String> = conn
+        .query_row(
+            "SELECT data FROM games WHERE id = ?1 AND updated_at >= ?2",
+            params![game_id, oldest_updated_at()],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    match data {
+        Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+        None => Ok(None),
+    }
+}
+
+/// Makes the query for Storage::delete, in the storage thread.
+fn delete_game(conn: &Connection, game_id: &str) -> Result<()> {
+    conn.execute("DELETE FROM games WHERE id = ?1", params![game_id])?;
+
+    Ok(())
+}
+
+impl StoredGame {
+    /// Whether the game is over, see GameData::is_over.
+    pub fn is_over(&self) -> bool {
+        let mut g = game::Game::new();
+        g.reset_board(&self.board);
+
+        g.get_win_row().is_some()
+            || matches!(self.game_state, GameState::WonBy(_, _) | GameState::Draw(_))
+    }
+}
+
+/// Oldest update time of the games which can still be resumed, see MAX_AGE.
+fn oldest_updated_at() -> i64 {
+    unix_time() - MAX_AGE.as_secs() as i64
+}
+
+/// Current time as the number of seconds since the Unix epoch.
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored_game() -> StoredGame {
+        StoredGame {
+            game_state: GameState::WaitingFor(game::Side::Black, game::Scores::default()),
+            board: game::BoardState::new(),
+            moves: vec![(game::Side::White, "A1".parse().unwrap())],
+            session_tokens: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn loads_what_was_queued_before() {
+        let storage = Storage::open(Path::new(":memory:")).unwrap();
+        assert!(storage.load("g").await.unwrap().is_none());
+
+        storage.save("g", stored_game());
+        let loaded = storage.load("g").await.unwrap().unwrap();
+        assert_eq!(loaded.moves, stored_game().moves);
+
+        storage.delete("g");
+        assert!(storage.load("g").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn deletes_the_expired_games_on_open() {
+        let path = std::env::temp_dir().join(format!("connectfour-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let storage = Storage::open(&path).unwrap();
+        storage.save("old", stored_game());
+        storage.save("new", stored_game());
+        // Also waits for the saves.
+        assert!(storage.load("old").await.unwrap().is_some());
+        drop(storage);
+
+        let conn = Connection::open(&path).unwrap();
+        conn.execute("UPDATE games SET updated_at = 0 WHERE id = 'old'", [])
+            .unwrap();
+
+        let _storage = Storage::open(&path).unwrap();
+        let ids: Vec<String> = conn
+            .prepare("SELECT id FROM games")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(ids, ["new"]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
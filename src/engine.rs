@@ -0,0 +1,133 @@
+pub mod mcts;
+pub mod minimax;
+pub mod review;
+pub mod solver;
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+
+use crate::game::eval::Style;
+use crate::game::{Game, PoleCoords, Side, ROW_SIZE};
+
+/// Depth which is enough to search any position till the end of the game: the
+/// number of cells on the board.
+pub const MAX_DEPTH: usize = ROW_SIZE * ROW_SIZE * ROW_SIZE;
+
+/// Minimax score which counts as a moderate advantage, see
+/// EngineKind::advantage. That's about two blunders, see
+/// EngineKind::blunder_threshold.
+const ADVANTAGE_SCALE: i32 = 100;
+
+/// Search engine which picks the moves for the AI.
+pub trait Engine: Send + Sync {
+    /// Finds the best move of the given side within the given limits. Returns
+    /// None if there are no legal moves.
+    fn best_move(&self, game: &Game, side: Side, limits: &SearchLimits) -> Option<SearchResult>;
+
+    /// Scores every legal move of the given side within the given limits, on
+    /// the same scale as best_move, in the order of Game::legal_moves. If the
+    /// deadline passes before some moves are scored, they are left out.
+    fn analyze(&self, game: &Game, side: Side, limits: &SearchLimits) -> Vec<MoveScore>;
+}
+
+/// Limits of a single search.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchLimits {
+    /// How deep to search; every engine interprets it in its own way, but the
+    /// deeper, the stronger (and the slower) it is.
+    pub depth: usize,
+    /// If given, the engine has to return its best guess once the deadline
+    /// passes.
+    pub deadline: Option<Instant>,
+    /// How the engine evaluates the positions, see eval::evaluate_styled.
+    /// MCTS doesn't evaluate positions at all, so it ignores the style.
+    pub style: Style,
+}
+
+/// Result of the search: the best move found, and its score from the point of
+/// view of the side which makes the move. The scale of the score depends on
+/// the engine, but it's always positive when the side is winning.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct SearchResult {
+    pub best_move: PoleCoords,
+    pub score: i32,
+}
+
+/// Score of a single move, see Engine::analyze; just like in SearchResult, it's
+/// from the point of view of the side which makes the move.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct MoveScore {
+    pub pcoords: PoleCoords,
+    pub score: i32,
+}
+
+/// Kind of the engine, to select it e.g. from the command line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineKind {
+    /// Minimax with alpha-beta pruning, see minimax::Minimax.
+    #[default]
+    Minimax,
+    /// Monte Carlo Tree Search, see mcts::Mcts.
+    Mcts,
+}
+
+impl EngineKind {
+    /// Create a new engine of this kind, which searches using the given
+    /// number of threads (0 is the same as 1).
+    pub fn new_engine(&self, threads: usize) -> Arc<dyn Engine> {
+        let threads = threads.max(1);
+        match self {
+            EngineKind::Minimax => Arc::new(minimax::Minimax::new(threads)),
+            EngineKind::Mcts => Arc::new(mcts::Mcts::new(threads)),
+        }
+    }
+
+    /// How much worse than the best one a move has to be scored by this
+    /// engine to count as a blunder, see review::review. The engines have
+    /// different scales: for minimax, it's about a few rows which are about
+    /// to be completed, and for MCTS, it's 15% of the win rate.
+    pub fn blunder_threshold(&self) -> i32 {
+        match self {
+            EngineKind::Minimax => 50,
+            EngineKind::Mcts => mcts::SCORE_SCALE * 3 / 10,
+        }
+    }
+
+    /// Turns the score of this engine into the advantage of the side, from
+    /// -1.0 (sure loss) to 1.0 (sure win), to show it to humans, e.g. as an
+    /// evaluation bar. For minimax, the heuristic scores are unbounded, so
+    /// they are squashed: a score of ADVANTAGE_SCALE is an advantage of 0.5.
+    pub fn advantage(&self, score: i32) -> f64 {
+        match self {
+            EngineKind::Minimax if minimax::is_decisive(score) => score.signum() as f64,
+            EngineKind::Minimax => score as f64 / (score.abs() + ADVANTAGE_SCALE) as f64,
+            EngineKind::Mcts => (score as f64 / mcts::SCORE_SCALE as f64).clamp(-1.0, 1.0),
+        }
+    }
+}
+
+impl FromStr for EngineKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "minimax" => Ok(EngineKind::Minimax),
+            "mcts" => Ok(EngineKind::Mcts),
+            _ => Err(anyhow!("invalid engine {:?}; try 'minimax' or 'mcts'", s)),
+        }
+    }
+}
+
+impl fmt::Display for EngineKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineKind::Minimax => write!(f, "minimax"),
+            EngineKind::Mcts => write!(f, "mcts"),
+        }
+    }
+}
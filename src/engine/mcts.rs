@@ -0,0 +1,314 @@
+use std::thread;
+use std::time::Instant;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use super::{Engine, MoveScore, SearchLimits, SearchResult};
+use crate::game::{Game, PoleCoords, Side};
+
+/// How many playouts to run per unit of SearchLimits::depth.
+pub const PLAYOUTS_PER_DEPTH: usize = 2000;
+
+/// Exploration constant of the UCT formula.
+const EXPLORATION: f64 = std::f64::consts::SQRT_2;
+
+/// Scale of the score returned by Mcts: a sure win is SCORE_SCALE, a sure loss
+/// is -SCORE_SCALE.
+pub const SCORE_SCALE: i32 = 1000;
+
+/// Monte Carlo Tree Search engine: instead of evaluating positions with a
+/// heuristic, it plays lots of random games (playouts), and concentrates on the
+/// moves which win most often. It doesn't need a good evaluation function, and
+/// copes with large branching factors better than minimax.
+///
+/// The number of playouts is SearchLimits::depth times PLAYOUTS_PER_DEPTH. The
+/// returned score is the win rate of the move, scaled to
+/// -SCORE_SCALE..SCORE_SCALE.
+///
+/// With more than one thread, every thread grows its own tree, with the full
+/// number of playouts, and then the statistics of the root moves are summed up
+/// (so called root parallelization).
+pub struct Mcts {
+    threads: usize,
+}
+
+impl Mcts {
+    /// Create a new engine, which searches using the given number of threads.
+    pub fn new(threads: usize) -> Mcts {
+        Mcts { threads }
+    }
+
+    /// Grows a tree in every thread, and returns the merged one, see
+    /// Tree::merge_roots.
+    fn search(&self, game: &Game, side: Side, limits: &SearchLimits) -> Tree {
+        if self.threads <= 1 {
+            return Tree::search(game, side, limits);
+        }
+
+        let trees = thread::scope(|s| {
+            let handles: Vec<_> = (0..self.threads)
+                .map(|_| s.spawn(|| Tree::search(game, side, limits)))
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        Tree::merge_roots(game, side, trees)
+    }
+}
+
+impl Engine for Mcts {
+    fn best_move(&self, game: &Game, side: Side, limits: &SearchLimits) -> Option<SearchResult> {
+        self.search(game, side, limits).best_root_move()
+    }
+
+    fn analyze(&self, game: &Game, side: Side, limits: &SearchLimits) -> Vec<MoveScore> {
+        let tree = self.search(game, side, limits);
+        let scores = tree.root_move_scores();
+
+        // Keep the order of Game::legal_moves, rather than the order in which
+        // the moves were expanded.
+        game.legal_moves()
+            .into_iter()
+            .filter_map(|pcoords| scores.iter().find(|s| s.pcoords == pcoords).copied())
+            .collect()
+    }
+}
+
+/// A node of the search tree: a position after some move.
+struct Node {
+    /// Parent node index, None for the root.
+    parent: Option<usize>,
+    /// The move which led to this node (made by the opposite of
+    /// side_to_move); None for the root.
+    pcoords: Option<PoleCoords>,
+    /// Side which has the move in this node.
+    side_to_move: Side,
+
+    /// Children node indices.
+    children: Vec<usize>,
+    /// Moves which don't have child nodes yet.
+    untried: Vec<PoleCoords>,
+
+    /// Number of playouts through this node, and the sum of their results from
+    /// the point of view of the side which made the move leading here: 1.0 for
+    /// a win, 0.5 for a draw, 0.0 for a loss.
+    visits: u32,
+    wins: f64,
+}
+
+/// Search tree, with nodes stored in a vec; the root is always at index 0.
+struct Tree {
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    /// Runs as many playouts as the limits allow, and returns the resulting
+    /// tree.
+    fn search(game: &Game, side: Side, limits: &SearchLimits) -> Tree {
+        let mut tree = Tree::new(game, side);
+        if tree.nodes[0].untried.is_empty() {
+            return tree;
+        }
+
+        let num_playouts = limits.depth.max(1) * PLAYOUTS_PER_DEPTH;
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..num_playouts {
+            if limits.deadline.is_some_and(|d| Instant::now() >= d) {
+                break;
+            }
+
+            tree.run_iteration(game, &mut rng);
+        }
+
+        tree
+    }
+
+    /// Returns a tree with just the root and its children, whose statistics
+    /// are summed up over the roots of the given trees. It's only good for
+    /// picking the root move.
+    fn merge_roots(game: &Game, side: Side, trees: Vec<Tree>) -> Tree {
+        let mut merged = Tree::new(game, side);
+
+        for tree in trees {
+            for &i in &tree.nodes[0].children {
+                let node = &tree.nodes[i];
+                let existing = merged.nodes[0]
+                    .children
+                    .iter()
+                    .copied()
+                    .find(|&j| merged.nodes[j].pcoords == node.pcoords);
+
+                let idx = match existing {
+                    Some(j) => j,
+                    None => {
+                        let j = merged.nodes.len();
+                        merged.nodes.push(Node {
+                            parent: Some(0),
+                            pcoords: node.pcoords,
+                            side_to_move: node.side_to_move,
+                            children: Vec::new(),
+                            untried: Vec::new(),
+                            visits: 0,
+                            wins: 0.0,
+                        });
+                        merged.nodes[0].children.push(j);
+                        j
+                    }
+                };
+
+                merged.nodes[idx].visits += node.visits;
+                merged.nodes[idx].wins += node.wins;
+            }
+
+            merged.nodes[0].visits += tree.nodes[0].visits;
+            merged.nodes[0].wins += tree.nodes[0].wins;
+        }
+
+        merged
+    }
+
+    fn new(game: &Game, side: Side) -> Tree {
+        Tree {
+            nodes: vec![Node {
+                parent: None,
+                pcoords: None,
+                side_to_move: side,
+                children: Vec::new(),
+                untried: game.legal_moves(),
+                visits: 0,
+                wins: 0.0,
+            }],
+        }
+    }
+
+    /// A single iteration of MCTS: select a node to expand, expand it, play a
+    /// random game from there, and propagate the result back up the tree.
+    fn run_iteration(&mut self, root_game: &Game, rng: &mut impl Rng) {
+        let mut game = root_game.clone();
+
+        // Selection: descend while the nodes are fully expanded.
+        let mut idx = 0;
+        while self.nodes[idx].untried.is_empty() && !self.nodes[idx].children.is_empty() {
+            idx = self.select_child(idx);
+            let node = &self.nodes[idx];
+            game.put_token(node.side_to_move.opposite(), node.pcoords.unwrap())
+                .unwrap();
+        }
+
+        // Expansion: add a child for one of the untried moves.
+        if !self.nodes[idx].untried.is_empty() {
+            let i = rng.gen_range(0..self.nodes[idx].untried.len());
+            let pcoords = self.nodes[idx].untried.swap_remove(i);
+            let side = self.nodes[idx].side_to_move;
+            game.put_token(side, pcoords).unwrap();
+
+            let child_idx = self.nodes.len();
+            self.nodes.push(Node {
+                parent: Some(idx),
+                pcoords: Some(pcoords),
+                side_to_move: side.opposite(),
+                children: Vec::new(),
+                untried: game.legal_moves(),
+                visits: 0,
+                wins: 0.0,
+            });
+            self.nodes[idx].children.push(child_idx);
+            idx = child_idx;
+        }
+
+        // Simulation.
+        let winner = playout(&mut game, self.nodes[idx].side_to_move, rng);
+
+        // Backpropagation.
+        let mut cur = Some(idx);
+        while let Some(i) = cur {
+            let node = &mut self.nodes[i];
+            node.visits += 1;
+            node.wins += match winner {
+                Some(side) if side == node.side_to_move.opposite() => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            };
+            cur = node.parent;
+        }
+    }
+
+    /// Returns the child of the given node with the highest UCT value.
+    fn select_child(&self, idx: usize) -> usize {
+        let ln_visits = (self.nodes[idx].visits as f64).ln();
+
+        let uct = |child_idx: usize| {
+            let child = &self.nodes[child_idx];
+            let visits = child.visits as f64;
+            child.wins / visits + EXPLORATION * (ln_visits / visits).sqrt()
+        };
+
+        *self.nodes[idx]
+            .children
+            .iter()
+            .max_by(|&&a, &&b| uct(a).total_cmp(&uct(b)))
+            .unwrap()
+    }
+
+    /// Returns the most visited move of the root, which is the most reliable
+    /// one.
+    fn best_root_move(&self) -> Option<SearchResult> {
+        let best = self.nodes[0]
+            .children
+            .iter()
+            .map(|&i| &self.nodes[i])
+            .max_by_key(|node| node.visits)?;
+
+        Some(SearchResult {
+            best_move: best.pcoords.unwrap(),
+            score: best.score(),
+        })
+    }
+
+    /// Returns the scores of all the moves of the root which were tried.
+    fn root_move_scores(&self) -> Vec<MoveScore> {
+        self.nodes[0]
+            .children
+            .iter()
+            .map(|&i| &self.nodes[i])
+            .map(|node| MoveScore {
+                pcoords: node.pcoords.unwrap(),
+                score: node.score(),
+            })
+            .collect()
+    }
+}
+
+impl Node {
+    /// Win rate of the move leading to this node, scaled to
+    /// -SCORE_SCALE..SCORE_SCALE.
+    fn score(&self) -> i32 {
+        let win_rate = self.wins / self.visits as f64;
+
+        ((win_rate * 2.0 - 1.0) * SCORE_SCALE as f64) as i32
+    }
+}
+
+/// Plays random moves until the game is over, starting with the given side,
+/// and returns the winner (None for a draw).
+fn playout(game: &mut Game, mut side: Side, rng: &mut impl Rng) -> Option<Side> {
+    loop {
+        if let Some(winner) = game.winner() {
+            return Some(winner);
+        }
+
+        let pcoords = match game.legal_moves().choose(rng) {
+            Some(&pcoords) => pcoords,
+            None => return None,
+        };
+
+        game.put_token(side, pcoords).unwrap();
+        side = side.opposite();
+    }
+}
@@ -0,0 +1,408 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use super::{Engine, MoveScore, SearchLimits, SearchResult, MAX_DEPTH};
+use crate::game::eval::{self, Style};
+use crate::game::{analysis, Game, PoleCoords, Side};
+
+thread_local! {
+    /// Number of positions visited by negamax on this thread, see
+    /// nodes_searched.
+    static NODES: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Returns the number of positions visited by the search on the current
+/// thread so far. It's only meant for benchmarks: the searches with more than
+/// one thread are only partly counted, since the other threads have their own
+/// counters.
+pub fn nodes_searched() -> u64 {
+    NODES.with(|n| n.get())
+}
+
+/// Minimax engine, see search. The score it returns is on the scale of
+/// eval::evaluate.
+pub struct Minimax {
+    threads: usize,
+}
+
+impl Minimax {
+    /// Create a new engine, which searches using the given number of threads,
+    /// see search.
+    pub fn new(threads: usize) -> Minimax {
+        Minimax { threads }
+    }
+}
+
+impl Engine for Minimax {
+    fn best_move(&self, game: &Game, side: Side, limits: &SearchLimits) -> Option<SearchResult> {
+        search(
+            game,
+            side,
+            limits.depth,
+            limits.deadline,
+            limits.style,
+            self.threads,
+        )
+    }
+
+    fn analyze(&self, game: &Game, side: Side, limits: &SearchLimits) -> Vec<MoveScore> {
+        analyze(game, side, limits.depth, limits.deadline, limits.style)
+    }
+}
+
+/// Searches for the best move of the given side using minimax with alpha-beta
+/// pruning, looking up to the given number of moves ahead (both sides' moves
+/// count; depth must be at least 1), and using eval::evaluate_styled with the
+/// given style for the positions at the end. Returns None if there are no
+/// legal moves.
+///
+/// Wins are scored as eval::WIN_SCORE minus the number of moves needed, so
+/// that faster wins (and slower losses) are preferred.
+///
+/// The search is iterative deepening: it searches 1 move ahead, then 2, and so
+/// on, every time starting from the best move of the previous iteration. So if
+/// the deadline is given and it passes before the search is done, there is
+/// still a reasonable move to return: the best one of the last iteration (or,
+/// if even the first iteration didn't finish, the first move according to
+/// analysis::order_moves). This way, with a generous depth, the search just
+/// goes as deep as it can within the time given.
+///
+/// With more than one thread, the moves of every iteration are split between
+/// the threads, see search_depth.
+pub fn search(
+    game: &Game,
+    side: Side,
+    depth: usize,
+    deadline: Option<Instant>,
+    style: Style,
+    threads: usize,
+) -> Option<SearchResult> {
+    // There's no point searching deeper than the number of moves left.
+    let board = game.get_board();
+    let moves_left = MAX_DEPTH - board.count(Side::White) - board.count(Side::Black);
+    let depth = depth.min(moves_left).max(1);
+
+    let mut best: Option<SearchResult> = None;
+
+    for cur_depth in 1..=depth {
+        if let Some(res) = search_depth(game, side, cur_depth, deadline, style, best, threads) {
+            best = Some(res);
+        }
+
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            break;
+        }
+
+        // If it's a forced win or loss, searching deeper won't change it.
+        if best.is_some_and(|b| is_decisive(b.score)) {
+            break;
+        }
+    }
+
+    best.or_else(|| {
+        analysis::order_moves(game, side)
+            .first()
+            .map(|&best_move| SearchResult {
+                best_move,
+                score: 0,
+            })
+    })
+}
+
+/// Scores every legal move of the given side, looking up to the given number
+/// of moves ahead, including the move itself (so depth must be at least 1).
+/// Unlike search, it doesn't prune the moves which are worse than the best
+/// one, so every score is exact (for that depth), but it's much slower. If the
+/// deadline passes, the moves which haven't been scored yet are left out.
+pub fn analyze(
+    game: &Game,
+    side: Side,
+    depth: usize,
+    deadline: Option<Instant>,
+    style: Style,
+) -> Vec<MoveScore> {
+    let mut scores = Vec::new();
+    let depth = depth.max(1);
+
+    for pcoords in game.legal_moves() {
+        let alpha = -eval::WIN_SCORE - 1;
+        let beta = eval::WIN_SCORE + 1;
+        let score = match search_move(game, side, pcoords, depth, alpha, beta, deadline, style) {
+            Some(score) => score,
+            None => break,
+        };
+
+        scores.push(MoveScore { pcoords, score });
+    }
+
+    scores
+}
+
+/// A single iteration of search, at the given depth. The best move of the
+/// previous iteration, if any, is searched first; so if the deadline passes in
+/// the middle of the iteration, the best move among the ones searched so far
+/// is at least as good as the previous one, and is returned. If not even the
+/// first move has been fully searched, returns None.
+///
+/// The first move is always searched alone, since it's most likely the best
+/// one, and its score makes a narrow window for the rest; then the rest of the
+/// moves are split between the given number of threads, which share the best
+/// score found so far. With a single thread, it's a plain alpha-beta search of
+/// the root.
+fn search_depth(
+    game: &Game,
+    side: Side,
+    depth: usize,
+    deadline: Option<Instant>,
+    style: Style,
+    prev_best: Option<SearchResult>,
+    threads: usize,
+) -> Option<SearchResult> {
+    let mut moves = analysis::order_moves(game, side);
+    if let Some(prev_best) = prev_best {
+        if let Some(i) = moves.iter().position(|&m| m == prev_best.best_move) {
+            let m = moves.remove(i);
+            moves.insert(0, m);
+        }
+    }
+
+    let beta = eval::WIN_SCORE + 1;
+
+    let (&first, rest) = moves.split_first()?;
+    let first_score = search_move(
+        game,
+        side,
+        first,
+        depth,
+        -eval::WIN_SCORE - 1,
+        beta,
+        deadline,
+        style,
+    )?;
+
+    // Best move so far, with its index in moves, so that among the moves with
+    // equal scores, the first one wins regardless of which thread finishes
+    // first.
+    let best = Mutex::new((
+        0,
+        SearchResult {
+            best_move: first,
+            score: first_score,
+        },
+    ));
+    let alpha = AtomicI32::new(first_score);
+    let next = AtomicUsize::new(0);
+    let timed_out = AtomicBool::new(false);
+
+    let worker = || {
+        while !timed_out.load(Ordering::Relaxed) {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            let pcoords = match rest.get(i) {
+                Some(&pcoords) => pcoords,
+                None => break,
+            };
+
+            // The window starts just below the best score so far, so that a
+            // move which only ties it still gets its exact score, rather than
+            // a bound which merely says it's no better; otherwise the
+            // tie-break below could pick a move which is in fact worse.
+            let a = alpha.load(Ordering::Relaxed) - 1;
+            let score = match search_move(game, side, pcoords, depth, a, beta, deadline, style) {
+                Some(score) => score,
+                None => {
+                    timed_out.store(true, Ordering::Relaxed);
+                    break;
+                }
+            };
+
+            alpha.fetch_max(score, Ordering::Relaxed);
+
+            let mut best = best.lock().unwrap();
+            if score > best.1.score || (score == best.1.score && i + 1 < best.0) {
+                *best = (
+                    i + 1,
+                    SearchResult {
+                        best_move: pcoords,
+                        score,
+                    },
+                );
+            }
+        }
+    };
+
+    // The current thread is one of the workers too.
+    thread::scope(|s| {
+        for _ in 1..threads {
+            s.spawn(worker);
+        }
+        worker();
+    });
+
+    let (_, best) = best.into_inner().unwrap();
+    Some(best)
+}
+
+/// Makes the given move, and returns its score from the point of view of the
+/// side which makes it, given the alpha-beta window; None if the deadline has
+/// passed.
+#[allow(clippy::too_many_arguments)]
+fn search_move(
+    game: &Game,
+    side: Side,
+    pcoords: PoleCoords,
+    depth: usize,
+    alpha: i32,
+    beta: i32,
+    deadline: Option<Instant>,
+    style: Style,
+) -> Option<i32> {
+    let mut g = game.clone();
+    g.put_token(side, pcoords).unwrap();
+
+    let score = negamax(
+        &g,
+        side.opposite(),
+        depth - 1,
+        1,
+        -beta,
+        -alpha,
+        deadline,
+        style,
+    )?;
+    Some(-score)
+}
+
+/// Whether the score means a forced win or loss.
+pub fn is_decisive(score: i32) -> bool {
+    score.abs() > eval::WIN_SCORE - MAX_DEPTH as i32
+}
+
+/// Returns the score of the position from the point of view of the given side,
+/// which has the move. Ply is the number of moves made since the root of the
+/// search; the positions at the end are evaluated the way the side at the
+/// root sees them, in the given style. Returns None if the deadline has
+/// passed.
+#[allow(clippy::too_many_arguments)]
+fn negamax(
+    game: &Game,
+    side: Side,
+    depth: usize,
+    ply: usize,
+    mut alpha: i32,
+    beta: i32,
+    deadline: Option<Instant>,
+    style: Style,
+) -> Option<i32> {
+    NODES.with(|n| n.set(n.get() + 1));
+
+    if let Some(winner) = game.winner() {
+        let score = eval::WIN_SCORE - ply as i32;
+        return Some(if winner == side { score } else { -score });
+    }
+
+    if game.is_draw() {
+        return Some(0);
+    }
+
+    if depth == 0 {
+        let root_side = if ply.is_multiple_of(2) {
+            side
+        } else {
+            side.opposite()
+        };
+        return Some(side_score(
+            side,
+            eval::evaluate_styled(game, style, root_side),
+        ));
+    }
+
+    if deadline.is_some_and(|d| Instant::now() >= d) {
+        return None;
+    }
+
+    let mut best = -eval::WIN_SCORE - 1;
+
+    for pcoords in analysis::order_moves(game, side) {
+        let mut g = game.clone();
+        g.put_token(side, pcoords).unwrap();
+
+        let score = -negamax(
+            &g,
+            side.opposite(),
+            depth - 1,
+            ply + 1,
+            -beta,
+            -alpha,
+            deadline,
+            style,
+        )?;
+
+        best = best.max(score);
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
+    }
+
+    Some(best)
+}
+
+/// Converts a score from White's point of view (like the ones returned by
+/// eval::evaluate) to the given side's point of view.
+pub fn side_score(side: Side, white_score: i32) -> i32 {
+    match side {
+        Side::White => white_score,
+        Side::Black => -white_score,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn picks_the_same_move_with_any_number_of_threads() {
+        // The empty board is symmetric, so lots of moves tie for the best,
+        // and it's down to the tie-break; the random openings (from a fixed
+        // seed) have plenty of ties too.
+        let mut positions = vec![Game::new()];
+        let mut rng = StdRng::seed_from_u64(1);
+        while positions.len() < 20 {
+            let mut game = Game::new();
+            let mut side = Side::White;
+            for _ in 0..6 {
+                let pcoords = *game.legal_moves().choose(&mut rng).unwrap();
+                game.put_token(side, pcoords).unwrap();
+                side = side.opposite();
+            }
+            if game.winner().is_none() {
+                positions.push(game);
+            }
+        }
+
+        // It's White's turn in all of them.
+        for game in &positions {
+            for depth in 1..=3 {
+                let single = search(game, Side::White, depth, None, Style::Balanced, 1).unwrap();
+                for threads in [2, 4, 8] {
+                    let parallel =
+                        search(game, Side::White, depth, None, Style::Balanced, threads).unwrap();
+                    assert_eq!(
+                        (parallel.best_move, parallel.score),
+                        (single.best_move, single.score),
+                        "depth {}, {} threads",
+                        depth,
+                        threads
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::{anyhow, Context, Result};
+
+use super::{Engine, SearchLimits};
+use crate::game::eval::Style;
+use crate::game::record::{Annotation, GameRecord, MoveMark};
+use crate::game::{Game, Side};
+
+/// Reviews a finished game: replays the recorded moves starting from the given
+/// game (normally with an empty board, but e.g. with a handicap, it's not),
+/// and scores every position with the engine at the given depth. Returns the
+/// record with every move annotated with the score of the position after it
+/// (from White's point of view, on the engine's scale, see
+/// Annotation::eval), and the moves which are worse than the best one by more
+/// than blunder_threshold marked as blunders, with the best move in the
+/// comment. Existing annotations are overwritten.
+pub fn review(
+    engine: &dyn Engine,
+    start: &Game,
+    record: &GameRecord,
+    depth: usize,
+    blunder_threshold: i32,
+) -> Result<GameRecord> {
+    let limits = SearchLimits {
+        depth,
+        deadline: None,
+        style: Style::Balanced,
+    };
+
+    let mut game = start.clone();
+    let mut reviewed = record.clone();
+
+    for (i, m) in reviewed.moves.iter_mut().enumerate() {
+        let scores = engine.analyze(&game, m.side, &limits);
+        let played = scores
+            .iter()
+            .find(|s| s.pcoords == m.pole)
+            .ok_or_else(|| anyhow!("move {}: {} is not a legal move", i, m.pole))?;
+        let best = scores.iter().max_by_key(|s| s.score).unwrap();
+
+        let mut annotation = Annotation {
+            eval: Some(match m.side {
+                Side::White => played.score,
+                Side::Black => -played.score,
+            }),
+            ..Annotation::default()
+        };
+        if best.score - played.score > blunder_threshold {
+            annotation.mark = Some(MoveMark::Blunder);
+            annotation.comment = Some(format!("best was {} ({:+})", best.pcoords, best.score));
+        }
+        m.annotation = annotation;
+
+        game.put_token(m.side, m.pole)
+            .with_context(|| format!("move {}", i))?;
+    }
+
+    Ok(reviewed)
+}
@@ -0,0 +1,279 @@
+use std::time::Instant;
+
+use crate::game::{analysis, Game, PoleCoords, Side, ROW_SIZE};
+
+/// Proven outcome of a position for the side to move, with the number of
+/// plies (moves of both sides, including the side's own next move) until the
+/// game is won, given the best play of both sides: the winner wins as fast as
+/// possible, and the loser resists as long as possible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Outcome {
+    Win(usize),
+    Loss(usize),
+    /// The board gets full without anyone winning.
+    Draw,
+}
+
+/// Result of solve: the outcome of the position, and the move which achieves
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Solution {
+    pub outcome: Outcome,
+    pub best_move: PoleCoords,
+}
+
+/// Tries to prove the outcome of the position for the given side, which has
+/// the move, looking at most max_plies ahead: unlike the engines, it doesn't
+/// guess, so it only returns the solution if the game is sure to end within
+/// the horizon given the best play of both sides; e.g. a forced win in 3
+/// plies needs max_plies of at least 3, and a draw can only be proven when
+/// the board gets full within the horizon. Returns None if it can't be proven,
+/// if the deadline passes, or if the game is over already.
+///
+/// It only answers yes/no questions ("can this side force a win within n
+/// plies?"), which lets it stop looking at the moves as soon as the answer is
+/// known; so it's much faster than the minimax search to the same depth.
+pub fn solve(
+    game: &Game,
+    side: Side,
+    max_plies: usize,
+    deadline: Option<Instant>,
+) -> Option<Solution> {
+    if max_plies == 0 || game.winner().is_some() || game.is_draw() {
+        return None;
+    }
+
+    // The shortest forced win, if any.
+    for plies in 1..=max_plies {
+        if let Some(best_move) = winning_move(game, side, plies, deadline)? {
+            return Some(Solution {
+                outcome: Outcome::Win(plies),
+                best_move,
+            });
+        }
+    }
+
+    // Otherwise, check how soon every move loses; if all of them do within the
+    // horizon, it's a loss, and the best move is the one which resists the
+    // longest. A move which doesn't lose is good enough for a draw, as long as
+    // the game can't go on beyond the horizon.
+    let mut longest: Option<(PoleCoords, usize)> = None;
+    for pcoords in analysis::order_moves(game, side) {
+        let mut g = game.clone();
+        let res = g.put_token(side, pcoords).unwrap();
+
+        let loses_in = if res.winner.is_some() {
+            // It can't be our win, that would have been found above.
+            Some(1)
+        } else if res.draw {
+            None
+        } else {
+            shortest_win(&g, side.opposite(), max_plies - 1, deadline)?.map(|p| p + 1)
+        };
+
+        match loses_in {
+            Some(plies) => {
+                if longest.is_none_or(|(_, l)| plies > l) {
+                    longest = Some((pcoords, plies));
+                }
+            }
+            None => {
+                let board = game.get_board();
+                let empty = ROW_SIZE * ROW_SIZE * ROW_SIZE
+                    - board.count(Side::White)
+                    - board.count(Side::Black);
+                if empty > max_plies {
+                    return None;
+                }
+
+                return Some(Solution {
+                    outcome: Outcome::Draw,
+                    best_move: pcoords,
+                });
+            }
+        }
+    }
+
+    longest.map(|(best_move, plies)| Solution {
+        outcome: Outcome::Loss(plies),
+        best_move,
+    })
+}
+
+/// Returns the smallest number of plies, up to max_plies, within which the
+/// given side, which has the move, can force a win; None (inside Some) if it
+/// can't. Returns None if the deadline has passed.
+fn shortest_win(
+    game: &Game,
+    side: Side,
+    max_plies: usize,
+    deadline: Option<Instant>,
+) -> Option<Option<usize>> {
+    for plies in 1..=max_plies {
+        if winning_move(game, side, plies, deadline)?.is_some() {
+            return Some(Some(plies));
+        }
+    }
+
+    Some(None)
+}
+
+/// Returns the move with which the given side, which has the move, forces a
+/// win within the given number of plies, if any (inside Some). Returns None if
+/// the deadline has passed.
+fn winning_move(
+    game: &Game,
+    side: Side,
+    plies: usize,
+    deadline: Option<Instant>,
+) -> Option<Option<PoleCoords>> {
+    if plies == 0 {
+        return Some(None);
+    }
+
+    if deadline.is_some_and(|d| Instant::now() >= d) {
+        return None;
+    }
+
+    for pcoords in analysis::order_moves(game, side) {
+        let mut g = game.clone();
+        let res = g.put_token(side, pcoords).unwrap();
+
+        // Depending on the rules, the move can also lose right away.
+        let wins = match res.winner {
+            Some(winner) => winner == side,
+            None if res.draw => false,
+            None => loses_within(&g, side.opposite(), plies - 1, deadline)?,
+        };
+
+        if wins {
+            return Some(Some(pcoords));
+        }
+    }
+
+    Some(None)
+}
+
+/// Returns whether the given side, which has the move, loses within the given
+/// number of plies whatever it does (inside Some). Returns None if the
+/// deadline has passed.
+fn loses_within(game: &Game, side: Side, plies: usize, deadline: Option<Instant>) -> Option<bool> {
+    if plies == 0 {
+        return Some(false);
+    }
+
+    for pcoords in analysis::order_moves(game, side) {
+        let mut g = game.clone();
+        let res = g.put_token(side, pcoords).unwrap();
+
+        let loses = match res.winner {
+            Some(winner) => winner != side,
+            None if res.draw => false,
+            None => winning_move(&g, side.opposite(), plies - 1, deadline)?.is_some(),
+        };
+
+        if !loses {
+            return Some(false);
+        }
+    }
+
+    Some(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A full game which ends in a draw.
+    const DRAWN_GAME: &str = "C4 A4 A1 C1 D2 D2 C1 A4 C1 D2 B2 A2 C3 C3 A3 C3 \
+        A4 C1 B1 C4 B2 D4 B2 B1 D4 D2 B3 D4 A4 A3 B3 B1 \
+        C4 D1 C3 B2 B1 D1 A1 A2 D1 D4 A1 A1 C2 D3 D1 A3 \
+        B3 A3 D3 B3 C4 C2 D3 C2 D3 B4 C2 B4 B4 B4 A2 A2";
+
+    /// Plays the given moves, in the pole notation, starting with White;
+    /// returns the game and the side to move.
+    fn play(moves: &str) -> (Game, Side) {
+        let mut game = Game::new();
+        let mut side = Side::White;
+        for m in moves.split_whitespace() {
+            game.put_token(side, m.parse().unwrap()).unwrap();
+            side = side.opposite();
+        }
+
+        (game, side)
+    }
+
+    fn pole(s: &str) -> PoleCoords {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn finds_a_win_in_one() {
+        let (game, side) = play("A1 B1 A1 B1 A1 B1");
+
+        let solution = solve(&game, side, 5, None).unwrap();
+        assert_eq!(solution.outcome, Outcome::Win(1));
+        assert_eq!(solution.best_move, pole("A1"));
+    }
+
+    #[test]
+    fn finds_a_win_in_three() {
+        // A1 makes two rows at once: A1 B1 C1 D1 and A1 A2 A3 A4, and Black
+        // can only block one of them.
+        let (game, side) = play("B1 D3 C1 D3 A2 D2 A3 D2");
+
+        let solution = solve(&game, side, 3, None).unwrap();
+        assert_eq!(solution.outcome, Outcome::Win(3));
+        // It takes 3 plies, so it can't be proven with fewer.
+        assert_eq!(solve(&game, side, 2, None), None);
+
+        // Whatever Black replies, White wins with the next move.
+        let mut game = game;
+        game.put_token(side, solution.best_move).unwrap();
+        for reply in game.legal_moves() {
+            let mut g = game.clone();
+            g.put_token(side.opposite(), reply).unwrap();
+            let solution = solve(&g, side, 1, None).unwrap();
+            assert_eq!(solution.outcome, Outcome::Win(1), "after {}", reply);
+        }
+    }
+
+    #[test]
+    fn finds_a_forced_loss() {
+        // White threatens both A1 and C1, so whatever Black does, White wins
+        // with the next move.
+        let (game, side) = play("A1 B1 A1 B1 A1 D4 C1 D3 C1 D4 C1");
+        assert_eq!(side, Side::Black);
+
+        let solution = solve(&game, side, 2, None).unwrap();
+        assert_eq!(solution.outcome, Outcome::Loss(2));
+        assert_eq!(solve(&game, side, 1, None), None);
+    }
+
+    #[test]
+    fn proves_a_draw_only_when_the_board_gets_full_within_the_horizon() {
+        // Four empty cells left.
+        let moves: Vec<&str> = DRAWN_GAME.split_whitespace().collect();
+        let (game, side) = play(&moves[..60].join(" "));
+
+        let solution = solve(&game, side, 4, None).unwrap();
+        assert_eq!(solution.outcome, Outcome::Draw);
+        assert_eq!(solve(&game, side, 3, None), None);
+
+        let (game, side) = play(DRAWN_GAME);
+        assert!(game.is_draw());
+        assert_eq!(solve(&game, side, 4, None), None);
+    }
+
+    #[test]
+    fn gives_up_beyond_the_horizon() {
+        assert_eq!(solve(&Game::new(), Side::White, 4, None), None);
+        assert_eq!(solve(&Game::new(), Side::White, 0, None), None);
+    }
+
+    #[test]
+    fn gives_up_when_the_deadline_passes() {
+        let (game, side) = play("B1 D3 C1 D3 A2 D2 A3 D2");
+        assert_eq!(solve(&game, side, 3, Some(Instant::now())), None);
+    }
+}
@@ -1,3 +1,14 @@
+pub mod analysis;
+pub mod eval;
+pub mod handicap;
+pub mod puzzle;
+pub mod record;
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
 use anyhow::{anyhow, Result};
 
 /// In "Connect Four", ROW_SIZE is the "Four". It can be changed to any positive
@@ -5,18 +16,46 @@ use anyhow::{anyhow, Result};
 /// didn't bother.
 pub const ROW_SIZE: usize = 4;
 
+/// Human readable reason of the adjudicated draw, see Game::is_dead_draw.
+pub const DEAD_DRAW_REASON: &str = "no winning lines remain";
+
 /// Describes state of the board, a winner (if any), and has useful methods for
 /// putting tokens and checking for the winner.
+#[derive(Clone)]
 pub struct Game {
+    rules: Rules,
     board: BoardState,
 
     win_row: Option<WinRow>,
+    /// Completed rows of each side, only counted in the scoring variant.
+    scores: Scores,
+}
+
+/// Rules of the game, which can differ from the classic ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Rules {
+    /// Misère variant: whoever completes a row, loses.
+    pub misere: bool,
+    /// Scoring variant: completing a row doesn't end the game; instead, each
+    /// completed row scores a point, and once the board is full, whoever has
+    /// more rows wins (or fewer rows, if it's also misère).
+    pub scoring: bool,
+}
+
+/// Number of rows completed by each side, see Rules::scoring.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub struct Scores {
+    pub white: usize,
+    pub black: usize,
 }
 
 /// Winning row.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct WinRow {
-    /// Side of the winner.
+    /// Side which completed the row. Normally it's the winner, but see
+    /// Rules::misere.
     pub side: Side,
     /// Coords of all winning tokens.
     pub row: [TokenCoords; ROW_SIZE],
@@ -25,18 +64,51 @@ pub struct WinRow {
 /// State of the connect-four board, i.e. placement and sides of all tokens.
 /// There is no validation done, so technically one can construct an impossible
 /// state like hanging tokens.
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+///
+/// Two boards are equal if they have the same tokens in the same places, so
+/// boards can be compared (e.g. to detect desync between client and server) or
+/// used as hash keys.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct BoardState {
     tokens: Vec<Option<Side>>,
 }
 
+/// Difference between two boards, see BoardState::diff.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BoardDiff {
+    /// Tokens which were added, or which changed their side.
+    pub set: Vec<(Side, TokenCoords)>,
+    /// Coords of the tokens which were removed.
+    pub removed: Vec<TokenCoords>,
+}
+
 /// Successful result of putting a token on a pole.
 pub struct PutResult {
     /// The resulting y where the new token ended up.
     pub y: usize,
 
-    /// True if the new token won the game; use get_win_row to get the details.
-    pub won: bool,
+    /// If the game is won after the new token, it's the winning side
+    /// (depending on the rules, it's not necessarily the side which put the
+    /// token); use get_win_row to get the details.
+    pub winner: Option<Side>,
+    /// Whether the game ended in a draw: the board is full, and nobody won.
+    pub draw: bool,
+}
+
+/// Simple state of the game: either waiting for someone's turn, or the game is
+/// over: someone has won already, or it's a draw. Every state carries the
+/// scores of the scoring variant (see Rules::scoring, Game::get_scores) at
+/// that point; in the classic rules, they're always zero.
+///
+/// With zero scores, it's serialized just like before the scores were there,
+/// so the older clients, which only play the classic rules, and the stored
+/// games keep working; see GameStateRepr.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum GameState {
+    WaitingFor(Side, Scores),
+    WonBy(Side, Scores),
+    /// The board is full, and nobody won.
+    Draw(Scores),
 }
 
 /// Side of the player: either Black or White.
@@ -47,7 +119,7 @@ pub enum Side {
 }
 
 /// Contains coords of a token: X, Y, Z. All of those must be >= 0 and < ROW_SIZE.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, serde::Serialize, serde::Deserialize)]
 pub struct TokenCoords {
     pub x: usize,
     pub y: usize,
@@ -55,7 +127,7 @@ pub struct TokenCoords {
 }
 
 /// Contains coords of a pole: X, Z. Each of those must be >= 0 and < ROW_SIZE.
-#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, serde::Serialize, serde::Deserialize)]
 pub struct PoleCoords {
     pub x: usize,
     pub z: usize,
@@ -77,6 +149,42 @@ impl PoleCoords {
     }
 }
 
+/// Pole notation is a letter for X and a 1-based number for Z, e.g. "A1" is
+/// the pole 0, 0, and "B3" is the pole 1, 2.
+impl fmt::Display for PoleCoords {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", (b'A' + self.x as u8) as char, self.z + 1)
+    }
+}
+
+impl FromStr for PoleCoords {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut chars = s.chars();
+        let letter = chars
+            .next()
+            .ok_or_else(|| anyhow!("empty pole notation"))?
+            .to_ascii_uppercase();
+
+        if !letter.is_ascii_uppercase() {
+            return Err(anyhow!("invalid pole notation {:?}", s));
+        }
+
+        let x = (letter as u8 - b'A') as usize;
+        let z = chars
+            .as_str()
+            .parse::<usize>()
+            .map_err(|_| anyhow!("invalid pole notation {:?}", s))?;
+
+        if x >= ROW_SIZE || z == 0 || z > ROW_SIZE {
+            return Err(anyhow!("pole {:?} is out of bounds", s));
+        }
+
+        Ok(PoleCoords::new(x, z - 1))
+    }
+}
+
 impl TokenCoords {
     /// Create new token coords X, Z.
     pub fn new(x: usize, y: usize, z: usize) -> TokenCoords {
@@ -93,14 +201,26 @@ impl TokenCoords {
 }
 
 impl Game {
-    /// Create a new game with an empty board.
+    /// Create a new game with an empty board and classic rules.
     pub fn new() -> Game {
+        Game::with_rules(Rules::default())
+    }
+
+    /// Create a new game with an empty board and the given rules.
+    pub fn with_rules(rules: Rules) -> Game {
         Game {
+            rules,
             board: BoardState::new(),
             win_row: None,
+            scores: Scores::default(),
         }
     }
 
+    /// Returns the rules of the game.
+    pub fn get_rules(&self) -> Rules {
+        self.rules
+    }
+
     /// Put a new token on the pole with the given coords X, Z. Note that Y is
     /// not passed here: it will be returned in the result, if successful.
     ///
@@ -119,11 +239,18 @@ impl Game {
             match self.board.get(tcoords) {
                 None => {
                     self.board.set(side, tcoords);
-                    self.win_row = self.check_win();
+
+                    if self.rules.scoring {
+                        let num_rows = self.count_rows_through(tcoords);
+                        *self.scores.get_mut(side) += num_rows;
+                    } else {
+                        self.win_row = self.check_win();
+                    }
 
                     return Ok(PutResult {
                         y,
-                        won: self.win_row.is_some(),
+                        winner: self.winner(),
+                        draw: self.is_draw(),
                     });
                 }
 
@@ -136,6 +263,36 @@ impl Game {
         Err(anyhow!("pole {}, {} is full", pcoords.x, pcoords.z))
     }
 
+    /// Take back the move of the given side on the pole with the given coords
+    /// X, Z: remove the topmost token from it, and return its coords. Moves
+    /// have to be taken back in the reverse order, so an error is returned if
+    /// the pole is empty, or if its topmost token is of the other side.
+    pub fn take_back(&mut self, side: Side, pcoords: PoleCoords) -> Result<TokenCoords> {
+        panic_if_out_of_bounds(pcoords.x, 0, pcoords.z);
+
+        let tcoords = (0..ROW_SIZE)
+            .rev()
+            .map(|y| pcoords.token_coords(y))
+            .find(|&tcoords| self.board.get(tcoords).is_some())
+            .ok_or_else(|| anyhow!("pole {} is empty", pcoords))?;
+
+        if self.board.get(tcoords) != Some(side) {
+            return Err(anyhow!("top token on pole {} is not {:?}", pcoords, side));
+        }
+
+        self.board.clear(tcoords);
+
+        // The token might have been a part of the winning row, or of some
+        // completed rows in the scoring variant, so recalculate it all.
+        if self.rules.scoring {
+            self.scores = self.count_all_rows();
+        } else {
+            self.win_row = self.check_win();
+        }
+
+        Ok(tcoords)
+    }
+
     /// Get the token (if any) with the given coords X, Y, Z.
     pub fn get_token(&self, tcoords: TokenCoords) -> Option<Side> {
         self.board.get(tcoords)
@@ -146,161 +303,135 @@ impl Game {
         &self.board
     }
 
-    /// Reset the board to the data of the provided one.
+    /// Reset the board to the data of the provided one. The board isn't
+    /// validated here; if it comes from an untrusted source, check it with
+    /// BoardState::sanitize first.
     pub fn reset_board(&mut self, board: &BoardState) {
-        // TODO: sanitize the board: don't allow hanging tokens, multiple wins, and unbalanced
-        // sides.
-
         self.board.copy_from(board);
-        self.win_row = self.check_win();
+
+        if self.rules.scoring {
+            self.win_row = None;
+            self.scores = self.count_all_rows();
+        } else {
+            self.win_row = self.check_win();
+        }
     }
 
     /// Returns current winning row, if any. Once that function returns Some
-    /// row, no more tokens can be put, until reset_board is called.
+    /// row, no more tokens can be put, until reset_board is called. In the
+    /// scoring variant, there is never a winning row.
     pub fn get_win_row(&self) -> &Option<WinRow> {
         &self.win_row
     }
 
-    /// Checks all possible rows and returns the first one that is full of
-    /// tokens of the same side.  It's called every time a new token is put, or
-    /// a whole board is imported.
-    fn check_win(&self) -> Option<WinRow> {
-        // Vertical rows (constant x, z).
-        for x in 0..ROW_SIZE {
-            for z in 0..ROW_SIZE {
-                let row = self.check_win_row(|y| -> TokenCoords { TokenCoords { x, y, z } });
-                if let Some(win_row) = row {
-                    return Some(win_row);
-                }
+    /// Returns the winner, if any. Normally it's the side which completed the
+    /// winning row, but in the misère variant, it's the opposite one. In the
+    /// scoring variant, there is a winner only once the board is full.
+    pub fn winner(&self) -> Option<Side> {
+        if self.rules.scoring {
+            if !self.board.is_full() {
+                return None;
             }
-        }
 
-        // Horizontal rows with constant x, y.
-        for x in 0..ROW_SIZE {
-            for y in 0..ROW_SIZE {
-                let row = self.check_win_row(|z| -> TokenCoords { TokenCoords { x, y, z } });
-                if let Some(win_row) = row {
-                    return Some(win_row);
-                }
-            }
-        }
+            let more_rows = match self.scores.white.cmp(&self.scores.black) {
+                Ordering::Greater => Side::White,
+                Ordering::Less => Side::Black,
+                Ordering::Equal => return None,
+            };
 
-        // Horizontal rows with constant z, y.
-        for z in 0..ROW_SIZE {
-            for y in 0..ROW_SIZE {
-                let row = self.check_win_row(|x| -> TokenCoords { TokenCoords { x, y, z } });
-                if let Some(win_row) = row {
-                    return Some(win_row);
-                }
-            }
+            return if self.rules.misere {
+                Some(more_rows.opposite())
+            } else {
+                Some(more_rows)
+            };
         }
 
-        // Diagonal rows with constant x.
-        for x in 0..ROW_SIZE {
-            // Ascending y
-            let row = self.check_win_row(|n| -> TokenCoords { TokenCoords { x, y: n, z: n } });
-            if let Some(win_row) = row {
-                return Some(win_row);
-            }
+        let win_row = self.win_row.as_ref()?;
 
-            // Descending y
-            let row = self.check_win_row(|n| -> TokenCoords {
-                TokenCoords {
-                    x,
-                    y: ROW_SIZE - 1 - n,
-                    z: n,
-                }
-            });
-            if let Some(win_row) = row {
-                return Some(win_row);
-            }
+        if self.rules.misere {
+            Some(win_row.side.opposite())
+        } else {
+            Some(win_row.side)
         }
+    }
 
-        // Diagonal rows with constant z.
-        for z in 0..ROW_SIZE {
-            // Ascending y
-            let row = self.check_win_row(|n| -> TokenCoords { TokenCoords { x: n, y: n, z } });
-            if let Some(win_row) = row {
-                return Some(win_row);
-            }
+    /// Returns whether the game ended in a draw: the board is full, and nobody
+    /// won.
+    pub fn is_draw(&self) -> bool {
+        self.board.is_full() && self.winner().is_none()
+    }
 
-            // Descending y
-            let row = self.check_win_row(|n| -> TokenCoords {
-                TokenCoords {
-                    x: n,
-                    y: ROW_SIZE - 1 - n,
-                    z,
-                }
-            });
-            if let Some(win_row) = row {
-                return Some(win_row);
-            }
+    /// Returns whether the game is a dead draw: nobody has won, and every row
+    /// has tokens of both sides already, so nobody can complete any row anymore,
+    /// even though the board isn't full yet. Unlike is_draw, the game itself
+    /// doesn't stop there; it's up to whoever runs the game to adjudicate it.
+    /// In the scoring variant, the game is never a dead draw, since the rows
+    /// completed already still decide the winner once the board is full.
+    pub fn is_dead_draw(&self) -> bool {
+        if self.rules.scoring || self.win_row.is_some() || self.board.is_full() {
+            return false;
         }
 
-        // Diagonal rows with constant y.
-        for y in 0..ROW_SIZE {
-            // Ascending z
-            let row = self.check_win_row(|n| -> TokenCoords { TokenCoords { x: n, y, z: n } });
-            if let Some(win_row) = row {
-                return Some(win_row);
-            }
+        all_rows()
+            .iter()
+            .all(|row| analysis::line_owner(&self.board, row) == analysis::LineOwner::Dead)
+    }
 
-            // Descending z
-            let row = self.check_win_row(|n| -> TokenCoords {
-                TokenCoords {
-                    x: n,
-                    y,
-                    z: ROW_SIZE - 1 - n,
-                }
-            });
-            if let Some(win_row) = row {
-                return Some(win_row);
-            }
-        }
+    /// Returns the number of rows completed by each side so far. It's only
+    /// counted in the scoring variant, otherwise it's always zero.
+    pub fn get_scores(&self) -> Scores {
+        self.scores
+    }
 
-        // 3D diagonal with ascending x, y, z
-        let row = self.check_win_row(|n| -> TokenCoords { TokenCoords { x: n, y: n, z: n } });
-        if let Some(win_row) = row {
-            return Some(win_row);
+    /// Returns all poles where a token can be put: the ones which aren't full
+    /// yet. If there is a winner already, there are no legal moves at all.
+    pub fn legal_moves(&self) -> Vec<PoleCoords> {
+        if self.win_row.is_some() {
+            return Vec::new();
         }
 
-        // 3D diagonal with ascending x, z; descending y
-        let row = self.check_win_row(|n| -> TokenCoords {
-            TokenCoords {
-                x: n,
-                y: ROW_SIZE - 1 - n,
-                z: n,
+        let mut moves = Vec::new();
+        for x in 0..ROW_SIZE {
+            for z in 0..ROW_SIZE {
+                let pcoords = PoleCoords::new(x, z);
+                if self.board.get(pcoords.token_coords(ROW_SIZE - 1)).is_none() {
+                    moves.push(pcoords);
+                }
             }
-        });
-        if let Some(win_row) = row {
-            return Some(win_row);
         }
 
-        // 3D diagonal with ascending x, y; descending z
-        let row = self.check_win_row(|n| -> TokenCoords {
-            TokenCoords {
-                x: n,
-                y: n,
-                z: ROW_SIZE - 1 - n,
-            }
-        });
-        if let Some(win_row) = row {
-            return Some(win_row);
-        }
+        moves
+    }
 
-        // 3D diagonal with ascending x; descending y, z
-        let row = self.check_win_row(|n| -> TokenCoords {
-            TokenCoords {
-                x: n,
-                y: ROW_SIZE - 1 - n,
-                z: ROW_SIZE - 1 - n,
+    /// Checks all possible rows and returns the first one that is full of
+    /// tokens of the same side.  It's called every time a new token is put, or
+    /// a whole board is imported.
+    fn check_win(&self) -> Option<WinRow> {
+        all_rows()
+            .iter()
+            .find_map(|row| self.check_win_row(|i| row[i]))
+    }
+
+    /// Returns the number of completed rows which go through the given token.
+    fn count_rows_through(&self, tcoords: TokenCoords) -> usize {
+        all_rows()
+            .iter()
+            .filter(|row| row.contains(&tcoords))
+            .filter(|row| self.check_win_row(|i| row[i]).is_some())
+            .count()
+    }
+
+    /// Counts all completed rows on the board, for each side.
+    fn count_all_rows(&self) -> Scores {
+        let mut scores = Scores::default();
+
+        for row in all_rows() {
+            if let Some(win_row) = self.check_win_row(|i| row[i]) {
+                *scores.get_mut(win_row.side) += 1;
             }
-        });
-        if let Some(win_row) = row {
-            return Some(win_row);
         }
 
-        None
+        scores
     }
 
     /// A helper to check if a single row is full of tokens of the same size.
@@ -375,15 +506,154 @@ impl BoardState {
         self.tokens[Self::coord_to_idx(tcoords)] = Some(side);
     }
 
+    /// Remove the token with the given coords, if any. If coords are outside
+    /// of the board size, it panics. Just like with set, no validation is
+    /// done, so it can leave hanging tokens above.
+    pub fn clear(&mut self, tcoords: TokenCoords) {
+        panic_if_out_of_bounds(tcoords.x, tcoords.y, tcoords.z);
+
+        self.tokens[Self::coord_to_idx(tcoords)] = None;
+    }
+
+    /// Returns whether there are no empty spots left on the board.
+    pub fn is_full(&self) -> bool {
+        self.tokens.iter().all(|t| t.is_some())
+    }
+
+    /// Checks that the board makes sense under the given rules: there are no
+    /// hanging tokens, and unless it's the scoring variant, there are no
+    /// completed rows of both sides at once. Note that the number of tokens of
+    /// each side isn't checked, since e.g. a handicap setup gives extra tokens
+    /// to one side.
+    pub fn sanitize(&self, rules: Rules) -> Result<()> {
+        for x in 0..ROW_SIZE {
+            for z in 0..ROW_SIZE {
+                let pcoords = PoleCoords::new(x, z);
+                for y in 1..ROW_SIZE {
+                    if self.get(pcoords.token_coords(y)).is_some()
+                        && self.get(pcoords.token_coords(y - 1)).is_none()
+                    {
+                        return Err(anyhow!("hanging token on pole {} at y={}", pcoords, y));
+                    }
+                }
+            }
+        }
+
+        if rules.scoring {
+            return Ok(());
+        }
+
+        let mut row_sides = all_rows().iter().filter_map(|row| {
+            let side = self.get(row[0])?;
+            row.iter()
+                .all(|&tcoords| self.get(tcoords) == Some(side))
+                .then_some(side)
+        });
+
+        if let Some(side) = row_sides.next() {
+            if row_sides.any(|s| s != side) {
+                return Err(anyhow!("both sides have completed rows"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the board could arise from legal alternating play by the
+    /// classic rules, with White moving first: there are no hanging tokens,
+    /// White has either the same number of tokens as Black or one more, and
+    /// if there are completed rows, they could all have been completed by the
+    /// very last move (so no tokens were placed after the game was won).
+    pub fn is_reachable(&self) -> bool {
+        if self.sanitize(Rules::default()).is_err() {
+            return false;
+        }
+
+        let num_white = self.count(Side::White);
+        let num_black = self.count(Side::Black);
+        if num_white != num_black && num_white != num_black + 1 {
+            return false;
+        }
+
+        let win_rows: Vec<_> = all_rows()
+            .iter()
+            .filter(|row| {
+                let side = self.get(row[0]);
+                side.is_some() && row.iter().all(|&tcoords| self.get(tcoords) == side)
+            })
+            .collect();
+
+        let win_row = match win_rows.first() {
+            Some(row) => row,
+            None => return true,
+        };
+
+        // The side which completed the rows (sanitize made sure it's the same
+        // for all of them) must have made the last move.
+        let win_side = self.get(win_row[0]).unwrap();
+        let last_move_side = if num_white > num_black {
+            Side::White
+        } else {
+            Side::Black
+        };
+        if win_side != last_move_side {
+            return false;
+        }
+
+        // And there must be a token which could have been put last: it's on
+        // top of its pole, and it belongs to all completed rows.
+        win_row.iter().any(|tcoords| {
+            let on_top = tcoords.y == ROW_SIZE - 1
+                || self
+                    .get(TokenCoords::new(tcoords.x, tcoords.y + 1, tcoords.z))
+                    .is_none();
+
+            on_top && win_rows.iter().all(|row| row.contains(tcoords))
+        })
+    }
+
+    /// Returns the number of tokens of the given side.
+    pub fn count(&self, side: Side) -> usize {
+        self.tokens.iter().filter(|&&t| t == Some(side)).count()
+    }
+
     /// Copy data from another board. Existing data is discarded.
     pub fn copy_from(&mut self, another: &BoardState) {
         self.tokens.copy_from_slice(&another.tokens);
     }
 
+    /// Returns what has to be changed on this board to get the new one.
+    pub fn diff(&self, new: &BoardState) -> BoardDiff {
+        let mut diff = BoardDiff::default();
+
+        for (idx, (old_token, new_token)) in self.tokens.iter().zip(&new.tokens).enumerate() {
+            if old_token == new_token {
+                continue;
+            }
+
+            let tcoords = Self::idx_to_coord(idx);
+            match new_token {
+                Some(side) => diff.set.push((*side, tcoords)),
+                None => diff.removed.push(tcoords),
+            }
+        }
+
+        diff
+    }
+
     /// A helper to convert token coords X, Y, Z into an index in the slice.
     fn coord_to_idx(tcoords: TokenCoords) -> usize {
         tcoords.x + tcoords.y * ROW_SIZE + tcoords.z * ROW_SIZE * ROW_SIZE
     }
+
+    /// The opposite of coord_to_idx.
+    fn idx_to_coord(idx: usize) -> TokenCoords {
+        TokenCoords {
+            x: idx % ROW_SIZE,
+            y: idx / ROW_SIZE % ROW_SIZE,
+            z: idx / (ROW_SIZE * ROW_SIZE),
+        }
+    }
 }
 
 impl Default for BoardState {
@@ -392,6 +662,128 @@ impl Default for BoardState {
     }
 }
 
+impl Rules {
+    /// Size of the board: the number of poles in a row, and of tokens on a
+    /// pole. For now it's always ROW_SIZE, but whatever lays out the board
+    /// should take it from here, so that it keeps working once the size is
+    /// part of the rules.
+    pub fn row_size(&self) -> usize {
+        ROW_SIZE
+    }
+}
+
+impl Scores {
+    /// Returns the score of the given side.
+    pub fn get(&self, side: Side) -> usize {
+        match side {
+            Side::White => self.white,
+            Side::Black => self.black,
+        }
+    }
+
+    /// Returns a mutable reference to the score of the given side.
+    fn get_mut(&mut self, side: Side) -> &mut usize {
+        match side {
+            Side::White => &mut self.white,
+            Side::Black => &mut self.black,
+        }
+    }
+}
+
+impl GameState {
+    /// Returns the scores, see Rules::scoring.
+    pub fn scores(self) -> Scores {
+        match self {
+            GameState::WaitingFor(_, scores) | GameState::WonBy(_, scores) => scores,
+            GameState::Draw(scores) => scores,
+        }
+    }
+
+    /// Returns the same state with the given scores.
+    pub fn with_scores(self, scores: Scores) -> GameState {
+        match self {
+            GameState::WaitingFor(side, _) => GameState::WaitingFor(side, scores),
+            GameState::WonBy(side, _) => GameState::WonBy(side, scores),
+            GameState::Draw(_) => GameState::Draw(scores),
+        }
+    }
+
+    /// Whether the game is still on, and it's the given side's turn.
+    pub fn is_waiting_for(self, side: Side) -> bool {
+        matches!(self, GameState::WaitingFor(s, _) if s == side)
+    }
+}
+
+/// Serialized form of GameState, see its doc: the plain one, which predates
+/// the scores, and the scored one.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum GameStateRepr {
+    Scored(ScoredGameState),
+    Plain(PlainGameState),
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename = "GameState")]
+enum ScoredGameState {
+    WaitingFor(Side, Scores),
+    WonBy(Side, Scores),
+    Draw(Scores),
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename = "GameState")]
+enum PlainGameState {
+    WaitingFor(Side),
+    WonBy(Side),
+    Draw,
+}
+
+impl serde::Serialize for GameState {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = match *self {
+            GameState::WaitingFor(side, scores) if scores == Scores::default() => {
+                GameStateRepr::Plain(PlainGameState::WaitingFor(side))
+            }
+            GameState::WonBy(side, scores) if scores == Scores::default() => {
+                GameStateRepr::Plain(PlainGameState::WonBy(side))
+            }
+            GameState::Draw(scores) if scores == Scores::default() => {
+                GameStateRepr::Plain(PlainGameState::Draw)
+            }
+            GameState::WaitingFor(side, scores) => {
+                GameStateRepr::Scored(ScoredGameState::WaitingFor(side, scores))
+            }
+            GameState::WonBy(side, scores) => {
+                GameStateRepr::Scored(ScoredGameState::WonBy(side, scores))
+            }
+            GameState::Draw(scores) => GameStateRepr::Scored(ScoredGameState::Draw(scores)),
+        };
+
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for GameState {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let no_scores = Scores::default();
+        Ok(match GameStateRepr::deserialize(deserializer)? {
+            GameStateRepr::Scored(ScoredGameState::WaitingFor(side, scores)) => {
+                GameState::WaitingFor(side, scores)
+            }
+            GameStateRepr::Scored(ScoredGameState::WonBy(side, scores)) => {
+                GameState::WonBy(side, scores)
+            }
+            GameStateRepr::Scored(ScoredGameState::Draw(scores)) => GameState::Draw(scores),
+            GameStateRepr::Plain(PlainGameState::WaitingFor(side)) => {
+                GameState::WaitingFor(side, no_scores)
+            }
+            GameStateRepr::Plain(PlainGameState::WonBy(side)) => GameState::WonBy(side, no_scores),
+            GameStateRepr::Plain(PlainGameState::Draw) => GameState::Draw(no_scores),
+        })
+    }
+}
+
 impl Side {
     /// Opposite side.
     pub fn opposite(&self) -> Side {
@@ -402,6 +794,142 @@ impl Side {
     }
 }
 
+impl FromStr for Side {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "white" => Ok(Side::White),
+            "black" => Ok(Side::Black),
+            _ => Err(anyhow!("invalid side {:?}; try 'white' or 'black'", s)),
+        }
+    }
+}
+
+/// Returns all possible rows on the board: vertical, horizontal, and all kinds
+/// of diagonals. The rows are generated only once.
+pub fn all_rows() -> &'static [[TokenCoords; ROW_SIZE]] {
+    static ROWS: OnceLock<Vec<[TokenCoords; ROW_SIZE]>> = OnceLock::new();
+    ROWS.get_or_init(gen_all_rows)
+}
+
+/// Generates all possible rows on the board, see all_rows.
+fn gen_all_rows() -> Vec<[TokenCoords; ROW_SIZE]> {
+    let mut rows = Vec::new();
+
+    // Vertical rows (constant x, z).
+    for x in 0..ROW_SIZE {
+        for z in 0..ROW_SIZE {
+            add_row(&mut rows, |y| -> TokenCoords { TokenCoords { x, y, z } });
+        }
+    }
+
+    // Horizontal rows with constant x, y.
+    for x in 0..ROW_SIZE {
+        for y in 0..ROW_SIZE {
+            add_row(&mut rows, |z| -> TokenCoords { TokenCoords { x, y, z } });
+        }
+    }
+
+    // Horizontal rows with constant z, y.
+    for z in 0..ROW_SIZE {
+        for y in 0..ROW_SIZE {
+            add_row(&mut rows, |x| -> TokenCoords { TokenCoords { x, y, z } });
+        }
+    }
+
+    // Diagonal rows with constant x.
+    for x in 0..ROW_SIZE {
+        // Ascending y
+        add_row(&mut rows, |n| -> TokenCoords {
+            TokenCoords { x, y: n, z: n }
+        });
+
+        // Descending y
+        add_row(&mut rows, |n| -> TokenCoords {
+            TokenCoords {
+                x,
+                y: ROW_SIZE - 1 - n,
+                z: n,
+            }
+        });
+    }
+
+    // Diagonal rows with constant z.
+    for z in 0..ROW_SIZE {
+        // Ascending y
+        add_row(&mut rows, |n| -> TokenCoords {
+            TokenCoords { x: n, y: n, z }
+        });
+
+        // Descending y
+        add_row(&mut rows, |n| -> TokenCoords {
+            TokenCoords {
+                x: n,
+                y: ROW_SIZE - 1 - n,
+                z,
+            }
+        });
+    }
+
+    // Diagonal rows with constant y.
+    for y in 0..ROW_SIZE {
+        // Ascending z
+        add_row(&mut rows, |n| -> TokenCoords {
+            TokenCoords { x: n, y, z: n }
+        });
+
+        // Descending z
+        add_row(&mut rows, |n| -> TokenCoords {
+            TokenCoords {
+                x: n,
+                y,
+                z: ROW_SIZE - 1 - n,
+            }
+        });
+    }
+
+    // 3D diagonal with ascending x, y, z
+    add_row(&mut rows, |n| -> TokenCoords {
+        TokenCoords { x: n, y: n, z: n }
+    });
+
+    // 3D diagonal with ascending x, z; descending y
+    add_row(&mut rows, |n| -> TokenCoords {
+        TokenCoords {
+            x: n,
+            y: ROW_SIZE - 1 - n,
+            z: n,
+        }
+    });
+
+    // 3D diagonal with ascending x, y; descending z
+    add_row(&mut rows, |n| -> TokenCoords {
+        TokenCoords {
+            x: n,
+            y: n,
+            z: ROW_SIZE - 1 - n,
+        }
+    });
+
+    // 3D diagonal with ascending x; descending y, z
+    add_row(&mut rows, |n| -> TokenCoords {
+        TokenCoords {
+            x: n,
+            y: ROW_SIZE - 1 - n,
+            z: ROW_SIZE - 1 - n,
+        }
+    });
+
+    rows
+}
+
+/// A helper for gen_all_rows: adds a single row. The tcoord_getter callback
+/// takes an index from 0 to ROW_SIZE-1, and returns full coords for that token.
+fn add_row(rows: &mut Vec<[TokenCoords; ROW_SIZE]>, tcoord_getter: impl Fn(usize) -> TokenCoords) {
+    rows.push(std::array::from_fn(tcoord_getter));
+}
+
 /// A helper which panics if given coords are outside of the board.
 fn panic_if_out_of_bounds(x: usize, y: usize, z: usize) {
     if x >= ROW_SIZE {
@@ -416,3 +944,105 @@ fn panic_if_out_of_bounds(x: usize, y: usize, z: usize) {
         panic!("z is out of bounds: {}", z);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays the given moves, in the pole notation, starting with White.
+    fn play(moves: &str) -> Game {
+        let mut game = Game::new();
+        let mut side = Side::White;
+        for m in moves.split_whitespace() {
+            game.put_token(side, m.parse().unwrap()).unwrap();
+            side = side.opposite();
+        }
+
+        game
+    }
+
+    fn tcoords(pole: &str, y: usize) -> TokenCoords {
+        pole.parse::<PoleCoords>().unwrap().token_coords(y)
+    }
+
+    #[test]
+    fn boards_from_legal_play_are_reachable() {
+        assert!(BoardState::new().is_reachable());
+        assert!(play("A1").get_board().is_reachable());
+        assert!(play("A1 B2 A1 C3 D4").get_board().is_reachable());
+
+        // Won by the last move, with a vertical and a horizontal row.
+        assert!(play("A1 B1 A1 B1 A1 B1 A1").get_board().is_reachable());
+        assert!(play("A1 A1 B1 B1 C1 C1 D1").get_board().is_reachable());
+    }
+
+    #[test]
+    fn floating_tokens_are_unreachable() {
+        let mut board = play("A1 B1").get_board().clone();
+        board.set(Side::White, tcoords("C1", 1));
+        board.set(Side::Black, tcoords("D1", 0));
+        assert!(!board.is_reachable());
+    }
+
+    #[test]
+    fn wrong_turn_parity_is_unreachable() {
+        // Black has more tokens than White.
+        let mut board = play("A1 B1").get_board().clone();
+        board.set(Side::Black, tcoords("C1", 0));
+        assert!(!board.is_reachable());
+
+        // White has two more tokens than Black.
+        let mut board = play("A1").get_board().clone();
+        board.set(Side::White, tcoords("C1", 0));
+        assert!(!board.is_reachable());
+    }
+
+    #[test]
+    fn play_after_a_win_is_unreachable() {
+        // White has won on A1, and then Black moved.
+        let mut board = play("A1 B1 A1 B1 A1 B1 A1").get_board().clone();
+        board.set(Side::Black, tcoords("C1", 0));
+        assert!(!board.is_reachable());
+
+        // White has won on A1 B1 C1 D1, and then both sides moved: the
+        // parity is fine, but none of the row's tokens could be the last one.
+        let mut board = play("A1 A1 B1 B1 C1 C1 D1").get_board().clone();
+        board.set(Side::Black, tcoords("D1", 1));
+        board.set(Side::White, tcoords("A1", 2));
+        assert!(!board.is_reachable());
+
+        // Both sides have completed rows.
+        let mut board = play("A1 B1 A1 B1 A1 B1 A1").get_board().clone();
+        board.set(Side::Black, tcoords("B1", 3));
+        assert!(!board.is_reachable());
+    }
+
+    #[test]
+    fn game_state_keeps_the_old_form_without_scores() {
+        let no_scores = Scores::default();
+        for (state, json) in [
+            (
+                GameState::WaitingFor(Side::White, no_scores),
+                r#"{"WaitingFor":"White"}"#,
+            ),
+            (
+                GameState::WonBy(Side::Black, no_scores),
+                r#"{"WonBy":"Black"}"#,
+            ),
+            (GameState::Draw(no_scores), r#""Draw""#),
+        ] {
+            assert_eq!(serde_json::to_string(&state).unwrap(), json);
+            assert_eq!(serde_json::from_str::<GameState>(json).unwrap(), state);
+        }
+
+        let scores = Scores { white: 2, black: 1 };
+        for state in [
+            GameState::WaitingFor(Side::White, scores),
+            GameState::WonBy(Side::Black, scores),
+            GameState::Draw(scores),
+        ] {
+            let json = serde_json::to_string(&state).unwrap();
+            assert_eq!(serde_json::from_str::<GameState>(&json).unwrap(), state);
+        }
+    }
+}
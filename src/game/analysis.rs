@@ -0,0 +1,237 @@
+use super::{all_rows, BoardState, Game, PoleCoords, Side, TokenCoords, ROW_SIZE};
+
+/// Forced win, as found by find_forced_win.
+#[derive(Debug, Clone)]
+pub struct ForcedWin {
+    /// How many moves the winning side needs to win, including the winning
+    /// move itself.
+    pub num_moves: usize,
+
+    /// The main line: moves of both sides in turn, starting and ending with
+    /// the winning side. Opponent moves in this line are the ones which delay
+    /// the loss the most.
+    pub line: Vec<PoleCoords>,
+}
+
+/// Who can still complete a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineOwner {
+    /// There are no tokens in the row yet, so either side can complete it.
+    Empty,
+    /// Only the given side has tokens in the row (the given number of them),
+    /// so only it can complete the row.
+    Side(Side, usize),
+    /// Both sides have tokens in the row, so nobody can complete it anymore.
+    Dead,
+}
+
+/// Number of rows going through a pole which can still be completed by each
+/// side, see open_lines_through_pole.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoleLines {
+    pub white: usize,
+    pub black: usize,
+}
+
+/// Counts the positions reachable from the given one in exactly the given
+/// number of moves, starting with the given side; finished games count as
+/// well, if they end earlier. It's the classic perft: exhaustive, and only
+/// useful to benchmark the move generation, or to check it against known
+/// counts.
+pub fn perft(game: &Game, side: Side, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = game.legal_moves();
+    if moves.is_empty() {
+        return 1;
+    }
+
+    let mut nodes = 0;
+    for pcoords in moves {
+        let mut g = game.clone();
+        let res = g.put_token(side, pcoords).unwrap();
+        nodes += if res.winner.is_some() || res.draw {
+            1
+        } else {
+            perft(&g, side.opposite(), depth - 1)
+        };
+    }
+
+    nodes
+}
+
+/// Returns who can still complete the given row (e.g. one of all_rows).
+pub fn line_owner(board: &BoardState, row: &[TokenCoords; ROW_SIZE]) -> LineOwner {
+    let mut owner = LineOwner::Empty;
+
+    for &tcoords in row {
+        owner = match (owner, board.get(tcoords)) {
+            (owner, None) => owner,
+            (LineOwner::Empty, Some(side)) => LineOwner::Side(side, 1),
+            (LineOwner::Side(owner_side, n), Some(side)) if owner_side == side => {
+                LineOwner::Side(side, n + 1)
+            }
+            _ => return LineOwner::Dead,
+        };
+    }
+
+    owner
+}
+
+/// For each side, counts the rows which go through the empty spots of the
+/// given pole and which can still be completed by that side. Empty rows count
+/// for both sides. It shows how much potential a pole still has.
+pub fn open_lines_through_pole(board: &BoardState, pcoords: PoleCoords) -> PoleLines {
+    let mut lines = PoleLines::default();
+
+    for row in all_rows() {
+        let through_empty_spot = row
+            .iter()
+            .any(|t| t.pole_coords() == pcoords && board.get(*t).is_none());
+        if !through_empty_spot {
+            continue;
+        }
+
+        match line_owner(board, row) {
+            LineOwner::Empty => {
+                lines.white += 1;
+                lines.black += 1;
+            }
+            LineOwner::Side(Side::White, _) => lines.white += 1,
+            LineOwner::Side(Side::Black, _) => lines.black += 1,
+            LineOwner::Dead => {}
+        }
+    }
+
+    lines
+}
+
+/// Returns all legal moves of the given side, ordered so that the most
+/// promising ones come first: immediate wins, then moves which block the
+/// opponent's immediate wins, then all the rest; within each group, the moves
+/// closer to the center of the board come first. Good ordering makes
+/// alpha-beta search prune much more.
+pub fn order_moves(game: &Game, side: Side) -> Vec<PoleCoords> {
+    let mut moves = game.legal_moves();
+
+    moves.sort_by_cached_key(|&pcoords| {
+        let group = if wins_right_away(game, side, pcoords) {
+            0
+        } else if wins_right_away(game, side.opposite(), pcoords) {
+            1
+        } else {
+            2
+        };
+
+        (group, center_distance(pcoords))
+    });
+
+    moves
+}
+
+/// Returns whether the given side wins right away by putting a token on the
+/// given pole.
+fn wins_right_away(game: &Game, side: Side, pcoords: PoleCoords) -> bool {
+    let mut game = game.clone();
+    matches!(game.put_token(side, pcoords), Ok(res) if res.winner == Some(side))
+}
+
+/// Manhattan distance from the pole to the center of the board, doubled so
+/// that it's an integer for any ROW_SIZE.
+fn center_distance(pcoords: PoleCoords) -> usize {
+    let center = ROW_SIZE - 1;
+    (pcoords.x * 2).abs_diff(center) + (pcoords.z * 2).abs_diff(center)
+}
+
+/// Checks whether the given side, having the move, can force a win in at most
+/// max_moves of its own moves, no matter how the opponent plays. If several
+/// wins exist, the fastest one is returned.
+///
+/// It's a plain exhaustive search, so the cost grows very quickly with
+/// max_moves; it's meant for short forced wins like in puzzles.
+pub fn find_forced_win(game: &Game, side: Side, max_moves: usize) -> Option<ForcedWin> {
+    (1..=max_moves).find_map(|num_moves| {
+        win_in(game, side, num_moves).map(|line| ForcedWin { num_moves, line })
+    })
+}
+
+/// Returns the main line if the given side, having the move, can win in at
+/// most num_moves moves.
+fn win_in(game: &Game, side: Side, num_moves: usize) -> Option<Vec<PoleCoords>> {
+    if num_moves == 0 {
+        return None;
+    }
+
+    let moves = game.legal_moves();
+
+    // Check immediate wins first, so the shortest win is found on this level.
+    for &pcoords in &moves {
+        let mut g = game.clone();
+        if g.put_token(side, pcoords).unwrap().winner == Some(side) {
+            return Some(vec![pcoords]);
+        }
+    }
+
+    if num_moves == 1 {
+        return None;
+    }
+
+    for &pcoords in &moves {
+        let mut g = game.clone();
+        if g.put_token(side, pcoords).unwrap().winner.is_some() {
+            // We've already checked all the winning moves above, so this one
+            // loses right away (it's possible in the misère variant).
+            continue;
+        }
+
+        if let Some(rest) = all_replies_lose(&g, side.opposite(), num_moves - 1) {
+            let mut line = vec![pcoords];
+            line.extend(rest);
+            return Some(line);
+        }
+    }
+
+    None
+}
+
+/// Returns the main line if every move of the given defending side loses in at
+/// most num_moves moves of the opponent.
+fn all_replies_lose(game: &Game, defender: Side, num_moves: usize) -> Option<Vec<PoleCoords>> {
+    let moves = game.legal_moves();
+
+    // If the board is full, it's a draw, so no win for the opponent.
+    if moves.is_empty() {
+        return None;
+    }
+
+    let mut longest: Option<Vec<PoleCoords>> = None;
+
+    for &pcoords in &moves {
+        let mut g = game.clone();
+        let mut line = vec![pcoords];
+
+        match g.put_token(defender, pcoords).unwrap().winner {
+            // The defender wins with this move, so there's no forced win.
+            Some(winner) if winner == defender => return None,
+
+            // The defender loses right away (it's possible in the misère
+            // variant).
+            Some(_) => {}
+
+            None => {
+                // Find the fastest win after this reply, so the main line
+                // doesn't contain needlessly long wins.
+                let rest = (1..=num_moves).find_map(|n| win_in(&g, defender.opposite(), n))?;
+                line.extend(rest);
+            }
+        }
+
+        if longest.as_ref().is_none_or(|l| line.len() > l.len()) {
+            longest = Some(line);
+        }
+    }
+
+    longest
+}
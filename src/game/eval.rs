@@ -0,0 +1,191 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use rand::Rng;
+
+use super::analysis::{self, LineOwner};
+use super::{Game, PoleCoords, Side, ROW_SIZE};
+
+/// Score of a won game. Any heuristic score is much smaller than that.
+pub const WIN_SCORE: i32 = 100_000;
+
+/// Playing style of the AI: how it weighs its own rows against the
+/// opponent's ones, see evaluate_styled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Style {
+    /// Both sides' rows are equally important, just like in evaluate.
+    #[default]
+    Balanced,
+    /// Own rows are more important: builds up threats rather than blocking.
+    Aggressive,
+    /// Opponent's rows are more important: blocks rather than builds up.
+    Defensive,
+    /// Every evaluation is a bit random, so the play is less predictable (and
+    /// weaker).
+    Casual,
+}
+
+/// Weights of the evaluation for a style, see Style::weights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Weights {
+    /// Weight of own rows, and of the opponent's ones, in percent.
+    pub own: i32,
+    pub opponent: i32,
+    /// Max random noise added to every evaluation, in both directions.
+    pub noise: i32,
+}
+
+impl Style {
+    pub const ALL: [Style; 4] = [
+        Style::Balanced,
+        Style::Aggressive,
+        Style::Defensive,
+        Style::Casual,
+    ];
+
+    pub fn weights(&self) -> Weights {
+        match self {
+            Style::Balanced => Weights {
+                own: 100,
+                opponent: 100,
+                noise: 0,
+            },
+            Style::Aggressive => Weights {
+                own: 150,
+                opponent: 75,
+                noise: 0,
+            },
+            Style::Defensive => Weights {
+                own: 75,
+                opponent: 150,
+                noise: 0,
+            },
+            Style::Casual => Weights {
+                own: 100,
+                opponent: 100,
+                noise: line_weight(ROW_SIZE - 1),
+            },
+        }
+    }
+}
+
+impl FromStr for Style {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "balanced" => Ok(Style::Balanced),
+            "aggressive" => Ok(Style::Aggressive),
+            "defensive" => Ok(Style::Defensive),
+            "casual" => Ok(Style::Casual),
+            _ => Err(anyhow!(
+                "invalid style {:?}; try 'balanced', 'aggressive', 'defensive' or 'casual'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Style::Balanced => write!(f, "balanced"),
+            Style::Aggressive => write!(f, "aggressive"),
+            Style::Defensive => write!(f, "defensive"),
+            Style::Casual => write!(f, "casual"),
+        }
+    }
+}
+
+/// Heuristic evaluation of the position: positive values are good for White,
+/// negative ones are good for Black, just like Annotation::eval.
+///
+/// A finished game is scored as WIN_SCORE for the winner, or 0 for a draw.
+/// Otherwise, every row which can still be completed by a single side adds to
+/// that side's score, and the more tokens the side already has in the row,
+/// the more it adds.
+pub fn evaluate(game: &Game) -> i32 {
+    evaluate_styled(game, Style::Balanced, Side::White)
+}
+
+/// Like evaluate, but the way the given side sees it, playing in the given
+/// style (see Style::weights): its own rows and the opponent's ones are
+/// weighted differently, and there might be some noise. The score is still
+/// from White's point of view, and finished games are scored just like in
+/// evaluate.
+pub fn evaluate_styled(game: &Game, style: Style, me: Side) -> i32 {
+    if let Some(winner) = game.winner() {
+        return side_sign(winner) * WIN_SCORE;
+    }
+
+    if game.is_draw() {
+        return 0;
+    }
+
+    let rules = game.get_rules();
+    let weights = style.weights();
+
+    // Rows of both sides are summed up separately, to be weighted.
+    let mut own = 0;
+    let mut opponent = 0;
+    for row in super::all_rows() {
+        if let LineOwner::Side(side, num_tokens) = analysis::line_owner(game.get_board(), row) {
+            if side == me {
+                own += line_weight(num_tokens);
+            } else {
+                opponent += line_weight(num_tokens);
+            }
+        }
+    }
+    let mut score = side_sign(me) * (own * weights.own - opponent * weights.opponent) / 100;
+
+    // In the misère variant, rows are a liability rather than an asset.
+    if rules.misere {
+        score = -score;
+    }
+
+    // In the scoring variant, the rows which are completed already matter the
+    // most.
+    if rules.scoring {
+        let scores = game.get_scores();
+        let diff = scores.white as i32 - scores.black as i32;
+        let diff = if rules.misere { -diff } else { diff };
+        score += diff * line_weight(ROW_SIZE);
+    }
+
+    if weights.noise > 0 {
+        score += rand::thread_rng().gen_range(-weights.noise..=weights.noise);
+    }
+
+    score
+}
+
+/// Evaluate the position after the given side puts a token on the given pole,
+/// see evaluate. Returns None if the move is not possible.
+pub fn evaluate_move(game: &Game, side: Side, pcoords: PoleCoords) -> Option<i32> {
+    let mut game = game.clone();
+    game.put_token(side, pcoords).ok()?;
+
+    Some(evaluate(&game))
+}
+
+/// Weight of a row which can still be completed, depending on how many tokens
+/// are there already.
+fn line_weight(num_tokens: usize) -> i32 {
+    if num_tokens == 0 {
+        return 0;
+    }
+
+    4i32.pow(num_tokens as u32 - 1)
+}
+
+/// Returns 1 for White and -1 for Black, to turn scores into White's point of
+/// view.
+fn side_sign(side: Side) -> i32 {
+    match side {
+        Side::White => 1,
+        Side::Black => -1,
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+
+use super::{all_rows, BoardState, Game, PoleCoords, Rules, Side, ROW_SIZE};
+
+/// Handicap setup: the weaker side gets a few tokens on the board, and the
+/// stronger side moves first. The tokens are either pre-placed, or the weaker
+/// side places them wherever it likes, in a setup phase before the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Handicap {
+    /// Side which gets the handicap tokens.
+    pub side: Side,
+    /// How many tokens it gets, at most MAX_TOKENS, or MAX_FREE_TOKENS if
+    /// they're free.
+    pub num_tokens: usize,
+    /// Whether the side places the tokens itself, one after another, before
+    /// the stronger side makes its first move; see setup_tokens.
+    #[serde(default)]
+    pub free: bool,
+}
+
+/// Max number of pre-placed tokens: one on each of the central poles.
+pub const MAX_TOKENS: usize = 4;
+
+/// Max number of free tokens: one less than a row, so that the setup can't
+/// complete one.
+pub const MAX_FREE_TOKENS: usize = ROW_SIZE - 1;
+
+impl Handicap {
+    /// Returns the board with the pre-placed tokens. They go to the bottom of
+    /// the central poles, which are the most valuable ones, since they take
+    /// part in the most rows. The board is checked with BoardState::sanitize,
+    /// using the classic rules, since they're the strictest ones. If the
+    /// tokens are free, the board is empty: they're only placed in the game.
+    pub fn board(&self) -> Result<BoardState> {
+        let max_tokens = if self.free {
+            MAX_FREE_TOKENS
+        } else {
+            MAX_TOKENS
+        };
+        if self.num_tokens > max_tokens {
+            return Err(anyhow!(
+                "handicap can be at most {} tokens, got {}",
+                max_tokens,
+                self.num_tokens
+            ));
+        }
+
+        if self.free {
+            return Ok(BoardState::new());
+        }
+
+        let c = ROW_SIZE / 2;
+        let poles = [
+            PoleCoords::new(c - 1, c - 1),
+            PoleCoords::new(c, c),
+            PoleCoords::new(c, c - 1),
+            PoleCoords::new(c - 1, c),
+        ];
+
+        let mut board = BoardState::new();
+        for pcoords in &poles[..self.num_tokens] {
+            board.set(self.side, pcoords.token_coords(0));
+        }
+
+        board.sanitize(Rules::default()).context("handicap board")?;
+
+        Ok(board)
+    }
+
+    /// Side which moves first: the one which didn't get the handicap tokens,
+    /// unless it has to place them first, see setup_tokens.
+    pub fn first_move_side(&self) -> Side {
+        if self.setup_tokens() > 0 {
+            self.side
+        } else {
+            self.side.opposite()
+        }
+    }
+
+    /// Number of tokens which the side with the handicap places itself before
+    /// the stronger side makes its first move; zero unless they're free.
+    pub fn setup_tokens(&self) -> usize {
+        if self.free {
+            self.num_tokens
+        } else {
+            0
+        }
+    }
+
+    /// Create a new game with the handicap tokens pre-placed.
+    pub fn game(&self, rules: Rules) -> Result<Game> {
+        let mut game = Game::with_rules(rules);
+        game.reset_board(&self.board()?);
+
+        Ok(game)
+    }
+}
+
+/// Checks the board during the setup phase, see Handicap::free: it has to
+/// pass BoardState::sanitize under the given rules, and since the game hasn't
+/// started yet, the handicap tokens can't complete any rows.
+pub fn check_setup(board: &BoardState, rules: Rules) -> Result<()> {
+    board.sanitize(rules)?;
+
+    let completed = all_rows().iter().any(|row| {
+        let side = board.get(row[0]);
+        side.is_some() && row.iter().all(|&tcoords| board.get(tcoords) == side)
+    });
+    if completed {
+        return Err(anyhow!("handicap tokens can't complete a row"));
+    }
+
+    Ok(())
+}
+
+/// Handicap notation is the side and the number of tokens, e.g. "black:2",
+/// and ":free" if the side places them itself, e.g. "black:2:free".
+impl fmt::Display for Handicap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}:{}", self.side, self.num_tokens)?;
+        if self.free {
+            write!(f, ":free")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Handicap {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (s, free) = match s.strip_suffix(":free") {
+            Some(s) => (s, true),
+            None => (s, false),
+        };
+        let (side, num_tokens) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid handicap {:?}; try e.g. 'black:2'", s))?;
+
+        let handicap = Handicap {
+            side: side.parse()?,
+            num_tokens: num_tokens
+                .parse()
+                .with_context(|| format!("invalid number of tokens {:?}", num_tokens))?,
+            free,
+        };
+
+        // Make sure the setup is valid right away.
+        handicap.board()?;
+
+        Ok(handicap)
+    }
+}
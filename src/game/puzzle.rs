@@ -0,0 +1,50 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use super::{BoardState, Game, PoleCoords, Side};
+
+/// A puzzle: a position where the side to move can force a win, together with
+/// the solution.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Puzzle {
+    /// The position to solve.
+    pub board: BoardState,
+    /// Side which has the move, and which can force a win.
+    pub side_to_move: Side,
+    /// In how many moves the side to move can win.
+    pub num_moves: usize,
+    /// Main line of the solution, see analysis::ForcedWin::line.
+    pub solution: Vec<PoleCoords>,
+
+    /// Human readable description of where the puzzle came from, e.g. the
+    /// game file and move number.
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+impl Puzzle {
+    /// Return the game with the puzzle position.
+    pub fn game(&self) -> Game {
+        let mut game = Game::new();
+        game.reset_board(&self.board);
+        game
+    }
+
+    /// Load the puzzle from a JSON file.
+    pub fn load(path: &Path) -> Result<Puzzle> {
+        let data = fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+        let puzzle = serde_json::from_str(&data).with_context(|| format!("parsing {:?}", path))?;
+
+        Ok(puzzle)
+    }
+
+    /// Save the puzzle to a JSON file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_string_pretty(self)?;
+        fs::write(path, data).with_context(|| format!("writing {:?}", path))?;
+
+        Ok(())
+    }
+}
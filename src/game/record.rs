@@ -0,0 +1,440 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+
+use super::{Game, PoleCoords, Side};
+
+/// Record of a game: all the moves in the order they were made, each of them
+/// optionally annotated. It can be saved to and loaded from a JSON file, so
+/// annotated games can be shared, or converted to and from a human-readable
+/// transcript (see to_transcript).
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct GameRecord {
+    pub moves: Vec<RecordedMove>,
+}
+
+/// A single move in the game record.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RecordedMove {
+    /// Side which made the move.
+    pub side: Side,
+    /// Pole on which the token was put.
+    pub pole: PoleCoords,
+
+    /// Optional annotation, empty by default.
+    #[serde(default)]
+    pub annotation: Annotation,
+}
+
+/// Annotation of a single move, all fields are optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Annotation {
+    /// Quality mark, like "!" or "??".
+    pub mark: Option<MoveMark>,
+    /// Free-form comment; in the transcript, the line breaks and the
+    /// backslashes in it are escaped, see to_transcript.
+    pub comment: Option<String>,
+    /// Evaluation score of the position after the move; positive values are
+    /// good for White, negative ones are good for Black.
+    #[serde(default)]
+    pub eval: Option<i32>,
+    /// Time remaining on the clock of the side which made the move. The
+    /// transcript keeps millisecond precision.
+    #[serde(default)]
+    pub clock: Option<Duration>,
+}
+
+/// Quality mark of a move, like the ones used in chess annotations.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, serde::Serialize, serde::Deserialize)]
+pub enum MoveMark {
+    /// "!!"
+    Brilliant,
+    /// "!"
+    Good,
+    /// "!?"
+    Interesting,
+    /// "?!"
+    Dubious,
+    /// "?"
+    Mistake,
+    /// "??"
+    Blunder,
+}
+
+impl MoveMark {
+    /// All the marks, from the best move to the worst.
+    pub const ALL: [MoveMark; 6] = [
+        MoveMark::Brilliant,
+        MoveMark::Good,
+        MoveMark::Interesting,
+        MoveMark::Dubious,
+        MoveMark::Mistake,
+        MoveMark::Blunder,
+    ];
+}
+
+impl GameRecord {
+    /// Create a new empty record.
+    pub fn new() -> GameRecord {
+        GameRecord { moves: Vec::new() }
+    }
+
+    /// Append a new move without annotation.
+    pub fn push(&mut self, side: Side, pole: PoleCoords) {
+        self.moves.push(RecordedMove {
+            side,
+            pole,
+            annotation: Annotation::default(),
+        });
+    }
+
+    /// Set annotation of the move with the given index (0-based), overwriting
+    /// the existing annotation, if any.
+    pub fn annotate(&mut self, idx: usize, annotation: Annotation) -> Result<()> {
+        let num_moves = self.moves.len();
+        let m = self
+            .moves
+            .get_mut(idx)
+            .ok_or_else(|| anyhow!("no move {}, there are {} moves", idx, num_moves))?;
+
+        m.annotation = annotation;
+
+        Ok(())
+    }
+
+    /// Replay all the recorded moves on a new empty board, and return the
+    /// resulting game. An error is returned if some of the moves is illegal.
+    pub fn replay(&self) -> Result<Game> {
+        let mut game = Game::new();
+
+        for (i, m) in self.moves.iter().enumerate() {
+            game.put_token(m.side, m.pole)
+                .with_context(|| format!("move {}", i))?;
+        }
+
+        Ok(game)
+    }
+
+    /// Load the record from a JSON file.
+    pub fn load(path: &Path) -> Result<GameRecord> {
+        let data = fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+        let record = serde_json::from_str(&data).with_context(|| format!("parsing {:?}", path))?;
+
+        Ok(record)
+    }
+
+    /// Load the record either from JSON or from the transcript, depending on
+    /// the file extension.
+    pub fn load_file(path: &Path) -> Result<GameRecord> {
+        if path.extension().is_some_and(|ext| ext == "json") {
+            return GameRecord::load(path);
+        }
+
+        let transcript = fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+        GameRecord::from_transcript(&transcript).with_context(|| format!("parsing {:?}", path))
+    }
+
+    /// Save the record to a JSON file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_string_pretty(self)?;
+        fs::write(path, data).with_context(|| format!("writing {:?}", path))?;
+
+        Ok(())
+    }
+
+    /// Convert the record to a transcript: one line per move, like this:
+    ///
+    /// ```text
+    /// 1. White B3 !? [eval 12] [clock 83.400] {a comment}
+    /// 2. Black A1
+    /// ```
+    ///
+    /// Everything after the pole is optional, but when present, it has to be
+    /// in this order. So that every move stays on its own line, the comment
+    /// has its line breaks written as `\n` (and `\r`), and its backslashes
+    /// as `\\`. The transcript can be parsed back with from_transcript.
+    pub fn to_transcript(&self) -> String {
+        let mut s = String::new();
+
+        for (i, m) in self.moves.iter().enumerate() {
+            s.push_str(&format!("{}. {:?} {}", i + 1, m.side, m.pole));
+
+            let a = &m.annotation;
+            if let Some(mark) = a.mark {
+                s.push_str(&format!(" {}", mark));
+            }
+            if let Some(eval) = a.eval {
+                s.push_str(&format!(" [eval {}]", eval));
+            }
+            if let Some(clock) = a.clock {
+                s.push_str(&format!(
+                    " [clock {}.{:03}]",
+                    clock.as_secs(),
+                    clock.subsec_millis()
+                ));
+            }
+            if let Some(comment) = &a.comment {
+                s.push_str(&format!(" {{{}}}", escape_comment(comment)));
+            }
+
+            s.push('\n');
+        }
+
+        s
+    }
+
+    /// Parse the transcript generated by to_transcript. Empty lines are
+    /// ignored.
+    pub fn from_transcript(transcript: &str) -> Result<GameRecord> {
+        let mut record = GameRecord::new();
+
+        for (i, line) in transcript.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let m = Self::parse_transcript_line(line, record.moves.len() + 1)
+                .with_context(|| format!("line {}: {:?}", i + 1, line))?;
+            record.moves.push(m);
+        }
+
+        Ok(record)
+    }
+
+    /// Parse a single non-empty line of the transcript, see to_transcript for
+    /// the format. The move number must be equal to the given one.
+    fn parse_transcript_line(line: &str, move_num: usize) -> Result<RecordedMove> {
+        // The comment is always the last, and it's the only part which can
+        // contain spaces, so cut it off first.
+        let (line, comment) = match line.find('{') {
+            Some(idx) => {
+                let comment = line[idx + 1..]
+                    .strip_suffix('}')
+                    .ok_or_else(|| anyhow!("unterminated comment"))?;
+                (&line[..idx], Some(unescape_comment(comment)?))
+            }
+            None => (line, None),
+        };
+
+        let mut parts = line.split_whitespace();
+        let mut next_part = |what: &str| parts.next().ok_or_else(|| anyhow!("no {}", what));
+
+        let num = next_part("move number")?;
+        if num != format!("{}.", move_num) {
+            return Err(anyhow!("expected move number {}, got {:?}", move_num, num));
+        }
+
+        let side: Side = next_part("side")?.parse()?;
+        let pole: PoleCoords = next_part("pole")?.parse()?;
+
+        let mut annotation = Annotation {
+            comment,
+            ..Default::default()
+        };
+
+        // The rest is optional: the mark, then eval and clock.
+        let rest: Vec<&str> = parts.collect();
+        let mut rest = rest.as_slice();
+
+        if let Some(v) = rest.first() {
+            if !v.starts_with('[') {
+                annotation.mark = Some(v.parse()?);
+                rest = &rest[1..];
+            }
+        }
+
+        while !rest.is_empty() {
+            if rest.len() < 2 {
+                return Err(anyhow!("unexpected {:?}", rest[0]));
+            }
+
+            let val = rest[1]
+                .strip_suffix(']')
+                .ok_or_else(|| anyhow!("expected ], got {:?}", rest[1]))?;
+
+            match rest[0] {
+                "[eval" => {
+                    annotation.eval = Some(val.parse()?);
+                }
+                "[clock" => {
+                    annotation.clock = Some(parse_clock(val)?);
+                }
+                v => {
+                    return Err(anyhow!("unexpected {:?}", v));
+                }
+            }
+
+            rest = &rest[2..];
+        }
+
+        Ok(RecordedMove {
+            side,
+            pole,
+            annotation,
+        })
+    }
+}
+
+/// Escape the comment for the transcript, see to_transcript.
+fn escape_comment(comment: &str) -> String {
+    let mut s = String::with_capacity(comment.len());
+    for c in comment.chars() {
+        match c {
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '\r' => s.push_str("\\r"),
+            c => s.push(c),
+        }
+    }
+
+    s
+}
+
+/// Undo escape_comment.
+fn unescape_comment(s: &str) -> Result<String> {
+    let mut comment = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            comment.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('\\') => comment.push('\\'),
+            Some('n') => comment.push('\n'),
+            Some('r') => comment.push('\r'),
+            Some(c) => return Err(anyhow!("invalid escape \\{} in the comment", c)),
+            None => return Err(anyhow!("unterminated escape in the comment")),
+        }
+    }
+
+    Ok(comment)
+}
+
+/// Parse clock time as written by to_transcript: seconds and milliseconds,
+/// like "83.400".
+fn parse_clock(s: &str) -> Result<Duration> {
+    let (secs, millis) = s
+        .split_once('.')
+        .ok_or_else(|| anyhow!("invalid clock {:?}", s))?;
+
+    if millis.len() != 3 {
+        return Err(anyhow!("invalid clock {:?}", s));
+    }
+
+    let secs: u64 = secs.parse()?;
+    let millis: u64 = millis.parse()?;
+
+    Ok(Duration::from_millis(secs * 1000 + millis))
+}
+
+impl fmt::Display for MoveMark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            MoveMark::Brilliant => "!!",
+            MoveMark::Good => "!",
+            MoveMark::Interesting => "!?",
+            MoveMark::Dubious => "?!",
+            MoveMark::Mistake => "?",
+            MoveMark::Blunder => "??",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for MoveMark {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "!!" => Ok(MoveMark::Brilliant),
+            "!" => Ok(MoveMark::Good),
+            "!?" => Ok(MoveMark::Interesting),
+            "?!" => Ok(MoveMark::Dubious),
+            "?" => Ok(MoveMark::Mistake),
+            "??" => Ok(MoveMark::Blunder),
+            _ => Err(anyhow!("invalid move mark {:?}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_with_comments(comments: &[&str]) -> GameRecord {
+        let mut record = GameRecord::new();
+        for (i, comment) in comments.iter().enumerate() {
+            let side = if i % 2 == 0 { Side::White } else { Side::Black };
+            record.push(side, PoleCoords::new(i % 4, i / 4));
+            let annotation = Annotation {
+                comment: Some(comment.to_string()),
+                ..Annotation::default()
+            };
+            record.annotate(i, annotation).unwrap();
+        }
+
+        record
+    }
+
+    #[test]
+    fn transcript_round_trip() {
+        let mut record = GameRecord::new();
+        record.push(Side::White, PoleCoords::new(1, 2));
+        record.push(Side::Black, PoleCoords::new(0, 0));
+        record.push(Side::White, PoleCoords::new(3, 3));
+        let annotation = Annotation {
+            mark: Some(MoveMark::Interesting),
+            comment: Some("a comment".to_string()),
+            eval: Some(-12),
+            clock: Some(Duration::from_millis(83400)),
+        };
+        record.annotate(0, annotation).unwrap();
+        let annotation = Annotation {
+            mark: Some(MoveMark::Blunder),
+            ..Annotation::default()
+        };
+        record.annotate(2, annotation).unwrap();
+
+        let transcript = record.to_transcript();
+        assert_eq!(
+            transcript.lines().next(),
+            Some("1. White B3 !? [eval -12] [clock 83.400] {a comment}")
+        );
+        assert_eq!(GameRecord::from_transcript(&transcript).unwrap(), record);
+    }
+
+    #[test]
+    fn transcript_round_trip_with_special_characters() {
+        let record = record_with_comments(&[
+            "two\nlines",
+            "windows\r\nline break",
+            "\n",
+            "back\\slash",
+            "\\n is not a line break",
+            "ends with a backslash\\",
+            "{braces} and [brackets]",
+            "}",
+            "",
+            "  spaces around  ",
+            "unicode: \u{2654} \u{00e9}",
+        ]);
+
+        let transcript = record.to_transcript();
+        assert_eq!(transcript.lines().count(), record.moves.len());
+        assert_eq!(GameRecord::from_transcript(&transcript).unwrap(), record);
+    }
+
+    #[test]
+    fn transcript_rejects_invalid_escapes() {
+        assert!(GameRecord::from_transcript("1. White A1 {\\x}").is_err());
+        assert!(GameRecord::from_transcript("1. White A1 {ends with \\}").is_err());
+    }
+}
@@ -1,10 +1,86 @@
+pub mod announce;
+pub mod clock;
+pub mod player_ai;
+#[cfg(feature = "external")]
+pub mod player_external;
 pub mod player_local;
+pub mod player_random;
+pub mod player_replay;
+pub mod player_scripted;
+#[cfg(feature = "net")]
 pub mod player_ws_client;
+#[cfg(feature = "record")]
+pub mod record;
+pub mod save;
+pub mod series;
+#[cfg(test)]
+mod testing;
+#[cfg(test)]
+mod tests;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, Context, Result};
-use tokio::sync::mpsc;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::time;
 
+use super::engine::solver::{self, Outcome, Solution};
+use super::engine::{review, Engine, EngineKind, MoveScore, SearchLimits};
 use super::game;
+use super::game::eval::Style;
+use super::game::record::GameRecord;
+
+// GameState belongs to the rules, but it's used all over the GameManager API,
+// so it's available from here too.
+pub use game::GameState;
+
+/// Depth and time limit of the search for hints, see
+/// UIToGameManager::RequestHint.
+const HINT_DEPTH: usize = 4;
+const HINT_TIME_LIMIT: Duration = Duration::from_secs(2);
+
+/// Before asking the engine for a hint, the solver looks this many plies
+/// ahead for a forced win, see engine::solver.
+const HINT_SOLVE_PLIES: usize = 5;
+
+/// Depth of the search for every move, see UIToGameManager::RequestAnalysis.
+/// Every move is searched without pruning, so it's shallower than for hints.
+const ANALYSIS_DEPTH: usize = 3;
+
+/// Depth and time limit of the search for the evaluation of the position, see
+/// UIToGameManager::SetEvaluation. It runs after every move, so it's quicker
+/// than the one for hints.
+const EVALUATION_DEPTH: usize = 4;
+const EVALUATION_TIME_LIMIT: Duration = Duration::from_secs(1);
+
+/// How often to check the clock of the side to move in a timed game, see
+/// GameManager::set_time_control; it's also how late the flag can fall.
+const CLOCK_TICK: Duration = Duration::from_millis(100);
+
+/// How many events a subscriber can fall behind before it starts missing
+/// them, see GameManager::subscribe_events.
+const EVENTS_CAPACITY: usize = 256;
+
+/// How long to wait for the players to wrap up on shutdown, see
+/// UIToGameManager::Shutdown.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long the result of a game stays on the board before the next game of
+/// a match starts, see GameManager::set_match.
+const NEXT_MATCH_GAME_DELAY: Duration = Duration::from_secs(5);
+
+/// Depth of the search for every move of the post-game review, see
+/// GameManagerToUI::GameReview.
+const REVIEW_DEPTH: usize = 3;
 
 /// Game manager which orchestrates the game between the UI and two players. It
 /// communicates with the players and UI via the channels, see
@@ -13,11 +89,144 @@ pub struct GameManager {
     /// Current game and its state.
     game: game::Game,
     game_state: Option<GameState>,
+    /// Moves made since the board was last reset, and the board at that
+    /// moment; the game is reviewed from there once it's over, and the UI
+    /// can ask for the moves, see UIToGameManager::RequestMoveHistory.
+    history: Vec<HistoryMove>,
+    history_start: game::BoardState,
 
-    /// Sender to the UI.
+    /// Sender to and receiver from the UI.
     to_ui: mpsc::Sender<GameManagerToUI>,
-    /// Contexts of both players.
-    players: [PlayerCtx; 2],
+    from_ui: mpsc::Receiver<UIToGameManager>,
+    /// Other frontends which watch the game, see add_ui_subscriber, and the
+    /// channel of the ones which join while the game goes on, see
+    /// ui_subscriptions.
+    ui_subscribers: Vec<mpsc::Sender<GameManagerToUI>>,
+    new_ui_subscribers_tx: mpsc::Sender<mpsc::Sender<GameManagerToUI>>,
+    new_ui_subscribers_rx: mpsc::Receiver<mpsc::Sender<GameManagerToUI>>,
+    /// Sender of the game events, once someone subscribes, see
+    /// subscribe_events.
+    events: Option<broadcast::Sender<GameEvent>>,
+    /// Read-only view of the current board for the UI, so that it can always
+    /// re-derive what it shows from the authoritative state.
+    board_watch: watch::Sender<game::BoardState>,
+    /// Contexts of the players: the primary and the secondary one, and in a
+    /// team game, their teammates, see set_teammates.
+    players: Vec<PlayerCtx>,
+
+    /// Engine to search for hints and analysis with. The searches run in the
+    /// background, and send the results back via the searches channel.
+    engine: Arc<dyn Engine>,
+    /// See EngineKind::blunder_threshold.
+    blunder_threshold: i32,
+    /// Whether to evaluate the position after every move, see
+    /// UIToGameManager::SetEvaluation.
+    evaluation: bool,
+    /// Board which the engine is searching a hint for, if any, so that asking
+    /// again meanwhile doesn't start another search, see
+    /// UIToGameManager::RequestHint.
+    hint_search: Option<game::BoardState>,
+    /// Side which has offered a draw, if the offer still stands, see
+    /// PlayerToGameManager::OfferDraw.
+    draw_offer: Option<game::Side>,
+    /// Side which has asked to take back its last move, if the request still
+    /// stands, see PlayerToGameManager::RequestUndo.
+    undo_request: Option<game::Side>,
+    /// Which players have agreed to start a new game, see
+    /// UIToGameManager::NewGame; in a team game, it's per team.
+    rematch: [bool; 2],
+    /// Whether the game is paused, see UIToGameManager::Pause; and which
+    /// players have had a move refused meanwhile, so that they're reset to
+    /// the actual board on resume.
+    paused: bool,
+    refused_while_paused: Vec<bool>,
+    /// Whether the primary player stands for a remote user, see
+    /// set_network_game.
+    network_game: bool,
+    /// Handicap which every new game starts with, if any, see set_handicap;
+    /// and how many of the moves since the history start are the handicap
+    /// setup of the current game, see FullGameState::setup_tokens.
+    handicap: Option<game::handicap::Handicap>,
+    setup_moves: usize,
+    /// Number of random moves which every game starts with, see
+    /// set_random_opening.
+    random_opening: usize,
+    /// How to pick the side of the primary player for every new game, if at
+    /// all, see set_side_choice.
+    side_choice: Option<SideChoice>,
+    /// Whether the players swap sides for every new game, see
+    /// set_alternate_sides.
+    alternate_sides: bool,
+    /// Clocks of both sides, if the game is timed, see set_time_control; and
+    /// what everyone was last told about them, see send_clocks.
+    clocks: Option<clock::Clocks>,
+    clocks_shown: Option<(u64, u64, Option<game::Side>)>,
+    /// Time taken off the clock for every peek at the board in blindfold
+    /// mode, see set_peek_penalty; and how many times the user has peeked in
+    /// the current game, see UIToGameManager::Peek.
+    peek_penalty: Duration,
+    peeks: u32,
+    /// Which players report their own clocks, e.g. the network player on
+    /// behalf of the remote user, see PlayerToGameManager::ClockSync. Their
+    /// sides only lose on time once they say so.
+    clocks_synced: [bool; 2],
+    /// Score of the match, if the games are played as one, see set_match; and
+    /// when its next game starts, once the previous one is over.
+    match_score: Option<series::MatchScore>,
+    next_match_game: Option<time::Instant>,
+    /// Results of the games played so far, see GameManagerToUI::SessionStats;
+    /// and the result of the current game, once it's counted there: the index
+    /// of the winner, or None for a draw.
+    session_stats: series::SessionStats,
+    session_result: Option<Option<usize>>,
+    /// File to save the game to whenever its state changes, if any, see
+    /// set_save_path, and whether it's only for crash recovery, see
+    /// set_autosave_path; and the game being resumed, until its board is set
+    /// up, see resume.
+    save_path: Option<PathBuf>,
+    autosave: bool,
+    resumed: Option<save::SavedGame>,
+    /// Whether the app is shutting down, see UIToGameManager::Shutdown; once
+    /// set, run returns.
+    shutdown: bool,
+    searches_tx: mpsc::Sender<SearchDone>,
+    searches_rx: mpsc::Receiver<SearchDone>,
+    /// Generator of the random choices which the GameManager makes itself,
+    /// seeded from GameManagerConfig::seed.
+    rng: StdRng,
+    /// When the GameManager was created, both as the Instant and the wall
+    /// clock time, see wall_time; and the time as of which the event at hand
+    /// is handled, see update_now.
+    created_at: time::Instant,
+    created_wall: SystemTime,
+    now: time::Instant,
+
+    /// Time of the event to be replayed next, see record::replay.
+    #[cfg(feature = "record")]
+    replay_now: Option<time::Instant>,
+    /// Config which GameManager was created with; only needed to record it.
+    #[cfg(feature = "record")]
+    config: GameManagerConfig,
+    /// If set, all the traffic is recorded there, see set_recorder.
+    #[cfg(feature = "record")]
+    recorder: Option<record::Recorder>,
+}
+
+/// Configuration of the GameManager, which stays the same during its whole
+/// lifetime.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct GameManagerConfig {
+    /// Rules of the game.
+    pub rules: game::Rules,
+    /// Engine to search for hints and analysis with, see
+    /// UIToGameManager::RequestHint and UIToGameManager::RequestAnalysis.
+    pub engine: EngineKind,
+    /// Seed of the random choices which the GameManager makes itself, e.g.
+    /// the random opening (see GameManager::set_random_opening); if None,
+    /// it's random. The seed is recorded either way, so that the replay
+    /// makes the same choices, see record::replay.
+    #[serde(default)]
+    pub seed: Option<u64>,
 }
 
 /// Context of a single player.
@@ -26,12 +235,30 @@ struct PlayerCtx {
     side: Option<game::Side>,
     /// Player's current state.
     state: PlayerState,
+    /// Player's display name, if it has told it, see
+    /// PlayerToGameManager::SetName.
+    name: Option<String>,
 
     /// Sender to and receiver from the player.
     to: mpsc::Sender<GameManagerToPlayer>,
     from: mpsc::Receiver<PlayerToGameManager>,
 }
 
+impl PlayerCtx {
+    fn new(
+        to: mpsc::Sender<GameManagerToPlayer>,
+        from: mpsc::Receiver<PlayerToGameManager>,
+    ) -> PlayerCtx {
+        PlayerCtx {
+            state: PlayerState::NotReady("unknown".to_string()),
+            name: None,
+            side: None,
+            to,
+            from,
+        }
+    }
+}
+
 impl GameManager {
     /// Creates a new GameManager, which will communicate with the UI and
     /// players using the given channels.
@@ -40,198 +267,1862 @@ impl GameManager {
     /// listen to it when it says to reset the whole game. As such, in a network
     /// game, the network player has to be primary (p0), and local will be
     /// secondary (p1). See more details in PlayerToGameManager::SetFullGameState.
+    ///
+    /// The board_watch is updated with the current board whenever it changes.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         to_ui: mpsc::Sender<GameManagerToUI>,
+        from_ui: mpsc::Receiver<UIToGameManager>,
+        board_watch: watch::Sender<game::BoardState>,
 
         to_p0: mpsc::Sender<GameManagerToPlayer>,
         from_p0: mpsc::Receiver<PlayerToGameManager>,
 
         to_p1: mpsc::Sender<GameManagerToPlayer>,
         from_p1: mpsc::Receiver<PlayerToGameManager>,
+
+        config: GameManagerConfig,
     ) -> GameManager {
-        let p0 = PlayerCtx {
-            state: PlayerState::NotReady("unknown".to_string()),
-            side: None,
-            to: to_p0,
-            from: from_p0,
+        let (searches_tx, searches_rx) = mpsc::channel(1);
+        let (new_ui_subscribers_tx, new_ui_subscribers_rx) = mpsc::channel(1);
+        let seed = config.seed.unwrap_or_else(rand::random);
+        let now = time::Instant::now();
+
+        GameManager {
+            game: game::Game::with_rules(config.rules),
+            // Hints and analysis are shallow, so a single thread is plenty.
+            engine: config.engine.new_engine(1),
+            blunder_threshold: config.engine.blunder_threshold(),
+            evaluation: false,
+            hint_search: None,
+            draw_offer: None,
+            undo_request: None,
+            rematch: [false; 2],
+            paused: false,
+            refused_while_paused: vec![false; 2],
+            network_game: false,
+            handicap: None,
+            setup_moves: 0,
+            random_opening: 0,
+            side_choice: None,
+            alternate_sides: false,
+            clocks: None,
+            clocks_shown: None,
+            peek_penalty: Duration::ZERO,
+            peeks: 0,
+            clocks_synced: [false; 2],
+            match_score: None,
+            next_match_game: None,
+            session_stats: series::SessionStats::default(),
+            session_result: None,
+            save_path: None,
+            autosave: false,
+            resumed: None,
+            shutdown: false,
+
+            game_state: None,
+            history: Vec::new(),
+            history_start: game::BoardState::new(),
+
+            to_ui,
+            ui_subscribers: Vec::new(),
+            new_ui_subscribers_tx,
+            new_ui_subscribers_rx,
+            events: None,
+            from_ui,
+            board_watch,
+            players: vec![
+                PlayerCtx::new(to_p0, from_p0),
+                PlayerCtx::new(to_p1, from_p1),
+            ],
+
+            searches_tx,
+            searches_rx,
+            rng: StdRng::seed_from_u64(seed),
+            created_at: now,
+            created_wall: SystemTime::now(),
+            now,
+
+            #[cfg(feature = "record")]
+            replay_now: None,
+            #[cfg(feature = "record")]
+            config: GameManagerConfig {
+                seed: Some(seed),
+                ..config
+            },
+            #[cfg(feature = "record")]
+            recorder: None,
+        }
+    }
+
+    /// Makes it a team game, two against two: the given players (A2 and B2,
+    /// see seat_name) join the primary and the secondary one (A1 and B1) on
+    /// their sides, and the teammates take turns at their side's moves, so
+    /// the seats go A1, B1, A2, B2 (if A has the first move). Only the
+    /// primary player can set up the game, just like always.
+    pub fn set_teammates(
+        &mut self,
+        to_p2: mpsc::Sender<GameManagerToPlayer>,
+        from_p2: mpsc::Receiver<PlayerToGameManager>,
+        to_p3: mpsc::Sender<GameManagerToPlayer>,
+        from_p3: mpsc::Receiver<PlayerToGameManager>,
+    ) {
+        self.players.truncate(2);
+        self.players.push(PlayerCtx::new(to_p2, from_p2));
+        self.players.push(PlayerCtx::new(to_p3, from_p3));
+        self.refused_while_paused = vec![false; self.players.len()];
+    }
+
+    /// Name of the seat of the player with the given index in a team game,
+    /// see set_teammates: A1, B1, A2 or B2.
+    pub fn seat_name(i: usize) -> String {
+        format!("{}{}", (b'A' + (i % 2) as u8) as char, i / 2 + 1)
+    }
+
+    /// Makes the GameManager pick the side of the primary player for every new
+    /// game (see UIToGameManager::NewGame) as given, e.g. a random one every
+    /// time. The first game is up to the primary player, like always; normally
+    /// it's given a side picked the same way. By default, new games keep the
+    /// sides of the previous one.
+    pub fn set_side_choice(&mut self, side_choice: SideChoice) {
+        self.side_choice = Some(side_choice);
+    }
+
+    /// Makes the players swap sides for every new game (see
+    /// UIToGameManager::NewGame), like people usually play a series of games.
+    /// It takes precedence over set_side_choice, which then only applies to
+    /// the first game.
+    pub fn set_alternate_sides(&mut self, alternate_sides: bool) {
+        self.alternate_sides = alternate_sides;
+    }
+
+    /// Makes the GameManager send everything it sends to the UI to the given
+    /// sender too, e.g. for a headless logger, or another frontend watching
+    /// the same game. Subscribers only watch: they can't send anything to the
+    /// GameManager, and they can't hold up the game either: one which drops
+    /// its receiver, or lets its channel fill up, is just forgotten, while
+    /// the game goes on. First, the subscriber gets the whole current state,
+    /// see ui_snapshot, so it doesn't need to be there from the start; to add
+    /// one while the game goes on, see ui_subscriptions.
+    pub fn add_ui_subscriber(&mut self, to_ui: mpsc::Sender<GameManagerToUI>) {
+        for msg in self.ui_snapshot() {
+            if to_ui.try_send(msg).is_err() {
+                return;
+            }
+        }
+
+        self.ui_subscribers.push(to_ui);
+    }
+
+    /// Returns the sender through which the UI subscribers can be added
+    /// while the GameManager runs, just like with add_ui_subscriber.
+    pub fn ui_subscriptions(&self) -> mpsc::Sender<mpsc::Sender<GameManagerToUI>> {
+        self.new_ui_subscribers_tx.clone()
+    }
+
+    /// Returns the messages which bring a new UI subscriber, which has seen
+    /// nothing yet, up to date: the players, the whole board (it's against
+    /// the empty one, since GameManagerToUI::ResetBoard is only a diff), and
+    /// the state of the game, see add_ui_subscriber.
+    fn ui_snapshot(&self) -> Vec<GameManagerToUI> {
+        let mut msgs = Vec::new();
+        for (i, p) in self.players.iter().enumerate() {
+            msgs.push(GameManagerToUI::PlayerStateChanged(i, p.state.clone()));
+            if let Some(name) = &p.name {
+                msgs.push(GameManagerToUI::PlayerNameChanged(i, name.clone()));
+            }
+        }
+
+        // Until the first game starts, there's nothing else to show.
+        let game_state = match self.game_state {
+            Some(game_state) => game_state,
+            None => return msgs,
+        };
+
+        let rules = self.game.get_rules();
+        let diff = game::BoardState::new().diff(self.game.get_board());
+        msgs.push(GameManagerToUI::ResetBoard(rules, diff));
+        if let (Some(pri_side), Some(sec_side)) = (self.players[0].side, self.players[1].side) {
+            msgs.push(GameManagerToUI::PlayerSidesChanged(pri_side, sec_side));
+        }
+        if let Some(score) = self.match_score {
+            msgs.push(GameManagerToUI::MatchScore(score));
+        }
+        msgs.push(GameManagerToUI::SessionStats(self.session_stats));
+
+        msgs.push(GameManagerToUI::GameStateChanged(
+            game_state,
+            self.history.len(),
+        ));
+        if let (true, Some(i)) = (self.players.len() > 2, self.player_to_move()) {
+            msgs.push(GameManagerToUI::SeatToMove(i));
+        }
+        if let Some(win_row) = self.game.get_win_row().clone() {
+            msgs.push(GameManagerToUI::WinRow(win_row));
+        }
+        msgs.push(GameManagerToUI::LegalMovesChanged(self.game.legal_moves()));
+
+        if let Some(clocks) = &self.clocks {
+            msgs.push(GameManagerToUI::ClockUpdate(clocks.times(self.now)));
+        }
+        if self.paused {
+            msgs.push(GameManagerToUI::Paused(true));
+        }
+        if self.peeks > 0 {
+            msgs.push(GameManagerToUI::Peeks(self.peeks));
+        }
+
+        msgs
+    }
+
+    /// Returns the receiver of the game events, see GameEvent. Unlike the UI
+    /// messages, the events don't hold up the game: a subscriber which falls
+    /// behind by more than EVENTS_CAPACITY events misses some (and its
+    /// receiver says so, see broadcast::Receiver::recv).
+    pub fn subscribe_events(&mut self) -> broadcast::Receiver<GameEvent> {
+        self.events
+            .get_or_insert_with(|| broadcast::channel(EVENTS_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Sends the game event to the subscribers, if any, see subscribe_events.
+    fn emit(&self, event: GameEvent) {
+        if let Some(events) = &self.events {
+            // It's only an error if nobody is subscribed at the moment.
+            let _ = events.send(event);
+        }
+    }
+
+    /// Makes every new game (see UIToGameManager::NewGame) start with the
+    /// given handicap setup, normally the same one as the first game. By
+    /// default, new games start on an empty board.
+    pub fn set_handicap(&mut self, handicap: game::handicap::Handicap) {
+        self.handicap = Some(handicap);
+    }
+
+    /// Marks the game as a network one: the primary player stands for the
+    /// remote user, whose game goes on regardless of what happens here. So
+    /// the game can't be paused, see UIToGameManager::Pause.
+    pub fn set_network_game(&mut self) {
+        self.network_game = true;
+    }
+
+    /// Makes every game start with the given number of random moves, which
+    /// the GameManager plays itself before the players take over, for the
+    /// variety: e.g. against the AI, or to get diverse self-play games. The
+    /// moves are picked among the legal ones which don't end the game. A
+    /// resumed game, or the one with the handicap setup to do (see
+    /// FullGameState::setup_tokens), starts as it is. Only meant for local
+    /// games: a network game is set up by the server.
+    pub fn set_random_opening(&mut self, plies: usize) {
+        self.random_opening = plies;
+    }
+
+    /// Makes the games timed, with the given time control: the clock of the
+    /// side to move runs, and once it runs out of time, the side loses. The
+    /// UI and the players get GameManagerToUI::ClockUpdate and
+    /// GameManagerToPlayer::ClockUpdate, and the player to move gets its
+    /// clock with every turn too, see PlayerConfig::ai_clock. By default, the
+    /// games are not timed.
+    pub fn set_time_control(&mut self, time_control: clock::TimeControl) {
+        self.clocks = Some(clock::Clocks::new(time_control));
+    }
+
+    /// Sets the time which every peek at the board in blindfold mode takes
+    /// off the clock of the peeking side, see UIToGameManager::Peek. It only
+    /// matters in a timed game, see set_time_control; by default, peeks are
+    /// free.
+    pub fn set_peek_penalty(&mut self, penalty: Duration) {
+        self.peek_penalty = penalty;
+    }
+
+    /// Makes the games a best-of-N match (see series::MatchScore): after every
+    /// game, the UI gets GameManagerToUI::MatchScore, and unless the match is
+    /// decided, the next game starts by itself NEXT_MATCH_GAME_DELAY later, as
+    /// if both players agreed to it (see UIToGameManager::NewGame). By
+    /// default, every game stands on its own.
+    pub fn set_match(&mut self, best_of: u32) {
+        self.match_score = Some(series::MatchScore::new(best_of));
+    }
+
+    /// Makes the GameManager save the game to the given file whenever its
+    /// state changes, so that it can be resumed later, see resume. Failures
+    /// to save are only printed, the game goes on.
+    pub fn set_save_path(&mut self, path: PathBuf) {
+        self.save_path = Some(path);
+    }
+
+    /// Like set_save_path, but the file is only meant for recovering from a
+    /// crash: once the game is over, the file is deleted, so that there's
+    /// only ever an unfinished game to resume. Also, nothing is saved until
+    /// the first move.
+    pub fn set_autosave_path(&mut self, path: PathBuf) {
+        self.save_path = Some(path);
+        self.autosave = true;
+    }
+
+    /// Resumes the saved game: once the primary player sets up its board
+    /// (normally, it's given saved.state as the initial state), the move
+    /// history is taken from the saved game too, so the undo and the review
+    /// work as if the game was never interrupted.
+    pub fn resume(&mut self, saved: save::SavedGame) {
+        self.resumed = Some(saved);
+    }
+
+    /// Starts recording all the messages that GameManager receives and sends,
+    /// see record::Recorder. Must be called before run, so that the recording
+    /// can later be replayed from the very beginning by record::replay.
+    #[cfg(feature = "record")]
+    pub fn set_recorder(&mut self, recorder: record::Recorder) {
+        self.recorder = Some(recorder);
+        self.record(record::Event::Start(self.config.clone()));
+    }
+
+    /// Records the event, if recording is on, along with the time as of
+    /// which it's handled, see update_now. Failure to record doesn't stop the
+    /// game; it just stops the recording.
+    #[cfg(feature = "record")]
+    fn record(&mut self, event: record::Event) {
+        let at = self.now - self.created_at;
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(err) = recorder.record(at, event) {
+                println!("failed to record, stopping recording: {:#}", err);
+                self.recorder = None;
+            }
+        }
+    }
+
+    /// What the GameManager was set up with before it started running, see
+    /// record::Setup.
+    #[cfg(feature = "record")]
+    fn setup(&self) -> record::Setup {
+        record::Setup {
+            teams: self.players.len() > 2,
+            side_choice: self.side_choice,
+            alternate_sides: self.alternate_sides,
+            handicap: self.handicap,
+            random_opening: self.random_opening,
+            time_control: self.clocks.as_ref().map(|clocks| clocks.time_control()),
+            peek_penalty: self.peek_penalty,
+            best_of: self.match_score.map(|score| score.best_of),
+            resumed: self.resumed.clone(),
+            network_game: self.network_game,
+            created_wall: self.created_wall,
+        }
+    }
+
+    /// Sets the time as of which the next event is handled, so that it's
+    /// the same all the way through, and the time the event is recorded with
+    /// (see record) is exactly the one the clocks see. Normally it's just
+    /// the current time, but while replaying, it's the recorded one.
+    fn update_now(&mut self) {
+        #[cfg(feature = "record")]
+        if let Some(now) = self.replay_now.take() {
+            self.now = now;
+            return;
+        }
+
+        self.now = time::Instant::now();
+    }
+
+    /// Wall clock time as of the event at hand, see update_now; it follows
+    /// the same clock, so the replay gets the same time as recorded.
+    fn wall_time(&self) -> SystemTime {
+        self.created_wall + (self.now - self.created_at)
+    }
+
+    /// Sends the message to the player with the given index.
+    async fn send_to_player(&mut self, i: usize, msg: GameManagerToPlayer) -> Result<()> {
+        // A player which is gone won't get it anyway.
+        if self.is_player_gone(i) {
+            return Ok(());
+        }
+
+        #[cfg(feature = "record")]
+        self.record(record::Event::ToPlayer(i, msg.clone()));
+
+        // The player might have stopped before we've noticed its channel
+        // closing; not an error either, see handle_player_gone.
+        if self.players[i].to.send(msg).await.is_err() {
+            self.handle_player_gone(i).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends the message to the UI.
+    async fn send_to_ui(&mut self, msg: GameManagerToUI) -> Result<()> {
+        #[cfg(feature = "record")]
+        self.record(record::Event::ToUI(msg.clone()));
+
+        // A subscriber which is gone, or has fallen too far behind, is
+        // dropped rather than waited for, see add_ui_subscriber.
+        self.ui_subscribers
+            .retain(|to_ui| to_ui.try_send(msg.clone()).is_ok());
+
+        self.to_ui.send(msg).await.context("updating UI")?;
+
+        Ok(())
+    }
+
+    /// Event loop, runs until UIToGameManager::Shutdown, should be swapned by
+    /// the client code as a separate task.
+    pub async fn run(&mut self) -> Result<()> {
+        let mut clock_interval = time::interval(CLOCK_TICK);
+
+        #[cfg(feature = "record")]
+        {
+            self.update_now();
+            let setup = self.setup();
+            self.record(record::Event::Setup(setup));
+        }
+
+        while !self.shutdown {
+            let timed = self.clocks.is_some();
+            let next_match_game = self
+                .next_match_game
+                .map(|at| at.saturating_duration_since(time::Instant::now()));
+            // There are only 2 players, unless it's a team game.
+            let listen: Vec<bool> = (0..4)
+                .map(|i| i < self.players.len() && !self.is_player_gone(i))
+                .collect();
+            let mut players = self.players.iter_mut();
+            let (p0_mut, p1_mut, p2_mut, p3_mut) = (
+                players.next(),
+                players.next(),
+                players.next(),
+                players.next(),
+            );
+
+            tokio::select! {
+                val = Self::recv_from(p0_mut), if listen[0] => {
+                    self.handle_player_recv(0, val).await?;
+                }
+
+                val = Self::recv_from(p1_mut), if listen[1] => {
+                    self.handle_player_recv(1, val).await?;
+                }
+
+                val = Self::recv_from(p2_mut), if listen[2] => {
+                    self.handle_player_recv(2, val).await?;
+                }
+
+                val = Self::recv_from(p3_mut), if listen[3] => {
+                    self.handle_player_recv(3, val).await?;
+                }
+
+                val = self.from_ui.recv() => {
+                    // If the UI is gone, there's nobody to play for.
+                    let val = val.unwrap_or(UIToGameManager::Shutdown);
+                    self.handle_ui_msg(val).await?;
+                }
+
+                Some(val) = self.searches_rx.recv() => {
+                    self.handle_search_done(val).await?;
+                }
+
+                Some(to_ui) = self.new_ui_subscribers_rx.recv() => {
+                    self.update_now();
+                    self.add_ui_subscriber(to_ui);
+                }
+
+                _ = clock_interval.tick(), if timed => {
+                    self.handle_clock_tick().await?;
+                }
+
+                _ = time::sleep(next_match_game.unwrap_or_default()), if next_match_game.is_some() => {
+                    self.handle_next_match_game().await?;
+                }
+            }
+        }
+
+        // Let the players wrap up: they might still be sending something,
+        // e.g. the AI finishing its move, and would fail if nobody listened.
+        // Once a player returns from its run, its channel closes.
+        let drain = async {
+            for p in &mut self.players {
+                while p.from.recv().await.is_some() {}
+            }
+        };
+        if time::timeout(SHUTDOWN_DRAIN_TIMEOUT, drain).await.is_err() {
+            println!("not all players have wrapped up in time");
+        }
+
+        Ok(())
+    }
+
+    /// Propagate current game state to both players and the UI. Without a
+    /// game state (e.g. the primary player's one was rejected), there's
+    /// nothing to propagate.
+    async fn propagate_game_state_change(&mut self) -> Result<()> {
+        let gs = match self.game_state {
+            Some(gs) => gs,
+            None => return Ok(()),
+        };
+
+        self.update_clocks().await?;
+
+        let moves = self.history.len();
+        let to_move = self.player_to_move();
+
+        for i in 0..self.players.len() {
+            // In a team game, the teammate who sits this move out isn't told
+            // that it's their side's turn, or they'd try to move too.
+            let sits_out = matches!(gs, GameState::WaitingFor(side, _) if self.players[i].side == Some(side))
+                && to_move != Some(i);
+            if sits_out {
+                continue;
+            }
+
+            self.send_to_player(i, GameManagerToPlayer::GameStateChanged(gs, moves))
+                .await?;
+        }
+
+        self.send_to_ui(GameManagerToUI::GameStateChanged(gs, moves))
+            .await?;
+        if let (true, Some(i)) = (self.players.len() > 2, to_move) {
+            self.send_to_ui(GameManagerToUI::SeatToMove(i)).await?;
+        }
+        if let (GameState::WaitingFor(side, _), n @ 1..) = (gs, self.setup_tokens_left()) {
+            let text = announce::handicap_setup(side, n);
+            self.send_to_ui(GameManagerToUI::Notice(text.clone()))
+                .await?;
+            self.send_to_ui(GameManagerToUI::Announcement(text)).await?;
+        }
+        self.emit(GameEvent::StateChanged(gs));
+
+        // Also let the UI know where tokens can be put now, so that it doesn't
+        // even offer to put them on full poles.
+        self.send_to_ui(GameManagerToUI::LegalMovesChanged(self.game.legal_moves()))
+            .await?;
+
+        self.save();
+
+        Ok(())
+    }
+
+    /// Saves the game, if there's a file to save it to, see set_save_path;
+    /// or deletes the file, if it's only for crash recovery, and there's
+    /// nothing to recover, see set_autosave_path.
+    fn save(&self) {
+        let path = match &self.save_path {
+            Some(path) => path,
+            None => return,
+        };
+
+        // With no moves yet, or with the game over, there's nothing to recover.
+        let in_progress = matches!(self.game_state, Some(GameState::WaitingFor(_, _)));
+        if self.autosave && (self.history.is_empty() || !in_progress) {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                    println!("failed to delete the autosave: {}", err);
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        let state = match self.full_game_state(0) {
+            Some(state) => state,
+            None => return,
+        };
+
+        let saved = save::SavedGame {
+            state,
+            history: self.history.clone(),
+            history_start: self.history_start.clone(),
+        };
+
+        if let Err(err) = saved.save(path) {
+            println!("failed to save the game: {:#}", err);
+        }
+    }
+
+    /// If the game is timed, runs the clock of the side to move (or stops
+    /// both, if the game is over or paused), and lets the player whose turn it is know
+    /// its clock, and the UI both of them.
+    async fn update_clocks(&mut self) -> Result<()> {
+        let side = match self.game_state {
+            Some(GameState::WaitingFor(side, _)) if !self.paused => Some(side),
+            _ => None,
+        };
+
+        let clocks = match &mut self.clocks {
+            Some(clocks) => clocks,
+            None => return Ok(()),
+        };
+        clocks.run(side, self.now);
+
+        if let Some(side) = side {
+            let config = PlayerConfig {
+                ai_clock: Some(clocks.state(side, self.now)),
+                ..PlayerConfig::default()
+            };
+            if let Some(i) = self.player_to_move() {
+                self.send_to_player(i, GameManagerToPlayer::Configure(config))
+                    .await?;
+            }
+        }
+
+        self.send_clocks(true).await
+    }
+
+    /// Lets the UI and the players know the clocks, if the game is timed;
+    /// unless forced, only if they show something else than the last time,
+    /// so that nobody is flooded with updates on every tick.
+    async fn send_clocks(&mut self, force: bool) -> Result<()> {
+        let times = match &self.clocks {
+            Some(clocks) => clocks.times(self.now),
+            None => return Ok(()),
+        };
+
+        let secs = |d: Duration| d.as_millis().div_ceil(1000) as u64;
+        let shown = Some((secs(times.white), secs(times.black), times.running));
+        if !force && shown == self.clocks_shown {
+            return Ok(());
+        }
+        self.clocks_shown = shown;
+
+        for i in 0..self.players.len() {
+            self.send_to_player(i, GameManagerToPlayer::ClockUpdate(times))
+                .await?;
+        }
+        self.send_to_ui(GameManagerToUI::ClockUpdate(times)).await
+    }
+
+    /// Called every CLOCK_TICK in a timed game: if the side to move has run
+    /// out of time, it loses (unless its player reports its own clock, see
+    /// handle_clock_sync); otherwise, everyone gets the clocks, if needed.
+    async fn handle_clock_tick(&mut self) -> Result<()> {
+        self.update_now();
+        #[cfg(feature = "record")]
+        self.record(record::Event::ClockTick);
+
+        let flagged = self
+            .clocks
+            .as_ref()
+            .and_then(|clocks| clocks.flagged(self.now))
+            .filter(|&side| {
+                !self
+                    .players
+                    .iter()
+                    .zip(self.clocks_synced)
+                    .any(|(p, synced)| synced && p.side == Some(side))
+            });
+        if let Some(side) = flagged {
+            return self
+                .end_game(
+                    GameState::WonBy(side.opposite(), self.game.get_scores()),
+                    GameOverReason::Timeout(side),
+                    announce::out_of_time(side),
+                )
+                .await;
+        }
+
+        self.send_clocks(false).await
+    }
+
+    /// Called when a player reports the time remaining on its own clock: the
+    /// clock is set to it, and from then on, the player's side only loses on
+    /// time once the player reports zero. That's how the clock of the remote
+    /// user stays in sync, as measured on their end.
+    async fn handle_clock_sync(&mut self, i: usize, remaining: Duration) -> Result<()> {
+        let (clocks, side) = match (&mut self.clocks, self.players[i].side) {
+            (Some(clocks), Some(side)) => (clocks, side),
+            _ => return Ok(()),
         };
 
-        let p1 = PlayerCtx {
-            state: PlayerState::NotReady("unknown".to_string()),
-            side: None,
-            to: to_p1,
-            from: from_p1,
+        self.clocks_synced[i] = true;
+        clocks.set_remaining(side, remaining, self.now);
+
+        if remaining.is_zero() && self.game_state.is_some_and(|gs| gs.is_waiting_for(side)) {
+            return self
+                .end_game(
+                    GameState::WonBy(side.opposite(), self.game.get_scores()),
+                    GameOverReason::Timeout(side),
+                    announce::out_of_time(side),
+                )
+                .await;
+        }
+
+        self.send_clocks(false).await
+    }
+
+    async fn handle_player_state_change(&mut self, i: usize, state: PlayerState) -> Result<()> {
+        // Remember state for the player which sent us the update.
+        self.players[i].state = state.clone();
+
+        // Update UI about the player state
+        self.send_to_ui(GameManagerToUI::PlayerStateChanged(i, state))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Called when the channels to the player are closed, i.e. the player has
+    /// stopped: it's marked as gone, so that nothing is sent to it anymore,
+    /// and the UI is told about it. Once both players are gone, there's
+    /// nothing left to manage, so the GameManager stops, just like on
+    /// UIToGameManager::Shutdown.
+    async fn handle_player_gone(&mut self, i: usize) -> Result<()> {
+        if self.is_player_gone(i) {
+            return Ok(());
+        }
+
+        println!("GM: player {} is gone", i);
+        self.handle_player_state_change(i, PlayerState::Gone)
+            .await?;
+
+        if (0..self.players.len()).all(|i| self.is_player_gone(i)) {
+            println!("GM: both players are gone, stopping");
+            self.shutdown = true;
+        }
+
+        Ok(())
+    }
+
+    fn is_player_gone(&self, i: usize) -> bool {
+        matches!(self.players[i].state, PlayerState::Gone)
+    }
+
+    /// Handles full game reset; it happens when e.g. a network player connected
+    /// to the server and the server has dumped the current game state to it.
+    /// Here we should update internal state, the other player, and the UI.
+    async fn handle_full_game_state(&mut self, i: usize, fgstate: FullGameState) -> Result<()> {
+        if i != 0 {
+            println!(
+                "player {} is not primary, so ignoring its FullGameState update ({:?})",
+                i, fgstate
+            );
+            return Ok(());
+        }
+
+        // Make sure the state makes sense, otherwise refuse it, and let the
+        // player know; the game stays as it was. Just like with other sanity
+        // checks in GameManager, it's not an error.
+        if let Err(err) = self.check_full_game_state(&fgstate) {
+            println!("refusing invalid game state ({:#}): {:?}", err, fgstate);
+            let msg = GameManagerToPlayer::StateRejected(format!("{:#}", err));
+            return self.send_to_player(i, msg).await;
+        }
+
+        self.start_game(fgstate).await
+    }
+
+    /// Checks that the full game state from the primary player is consistent:
+    /// the board makes sense under the rules (see BoardState::sanitize), and
+    /// the game state agrees with the winner recomputed from the board. Also,
+    /// unless there are handicap tokens (see set_handicap and
+    /// FullGameState::setup_tokens), or it's the game being resumed (which
+    /// might have had them), the board has to be reachable by alternating
+    /// play, with the right side to move.
+    fn check_full_game_state(&self, fgstate: &FullGameState) -> Result<()> {
+        let rules = self.game.get_rules();
+        let board = &fgstate.board;
+        board.sanitize(rules)?;
+
+        let mut game = game::Game::with_rules(rules);
+        game.reset_board(board);
+        match (fgstate.game_state, game.winner()) {
+            // The game can be won without a row too, e.g. by a resignation.
+            (GameState::WonBy(side, _), Some(winner)) if side != winner => {
+                return Err(anyhow!("the board is won by {:?}, not {:?}", winner, side));
+            }
+            (GameState::WaitingFor(_, _) | GameState::Draw(_), Some(winner)) => {
+                return Err(anyhow!("the board is won by {:?} already", winner));
+            }
+            (GameState::WaitingFor(_, _), None) if game.is_draw() => {
+                return Err(anyhow!("the board is drawn already"));
+            }
+            _ => {}
+        }
+
+        let resumed = self
+            .resumed
+            .as_ref()
+            .is_some_and(|saved| saved.state.board == *board);
+        if self.handicap.is_some() || fgstate.setup_tokens > 0 || resumed {
+            return Ok(());
+        }
+
+        // White moves first, so it has either as many tokens as Black, or one
+        // more; and the rest of is_reachable only makes sense if there's at
+        // most one completed row.
+        let (white, black) = (
+            board.count(game::Side::White),
+            board.count(game::Side::Black),
+        );
+        let side_to_move = if white == black {
+            game::Side::White
+        } else if white == black + 1 {
+            game::Side::Black
+        } else {
+            return Err(anyhow!(
+                "{} white and {} black tokens can't come from alternating play",
+                white,
+                black
+            ));
+        };
+        if !rules.scoring && !board.is_reachable() {
+            return Err(anyhow!("the board can't come from legal play"));
+        }
+
+        match fgstate.game_state {
+            GameState::WaitingFor(side, _) if side != side_to_move => Err(anyhow!(
+                "it's {:?}'s turn on this board, not {:?}'s",
+                side_to_move,
+                side
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Starts the game from the given state, after the random opening, if
+    /// there's one to play, see set_random_opening.
+    async fn start_game(&mut self, fgstate: FullGameState) -> Result<()> {
+        if self.random_opening == 0 || fgstate.setup_tokens > 0 || self.resumed.is_some() {
+            return self.reset_game(fgstate).await;
+        }
+
+        let (opened, history) = self.play_random_opening(&fgstate);
+        if history.is_empty() {
+            return self.reset_game(fgstate).await;
+        }
+
+        // The opening moves go to the history just like the ones of a resumed
+        // game do, see reset_game; so e.g. the review covers them too.
+        self.resumed = Some(save::SavedGame {
+            state: opened.clone(),
+            history,
+            history_start: fgstate.board,
+        });
+
+        self.reset_game(opened).await
+    }
+
+    /// Plays the random opening (see set_random_opening) from the given
+    /// state, and returns the state after it, along with the moves. If it's
+    /// nobody's turn, or every move would end the game, it's cut short.
+    fn play_random_opening(
+        &mut self,
+        fgstate: &FullGameState,
+    ) -> (FullGameState, Vec<HistoryMove>) {
+        let mut side = match fgstate.game_state {
+            GameState::WaitingFor(side, _) => side,
+            _ => return (fgstate.clone(), Vec::new()),
+        };
+
+        let mut game = game::Game::with_rules(self.game.get_rules());
+        game.reset_board(&fgstate.board);
+        let mut history = Vec::new();
+
+        for _ in 0..self.random_opening {
+            let mut moves = game.legal_moves();
+            moves.shuffle(&mut self.rng);
+
+            let next = moves.into_iter().find_map(|pole| {
+                let mut next = game.clone();
+                let res = next.put_token(side, pole).ok()?;
+                let goes_on = res.winner.is_none() && !res.draw && !next.is_dead_draw();
+
+                goes_on.then_some((next, pole, res.y))
+            });
+            let (next, pole, y) = match next {
+                Some(v) => v,
+                None => break,
+            };
+
+            game = next;
+            history.push(HistoryMove {
+                side,
+                pole,
+                y,
+                time: self.wall_time(),
+            });
+            side = side.opposite();
+        }
+
+        let opened = FullGameState {
+            game_state: GameState::WaitingFor(side, game.get_scores()),
+            board: game.get_board().clone(),
+            ..fgstate.clone()
+        };
+
+        (opened, history)
+    }
+
+    /// Resets the whole game to the given state: updates internal state, both
+    /// players, and the UI.
+    async fn reset_game(&mut self, mut fgstate: FullGameState) -> Result<()> {
+        // Update board state, remembering what has changed, so that the UI
+        // doesn't have to rebuild the whole board.
+        let diff = self.game.get_board().diff(&fgstate.board);
+        self.game.reset_board(&fgstate.board);
+        self.update_board_watch();
+
+        // The scores are counted from the board, whatever the sender says,
+        // see game::Game::reset_board.
+        fgstate.game_state = fgstate.game_state.with_scores(self.game.get_scores());
+
+        // The history only goes on if the board is the same, e.g. when a
+        // network player has reconnected; or if it's the board of the game
+        // being resumed.
+        if !diff.set.is_empty() || !diff.removed.is_empty() {
+            match self.resumed.take() {
+                Some(saved) if saved.state.board == fgstate.board => {
+                    self.history = saved.history;
+                    self.history_start = saved.history_start;
+                }
+                _ => {
+                    self.history = Vec::new();
+                    self.history_start = fgstate.board.clone();
+                }
+            }
+        }
+        self.setup_moves = self.history.len() + fgstate.setup_tokens;
+
+        self.draw_offer = None;
+        self.undo_request = None;
+        self.rematch = [false; 2];
+        self.next_match_game = None;
+        self.session_result = None;
+        self.drop_pause().await?;
+        if let Some(clocks) = &mut self.clocks {
+            clocks.reset();
+        }
+        if self.peeks > 0 {
+            self.peeks = 0;
+            self.send_to_ui(GameManagerToUI::Peeks(0)).await?;
+        }
+
+        // Remember the sides of both players, and reset the game for them.
+        // Normally, the primary player has sent the state, so it knows it
+        // already, but e.g. for a new game, it's the GameManager which resets
+        // it.
+        let opposite_side = fgstate.primary_player_side.opposite();
+        let sides = [fgstate.primary_player_side, opposite_side];
+        for i in 0..self.players.len() {
+            // Teammates share the side, see set_teammates.
+            let side = sides[i % 2];
+            self.players[i].side = Some(side);
+            let rules = self.game.get_rules();
+            let board = fgstate.board.clone();
+            self.send_to_player(i, GameManagerToPlayer::Reset(rules, board, side))
+                .await
+                .context(format!(
+                    "resetting player {}, setting side to {:?}",
+                    i, side
+                ))?;
+        }
+
+        // Update UI.
+        self.send_to_ui(GameManagerToUI::ResetBoard(self.game.get_rules(), diff))
+            .await?;
+
+        if let Some(score) = self.match_score {
+            self.send_to_ui(GameManagerToUI::MatchScore(score)).await?;
+        }
+
+        // Update UI about the player sides.
+        self.send_to_ui(GameManagerToUI::PlayerSidesChanged(
+            fgstate.primary_player_side,
+            opposite_side,
+        ))
+        .await?;
+
+        // Update game state and propagate it to everyone.
+        self.game_state = Some(fgstate.game_state);
+        let announcement = announce::board_reset(&fgstate.board, fgstate.game_state);
+        self.send_to_ui(GameManagerToUI::Announcement(announcement))
+            .await?;
+        self.emit(GameEvent::GameStarted(fgstate));
+        self.propagate_game_state_change()
+            .await
+            .context("initial update")?;
+        self.start_evaluation();
+
+        Ok(())
+    }
+
+    /// Publish the current board to the board watch.
+    fn update_board_watch(&self) {
+        self.board_watch.send_replace(self.game.get_board().clone());
+    }
+
+    /// Receives the next message from the given player, if any; None means
+    /// the player is gone. Without the player at all (e.g. there are no
+    /// teammates), it never returns.
+    async fn recv_from(player: Option<&mut PlayerCtx>) -> Option<PlayerToGameManager> {
+        match player {
+            Some(player) => player.from.recv().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Handles whatever was received from the player, see recv_from.
+    async fn handle_player_recv(
+        &mut self,
+        i: usize,
+        val: Option<PlayerToGameManager>,
+    ) -> Result<()> {
+        match val {
+            Some(val) => self.handle_player_msg(i, val).await,
+            None => {
+                self.update_now();
+                #[cfg(feature = "record")]
+                self.record(record::Event::PlayerGone(i));
+
+                self.handle_player_gone(i).await
+            }
+        }
+    }
+
+    /// Indices of the players who play against the given one: the player of
+    /// the opposite side, and in a team game (see set_teammates), their
+    /// teammate too.
+    fn opponents_of(&self, i: usize) -> Vec<usize> {
+        let side = match self.players[i].side {
+            Some(side) => side,
+            None => return Vec::new(),
+        };
+
+        (0..self.players.len())
+            .filter(|&j| self.players[j].side == Some(side.opposite()))
+            .collect()
+    }
+
+    /// Sends the message to every opponent of the given player, see
+    /// opponents_of.
+    async fn send_to_opponents(&mut self, i: usize, msg: GameManagerToPlayer) -> Result<()> {
+        for j in self.opponents_of(i) {
+            self.send_to_player(j, msg.clone()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Index of the player whose turn it is, if it's anyone's: normally, it's
+    /// just the player of the side to move, but in a team game (see
+    /// set_teammates), the teammates take turns at their side's moves.
+    fn player_to_move(&self) -> Option<usize> {
+        let side = match self.game_state {
+            Some(GameState::WaitingFor(side, _)) => side,
+            _ => return None,
+        };
+        let team = self.player_idx_by_side(side).ok()?;
+
+        let team_size = self.players.len() / 2;
+        let side_moves = self.history.iter().filter(|m| m.side == side).count();
+
+        Some(team + 2 * (side_moves % team_size))
+    }
+
+    /// Number of tokens which the side to move still has to place in the
+    /// handicap setup, before the normal play starts, see
+    /// FullGameState::setup_tokens. Since it's derived from the history,
+    /// taking moves back returns to the setup too.
+    fn setup_tokens_left(&self) -> usize {
+        self.setup_moves.saturating_sub(self.history.len())
+    }
+
+    /// Index of the primary or the secondary player, whichever plays the
+    /// given side; in a team game, it's the index of the team.
+    fn player_idx_by_side(&self, side: game::Side) -> Result<usize> {
+        match self.players[0].side {
+            Some(v) => {
+                if side == v {
+                    return Ok(0);
+                }
+
+                Ok(1)
+            }
+            None => Err(anyhow!("player 0 doesn't have a side")),
+        }
+    }
+
+    pub async fn handle_player_msg(&mut self, i: usize, msg: PlayerToGameManager) -> Result<()> {
+        self.update_now();
+        #[cfg(feature = "record")]
+        self.record(record::Event::FromPlayer(i, msg.clone()));
+
+        match msg {
+            PlayerToGameManager::SetFullGameState(fgstate) => {
+                self.handle_full_game_state(i, fgstate).await?;
+                Ok(())
+            }
+            PlayerToGameManager::StateChanged(state) => {
+                self.handle_player_state_change(i, state).await?;
+                Ok(())
+            }
+            PlayerToGameManager::PutToken(pcoords) => {
+                self.handle_player_put_token(i, pcoords).await?;
+                Ok(())
+            }
+            PlayerToGameManager::Notice(notice) => {
+                self.send_to_ui(GameManagerToUI::Notice(notice)).await?;
+                Ok(())
+            }
+            PlayerToGameManager::SetName(name) => {
+                self.players[i].name = Some(name.clone());
+                self.send_to_ui(GameManagerToUI::PlayerNameChanged(i, name))
+                    .await?;
+                Ok(())
+            }
+            PlayerToGameManager::Chat { from, text } => {
+                self.send_to_ui(GameManagerToUI::Chat { from, text })
+                    .await?;
+                Ok(())
+            }
+            PlayerToGameManager::Latency(rtt) => {
+                self.send_to_ui(GameManagerToUI::PlayerLatency(i, rtt))
+                    .await?;
+                Ok(())
+            }
+            PlayerToGameManager::MovePending(pcoords) => {
+                if let Some(tcoords) = self.top_token(pcoords) {
+                    self.send_to_ui(GameManagerToUI::TokenPending(tcoords))
+                        .await?;
+                }
+                Ok(())
+            }
+            PlayerToGameManager::MoveConfirmed(pcoords) => {
+                if let Some(tcoords) = self.top_token(pcoords) {
+                    self.send_to_ui(GameManagerToUI::TokenConfirmed(tcoords))
+                        .await?;
+                }
+                Ok(())
+            }
+            PlayerToGameManager::Resign => {
+                self.handle_player_resign(i).await?;
+                Ok(())
+            }
+            PlayerToGameManager::OfferDraw => {
+                self.handle_draw_offer(i).await?;
+                Ok(())
+            }
+            PlayerToGameManager::AcceptDraw => {
+                self.handle_draw_accept(i).await?;
+                Ok(())
+            }
+            PlayerToGameManager::RequestUndo => {
+                self.handle_undo_request(i).await?;
+                Ok(())
+            }
+            PlayerToGameManager::AcceptUndo => {
+                self.handle_undo_accept(i).await?;
+                Ok(())
+            }
+            PlayerToGameManager::Rematch => {
+                self.handle_rematch(i).await?;
+                Ok(())
+            }
+            PlayerToGameManager::DeclineRematch => {
+                self.handle_rematch_decline(Some(i)).await?;
+                Ok(())
+            }
+            PlayerToGameManager::Pause => {
+                self.set_paused(true).await?;
+                Ok(())
+            }
+            PlayerToGameManager::Resume => {
+                self.set_paused(false).await?;
+                Ok(())
+            }
+            PlayerToGameManager::ClockSync(remaining) => {
+                self.handle_clock_sync(i, remaining).await?;
+                Ok(())
+            }
+            PlayerToGameManager::RequestFullState => {
+                let fgstate = self.full_game_state(i);
+                self.send_to_player(i, GameManagerToPlayer::ProvideFullState(fgstate))
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Full state of the current game from the point of view of the given
+    /// player, i.e. with its side as the primary one; None if there's no game
+    /// yet.
+    fn full_game_state(&self, i: usize) -> Option<FullGameState> {
+        match (self.game_state, self.players[i].side) {
+            (Some(game_state), Some(primary_player_side)) => Some(FullGameState {
+                game_state,
+                primary_player_side,
+                board: self.game.get_board().clone(),
+                setup_tokens: self.setup_tokens_left(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns the side of the given player, if the game is still going;
+    /// otherwise prints why the player can't do what it's trying to (e.g.
+    /// resign) and returns None. Just like with the moves, it's not an error.
+    fn side_in_game(&self, i: usize, what: &str) -> Option<game::Side> {
+        if !matches!(self.game_state, Some(GameState::WaitingFor(_, _))) {
+            println!("game is over, but player {} tries to {}", i, what);
+            return None;
+        }
+
+        let side = self.players[i].side;
+        if side.is_none() {
+            println!("player {} has no side, but tries to {}", i, what);
+        }
+
+        side
+    }
+
+    /// Called when a player resigns: the opponent wins, and gets
+    /// GameManagerToPlayer::OpponentResigned.
+    async fn handle_player_resign(&mut self, i: usize) -> Result<()> {
+        let side = match self.side_in_game(i, "resign") {
+            Some(side) => side,
+            None => return Ok(()),
+        };
+
+        self.send_to_opponents(i, GameManagerToPlayer::OpponentResigned)
+            .await?;
+
+        self.end_game(
+            GameState::WonBy(side.opposite(), self.game.get_scores()),
+            GameOverReason::Resignation(side),
+            announce::resigned(side),
+        )
+        .await
+    }
+
+    /// Called when a player offers a draw: the offer is passed on to the
+    /// opponent, and stands until the opponent either accepts it or moves.
+    async fn handle_draw_offer(&mut self, i: usize) -> Result<()> {
+        let side = match self.side_in_game(i, "offer a draw") {
+            Some(side) => side,
+            None => return Ok(()),
+        };
+
+        self.draw_offer = Some(side);
+
+        let text = announce::draw_offered(side);
+        self.send_to_ui(GameManagerToUI::Notice(text.clone()))
+            .await?;
+        self.send_to_ui(GameManagerToUI::Announcement(text)).await?;
+
+        self.send_to_opponents(i, GameManagerToPlayer::DrawOffered)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Called when a player accepts a draw, which only works if the opponent
+    /// has offered it.
+    async fn handle_draw_accept(&mut self, i: usize) -> Result<()> {
+        let side = match self.side_in_game(i, "accept a draw") {
+            Some(side) => side,
+            None => return Ok(()),
+        };
+
+        if self.draw_offer != Some(side.opposite()) {
+            println!("player {} accepts a draw, but there is no offer", i);
+            return Ok(());
+        }
+
+        self.end_game(
+            GameState::Draw(self.game.get_scores()),
+            GameOverReason::DrawAgreed,
+            announce::draw_agreed(side),
+        )
+        .await
+    }
+
+    /// Called when a player asks to take back its last move: the request is
+    /// passed on to the opponent, and stands until the opponent either
+    /// accepts it or moves.
+    async fn handle_undo_request(&mut self, i: usize) -> Result<()> {
+        let side = match self.players[i].side {
+            Some(side) => side,
+            None => {
+                println!("player {} has no side, but asks to undo", i);
+                return Ok(());
+            }
+        };
+
+        let moves = match self.undo_moves(side) {
+            Some(moves) => moves,
+            None => {
+                println!("player {} asks to undo, but has no moves to take back", i);
+                return Ok(());
+            }
+        };
+
+        // A finished game of a match has been counted already, see
+        // update_match_score.
+        if self.match_score.is_some()
+            && !matches!(self.game_state, Some(GameState::WaitingFor(_, _)))
+        {
+            println!("player {} asks to undo, but the match game is over", i);
+            return Ok(());
+        }
+
+        self.undo_request = Some(side);
+
+        let text = announce::undo_requested(side, moves);
+        self.send_to_ui(GameManagerToUI::Notice(text.clone()))
+            .await?;
+        self.send_to_ui(GameManagerToUI::Announcement(text)).await?;
+
+        self.send_to_opponents(i, GameManagerToPlayer::UndoRequested(moves))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Called when a player accepts the undo requested by the opponent: the
+    /// moves are taken back, so that it's the opponent's turn again.
+    async fn handle_undo_accept(&mut self, i: usize) -> Result<()> {
+        let side = match self.players[i].side {
+            Some(side) => side,
+            None => {
+                println!("player {} has no side, but accepts an undo", i);
+                return Ok(());
+            }
+        };
+
+        let requester = side.opposite();
+        if self.undo_request != Some(requester) {
+            println!("player {} accepts an undo, but there is no request", i);
+            return Ok(());
+        }
+        self.undo_request = None;
+
+        // The request only stands while nobody moves, so it can't be None.
+        let moves = self.undo_moves(requester).unwrap();
+
+        // Take the moves back on the copies first, so that if something is
+        // off, the game stays as it was.
+        let mut game = self.game.clone();
+        let mut history = self.history.clone();
+        for m in history.drain(history.len() - moves..).rev() {
+            if let Err(err) = game.take_back(m.side, m.pole) {
+                println!("failed to take back {}: {}", m.pole, err);
+                return Ok(());
+            }
+        }
+
+        let diff = self.game.get_board().diff(game.get_board());
+        self.game = game;
+        self.history = history;
+        self.update_board_watch();
+        self.draw_offer = None;
+
+        // Both players get the new board before the new state, so that the
+        // one whose turn it is can think about the right position.
+        let board = self.game.get_board().clone();
+        for i in 0..self.players.len() {
+            self.send_to_player(i, GameManagerToPlayer::UndoApplied(board.clone()))
+                .await?;
+        }
+
+        self.send_to_ui(GameManagerToUI::ResetBoard(self.game.get_rules(), diff))
+            .await?;
+
+        // If the game was over, it goes on now, so its result doesn't count.
+        if let Some(winner) = self.session_result.take() {
+            self.session_stats.remove_result(winner);
+            self.send_to_ui(GameManagerToUI::SessionStats(self.session_stats))
+                .await?;
+        }
+
+        let state = GameState::WaitingFor(requester, self.game.get_scores());
+        self.game_state = Some(state);
+        self.emit(GameEvent::MovesTakenBack(moves));
+        self.send_to_ui(GameManagerToUI::Announcement(announce::undone(
+            moves, state,
+        )))
+        .await?;
+        self.propagate_game_state_change().await?;
+        self.start_evaluation();
+
+        Ok(())
+    }
+
+    /// Number of the last moves to take back, so that it's the given side's
+    /// turn again: its own last move, and the opponent's reply to it, if any.
+    /// None if the side hasn't moved since the board was last reset.
+    fn undo_moves(&self, side: game::Side) -> Option<usize> {
+        self.history
+            .iter()
+            .rev()
+            .take(2)
+            .position(|m| m.side == side)
+            .map(|idx| idx + 1)
+    }
+
+    /// Called when a player agrees to a new game: once both have agreed, it
+    /// starts, with the same sides and setup.
+    async fn handle_rematch(&mut self, i: usize) -> Result<()> {
+        let side = match self.players[i].side {
+            Some(side) => side,
+            None => {
+                println!("player {} has no side, but wants a new game", i);
+                return Ok(());
+            }
+        };
+
+        // In a team game, either teammate can agree for the team.
+        let team = i % 2;
+        if !self.rematch[team] && !self.rematch[1 - team] {
+            let text = announce::rematch_offered(side);
+            self.send_to_ui(GameManagerToUI::Notice(text.clone()))
+                .await?;
+            self.send_to_ui(GameManagerToUI::Announcement(text)).await?;
+        }
+        self.rematch[team] = true;
+
+        if !self.rematch.iter().all(|&v| v) {
+            return Ok(());
+        }
+
+        self.new_game().await
+    }
+
+    /// Called when a player (or the UI, if None) calls off the new game:
+    /// whoever has asked for it, the current game goes on.
+    async fn handle_rematch_decline(&mut self, decliner: Option<usize>) -> Result<()> {
+        if !self.rematch.iter().any(|&v| v) {
+            return Ok(());
+        }
+        self.rematch = [false; 2];
+
+        let side = decliner.and_then(|i| self.players[i].side);
+        let text = announce::rematch_declined(side);
+        self.send_to_ui(GameManagerToUI::Notice(text.clone()))
+            .await?;
+        self.send_to_ui(GameManagerToUI::Announcement(text)).await?;
+
+        for j in 0..self.players.len() {
+            if decliner != Some(j) {
+                self.send_to_player(j, GameManagerToPlayer::RematchDeclined)
+                    .await
+                    .context("declining a new game")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts a new game with the same setup. The sides stay the same, unless
+    /// they're to be swapped or picked anew.
+    async fn new_game(&mut self) -> Result<()> {
+        let primary_side = match self.players[0].side {
+            Some(side) => side,
+            None => {
+                println!("primary player has no side, can't start a new game");
+                return Ok(());
+            }
+        };
+        let primary_side = if self.alternate_sides {
+            primary_side.opposite()
+        } else {
+            self.side_choice
+                .map_or(primary_side, |v| v.pick(&mut self.rng))
+        };
+        let fgstate = match &self.handicap {
+            Some(handicap) => FullGameState::from_handicap(handicap, primary_side)
+                .context("new game with the handicap")?,
+            None => FullGameState::new(primary_side),
+        };
+
+        self.start_game(fgstate).await
+    }
+
+    /// Called once it's time for the next game of the match, see set_match.
+    async fn handle_next_match_game(&mut self) -> Result<()> {
+        self.update_now();
+        #[cfg(feature = "record")]
+        self.record(record::Event::NextMatchGame);
+
+        self.next_match_game = None;
+        self.new_game().await
+    }
+
+    /// Ends the game which is still going with the given state, for the
+    /// reason other than a move, e.g. a resignation: lets the UI know why
+    /// (both as the reason, and as the text to show), and everyone about the
+    /// new state.
+    async fn end_game(
+        &mut self,
+        state: GameState,
+        reason: GameOverReason,
+        text: String,
+    ) -> Result<()> {
+        self.game_state = Some(state);
+        self.draw_offer = None;
+        self.undo_request = None;
+        self.drop_pause().await?;
+
+        self.send_to_ui(GameManagerToUI::Notice(text.clone()))
+            .await?;
+        self.send_to_ui(GameManagerToUI::Announcement(text)).await?;
+        self.send_to_ui(GameManagerToUI::GameOver(reason)).await?;
+        self.emit(GameEvent::GameEnded(state, reason));
+
+        let cue = match state {
+            GameState::WonBy(side, _) => SoundCue::Win(side),
+            GameState::Draw(_) | GameState::WaitingFor(_, _) => SoundCue::Draw,
+        };
+        self.send_to_ui(GameManagerToUI::SoundCue(cue)).await?;
+
+        self.propagate_game_state_change().await?;
+        self.start_review();
+        self.update_session_stats().await?;
+        self.update_match_score().await?;
+
+        Ok(())
+    }
+
+    /// Called when a game is over: counts its result to the match score, if
+    /// it's a match, lets the UI know the score, and schedules the next game
+    /// unless the match is decided.
+    async fn update_match_score(&mut self) -> Result<()> {
+        let score = match &mut self.match_score {
+            Some(score) => score,
+            None => return Ok(()),
+        };
+
+        let winner_side = match self.game_state {
+            Some(GameState::WonBy(side, _)) => Some(side),
+            _ => None,
+        };
+        let winner =
+            winner_side.and_then(|side| self.players.iter().position(|p| p.side == Some(side)));
+        score.add_result(winner);
+        let score = *score;
+
+        if score.winner().is_none() {
+            self.next_match_game = Some(self.now + NEXT_MATCH_GAME_DELAY);
+        }
+
+        let text = announce::match_score(&score, winner_side);
+        self.send_to_ui(GameManagerToUI::Notice(text.clone()))
+            .await?;
+        self.send_to_ui(GameManagerToUI::Announcement(text)).await?;
+        self.send_to_ui(GameManagerToUI::MatchScore(score)).await
+    }
+
+    /// Counts the result of the game which is just over in the session stats,
+    /// and lets the UI know them, see GameManagerToUI::SessionStats.
+    async fn update_session_stats(&mut self) -> Result<()> {
+        let winner = match self.game_state {
+            Some(GameState::WonBy(side, _)) => {
+                self.players.iter().position(|p| p.side == Some(side))
+            }
+            _ => None,
         };
+        self.session_stats.add_result(winner);
+        self.session_result = Some(winner);
 
-        GameManager {
-            game: game::Game::new(),
-            game_state: None,
+        self.send_to_ui(GameManagerToUI::SessionStats(self.session_stats))
+            .await
+    }
 
-            to_ui,
-            players: [p0, p1],
-        }
+    /// Coords of the topmost token on the given pole, if the pole isn't empty.
+    fn top_token(&self, pcoords: game::PoleCoords) -> Option<game::TokenCoords> {
+        (0..self.game.get_rules().row_size())
+            .rev()
+            .map(|y| pcoords.token_coords(y))
+            .find(|&tcoords| self.game.get_token(tcoords).is_some())
     }
 
-    /// Event loop, runs forever, should be swapned by the client code as a
-    /// separate task.
-    pub async fn run(&mut self) -> Result<()> {
-        loop {
-            let (p0_mut, p1_mut) = self.both_players_mut();
+    pub async fn handle_ui_msg(&mut self, msg: UIToGameManager) -> Result<()> {
+        self.update_now();
+        #[cfg(feature = "record")]
+        self.record(record::Event::FromUI(msg.clone()));
 
-            tokio::select! {
-                Some(val) = p0_mut.from.recv() => {
-                    self.handle_player_msg(0, val).await?;
+        match msg {
+            UIToGameManager::ConfigurePlayers(config) => {
+                // Every player just ignores the settings which don't apply to
+                // it, so send them to both.
+                for i in 0..self.players.len() {
+                    self.send_to_player(i, GameManagerToPlayer::Configure(config.clone()))
+                        .await
+                        .context("configuring players")?;
                 }
 
-                Some(val) = p1_mut.from.recv() => {
-                    self.handle_player_msg(1, val).await?;
+                Ok(())
+            }
+
+            UIToGameManager::RequestHint => {
+                if self.hint_search.as_ref() != Some(self.game.get_board())
+                    && self.start_search(SearchKind::Hint)
+                {
+                    self.hint_search = Some(self.game.get_board().clone());
                 }
+                Ok(())
             }
-        }
-    }
 
-    /// Propagate current game state to both players and the UI.
-    async fn propagate_game_state_change(&mut self) -> Result<()> {
-        let gs = self.game_state.unwrap();
+            UIToGameManager::RequestAnalysis => {
+                self.start_search(SearchKind::Analysis);
+                Ok(())
+            }
 
-        self.players[0]
-            .to
-            .send(GameManagerToPlayer::GameStateChanged(gs))
-            .await
-            .context("player 0")?;
+            UIToGameManager::SetEvaluation(evaluation) => {
+                self.evaluation = evaluation;
+                self.start_evaluation();
+                Ok(())
+            }
 
-        self.players[1]
-            .to
-            .send(GameManagerToPlayer::GameStateChanged(gs))
-            .await
-            .context("player 1")?;
+            UIToGameManager::NewGame => {
+                // Even if a player has agreed already (e.g. the remote user
+                // has asked for it), it has to hear that the local user
+                // agrees too.
+                for i in 0..self.players.len() {
+                    self.send_to_player(i, GameManagerToPlayer::RematchOffered)
+                        .await
+                        .context("offering a new game")?;
+                }
 
-        self.to_ui
-            .send(GameManagerToUI::GameStateChanged(gs))
-            .await
-            .context("updating UI")?;
+                Ok(())
+            }
 
-        Ok(())
-    }
+            UIToGameManager::RequestMoveHistory => {
+                self.send_to_ui(GameManagerToUI::MoveHistory(self.history.clone()))
+                    .await
+            }
 
-    async fn handle_player_state_change(&mut self, i: usize, state: PlayerState) -> Result<()> {
-        // Remember state for the player which sent us the update.
-        self.players[i].state = state.clone();
+            UIToGameManager::Chat(text) => {
+                // Only the network player has anyone to pass it on to, but
+                // it's up to the players to know that.
+                for i in 0..self.players.len() {
+                    self.send_to_player(i, GameManagerToPlayer::Chat(text.clone()))
+                        .await
+                        .context("sending a chat message")?;
+                }
 
-        // Update UI about the player state
-        self.to_ui
-            .send(GameManagerToUI::PlayerStateChanged(i, state))
-            .await
-            .context("updating UI")?;
+                Ok(())
+            }
 
-        Ok(())
+            UIToGameManager::DeclineNewGame => self.handle_rematch_decline(None).await,
+
+            UIToGameManager::Pause => self.set_paused(true).await,
+            UIToGameManager::Resume => self.set_paused(false).await,
+
+            UIToGameManager::Peek(side) => self.handle_peek(side).await,
+
+            UIToGameManager::Shutdown => {
+                // A player which is gone already is skipped, see send_to_player.
+                for i in 0..self.players.len() {
+                    self.send_to_player(i, GameManagerToPlayer::Shutdown)
+                        .await?;
+                }
+
+                self.shutdown = true;
+                Ok(())
+            }
+        }
     }
 
-    /// Handles full game reset; it happens when e.g. a network player connected
-    /// to the server and the server has dumped the current game state to it.
-    /// Here we should update internal state, the other player, and the UI.
-    async fn handle_full_game_state(&mut self, i: usize, fgstate: FullGameState) -> Result<()> {
-        if i != 0 {
-            println!(
-                "player {} is not primary, so ignoring its FullGameState update ({:?})",
-                i, fgstate
-            );
+    /// Called when the user peeks at the board in blindfold mode, playing the
+    /// given side: the peek is counted, and in a timed game, the penalty is
+    /// taken off the side's clock, see set_peek_penalty. If the clock runs
+    /// out that way, the side loses on the next tick.
+    async fn handle_peek(&mut self, side: game::Side) -> Result<()> {
+        if !matches!(self.game_state, Some(GameState::WaitingFor(_, _))) {
+            println!("game is over, the peek is free");
             return Ok(());
         }
 
-        // Update board state.
-        self.game.reset_board(&fgstate.board);
+        self.peeks += 1;
+        if let Some(clocks) = &mut self.clocks {
+            clocks.charge(side, self.peek_penalty, self.now);
+            self.send_clocks(true).await?;
+        }
 
-        // Remember state for the player which sent us the update.
-        self.players[0].side = Some(fgstate.primary_player_side);
+        self.send_to_ui(GameManagerToUI::Peeks(self.peeks)).await
+    }
 
-        // Update it for the opponent as well.
-        let opposite_side = fgstate.primary_player_side.opposite();
-        let opponent_idx = Self::opponent_idx(0);
-        self.players[opponent_idx].side = Some(opposite_side);
-
-        // Reset the game for the opponent.
-        let opponent = &self.players[opponent_idx];
-        opponent
-            .to
-            .send(GameManagerToPlayer::Reset(
-                fgstate.board.clone(),
-                opposite_side,
-            ))
-            .await
-            .context(format!(
-                "resetting player {}, setting side to {:?}",
-                opponent_idx, opposite_side
-            ))?;
+    /// Starts the search of the given kind for the side to move, if any. The
+    /// search can take a while, so it runs on a thread where blocking is ok;
+    /// once done, it sends the result back via the searches channel. Returns
+    /// whether the search has started.
+    fn start_search(&self, kind: SearchKind) -> bool {
+        let side = match self.game_state {
+            Some(GameState::WaitingFor(side, _)) => side,
+            _ => {
+                println!("nobody's turn, nothing to search for");
+                return false;
+            }
+        };
 
-        // Update UI.
-        self.to_ui
-            .send(GameManagerToUI::ResetBoard(fgstate.board))
-            .await
-            .context("updating UI")?;
+        let game = self.game.clone();
+        let engine = self.engine.clone();
+        let searches_tx = self.searches_tx.clone();
 
-        // Update UI about the player sides.
-        self.to_ui
-            .send(GameManagerToUI::PlayerSidesChanged(
-                fgstate.primary_player_side,
-                opposite_side,
-            ))
-            .await
-            .context("updating UI")?;
+        tokio::task::spawn_blocking(move || {
+            let outcome = match kind {
+                SearchKind::Hint => {
+                    let deadline = Instant::now() + HINT_TIME_LIMIT;
 
-        // Update game state and propagate it to everyone.
-        self.game_state = Some(fgstate.game_state);
-        self.propagate_game_state_change()
-            .await
-            .context("initial update")?;
+                    // A proven win is better than whatever the engine thinks;
+                    // otherwise, the engine gets the rest of the time.
+                    let hint = match solver::solve(&game, side, HINT_SOLVE_PLIES, Some(deadline)) {
+                        Some(Solution {
+                            outcome: Outcome::Win(plies),
+                            best_move,
+                        }) => Some((best_move, HintScore::ForcedWin(plies))),
+                        _ => {
+                            let limits = SearchLimits {
+                                depth: HINT_DEPTH,
+                                deadline: Some(deadline),
+                                style: Style::Balanced,
+                            };
+                            engine
+                                .best_move(&game, side, &limits)
+                                .map(|res| (res.best_move, HintScore::Score(res.score)))
+                        }
+                    };
+                    SearchOutcome::Hint(hint)
+                }
+                SearchKind::Analysis => {
+                    let limits = SearchLimits {
+                        depth: ANALYSIS_DEPTH,
+                        deadline: None,
+                        style: Style::Balanced,
+                    };
+                    SearchOutcome::Analysis(engine.analyze(&game, side, &limits))
+                }
+                SearchKind::Evaluation => {
+                    let limits = SearchLimits {
+                        depth: EVALUATION_DEPTH,
+                        deadline: Some(Instant::now() + EVALUATION_TIME_LIMIT),
+                        style: Style::Balanced,
+                    };
+                    let score = engine
+                        .best_move(&game, side, &limits)
+                        .map(|res| match side {
+                            game::Side::White => res.score,
+                            game::Side::Black => -res.score,
+                        });
+                    SearchOutcome::Evaluation(score)
+                }
+            };
 
-        Ok(())
+            let _ = searches_tx.blocking_send(SearchDone {
+                board: game.get_board().clone(),
+                side,
+                outcome,
+            });
+        });
+
+        true
     }
 
-    fn both_players_mut(&mut self) -> (&mut PlayerCtx, &mut PlayerCtx) {
-        let (v0, v1) = self.players.split_at_mut(1);
-        (&mut v0[0], &mut v1[0])
+    /// If the evaluation is on, and the game is still going, starts evaluating
+    /// the current position; see UIToGameManager::SetEvaluation.
+    fn start_evaluation(&self) {
+        if self.evaluation && matches!(self.game_state, Some(GameState::WaitingFor(_, _))) {
+            self.start_search(SearchKind::Evaluation);
+        }
     }
 
-    fn opponent_idx(i: usize) -> usize {
-        match i {
-            0 => 1,
-            1 => 0,
-            // TODO: create an enum for player indices, instead if usize
-            _ => {
-                panic!("invalid player index {}", i)
-            }
+    /// Returns the moves since the board was last reset as a game record,
+    /// without annotations, e.g. for the review.
+    fn history_record(&self) -> GameRecord {
+        let mut record = GameRecord::new();
+        for m in &self.history {
+            record.push(m.side, m.pole);
         }
+
+        record
     }
 
-    fn player_by_side(&self, side: game::Side) -> Result<&PlayerCtx> {
-        match self.players[0].side {
-            Some(v) => {
-                if side == v {
-                    return Ok(&self.players[0]);
+    /// Starts the review of the game which is over, in the background just
+    /// like start_search; once done, the result is sent to the UI as
+    /// GameManagerToUI::GameReview.
+    fn start_review(&self) {
+        let mut start = game::Game::with_rules(self.game.get_rules());
+        start.reset_board(&self.history_start);
+        let history = self.history_record();
+        let board = self.game.get_board().clone();
+        let side = match history.moves.last() {
+            Some(m) => m.side,
+            None => return,
+        };
+
+        let engine = self.engine.clone();
+        let blunder_threshold = self.blunder_threshold;
+        let searches_tx = self.searches_tx.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let reviewed = match review::review(
+                engine.as_ref(),
+                &start,
+                &history,
+                REVIEW_DEPTH,
+                blunder_threshold,
+            ) {
+                Ok(v) => v,
+                Err(err) => {
+                    println!("failed to review the game: {:#}", err);
+                    return;
                 }
+            };
+
+            let _ = searches_tx.blocking_send(SearchDone {
+                board,
+                side,
+                outcome: SearchOutcome::Review(reviewed),
+            });
+        });
+    }
+
+    /// Called when a search started by start_search or start_review is done.
+    pub async fn handle_search_done(&mut self, done: SearchDone) -> Result<()> {
+        self.update_now();
+        #[cfg(feature = "record")]
+        self.record(record::Event::SearchDone(done.clone()));
+
+        if matches!(done.outcome, SearchOutcome::Hint(_))
+            && self.hint_search.as_ref() == Some(&done.board)
+        {
+            self.hint_search = None;
+        }
 
-                Ok(&self.players[1])
+        // While the engine was thinking, the position might have changed, and
+        // then the result is useless.
+        let still_relevant = match done.outcome {
+            SearchOutcome::Review(_) => {
+                matches!(
+                    self.game_state,
+                    Some(GameState::WonBy(_, _) | GameState::Draw(_))
+                )
             }
-            None => Err(anyhow!("player 0 doesn't have a side")),
+            _ => self
+                .game_state
+                .is_some_and(|gs| gs.is_waiting_for(done.side)),
+        };
+        if &done.board != self.game.get_board() || !still_relevant {
+            return Ok(());
         }
-    }
 
-    pub async fn handle_player_msg(&mut self, i: usize, msg: PlayerToGameManager) -> Result<()> {
-        match msg {
-            PlayerToGameManager::SetFullGameState(fgstate) => {
-                self.handle_full_game_state(i, fgstate).await?;
-                Ok(())
+        match done.outcome {
+            SearchOutcome::Hint(Some((pcoords, score))) => {
+                self.send_to_ui(GameManagerToUI::Hint(pcoords, score))
+                    .await?;
             }
-            PlayerToGameManager::StateChanged(state) => {
-                self.handle_player_state_change(i, state).await?;
-                Ok(())
+            SearchOutcome::Hint(None) => {}
+            SearchOutcome::Analysis(scores) => {
+                self.send_to_ui(GameManagerToUI::Analysis(scores)).await?;
             }
-            PlayerToGameManager::PutToken(pcoords) => {
-                self.handle_player_put_token(i, pcoords).await?;
-                Ok(())
+            SearchOutcome::Review(record) => {
+                self.send_to_ui(GameManagerToUI::GameReview(record)).await?;
+            }
+            SearchOutcome::Evaluation(Some(score)) => {
+                self.send_to_ui(GameManagerToUI::Evaluation(score)).await?;
             }
+            SearchOutcome::Evaluation(None) => {}
         }
+
+        Ok(())
     }
 
     /// Called when a player puts a token.
@@ -244,109 +2135,313 @@ impl GameManager {
         println!("GM: player {:?} put token {:?}", maybe_side, pcoords);
 
         // Some sanity checks that the game state and the player side are all as
-        // expected. If something is off, for now we'll just print to stdout,
-        // update everyone about the current game state, and return Ok. We can't
+        // expected. If something is off, we let the player know why (see
+        // reject_move), update everyone about the current game state, and
+        // return Ok. We can't
         // return an error here because it'll be interpreted as communication
         // failure and the whole task will exit. It might be tempting to
         // actually do this, or even to panic, since it "shouldn't happen", but
         // since it can actually happen in a network game with a potentially
-        // broken server or the remote player, we take it mildly.  TODO: perhaps
-        // improve this error handling at some point.
-
-        let expected_move_side = match self.game_state.unwrap() {
-            GameState::WaitingFor(s) => s,
-            GameState::WonBy(_) => {
-                println!("game is won, but player put token");
-                self.propagate_game_state_change().await?;
+        // broken server or the remote player, we take it mildly.
+
+        // Without a game (e.g. the primary player's state was rejected, see
+        // GameManagerToPlayer::StateRejected, while the remote opponent keeps
+        // moving), there's no state to propagate either, so the player is
+        // only told that the move is rejected.
+        let game_state = match self.game_state {
+            Some(gs) => gs,
+            None => {
+                let reason = RejectReason::NoSide;
+                println!(
+                    "GM: rejecting the move {} of player {}: {}",
+                    pcoords, i, reason
+                );
+                self.send_to_player(i, GameManagerToPlayer::MoveRejected(pcoords, reason))
+                    .await?;
                 return Ok(());
             }
         };
 
         let side = match maybe_side {
             None => {
-                println!("no current player side, but player put token");
-                self.propagate_game_state_change().await?;
-                return Ok(());
+                return self.reject_move(i, pcoords, RejectReason::NoSide).await;
             }
             Some(s) => s,
         };
 
+        let expected_move_side = match game_state {
+            GameState::WaitingFor(s, _) => s,
+            GameState::WonBy(_, _) | GameState::Draw(_) => {
+                return self.reject_move(i, pcoords, RejectReason::GameOver).await;
+            }
+        };
+
+        if self.paused {
+            self.refused_while_paused[i] = true;
+            return self.reject_move(i, pcoords, RejectReason::Paused).await;
+        }
+
         if side != expected_move_side {
-            println!(
-                "wrong side: {:?}, waiting for {:?}",
-                side, expected_move_side
-            );
-            self.propagate_game_state_change().await?;
-            return Ok(());
+            let reason = RejectReason::WrongTurn(expected_move_side);
+            return self.reject_move(i, pcoords, reason).await;
+        }
+
+        if self.player_to_move() != Some(i) {
+            return self
+                .reject_move(i, pcoords, RejectReason::TeammatesTurn)
+                .await;
+        }
+
+        // Game::put_token panics on the poles which don't exist, and a remote
+        // player could send anything.
+        let row_size = self.game.get_rules().row_size();
+        if pcoords.x >= row_size || pcoords.z >= row_size {
+            return self.reject_move(i, pcoords, RejectReason::NoSuchPole).await;
+        }
+
+        // In the handicap setup, the token is tried on a copy first, so that
+        // the one which would complete a row is refused. If the pole is full,
+        // it's refused below, like any other move.
+        if self.setup_tokens_left() > 0 {
+            let mut game = self.game.clone();
+            if game.put_token(side, pcoords).is_ok() {
+                let rules = self.game.get_rules();
+                if let Err(err) = game::handicap::check_setup(game.get_board(), rules) {
+                    println!("refusing the handicap token: {}", err);
+                    return self
+                        .reject_move(i, pcoords, RejectReason::InvalidSetup)
+                        .await;
+                }
+            }
         }
 
         // The side matches, try to actually put the token. This can still fail
-        // if the pole is full. Again, we don't give any actual feedback to the
-        // player that the pole is full; we simply refuse to put the token and
-        // gonna request a move from it again. That's a reasonable UX imo.
+        // if the pole is full; the player is asked for a move again then.
         let res = match self.game.put_token(side, pcoords) {
             Ok(res) => res,
             Err(err) => {
                 println!("can't put: {}", err);
-                self.propagate_game_state_change().await?;
-                return Ok(());
+                return self.reject_move(i, pcoords, RejectReason::PoleFull).await;
             }
         };
 
+        self.update_board_watch();
+        self.history.push(HistoryMove {
+            side,
+            pole: pcoords,
+            y: res.y,
+            time: self.wall_time(),
+        });
+        if let Some(clocks) = &mut self.clocks {
+            clocks.moved(side, self.now);
+        }
+
+        // By moving, the opponent declines the draw offer; and any move
+        // declines the undo request, since it's about the moves before.
+        if self.draw_offer == Some(side.opposite()) {
+            self.draw_offer = None;
+        }
+        self.undo_request = None;
+
         // All good, add new token to the UI.
-        self.to_ui
-            .send(GameManagerToUI::SetToken(
-                side,
-                game::TokenCoords {
-                    x: pcoords.x,
-                    y: res.y,
-                    z: pcoords.z,
-                },
-            ))
-            .await
-            .context("updating UI")?;
+        let tcoords = game::TokenCoords {
+            x: pcoords.x,
+            y: res.y,
+            z: pcoords.z,
+        };
+        let move_num = self.history.len();
+        self.send_to_ui(GameManagerToUI::SetToken(PlacedToken {
+            side,
+            tcoords,
+            pole: pcoords,
+            move_num,
+            winner: res.winner,
+        }))
+        .await?;
+        self.emit(GameEvent::MovePlayed(side, tcoords, move_num));
+        let announcement = announce::token_placed(side, tcoords);
+        self.send_to_ui(GameManagerToUI::Announcement(announcement))
+            .await?;
+        self.send_to_ui(GameManagerToUI::SoundCue(SoundCue::TokenPlaced(side)))
+            .await?;
 
-        // Let the other player know.
+        // Let the other player know; in a team game, both opponents, and the
+        // teammate, who gets the whole board, since it's not its move, but
+        // not the opponent's either.
         let opposite_side = side.opposite();
-        self.player_by_side(opposite_side)
-            .unwrap()
-            .to
-            .send(GameManagerToPlayer::OpponentPutToken(pcoords))
-            .await?;
+        for j in 0..self.players.len() {
+            if self.players[j].side == Some(opposite_side) {
+                let msg = GameManagerToPlayer::OpponentPutToken(pcoords, move_num);
+                self.send_to_player(j, msg).await?;
+            } else if j != i {
+                let rules = self.game.get_rules();
+                let board = self.game.get_board().clone();
+                self.send_to_player(j, GameManagerToPlayer::Reset(rules, board, side))
+                    .await?;
+            }
+        }
 
-        // Update game state, depending on whether the new token won the game.
-        if res.won {
-            self.game_state = Some(GameState::WonBy(side));
+        // Update game state, depending on whether the new token won the game
+        // (which, depending on the rules, can be won by either side).
+        // In the scoring variant, the new token might have completed some
+        // rows, and the new state carries the current scores.
+        let scores = self.game.get_scores();
+        let dead_draw = self.game.is_dead_draw();
+        if let Some(winner) = res.winner {
+            self.game_state = Some(GameState::WonBy(winner, scores));
 
-            // Also let the UI know the full winning row.
-            self.to_ui
-                .send(GameManagerToUI::WinRow(
-                    self.game.get_win_row().clone().unwrap(),
-                ))
-                .await
-                .context("updating UI")?;
+            // Also let the UI know the full winning row, if any (in the scoring
+            // variant, there is none).
+            if let Some(win_row) = self.game.get_win_row().clone() {
+                self.send_to_ui(GameManagerToUI::WinRow(win_row)).await?;
+            }
+        } else if res.draw {
+            self.game_state = Some(GameState::Draw(scores));
+        } else if dead_draw {
+            // Nobody can win anymore, so there's no point in filling the rest
+            // of the board.
+            self.game_state = Some(GameState::Draw(scores));
+            let notice = format!("Draw: {}", game::DEAD_DRAW_REASON);
+            self.send_to_ui(GameManagerToUI::Notice(notice)).await?;
+        } else if self.setup_tokens_left() > 0 {
+            // The side with the handicap goes on with the setup.
+            self.game_state = Some(GameState::WaitingFor(side, scores));
+        } else {
+            self.game_state = Some(GameState::WaitingFor(opposite_side, scores));
+        }
+
+        let cue = match self.game_state.unwrap() {
+            GameState::WaitingFor(side, _) => SoundCue::TurnStart(side),
+            GameState::WonBy(side, _) => SoundCue::Win(side),
+            GameState::Draw(_) => SoundCue::Draw,
+        };
+        self.send_to_ui(GameManagerToUI::SoundCue(cue)).await?;
+
+        let announcement = if dead_draw {
+            format!("Draw: {}", game::DEAD_DRAW_REASON)
         } else {
-            self.game_state = Some(GameState::WaitingFor(opposite_side));
+            announce::game_state(self.game_state.unwrap())
+        };
+        self.send_to_ui(GameManagerToUI::Announcement(announcement))
+            .await?;
+
+        if !matches!(self.game_state, Some(GameState::WaitingFor(_, _))) {
+            let reason = if dead_draw {
+                GameOverReason::DeadDraw
+            } else {
+                GameOverReason::Move
+            };
+            self.send_to_ui(GameManagerToUI::GameOver(reason)).await?;
+            self.emit(GameEvent::GameEnded(self.game_state.unwrap(), reason));
         }
 
         // Let everyone know about the current game state.
         self.propagate_game_state_change().await?;
 
+        if !matches!(self.game_state, Some(GameState::WaitingFor(_, _))) {
+            self.start_review();
+            self.update_session_stats().await?;
+            self.update_match_score().await?;
+        }
+        self.start_evaluation();
+
         Ok(())
     }
-}
 
-/// Simple state of the game: either waiting for someone's turn, or someone has
-/// won already.
-#[derive(Debug, Copy, Clone, serde::Serialize, serde::Deserialize)]
-pub enum GameState {
-    WaitingFor(game::Side),
-    WonBy(game::Side),
+    /// Called when a player's move is refused: lets the player know why, and
+    /// the UI that something is off, and repeats the current game state to
+    /// everyone, so that the player whose turn it is gets asked for a move
+    /// again.
+    async fn reject_move(
+        &mut self,
+        i: usize,
+        pcoords: game::PoleCoords,
+        reason: RejectReason,
+    ) -> Result<()> {
+        println!(
+            "GM: rejecting the move {} of player {}: {}",
+            pcoords, i, reason
+        );
+        self.send_to_player(i, GameManagerToPlayer::MoveRejected(pcoords, reason))
+            .await?;
+
+        self.send_to_ui(GameManagerToUI::SoundCue(SoundCue::InvalidMove))
+            .await?;
+
+        // While paused, nobody is to be asked for a move; that happens on
+        // resume.
+        if reason != RejectReason::Paused {
+            self.propagate_game_state_change().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Pauses or resumes the game which is still going, see
+    /// UIToGameManager::Pause: while paused, the clocks are stopped, and
+    /// moves are refused. On resume, the players whose moves were refused are
+    /// reset to the actual board (they might have played the move on their
+    /// own copy), and the player to move is asked for a move again.
+    async fn set_paused(&mut self, paused: bool) -> Result<()> {
+        if paused == self.paused {
+            return Ok(());
+        }
+        if paused && !matches!(self.game_state, Some(GameState::WaitingFor(_, _))) {
+            println!("the game is over, nothing to pause");
+            return Ok(());
+        }
+        // The remote user's game would go on, and their moves would all be
+        // refused here.
+        if paused && self.network_game {
+            self.send_to_ui(GameManagerToUI::Notice(
+                "A network game can't be paused".to_string(),
+            ))
+            .await?;
+            return Ok(());
+        }
+
+        self.paused = paused;
+
+        let text = announce::paused(paused);
+        self.send_to_ui(GameManagerToUI::Notice(text.clone()))
+            .await?;
+        self.send_to_ui(GameManagerToUI::Announcement(text)).await?;
+        self.send_to_ui(GameManagerToUI::Paused(paused)).await?;
+
+        if paused {
+            return self.update_clocks().await;
+        }
+
+        for i in 0..self.players.len() {
+            if !std::mem::take(&mut self.refused_while_paused[i]) {
+                continue;
+            }
+            if let Some(side) = self.players[i].side {
+                let rules = self.game.get_rules();
+                let board = self.game.get_board().clone();
+                self.send_to_player(i, GameManagerToPlayer::Reset(rules, board, side))
+                    .await?;
+            }
+        }
+
+        self.propagate_game_state_change().await
+    }
+
+    /// Unpauses the game without resuming anything, since it's over or reset
+    /// anyway; only the UI is told.
+    async fn drop_pause(&mut self) -> Result<()> {
+        self.refused_while_paused.fill(false);
+        if std::mem::take(&mut self.paused) {
+            self.send_to_ui(GameManagerToUI::Paused(false)).await?;
+        }
+
+        Ok(())
+    }
 }
 
 /// Full state of the game, containing the board state, and side of the players.
 /// See PlayerToGameManager::SetFullGameState, where it is used.
-#[derive(Debug)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct FullGameState {
     /// Either waiting for someone's turn, or someone has won already.
     pub game_state: GameState,
@@ -358,31 +2453,235 @@ pub struct FullGameState {
 
     /// Full board state.
     pub board: game::BoardState,
+
+    /// Number of handicap tokens which the side to move has to place before
+    /// the normal play starts, see game::handicap::Handicap::free; normally
+    /// zero.
+    #[serde(default)]
+    pub setup_tokens: usize,
+}
+
+impl FullGameState {
+    /// Full state of a new game with an empty board, where White moves first.
+    pub fn new(primary_player_side: game::Side) -> FullGameState {
+        FullGameState {
+            game_state: GameState::WaitingFor(game::Side::White, game::Scores::default()),
+            primary_player_side,
+            board: game::BoardState::new(),
+            setup_tokens: 0,
+        }
+    }
+
+    /// Full state of a new game with the given handicap setup, see
+    /// game::handicap::Handicap.
+    pub fn from_handicap(
+        handicap: &game::handicap::Handicap,
+        primary_player_side: game::Side,
+    ) -> Result<FullGameState> {
+        Ok(FullGameState {
+            game_state: GameState::WaitingFor(handicap.first_move_side(), game::Scores::default()),
+            primary_player_side,
+            board: handicap.board()?,
+            setup_tokens: handicap.setup_tokens(),
+        })
+    }
+}
+
+/// Why the GameManager has refused a player's move, see
+/// GameManagerToPlayer::MoveRejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum RejectReason {
+    /// The game is over already.
+    GameOver,
+    /// The player has no side yet, e.g. the game hasn't started.
+    NoSide,
+    /// It's the given side's turn, not the player's.
+    WrongTurn(game::Side),
+    /// It's the player's side's turn, but the teammate's move, see
+    /// GameManager::set_teammates.
+    TeammatesTurn,
+    /// There is no such pole on the board.
+    NoSuchPole,
+    /// The pole is full.
+    PoleFull,
+    /// The game is paused, see UIToGameManager::Pause.
+    Paused,
+    /// The handicap token would complete a row, see
+    /// game::handicap::check_setup.
+    InvalidSetup,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::GameOver => write!(f, "the game is over"),
+            RejectReason::NoSide => write!(f, "the game hasn't started"),
+            RejectReason::WrongTurn(side) => write!(f, "it's {:?}'s turn", side),
+            RejectReason::TeammatesTurn => write!(f, "it's the teammate's turn"),
+            RejectReason::NoSuchPole => write!(f, "there is no such pole"),
+            RejectReason::PoleFull => write!(f, "the pole is full"),
+            RejectReason::Paused => write!(f, "the game is paused"),
+            RejectReason::InvalidSetup => write!(f, "handicap tokens can't complete a row"),
+        }
+    }
+}
+
+/// Side for a player to play, as chosen by the user: either a certain one, or
+/// a random one, picked anew for every game, see SideChoice::pick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum SideChoice {
+    #[default]
+    White,
+    Black,
+    Random,
+}
+
+impl SideChoice {
+    /// Returns the side to play; if it's SideChoice::Random, it's picked with
+    /// the given generator, e.g. the GameManager's seeded one, see
+    /// GameManagerConfig::seed.
+    pub fn pick(&self, rng: &mut impl Rng) -> game::Side {
+        match self {
+            SideChoice::White => game::Side::White,
+            SideChoice::Black => game::Side::Black,
+            SideChoice::Random if rng.gen() => game::Side::White,
+            SideChoice::Random => game::Side::Black,
+        }
+    }
+}
+
+impl FromStr for SideChoice {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "white" => Ok(SideChoice::White),
+            "black" => Ok(SideChoice::Black),
+            "random" => Ok(SideChoice::Random),
+            _ => Err(anyhow!(
+                "invalid side {:?}; try 'white', 'black' or 'random'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SideChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SideChoice::White => write!(f, "white"),
+            SideChoice::Black => write!(f, "black"),
+            SideChoice::Random => write!(f, "random"),
+        }
+    }
 }
 
 /// Player state from the point of view of the GameManager.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum PlayerState {
     /// Not-yet-ready, with a human-readable string message explaining the
     /// status. Only used by PlayerWSClient.
     NotReady(String),
     /// Ready to play. Local players are always ready.
     Ready,
+    /// The player has stopped, e.g. its task has failed, so it won't play
+    /// anymore. Players never report it themselves: the GameManager notices
+    /// it once the channels to the player are closed.
+    Gone,
 }
 
 /// Message that GameManager can send to a player.
-#[derive(Debug)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum GameManagerToPlayer {
-    /// Reset the game: the board state, and the side of the receiving player.
-    Reset(game::BoardState, game::Side),
-    /// Opponent has put token on the given pole.
-    OpponentPutToken(game::PoleCoords),
-    /// Game state has changed.
-    GameStateChanged(GameState),
+    /// Reset the game: the rules (which are the same for the whole life of the
+    /// GameManager, see GameManagerConfig::rules), the board state, and the
+    /// side of the receiving player.
+    Reset(game::Rules, game::BoardState, game::Side),
+    /// Opponent has put token on the given pole, with the given move number,
+    /// see PlacedToken::move_num.
+    OpponentPutToken(game::PoleCoords, usize),
+    /// Game state has changed; with the number of moves made so far, see
+    /// GameManagerToUI::GameStateChanged.
+    GameStateChanged(GameState, usize),
+    /// Change player settings, see PlayerConfig.
+    Configure(PlayerConfig),
+    /// Opponent offers a draw, see PlayerToGameManager::OfferDraw. To accept
+    /// it, the player sends PlayerToGameManager::AcceptDraw; to decline it,
+    /// it just moves.
+    DrawOffered,
+    /// Opponent asks to take back the given number of its last moves (its own
+    /// last one, and our reply, if any), see PlayerToGameManager::RequestUndo.
+    /// To accept it, the player sends PlayerToGameManager::AcceptUndo; to
+    /// decline it, it just ignores it.
+    UndoRequested(usize),
+    /// Some moves were taken back, and the board is now the given one; the
+    /// new game state comes next.
+    UndoApplied(game::BoardState),
+    /// Opponent has resigned, so we've won; the new game state comes next.
+    /// Only the network player cares, since the server has to know.
+    OpponentResigned,
+    /// The user asks for a new game, see UIToGameManager::NewGame. To agree,
+    /// the player sends PlayerToGameManager::Rematch; the network player
+    /// passes it on to the remote user instead.
+    RematchOffered,
+    /// The new game is called off, see UIToGameManager::DeclineNewGame and
+    /// PlayerToGameManager::DeclineRematch. Only the network player cares,
+    /// since the server has to know.
+    RematchDeclined,
+    /// The player's move on the given pole was refused, for the given reason;
+    /// the game state follows, so the player is asked for a move again, if
+    /// it's still its turn.
+    MoveRejected(game::PoleCoords, RejectReason),
+    /// The full game state sent by the player (see
+    /// PlayerToGameManager::SetFullGameState) was refused, for the given
+    /// reason; the game stays as it was.
+    StateRejected(String),
+    /// The app is shutting down: the player should wrap up (e.g. the network
+    /// player disconnects from the server, so that the opponent knows right
+    /// away), and return from its run.
+    Shutdown,
+    /// Time remaining on both clocks, in a timed game, see
+    /// GameManagerToUI::ClockUpdate. Only the network player cares, since the
+    /// remote user has to know the local user's clock, see
+    /// PlayerToGameManager::ClockSync.
+    ClockUpdate(clock::ClockTimes),
+    /// Full state of the current game, as asked for with
+    /// PlayerToGameManager::RequestFullState, with the receiving player's
+    /// side as the primary one; None if there's no game yet.
+    ProvideFullState(Option<FullGameState>),
+    /// The local user says the given text to the remote one, see
+    /// UIToGameManager::Chat. Only the network player cares, since the
+    /// server passes it on.
+    Chat(String),
+}
+
+/// Player settings which can be changed at runtime. Every field is optional,
+/// None means to leave the setting as is. Players ignore the settings which
+/// don't apply to them.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct PlayerConfig {
+    /// Difficulty of the AI, only used by PlayerAI.
+    pub ai_difficulty: Option<player_ai::Difficulty>,
+    /// Playing style of the AI, only used by PlayerAI.
+    #[serde(default)]
+    pub ai_style: Option<Style>,
+    /// Clock of the AI, if the game is timed; only used by PlayerAI, which
+    /// then budgets its thinking time from it, see ClockState.
+    #[serde(default)]
+    pub ai_clock: Option<ClockState>,
+}
+
+/// State of a player's clock in a timed game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ClockState {
+    /// Time remaining on the clock.
+    pub remaining: Duration,
+    /// Time added to the clock after every move.
+    pub increment: Duration,
 }
 
 /// Message that a player can send to GameManager.
-#[derive(Debug)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum PlayerToGameManager {
     /// Overwrite full game state. Only primary player can send SetFullGameState
     /// messages; if secondary player does this, GameManager will just ignore
@@ -397,25 +2696,368 @@ pub enum PlayerToGameManager {
     SetFullGameState(FullGameState),
     /// Player state has changed.
     StateChanged(PlayerState),
+    /// Display name of the player, e.g. given on the command line for a
+    /// local player; it's forwarded to the UI as
+    /// GameManagerToUI::PlayerNameChanged.
+    SetName(String),
     /// Player put a token on the given pole.
     PutToken(game::PoleCoords),
+    /// Human readable notice for the user, which is not about the player
+    /// state, e.g. that the client is outdated; it's forwarded to the UI as
+    /// GameManagerToUI::Notice.
+    Notice(String),
+    /// The opponent's move on the given pole was sent to the server, but the
+    /// server hasn't confirmed it yet; the GameManager has put the token
+    /// already, so it's only for the UI to mark it, see
+    /// GameManagerToUI::TokenPending. Once the server confirms the move, the
+    /// player sends MoveConfirmed; if it rejects it instead, the player resets
+    /// the game to the server's state with SetFullGameState.
+    MovePending(game::PoleCoords),
+    /// The server has confirmed the opponent's move on the given pole, see
+    /// MovePending.
+    MoveConfirmed(game::PoleCoords),
+    /// The player concedes the game, so the opponent wins; the UI gets
+    /// GameManagerToUI::GameOver with GameOverReason::Resignation, and the
+    /// opponent gets GameManagerToPlayer::OpponentResigned. In a network game,
+    /// the server lets the remote user know.
+    Resign,
+    /// The player offers a draw; the opponent gets
+    /// GameManagerToPlayer::DrawOffered, and the offer stands until the
+    /// opponent either accepts it (with AcceptDraw) or moves.
+    OfferDraw,
+    /// The player accepts the draw offered by the opponent.
+    AcceptDraw,
+    /// The player asks to take back its last move (and the opponent's reply,
+    /// if the opponent has moved already, e.g. when playing against the AI),
+    /// so that it's the player's turn again. The opponent gets
+    /// GameManagerToPlayer::UndoRequested, and the request stands until the
+    /// opponent either accepts it (with AcceptUndo) or anyone moves. In a
+    /// network game, the opponent is the network player, so the request goes
+    /// through the server to the remote user.
+    RequestUndo,
+    /// The player accepts the undo requested by the opponent; once the moves
+    /// are taken back, both players get GameManagerToPlayer::UndoApplied.
+    AcceptUndo,
+    /// The player agrees to a new game (for the network player, it means that
+    /// the remote user asks for one). Once both players agree, the game is
+    /// reset, see UIToGameManager::NewGame.
+    Rematch,
+    /// The player calls off the new game (for the network player, it means
+    /// that the remote user has declined it): nobody's agreement stands
+    /// anymore, and the other players get GameManagerToPlayer::RematchDeclined.
+    DeclineRematch,
+    /// The player pauses or resumes the game, see UIToGameManager::Pause.
+    Pause,
+    Resume,
+    /// Time remaining on the player's own clock, in a timed game, as measured
+    /// where the player really is: the network player passes on the remote
+    /// user's clock. GameManager corrects its own clock with it, and from
+    /// then on, the player only loses on time once it reports zero.
+    ClockSync(Duration),
+    /// The player asks for the full state of the current game, see
+    /// GameManagerToPlayer::ProvideFullState; e.g. the network player tells
+    /// it to the server on connect, so that the game can be resumed if the
+    /// server doesn't have it anymore.
+    RequestFullState,
+    /// Someone on the other end (e.g. the remote user, for the network
+    /// player) has said something in the chat; it's forwarded to the UI as
+    /// GameManagerToUI::Chat.
+    Chat {
+        from: String,
+        text: String,
+    },
+    /// Round-trip time to wherever the player really is, e.g. to the server
+    /// for the network player, as last measured; it's forwarded to the UI as
+    /// GameManagerToUI::PlayerLatency.
+    Latency(Duration),
+}
+
+/// Token put on the board by a move, see GameManagerToUI::SetToken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PlacedToken {
+    pub side: game::Side,
+    pub tcoords: game::TokenCoords,
+    /// Pole which the token was put on, i.e. the one of tcoords.
+    pub pole: game::PoleCoords,
+    /// Number of the move: the moves since the board was last reset (see
+    /// GameManagerToUI::MoveHistory) are numbered from 1 up, so that the
+    /// numbers only go down on undo, and the receivers can tell if they've
+    /// missed some.
+    pub move_num: usize,
+    /// Side which has won the game by this move, if any: normally the side
+    /// which has moved, but in the misère variant, its opponent.
+    pub winner: Option<game::Side>,
 }
 
 /// Message that a GameManager can send to UI.
-#[derive(Debug)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum GameManagerToUI {
-    /// Set token of the given size and coords.
-    SetToken(game::Side, game::TokenCoords),
-    /// The whole board is reset to this new state.
-    ResetBoard(game::BoardState),
+    /// A token was put on the board by a move; the new game state comes
+    /// later, but everything the UI needs to show the move itself is here.
+    SetToken(PlacedToken),
+    /// The whole board is reset to a new state, with the given rules (see
+    /// GameManagerToPlayer::Reset); the UI should lay out the board by
+    /// Rules::row_size. Since the UI already has the previous board, only the
+    /// difference is sent.
+    ResetBoard(game::Rules, game::BoardDiff),
     /// Player with the given index has changed its status.  The index can only
-    /// be 0 or 1. TODO: create an enum for those primary/secondary players.
+    /// be 0 or 1, or also 2 or 3 in a team game (see
+    /// GameManager::set_teammates). TODO: create an enum for those
+    /// primary/secondary players.
     PlayerStateChanged(usize, PlayerState),
+    /// Player with the given index has a display name, see
+    /// PlayerToGameManager::SetName.
+    PlayerNameChanged(usize, String),
     /// Players have changed their sides. The given sides correspond to player 0
-    /// and 1.
+    /// and 1 (and in a team game, to their teammates too).
     PlayerSidesChanged(game::Side, game::Side),
-    /// Game state has changed.
-    GameStateChanged(GameState),
+    /// In a team game (see GameManager::set_teammates), index of the player
+    /// whose turn it is, see GameManager::seat_name; sent right after every
+    /// GameStateChanged where it's someone's turn.
+    SeatToMove(usize),
+    /// Game state has changed; with the number of moves made since the board
+    /// was last reset, i.e. the number of the last move, see
+    /// PlacedToken::move_num.
+    GameStateChanged(GameState, usize),
     /// There is a winner.
     WinRow(game::WinRow),
+    /// Poles where a token can be put have changed, see
+    /// game::Game::legal_moves.
+    LegalMovesChanged(Vec<game::PoleCoords>),
+    /// Suggested move for the side to move, and how good it is, in response
+    /// to UIToGameManager::RequestHint.
+    Hint(game::PoleCoords, HintScore),
+    /// Scores of all the legal moves of the side to move, from that side's
+    /// point of view, in response to UIToGameManager::RequestAnalysis.
+    Analysis(Vec<MoveScore>),
+    /// Once the game is over, all its moves (since the board was last reset,
+    /// so e.g. without the handicap tokens), annotated by the engine: every
+    /// move has the score of the position after it, and the blunders are
+    /// marked as such, see engine::review.
+    GameReview(GameRecord),
+    /// All the moves since the board was last reset (so e.g. without the
+    /// handicap tokens), in the order they were made, in response to
+    /// UIToGameManager::RequestMoveHistory.
+    MoveHistory(Vec<HistoryMove>),
+    /// Human readable notice from a player, see PlayerToGameManager::Notice;
+    /// the UI should show it for a while.
+    Notice(String),
+    /// The token at the given coords is not confirmed by the server yet, see
+    /// PlayerToGameManager::MovePending; the UI should mark it somehow.
+    TokenPending(game::TokenCoords),
+    /// The token at the given coords is confirmed by the server, see
+    /// PlayerToGameManager::MoveConfirmed.
+    TokenConfirmed(game::TokenCoords),
+    /// Something has happened which deserves a sound, see SoundCue. It's sent
+    /// along with the messages about the actual change (e.g. SetToken), so
+    /// that all UIs play the same sounds at the same moments, without having
+    /// to guess it from the other messages.
+    SoundCue(SoundCue),
+    /// Plain text line describing what has just happened, e.g. "White plays
+    /// B3, stack height 2" or "Black wins", see the announce module. Every
+    /// move, game state change and result is announced, so that the game can
+    /// be followed without seeing the board, e.g. with a screen reader. Just
+    /// like SoundCue, it's sent along with the messages about the actual
+    /// change.
+    Announcement(String),
+    /// Evaluation of the current position by the engine, from White's point
+    /// of view, on the engine's scale (see engine::EngineKind::advantage to
+    /// make sense of it). Only sent while the evaluation is on, see
+    /// UIToGameManager::SetEvaluation, shortly after every move.
+    Evaluation(i32),
+    /// The game is over for the given reason; sent right before the
+    /// GameStateChanged with the result. It's not sent when the game which is
+    /// over already comes with a full reset (e.g. from the server), since
+    /// then the reason is unknown.
+    GameOver(GameOverReason),
+    /// Time remaining on both clocks, in a timed game (see
+    /// GameManager::set_time_control). Sent whenever the game state changes,
+    /// and whenever the running clock shows another second.
+    ClockUpdate(clock::ClockTimes),
+    /// Score of the match, if the games are played as one (see
+    /// GameManager::set_match). Sent whenever a game starts or ends.
+    MatchScore(series::MatchScore),
+    /// Running tally of the games played in this session, across the
+    /// rematches, see series::SessionStats. Sent whenever a game ends, or its
+    /// end is undone.
+    SessionStats(series::SessionStats),
+    /// The game has been paused (true) or resumed (false), see
+    /// UIToGameManager::Pause; a game which is over or reset is never paused.
+    Paused(bool),
+    /// Number of times the user has peeked at the board in the current game,
+    /// see UIToGameManager::Peek. Sent on every peek, and once a game with
+    /// some peeks is reset.
+    Peeks(u32),
+    /// Chat message from someone on the other end, see
+    /// PlayerToGameManager::Chat. The user's own messages are not echoed
+    /// back.
+    Chat { from: String, text: String },
+    /// Player with the given index has measured its round-trip time, see
+    /// PlayerToGameManager::Latency.
+    PlayerLatency(usize, Duration),
+}
+
+/// Event of the game progression, for the tools which follow the game without
+/// being its UI, like recorders, bots or stream overlays; see
+/// GameManager::subscribe_events. Unlike GameManagerToUI, it's only about the
+/// game itself, not about what to show.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum GameEvent {
+    /// The game was set up: a new one, a resumed one, or the same one again,
+    /// e.g. when a network player reconnects.
+    GameStarted(FullGameState),
+    /// The given side has put a token at the given coords; with the number
+    /// of the move, see PlacedToken::move_num.
+    MovePlayed(game::Side, game::TokenCoords, usize),
+    /// The given number of the last moves was taken back, see
+    /// PlayerToGameManager::RequestUndo.
+    MovesTakenBack(usize),
+    /// The game state has changed, e.g. it's the other side's turn now.
+    StateChanged(GameState),
+    /// The game is over, with the given final state, for the given reason.
+    /// StateChanged with the same state follows.
+    GameEnded(GameState, GameOverReason),
+}
+
+/// Why the game is over, see GameManagerToUI::GameOver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum GameOverReason {
+    /// The last move has ended it according to the rules: e.g. completed a
+    /// row, or filled the board.
+    Move,
+    /// Nobody can complete any row anymore, see game::Game::is_dead_draw.
+    DeadDraw,
+    /// The given side has resigned, see PlayerToGameManager::Resign.
+    Resignation(game::Side),
+    /// Both sides have agreed to a draw, see PlayerToGameManager::OfferDraw.
+    DrawAgreed,
+    /// The given side has run out of time, see GameManager::set_time_control.
+    Timeout(game::Side),
+}
+
+/// How good the suggested move is, see GameManagerToUI::Hint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum HintScore {
+    /// The move is proven to win within the given number of plies (moves of
+    /// both sides, including this one), see engine::solver.
+    ForcedWin(usize),
+    /// Score of the move, from the point of view of the side which makes it,
+    /// on the engine's scale (see engine::SearchResult).
+    Score(i32),
+}
+
+/// What kind of sound to play, see GameManagerToUI::SoundCue. It's up to the
+/// UI how exactly every cue sounds, or whether it sounds at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum SoundCue {
+    /// A token of the given side was put on the board.
+    TokenPlaced(game::Side),
+    /// After a move, it's the given side's turn.
+    TurnStart(game::Side),
+    /// The game was won by the given side.
+    Win(game::Side),
+    /// The game ended in a draw.
+    Draw,
+    /// A player tried to make a move which isn't allowed, e.g. on a full pole
+    /// or not in its turn.
+    InvalidMove,
+}
+
+/// Message that UI can send to GameManager.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum UIToGameManager {
+    /// Change settings of the players.
+    ConfigurePlayers(PlayerConfig),
+    /// Suggest a move for the side to move; the GameManager responds with
+    /// GameManagerToUI::Hint, unless the position changes before the engine
+    /// finds it. Asking again while the engine is at it changes nothing.
+    RequestHint,
+    /// Score every legal move of the side to move, with the engine at a fixed
+    /// depth; the GameManager responds with GameManagerToUI::Analysis, unless
+    /// the position changes before the engine is done.
+    RequestAnalysis,
+    /// Turn on or off the evaluation of the position after every move; while
+    /// it's on, the GameManager sends GameManagerToUI::Evaluation, e.g. for an
+    /// evaluation bar. It's off by default, since the search takes some CPU.
+    SetEvaluation(bool),
+    /// Start a new game, with the same sides and setup (see
+    /// GameManager::set_handicap), at any moment. Both players have to agree:
+    /// the GameManager asks them with GameManagerToPlayer::RematchOffered, and
+    /// once both have agreed, the UI gets GameManagerToUI::ResetBoard with
+    /// the empty board. Local players and the AI agree right away, while in a
+    /// network game, the remote user has to ask for it too.
+    NewGame,
+    /// Call off the new game, whoever has asked for it (see NewGame): the
+    /// players get GameManagerToPlayer::RematchDeclined, and in a network
+    /// game, the remote user hears about it. The UI gets the Notice and the
+    /// Announcement, just like when a player declines. If nobody has asked,
+    /// nothing happens.
+    DeclineNewGame,
+    /// Ask for the moves of the current game; the GameManager responds with
+    /// GameManagerToUI::MoveHistory.
+    RequestMoveHistory,
+    /// The app is shutting down, e.g. the window is closed: the GameManager
+    /// lets both players know (see GameManagerToPlayer::Shutdown), and its
+    /// run returns. The same happens if the UI just drops its sender.
+    Shutdown,
+    /// Pause the game which is still going: the clocks stop, and the
+    /// GameManager refuses moves (see RejectReason::Paused) until Resume. The
+    /// UI gets GameManagerToUI::Paused either way. A network game (see
+    /// GameManager::set_network_game) can't be paused, since the remote
+    /// user's game would go on: the UI just gets a notice.
+    Pause,
+    /// Resume the paused game, see Pause.
+    Resume,
+    /// The user, playing the given side, peeks at the board in blindfold
+    /// mode: in a timed game, it costs the side some time, see
+    /// GameManager::set_peek_penalty. The UI gets GameManagerToUI::Peeks.
+    Peek(game::Side),
+    /// The user says the given text in the chat; the GameManager passes it on
+    /// to the players as GameManagerToPlayer::Chat, and in a network game,
+    /// the remote user gets it.
+    Chat(String),
+}
+
+/// A move of the current game, see GameManagerToUI::MoveHistory.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HistoryMove {
+    /// Side which made the move.
+    pub side: game::Side,
+    /// Pole on which the token was put, and the y where it ended up.
+    pub pole: game::PoleCoords,
+    pub y: usize,
+    /// When the move was made, by the GameManager's clock, see
+    /// GameManager::wall_time.
+    pub time: SystemTime,
+}
+
+/// Kind of the search which GameManager runs on behalf of the UI.
+#[derive(Debug, Clone, Copy)]
+enum SearchKind {
+    /// See UIToGameManager::RequestHint.
+    Hint,
+    /// See UIToGameManager::RequestAnalysis.
+    Analysis,
+    /// See UIToGameManager::SetEvaluation.
+    Evaluation,
+}
+
+/// Finished search, see GameManager::start_search: the position and the side
+/// it was searched for (for the review, the side which made the last move),
+/// and the result.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SearchDone {
+    pub board: game::BoardState,
+    pub side: game::Side,
+    pub outcome: SearchOutcome,
+}
+
+/// Result of the search, depending on its kind.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum SearchOutcome {
+    Hint(Option<(game::PoleCoords, HintScore)>),
+    Analysis(Vec<MoveScore>),
+    Review(GameRecord),
+    /// From White's point of view, see GameManagerToUI::Evaluation.
+    Evaluation(Option<i32>),
 }
@@ -0,0 +1,140 @@
+use super::series::MatchScore;
+use super::GameState;
+use crate::game::{self, Side};
+
+/// Returns the announcement of a move, e.g. "White plays B3, stack height 2",
+/// using the same notation for the poles as the game records (see
+/// game::PoleCoords).
+pub fn token_placed(side: Side, tcoords: game::TokenCoords) -> String {
+    format!(
+        "{} plays {}, stack height {}",
+        side_name(side),
+        game::PoleCoords::new(tcoords.x, tcoords.z),
+        tcoords.y + 1
+    )
+}
+
+/// Returns the announcement of the game state, e.g. "Black to move" or
+/// "White wins".
+pub fn game_state(state: GameState) -> String {
+    match state {
+        GameState::WaitingFor(side, _) => format!("{} to move", side_name(side)),
+        GameState::WonBy(side, _) => format!("{} wins", side_name(side)),
+        GameState::Draw(_) => "Draw".to_string(),
+    }
+}
+
+/// Returns the announcement of the whole board being reset to the given
+/// state, e.g. "New game, White to move".
+pub fn board_reset(board: &game::BoardState, state: GameState) -> String {
+    let tokens = board.count(Side::White) + board.count(Side::Black);
+    if tokens == 0 {
+        format!("New game, {}", game_state(state))
+    } else {
+        format!(
+            "Board reset, {} tokens placed, {}",
+            tokens,
+            game_state(state)
+        )
+    }
+}
+
+/// Returns the announcement of the given side resigning.
+pub fn resigned(side: Side) -> String {
+    format!("{} resigns", side_name(side))
+}
+
+/// Returns the announcement of the given side running out of time.
+pub fn out_of_time(side: Side) -> String {
+    format!("{} is out of time", side_name(side))
+}
+
+/// Returns the announcement of the given side offering a draw.
+pub fn draw_offered(side: Side) -> String {
+    format!("{} offers a draw", side_name(side))
+}
+
+/// Returns the announcement of the draw offer being accepted by the given
+/// side.
+pub fn draw_agreed(side: Side) -> String {
+    format!("{} accepts the draw", side_name(side))
+}
+
+/// Returns the announcement of the given side asking to take back the given
+/// number of moves.
+pub fn undo_requested(side: Side, moves: usize) -> String {
+    format!(
+        "{} asks to take back {}",
+        side_name(side),
+        moves_name(moves)
+    )
+}
+
+/// Returns the announcement of the given number of moves taken back, e.g.
+/// "2 moves taken back, White to move".
+pub fn undone(moves: usize, state: GameState) -> String {
+    format!("{} taken back, {}", moves_name(moves), game_state(state))
+}
+
+/// Returns the announcement of the given side asking for a new game.
+pub fn rematch_offered(side: Side) -> String {
+    format!("{} asks for a new game", side_name(side))
+}
+
+/// Returns the announcement of the given side calling off the new game; None
+/// means it's called off by the user of the UI, see
+/// UIToGameManager::DeclineNewGame.
+pub fn rematch_declined(side: Option<Side>) -> String {
+    match side {
+        Some(side) => format!("{} declines a new game", side_name(side)),
+        None => "The new game is called off".to_string(),
+    }
+}
+
+/// Returns the announcement of the match score after a game, e.g. "Match
+/// score 2-1", or "White wins the match 3-1" once it's over, the side being
+/// the one the winner has played the last game with.
+pub fn match_score(score: &MatchScore, winner_side: Option<Side>) -> String {
+    let [w0, w1] = score.wins;
+    match (score.winner(), winner_side) {
+        (Some(i), Some(side)) => {
+            let (won, lost) = if i == 0 { (w0, w1) } else { (w1, w0) };
+            format!("{} wins the match {}-{}", side_name(side), won, lost)
+        }
+        _ => format!("Match score {}-{}", w0, w1),
+    }
+}
+
+/// Returns the announcement of the given side having to place the given
+/// number of handicap tokens before the game starts.
+pub fn handicap_setup(side: Side, tokens: usize) -> String {
+    if tokens == 1 {
+        format!("{} places 1 more handicap token", side_name(side))
+    } else {
+        format!("{} places {} more handicap tokens", side_name(side), tokens)
+    }
+}
+
+/// Returns the announcement of the game being paused or resumed.
+pub fn paused(paused: bool) -> String {
+    if paused {
+        "Game paused".to_string()
+    } else {
+        "Game resumed".to_string()
+    }
+}
+
+fn moves_name(moves: usize) -> String {
+    if moves == 1 {
+        "1 move".to_string()
+    } else {
+        format!("{} moves", moves)
+    }
+}
+
+fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::White => "White",
+        Side::Black => "Black",
+    }
+}
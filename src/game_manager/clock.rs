@@ -0,0 +1,238 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+// Tokio's, so that the clocks follow the paused time in tests.
+use tokio::time::Instant;
+
+use super::ClockState;
+use crate::game::Side;
+
+/// Time control of a timed game: every side starts with the main time on its
+/// clock, and gets the increment added after every move. In the string form,
+/// it's the main time in minutes and the increment in seconds, e.g. "5+3"; the
+/// increment can be omitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TimeControl {
+    pub main: Duration,
+    pub increment: Duration,
+}
+
+impl FromStr for TimeControl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (main, increment) = s.split_once('+').unwrap_or((s, "0"));
+
+        let main: f64 = main
+            .parse()
+            .with_context(|| format!("invalid main time {:?}; try e.g. '5+3'", main))?;
+        let increment: f64 = increment
+            .parse()
+            .with_context(|| format!("invalid increment {:?}; try e.g. '5+3'", increment))?;
+
+        let valid = main > 0.0 && main.is_finite() && increment >= 0.0 && increment.is_finite();
+        if !valid {
+            return Err(anyhow!("invalid time control {:?}; try e.g. '5+3'", s));
+        }
+
+        // Huge values don't fit a Duration.
+        Ok(TimeControl {
+            main: Duration::try_from_secs_f64(main * 60.0)
+                .with_context(|| format!("the main time in {:?} is too long", s))?,
+            increment: Duration::try_from_secs_f64(increment)
+                .with_context(|| format!("the increment in {:?} is too long", s))?,
+        })
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}+{}",
+            self.main.as_secs_f64() / 60.0,
+            self.increment.as_secs_f64()
+        )
+    }
+}
+
+/// Clocks of both sides in a timed game. At most one of them runs at a time:
+/// the one of the side to move.
+///
+/// The clocks don't look at the time themselves: it's given to every method
+/// which needs it, so that the GameManager decides what time it is, e.g. the
+/// recorded one while replaying, see GameManager::now.
+#[derive(Debug, Clone)]
+pub struct Clocks {
+    time_control: TimeControl,
+    /// Time remaining on the clocks of White and Black, as of when the running
+    /// clock (if any) was started.
+    remaining: [Duration; 2],
+    /// Side whose clock runs, and since when.
+    running: Option<(Side, Instant)>,
+}
+
+/// Snapshot of both clocks, see GameManagerToUI::ClockUpdate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ClockTimes {
+    pub white: Duration,
+    pub black: Duration,
+    /// Side whose clock runs, if any.
+    pub running: Option<Side>,
+}
+
+impl Clocks {
+    /// Creates the clocks with the main time on both, none of them running.
+    pub fn new(time_control: TimeControl) -> Clocks {
+        Clocks {
+            time_control,
+            remaining: [time_control.main; 2],
+            running: None,
+        }
+    }
+
+    /// Puts the main time back on both clocks, and stops them.
+    pub fn reset(&mut self) {
+        *self = Clocks::new(self.time_control);
+    }
+
+    /// Time control which the clocks were created with.
+    pub fn time_control(&self) -> TimeControl {
+        self.time_control
+    }
+
+    /// Runs the clock of the given side as of now, stopping the other one;
+    /// None stops both. If the side's clock runs already, it just keeps
+    /// running.
+    pub fn run(&mut self, side: Option<Side>, now: Instant) {
+        if self.running.map(|(s, _)| s) == side {
+            return;
+        }
+
+        self.stop(now);
+        self.running = side.map(|s| (s, now));
+    }
+
+    /// Called when the given side has moved: stops its clock, and adds the
+    /// increment to it.
+    pub fn moved(&mut self, side: Side, now: Instant) {
+        if self.running.map(|(s, _)| s) == Some(side) {
+            self.stop(now);
+            let remaining = &mut self.remaining[Self::idx(side)];
+            *remaining = remaining.saturating_add(self.time_control.increment);
+        }
+    }
+
+    /// Sets the time remaining on the clock of the given side, e.g. as
+    /// reported by the remote player; if the clock runs, it goes on from
+    /// there.
+    pub fn set_remaining(&mut self, side: Side, remaining: Duration, now: Instant) {
+        self.remaining[Self::idx(side)] = remaining;
+        if let Some((s, started)) = &mut self.running {
+            if *s == side {
+                *started = now;
+            }
+        }
+    }
+
+    /// Takes the given time off the clock of the given side as of now, e.g.
+    /// as a penalty; the clock doesn't go below zero.
+    pub fn charge(&mut self, side: Side, time: Duration, now: Instant) {
+        let remaining = self.remaining(side, now).saturating_sub(time);
+        self.set_remaining(side, remaining, now);
+    }
+
+    /// Time remaining on the clock of the given side as of now.
+    pub fn remaining(&self, side: Side, now: Instant) -> Duration {
+        let remaining = self.remaining[Self::idx(side)];
+        match self.running {
+            Some((s, started)) if s == side => {
+                remaining.saturating_sub(now.saturating_duration_since(started))
+            }
+            _ => remaining,
+        }
+    }
+
+    /// The clock of the given side, for the AI to budget its time, see
+    /// PlayerConfig::ai_clock.
+    pub fn state(&self, side: Side, now: Instant) -> ClockState {
+        ClockState {
+            remaining: self.remaining(side, now),
+            increment: self.time_control.increment,
+        }
+    }
+
+    /// Returns the side whose clock runs and has run out of time as of now,
+    /// if any.
+    pub fn flagged(&self, now: Instant) -> Option<Side> {
+        self.running
+            .map(|(s, _)| s)
+            .filter(|&s| self.remaining(s, now).is_zero())
+    }
+
+    /// Snapshot of both clocks as of now.
+    pub fn times(&self, now: Instant) -> ClockTimes {
+        ClockTimes {
+            white: self.remaining(Side::White, now),
+            black: self.remaining(Side::Black, now),
+            running: self.running.map(|(s, _)| s),
+        }
+    }
+
+    fn stop(&mut self, now: Instant) {
+        if let Some((side, started)) = self.running.take() {
+            let remaining = &mut self.remaining[Self::idx(side)];
+            *remaining = remaining.saturating_sub(now.saturating_duration_since(started));
+        }
+    }
+
+    fn idx(side: Side) -> usize {
+        match side {
+            Side::White => 0,
+            Side::Black => 1,
+        }
+    }
+}
+
+impl ClockTimes {
+    /// Time remaining on the clock of the given side.
+    pub fn remaining(&self, side: Side) -> Duration {
+        match side {
+            Side::White => self.white,
+            Side::Black => self.black,
+        }
+    }
+}
+
+/// Formats the time on a clock as minutes and seconds, e.g. "4:05"; the
+/// seconds are rounded up, so that it only shows "0:00" once the time is out.
+pub fn format_time(time: Duration) -> String {
+    let secs = time.as_millis().div_ceil(1000);
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_time_controls() {
+        let tc: TimeControl = "5+3".parse().unwrap();
+        assert_eq!(tc.main, Duration::from_secs(300));
+        assert_eq!(tc.increment, Duration::from_secs(3));
+
+        let tc: TimeControl = "0.5".parse().unwrap();
+        assert_eq!(tc.main, Duration::from_secs(30));
+        assert_eq!(tc.increment, Duration::ZERO);
+
+        for s in ["", "0", "-1", "5+-1", "inf", "NaN+1", "5+x"] {
+            assert!(s.parse::<TimeControl>().is_err(), "{:?}", s);
+        }
+
+        // Too big for a Duration.
+        assert!("1e300".parse::<TimeControl>().is_err());
+        assert!("5+1e300".parse::<TimeControl>().is_err());
+    }
+}
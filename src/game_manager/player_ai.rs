@@ -0,0 +1,501 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use tokio::sync::mpsc;
+
+use super::{
+    ClockState, FullGameState, GameManagerToPlayer, GameState, PlayerState, PlayerToGameManager,
+};
+use crate::engine::solver::{self, Outcome};
+use crate::engine::{Engine, SearchLimits, MAX_DEPTH};
+use crate::game;
+use crate::game::eval::Style;
+
+/// Number of moves which the AI expects the game to last for, when it budgets
+/// its thinking time from the clock, see think_budget.
+const MOVES_TO_GO: u32 = 20;
+
+/// Before every move, and when the opponent offers a draw, the solver looks
+/// this many plies ahead (but for no longer than the time limit) to find out
+/// whether the game is lost or drawn, see PlayerAI::solve.
+const SOLVE_PLIES: usize = 5;
+const SOLVE_TIME_LIMIT: Duration = Duration::from_millis(500);
+
+/// AI player, which picks its moves using a search engine over its own copy
+/// of the game. Normally it's secondary, and waits for the GameManager to
+/// reset the game and tell its side; but to let two AIs play each other, it can
+/// also be primary, see PlayerAI::set_initial_state.
+pub struct PlayerAI {
+    /// Current player side, if any.
+    side: Option<game::Side>,
+    /// Our own copy of the game, kept up to date with the moves of both sides.
+    game: game::Game,
+    /// Engine which searches for the moves.
+    engine: Arc<dyn Engine>,
+    /// How well the AI plays; can be changed with GameManagerToPlayer::Configure.
+    difficulty: Difficulty,
+    /// How the AI plays, see PlayerAI::set_style; can be changed with
+    /// GameManagerToPlayer::Configure too.
+    style: Style,
+    /// If set, the AI thinks about every move for this long, searching as deep
+    /// as it manages to, instead of using the depth and the time limit of the
+    /// difficulty.
+    think_time: Option<Duration>,
+    /// Our clock, if the game is timed. Then the time to think about every
+    /// move is budgeted from it (see think_budget), instead of using the fixed
+    /// think_time or the time limit of the difficulty. It's set with
+    /// GameManagerToPlayer::Configure, and in between, we keep it up to date
+    /// with our own moves.
+    clock: Option<ClockState>,
+    /// Side whose turn it is, as the GameManager has last told us; None if the
+    /// game is over.
+    turn: Option<game::Side>,
+    /// Whether we've offered a draw in this game already; we only do it once.
+    draw_offered: bool,
+    /// Board right after our last move. If the GameManager says it's our turn
+    /// while the board is still the same (e.g. it just repeats the state), we
+    /// shouldn't move again.
+    board_after_move: Option<game::BoardState>,
+    /// Whether our copy of the game has turned out to be off, and we've asked
+    /// the GameManager for the full state; meanwhile, we don't move, see
+    /// resync.
+    resyncing: bool,
+    /// Initial full game state to send to the GameManager, only for the
+    /// primary player, see PlayerAI::set_initial_state.
+    initial_state: Option<FullGameState>,
+    /// If set, every move takes at least this long, even if the search is
+    /// faster, see PlayerAI::set_move_delay.
+    move_delay: Option<Duration>,
+
+    /// Channels for communicating with the GameManager.
+    from_gm: mpsc::Receiver<GameManagerToPlayer>,
+    to_gm: mpsc::Sender<PlayerToGameManager>,
+}
+
+/// Difficulty of the AI: how deep it searches, and how often it blunders.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difficulty {
+    /// Name of the preset, see FromStr.
+    pub name: &'static str,
+    /// Search depth, see SearchLimits::depth.
+    pub depth: usize,
+    /// Probability (from 0.0 to 1.0) to make a random move instead of the best
+    /// one found.
+    pub blunder_rate: f64,
+    /// Max time to think about a single move.
+    pub time_limit: Duration,
+}
+
+impl Difficulty {
+    pub const EASY: Difficulty = Difficulty {
+        name: "easy",
+        depth: 2,
+        blunder_rate: 0.2,
+        time_limit: Duration::from_secs(1),
+    };
+
+    pub const MEDIUM: Difficulty = Difficulty {
+        name: "medium",
+        depth: 4,
+        blunder_rate: 0.05,
+        time_limit: Duration::from_secs(3),
+    };
+
+    pub const HARD: Difficulty = Difficulty {
+        name: "hard",
+        depth: 6,
+        blunder_rate: 0.0,
+        time_limit: Duration::from_secs(10),
+    };
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty::MEDIUM
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "easy" => Ok(Difficulty::EASY),
+            "medium" => Ok(Difficulty::MEDIUM),
+            "hard" => Ok(Difficulty::HARD),
+            _ => Err(anyhow!(
+                "invalid difficulty {:?}; try 'easy', 'medium' or 'hard'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Difficulty is serialized as its name, see FromStr.
+impl serde::Serialize for Difficulty {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Difficulty {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl PlayerAI {
+    /// Create a new AI player for the game with the given rules. See
+    /// PlayerAI::think_time for the think_time.
+    pub fn new(
+        rules: game::Rules,
+        engine: Arc<dyn Engine>,
+        difficulty: Difficulty,
+        think_time: Option<Duration>,
+        from_gm: mpsc::Receiver<GameManagerToPlayer>,
+        to_gm: mpsc::Sender<PlayerToGameManager>,
+    ) -> PlayerAI {
+        PlayerAI {
+            side: None,
+            game: game::Game::with_rules(rules),
+            engine,
+            difficulty,
+            style: Style::Balanced,
+            think_time,
+            clock: None,
+            turn: None,
+            draw_offered: false,
+            board_after_move: None,
+            resyncing: false,
+            initial_state: None,
+            move_delay: None,
+            from_gm,
+            to_gm,
+        }
+    }
+
+    /// Makes the player primary: it'll send the given state as the initial
+    /// update to set up the board, just like the primary PlayerLocal does (see
+    /// PlayerLocal::new). Must be called before run.
+    pub fn set_initial_state(&mut self, initial_state: FullGameState) {
+        self.side = Some(initial_state.primary_player_side);
+        self.initial_state = Some(initial_state);
+    }
+
+    /// Makes every move take at least the given time, so that a human can
+    /// follow the game, e.g. when two AIs play each other.
+    pub fn set_move_delay(&mut self, move_delay: Duration) {
+        self.move_delay = Some(move_delay);
+    }
+
+    /// Sets the playing style of the AI, see eval::Style; by default, it's
+    /// balanced.
+    pub fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    /// Event loop, runs until GameManagerToPlayer::Shutdown, should be swapned
+    /// by the client code as a separate task.
+    pub async fn run(&mut self) -> Result<()> {
+        if let Some(initial_state) = self.initial_state.take() {
+            self.game.reset_board(&initial_state.board);
+            self.to_gm
+                .send(PlayerToGameManager::SetFullGameState(initial_state))
+                .await?;
+        }
+
+        self.to_gm
+            .send(PlayerToGameManager::StateChanged(PlayerState::Ready))
+            .await?;
+
+        while let Some(val) = self.from_gm.recv().await {
+            match val {
+                GameManagerToPlayer::Reset(rules, board, new_side) => {
+                    if self.game.get_rules() != rules {
+                        self.game = game::Game::with_rules(rules);
+                    }
+                    self.game.reset_board(&board);
+                    self.side = Some(new_side);
+                    self.board_after_move = None;
+                    self.draw_offered = false;
+                    self.resyncing = false;
+                }
+                // While resyncing, the moves are in the full state already.
+                GameManagerToPlayer::OpponentPutToken(pcoords, _) if !self.resyncing => {
+                    if let Some(side) = self.side {
+                        if let Err(err) = self.game.put_token(side.opposite(), pcoords) {
+                            println!("AI: can't apply the opponent's move {}: {}", pcoords, err);
+                            self.resync().await?;
+                        }
+                    }
+                }
+                GameManagerToPlayer::OpponentPutToken(..) => {}
+                GameManagerToPlayer::GameStateChanged(state, _) if !self.resyncing => {
+                    self.handle_game_state(state).await?;
+                }
+                GameManagerToPlayer::GameStateChanged(..) => {}
+                GameManagerToPlayer::DrawOffered => {
+                    self.handle_draw_offer().await?;
+                }
+                // The AI doesn't mind the opponent taking moves back.
+                GameManagerToPlayer::UndoRequested(_) => {
+                    self.to_gm.send(PlayerToGameManager::AcceptUndo).await?;
+                }
+                GameManagerToPlayer::UndoApplied(board) => {
+                    self.game.reset_board(&board);
+                    self.board_after_move = None;
+                    self.resyncing = false;
+                }
+                // The game is over, and the state says so already.
+                GameManagerToPlayer::OpponentResigned => {}
+                // Our own clock comes with the turn, see PlayerConfig::ai_clock.
+                GameManagerToPlayer::ClockUpdate(_) => {}
+                // We only ask for it when resyncing, see resync.
+                GameManagerToPlayer::ProvideFullState(Some(fgstate)) if self.resyncing => {
+                    self.resyncing = false;
+                    self.game.reset_board(&fgstate.board);
+                    self.side = Some(fgstate.primary_player_side);
+                    self.board_after_move = None;
+                    self.handle_game_state(fgstate.game_state).await?;
+                }
+                GameManagerToPlayer::ProvideFullState(_) => {
+                    self.resyncing = false;
+                }
+                // Nor does it have anything to say.
+                GameManagerToPlayer::Chat(_) => {}
+                // Nor does it mind starting a new game.
+                GameManagerToPlayer::RematchOffered => {
+                    self.to_gm.send(PlayerToGameManager::Rematch).await?;
+                }
+                // We've agreed already, so it's up to the opponent.
+                GameManagerToPlayer::RematchDeclined => {}
+                GameManagerToPlayer::StateRejected(reason) => {
+                    println!("AI: the game state was rejected: {}", reason);
+                }
+                // Our moves are legal on our copy of the game, so it's only
+                // rejected if the copy is off somehow.
+                GameManagerToPlayer::MoveRejected(pcoords, reason) => {
+                    println!("AI: the move {} was rejected: {}", pcoords, reason);
+                    self.resync().await?;
+                }
+                GameManagerToPlayer::Shutdown => break,
+                GameManagerToPlayer::Configure(config) => {
+                    if let Some(difficulty) = config.ai_difficulty {
+                        println!("AI: difficulty is now {}", difficulty);
+                        self.difficulty = difficulty;
+                    }
+                    if let Some(style) = config.ai_style {
+                        println!("AI: style is now {}", style);
+                        self.style = style;
+                    }
+                    if let Some(clock) = config.ai_clock {
+                        self.clock = Some(clock);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Our copy of the game is off somehow, e.g. the opponent's move doesn't
+    /// fit it: rather than give up on the game, ask the GameManager for the
+    /// full state, and start over from it, see
+    /// GameManagerToPlayer::ProvideFullState.
+    async fn resync(&mut self) -> Result<()> {
+        if self.resyncing {
+            return Ok(());
+        }
+
+        self.resyncing = true;
+        self.to_gm
+            .send(PlayerToGameManager::RequestFullState)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Called whenever game state changes. Whenever the state changes so that
+    /// it's our turn now, it will think and make a move; or, if the solver
+    /// proves that the game is lost anyway, resign. If it proves a draw, we
+    /// offer it to the opponent, but still move.
+    async fn handle_game_state(&mut self, state: GameState) -> Result<()> {
+        self.turn = match state {
+            GameState::WaitingFor(side, _) => Some(side),
+            GameState::WonBy(_, _) | GameState::Draw(_) => None,
+        };
+
+        match state {
+            GameState::WaitingFor(next_move_side, _) => {
+                if self.side != Some(next_move_side)
+                    || self.board_after_move.as_ref() == Some(self.game.get_board())
+                {
+                    return Ok(());
+                }
+
+                let started = Instant::now();
+
+                match self.solve(next_move_side).await? {
+                    Some(Outcome::Loss(plies)) => {
+                        println!("AI: the game is lost in {} plies, resigning", plies);
+                        self.to_gm.send(PlayerToGameManager::Resign).await?;
+                        return Ok(());
+                    }
+                    Some(Outcome::Draw) if !self.draw_offered => {
+                        println!("AI: the game is a draw, offering it");
+                        self.to_gm.send(PlayerToGameManager::OfferDraw).await?;
+                        self.draw_offered = true;
+                    }
+                    _ => {}
+                }
+
+                let pcoords = match self.pick_move(next_move_side).await? {
+                    Some(pcoords) => pcoords,
+                    None => {
+                        println!("AI: no legal moves");
+                        return Ok(());
+                    }
+                };
+
+                if let Some(move_delay) = self.move_delay {
+                    tokio::time::sleep_until((started + move_delay).into()).await;
+                }
+
+                // Keep our clock roughly in sync until the GameManager
+                // sends the actual one.
+                if let Some(clock) = &mut self.clock {
+                    clock.remaining = clock
+                        .remaining
+                        .saturating_sub(started.elapsed())
+                        .saturating_add(clock.increment);
+                }
+
+                self.game.put_token(next_move_side, pcoords)?;
+                self.board_after_move = Some(self.game.get_board().clone());
+                self.to_gm
+                    .send(PlayerToGameManager::PutToken(pcoords))
+                    .await?;
+            }
+
+            // We don't need to do anything special on any other game state, but
+            // still enumerating them all explicitly so that if the enum
+            // changes, we're forced by the compiler to revisit this logic.
+            GameState::WonBy(_, _) | GameState::Draw(_) => {}
+        };
+
+        Ok(())
+    }
+
+    /// Called when the opponent offers a draw: we accept it if the solver
+    /// proves that we can't win anyway (and say nothing otherwise, so it's
+    /// declined once we move).
+    async fn handle_draw_offer(&mut self) -> Result<()> {
+        let (side, turn) = match (self.side, self.turn) {
+            (Some(side), Some(turn)) => (side, turn),
+            _ => return Ok(()),
+        };
+
+        // The solver tells the outcome for the side to move, which is not
+        // necessarily us.
+        let accept = match self.solve(turn).await? {
+            Some(Outcome::Draw) => true,
+            Some(Outcome::Win(_)) => turn != side,
+            Some(Outcome::Loss(_)) => turn == side,
+            None => false,
+        };
+
+        if accept {
+            println!("AI: accepting the draw offer");
+            self.to_gm.send(PlayerToGameManager::AcceptDraw).await?;
+        } else {
+            println!("AI: not accepting the draw offer");
+        }
+
+        Ok(())
+    }
+
+    /// Tries to prove the outcome of the current position for the given side,
+    /// which has the move, see solver::solve.
+    async fn solve(&self, side: game::Side) -> Result<Option<Outcome>> {
+        let game = self.game.clone();
+        let deadline = Instant::now() + SOLVE_TIME_LIMIT;
+        let solution = tokio::task::spawn_blocking(move || {
+            solver::solve(&game, side, SOLVE_PLIES, Some(deadline))
+        })
+        .await?;
+
+        Ok(solution.map(|s| s.outcome))
+    }
+
+    /// Pick the move for the given side according to the difficulty: usually
+    /// the best one found by the search, but sometimes a random one.
+    async fn pick_move(&self, side: game::Side) -> Result<Option<game::PoleCoords>> {
+        let difficulty = self.difficulty;
+
+        if rand::thread_rng().gen_bool(difficulty.blunder_rate) {
+            let pcoords = self
+                .game
+                .legal_moves()
+                .choose(&mut rand::thread_rng())
+                .copied();
+            if let Some(pcoords) = pcoords {
+                println!("AI: putting token {} at random", pcoords);
+            }
+
+            return Ok(pcoords);
+        }
+
+        // The search can take a while, so run it on a thread where blocking is
+        // ok.
+        let game = self.game.clone();
+        let engine = self.engine.clone();
+        let depth = match self.think_time {
+            Some(_) => MAX_DEPTH,
+            None => difficulty.depth,
+        };
+        let think_time = match (self.clock, self.think_time) {
+            (Some(clock), _) => think_budget(&clock),
+            (None, Some(think_time)) => think_time,
+            (None, None) => difficulty.time_limit,
+        };
+        let limits = SearchLimits {
+            depth,
+            // With a huge clock, there's no deadline to speak of.
+            deadline: Instant::now().checked_add(think_time),
+            style: self.style,
+        };
+        let res =
+            tokio::task::spawn_blocking(move || engine.best_move(&game, side, &limits)).await?;
+
+        if let Some(res) = &res {
+            println!(
+                "AI: putting token {} with score {}",
+                res.best_move, res.score
+            );
+        }
+
+        Ok(res.map(|res| res.best_move))
+    }
+}
+
+/// Returns how long to think about the next move given the clock: a share of
+/// the remaining time, assuming that the game lasts for MOVES_TO_GO more moves,
+/// plus the increment, which is added back after the move anyway. It never
+/// takes more than half the remaining time though, so that the AI doesn't run
+/// out of time even when the clock is low and the increment is large.
+fn think_budget(clock: &ClockState) -> Duration {
+    let budget = (clock.remaining / MOVES_TO_GO).saturating_add(clock.increment);
+    budget.min(clock.remaining / 2)
+}
@@ -0,0 +1,397 @@
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::mpsc;
+use tokio::time;
+
+use super::{FullGameState, GameManagerToPlayer, GameState, PlayerState, PlayerToGameManager};
+use crate::game::{self, ROW_SIZE};
+
+/// Version of the external engine protocol, see PlayerExternalEngine.
+pub const ENGINE_PROTOCOL_VERSION: u32 = 1;
+
+/// How long the engine may take to start and greet us back.
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// On top of the think time, the engine gets this much more to reply with its
+/// move, to account for the time it takes to pass the messages around.
+const MOVE_GRACE: Duration = Duration::from_secs(1);
+
+/// How long to wait for the engine to exit after asking it to quit; after
+/// that, it's killed.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Player whose moves are picked by an external engine: a separate program
+/// which talks a simple line-based protocol over its stdin and stdout, a bit
+/// like UCI in chess. This way, anyone can plug their own bot into the game
+/// without touching this crate.
+///
+/// Every line is a command, optionally followed by space-separated arguments.
+/// The player sends these to the engine:
+///
+/// - `connectfour <version>`: the greeting, with ENGINE_PROTOCOL_VERSION. The
+///   engine replies with `ready`, optionally followed by its name.
+/// - `rules <variant>...`: the rules of the game, sent after the greeting,
+///   and again if they change: either `standard`, or `misere` and/or
+///   `scoring` (see game::Rules).
+/// - `position <side> <cells>`: the position to think about; the side to move
+///   (`white` or `black`), and the board as 64 characters, `w`, `b` or `.`
+///   for empty, pole by pole in the order A1, A2, A3, A4, B1, ..., D4 (see
+///   game::PoleCoords), every pole from the bottom up.
+/// - `go <millis>`: find a move in the last position within the given time;
+///   the engine replies with `move <pole>`, e.g. `move B3`.
+/// - `quit`: the engine should exit.
+///
+/// Also, the engine can send `info <anything>` at any time, which is just
+/// printed, e.g. for debugging; other lines from the engine are ignored. A
+/// conversation looks like this:
+///
+/// ```text
+/// > connectfour 1
+/// < ready MyBot 1.0
+/// > rules standard
+/// > position white ................................................................
+/// > go 3000
+/// < info depth 6 score 0
+/// < move B2
+/// ```
+///
+/// If the engine exits, doesn't reply in time or makes an illegal move, the
+/// player errors out.
+pub struct PlayerExternalEngine {
+    /// The engine program, and its arguments.
+    command: Vec<String>,
+    rules: game::Rules,
+    /// How long the engine may think about every move.
+    think_time: Duration,
+
+    /// Current player side, if any.
+    side: Option<game::Side>,
+    /// Our own copy of the game, kept up to date with the moves of both sides.
+    game: game::Game,
+    /// Board right after our last move, see PlayerAI::board_after_move.
+    board_after_move: Option<game::BoardState>,
+    /// Initial full game state to send to the GameManager, only for the
+    /// primary player, see PlayerExternalEngine::set_initial_state.
+    initial_state: Option<FullGameState>,
+    /// If set, every move takes at least this long, see
+    /// PlayerExternalEngine::set_move_delay.
+    move_delay: Option<Duration>,
+
+    /// Channels for communicating with the GameManager.
+    from_gm: mpsc::Receiver<GameManagerToPlayer>,
+    to_gm: mpsc::Sender<PlayerToGameManager>,
+}
+
+/// Running engine process.
+struct EngineProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl PlayerExternalEngine {
+    /// Create a new player, which will run the given command (the program
+    /// and its arguments) as the engine, and give it the given think time for
+    /// every move.
+    pub fn new(
+        command: Vec<String>,
+        rules: game::Rules,
+        think_time: Duration,
+        from_gm: mpsc::Receiver<GameManagerToPlayer>,
+        to_gm: mpsc::Sender<PlayerToGameManager>,
+    ) -> PlayerExternalEngine {
+        PlayerExternalEngine {
+            command,
+            rules,
+            think_time,
+            side: None,
+            game: game::Game::with_rules(rules),
+            board_after_move: None,
+            initial_state: None,
+            move_delay: None,
+            from_gm,
+            to_gm,
+        }
+    }
+
+    /// Makes the player primary, see PlayerAI::set_initial_state. Must be
+    /// called before run.
+    pub fn set_initial_state(&mut self, initial_state: FullGameState) {
+        self.side = Some(initial_state.primary_player_side);
+        self.initial_state = Some(initial_state);
+    }
+
+    /// Makes every move take at least the given time, see
+    /// PlayerAI::set_move_delay.
+    pub fn set_move_delay(&mut self, move_delay: Duration) {
+        self.move_delay = Some(move_delay);
+    }
+
+    /// Event loop, runs until GameManagerToPlayer::Shutdown or until the
+    /// GameManager is gone, should be spawned by the client code as a separate
+    /// task. If the engine fails, the player
+    /// becomes not ready, and the error is returned.
+    pub async fn run(&mut self) -> Result<()> {
+        let res = self.play().await;
+        if let Err(err) = &res {
+            let state = PlayerState::NotReady(format!("engine failed: {:#}", err));
+            self.to_gm
+                .send(PlayerToGameManager::StateChanged(state))
+                .await?;
+        }
+
+        res
+    }
+
+    /// The actual event loop, see run.
+    async fn play(&mut self) -> Result<()> {
+        let mut engine = self.start_engine().await?;
+
+        if let Some(initial_state) = self.initial_state.take() {
+            self.game.reset_board(&initial_state.board);
+            self.to_gm
+                .send(PlayerToGameManager::SetFullGameState(initial_state))
+                .await?;
+        }
+
+        self.to_gm
+            .send(PlayerToGameManager::StateChanged(PlayerState::Ready))
+            .await?;
+
+        while let Some(val) = self.from_gm.recv().await {
+            match val {
+                GameManagerToPlayer::Reset(rules, board, new_side) => {
+                    if self.rules != rules {
+                        self.rules = rules;
+                        self.game = game::Game::with_rules(rules);
+                        engine.send(&rules_line(rules)).await?;
+                    }
+                    self.game.reset_board(&board);
+                    self.side = Some(new_side);
+                    self.board_after_move = None;
+                }
+                GameManagerToPlayer::OpponentPutToken(pcoords, _) => {
+                    if let Some(side) = self.side {
+                        self.game
+                            .put_token(side.opposite(), pcoords)
+                            .context("opponent's move")?;
+                    }
+                }
+                GameManagerToPlayer::GameStateChanged(state, _) => {
+                    self.handle_game_state(&mut engine, state).await?;
+                }
+                // The engine plays however it plays.
+                GameManagerToPlayer::Configure(_)
+                | GameManagerToPlayer::ClockUpdate(_)
+                | GameManagerToPlayer::Chat(_)
+                | GameManagerToPlayer::ProvideFullState(_) => {}
+                // The protocol has no draws, so the engine just plays on.
+                GameManagerToPlayer::DrawOffered => {}
+                // The protocol has no undo either, but the engine gets the
+                // whole position before every move anyway, so it doesn't
+                // have to know.
+                GameManagerToPlayer::UndoRequested(_) => {
+                    self.to_gm.send(PlayerToGameManager::AcceptUndo).await?;
+                }
+                GameManagerToPlayer::UndoApplied(board) => {
+                    self.game.reset_board(&board);
+                    self.board_after_move = None;
+                }
+                GameManagerToPlayer::OpponentResigned => {}
+                // A new game is just another reset for the engine.
+                GameManagerToPlayer::RematchOffered => {
+                    self.to_gm.send(PlayerToGameManager::Rematch).await?;
+                }
+                GameManagerToPlayer::RematchDeclined => {}
+                // We check the engine's moves on our copy of the game, so
+                // it's only rejected if the copy is off somehow.
+                GameManagerToPlayer::StateRejected(reason) => {
+                    println!("engine: the game state was rejected: {}", reason);
+                }
+                GameManagerToPlayer::MoveRejected(pcoords, reason) => {
+                    println!("engine: the move {} was rejected: {}", pcoords, reason);
+                }
+                // The engine is asked to quit below.
+                GameManagerToPlayer::Shutdown => break,
+            }
+        }
+
+        engine.quit().await;
+
+        Ok(())
+    }
+
+    /// Starts the engine, and waits for it to greet us back.
+    async fn start_engine(&self) -> Result<EngineProcess> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| anyhow!("empty engine command"))?;
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context(format!("running {:?}", program))?;
+
+        let mut engine = EngineProcess {
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()).lines(),
+            child,
+        };
+
+        engine
+            .send(&format!("connectfour {}", ENGINE_PROTOCOL_VERSION))
+            .await?;
+        let name = engine.wait_for("ready", READY_TIMEOUT).await?;
+        println!("engine: {:?} is ready", name);
+
+        engine.send(&rules_line(self.rules)).await?;
+
+        Ok(engine)
+    }
+
+    /// Called whenever game state changes. Whenever the state changes so that
+    /// it's our turn now, it will ask the engine for a move, and make it.
+    async fn handle_game_state(
+        &mut self,
+        engine: &mut EngineProcess,
+        state: GameState,
+    ) -> Result<()> {
+        match state {
+            GameState::WaitingFor(next_move_side, _) => {
+                if self.side != Some(next_move_side)
+                    || self.board_after_move.as_ref() == Some(self.game.get_board())
+                    || self.game.legal_moves().is_empty()
+                {
+                    return Ok(());
+                }
+
+                let started = Instant::now();
+
+                let position = position_line(next_move_side, self.game.get_board());
+                engine.send(&position).await?;
+                engine
+                    .send(&format!("go {}", self.think_time.as_millis()))
+                    .await?;
+
+                let reply = engine
+                    .wait_for("move", self.think_time + MOVE_GRACE)
+                    .await?;
+                let pcoords: game::PoleCoords = reply.trim().parse()?;
+
+                self.game
+                    .put_token(next_move_side, pcoords)
+                    .context(format!("engine's move {}", pcoords))?;
+                println!("engine: putting token {}", pcoords);
+
+                if let Some(move_delay) = self.move_delay {
+                    time::sleep_until((started + move_delay).into()).await;
+                }
+
+                self.board_after_move = Some(self.game.get_board().clone());
+                self.to_gm
+                    .send(PlayerToGameManager::PutToken(pcoords))
+                    .await?;
+            }
+
+            // Nothing to do when the game is over, but still enumerating the
+            // states explicitly, see PlayerAI::handle_game_state.
+            GameState::WonBy(_, _) | GameState::Draw(_) => {}
+        };
+
+        Ok(())
+    }
+}
+
+impl EngineProcess {
+    /// Sends a single line to the engine.
+    async fn send(&mut self, line: &str) -> Result<()> {
+        self.stdin
+            .write_all(format!("{}\n", line).as_bytes())
+            .await
+            .context("writing to the engine")?;
+        self.stdin.flush().await?;
+
+        Ok(())
+    }
+
+    /// Waits for a line with the given command from the engine, and returns
+    /// the rest of it. Info lines are printed, and other ones are ignored.
+    async fn wait_for(&mut self, cmd: &str, timeout: Duration) -> Result<String> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let line = time::timeout_at(deadline.into(), self.stdout.next_line())
+                .await
+                .map_err(|_| anyhow!("engine didn't reply with {:?} in time", cmd))?
+                .context("reading from the engine")?
+                .ok_or_else(|| anyhow!("engine exited"))?;
+
+            let (line_cmd, rest) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            match line_cmd {
+                c if c == cmd => return Ok(rest.to_string()),
+                "info" => println!("engine: {}", rest),
+                _ => {}
+            }
+        }
+    }
+
+    /// Asks the engine to quit, and kills it if it doesn't.
+    async fn quit(mut self) {
+        let _ = self.send("quit").await;
+        if time::timeout(QUIT_TIMEOUT, self.child.wait())
+            .await
+            .is_err()
+        {
+            let _ = self.child.kill().await;
+        }
+    }
+}
+
+/// Returns the `rules` line for the given rules, see PlayerExternalEngine.
+fn rules_line(rules: game::Rules) -> String {
+    let mut variants = Vec::new();
+    if rules.misere {
+        variants.push("misere");
+    }
+    if rules.scoring {
+        variants.push("scoring");
+    }
+    if variants.is_empty() {
+        variants.push("standard");
+    }
+
+    format!("rules {}", variants.join(" "))
+}
+
+/// Returns the `position` line for the given side to move and the board, see
+/// PlayerExternalEngine.
+fn position_line(side: game::Side, board: &game::BoardState) -> String {
+    let side = match side {
+        game::Side::White => "white",
+        game::Side::Black => "black",
+    };
+
+    let mut cells = String::with_capacity(ROW_SIZE * ROW_SIZE * ROW_SIZE);
+    for x in 0..ROW_SIZE {
+        for z in 0..ROW_SIZE {
+            let pcoords = game::PoleCoords::new(x, z);
+            for y in 0..ROW_SIZE {
+                cells.push(match board.get(pcoords.token_coords(y)) {
+                    Some(game::Side::White) => 'w',
+                    Some(game::Side::Black) => 'b',
+                    None => '.',
+                });
+            }
+        }
+    }
+
+    format!("position {} {}", side, cells)
+}
@@ -1,14 +1,36 @@
-use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use rand::seq::SliceRandom;
 use tokio::sync::mpsc;
+use tokio::time;
 
-use super::{FullGameState, GameManagerToPlayer, GameState, PlayerState, PlayerToGameManager};
+use super::{
+    FullGameState, GameManagerToPlayer, GameState, PlayerState, PlayerToGameManager, RejectReason,
+};
+use crate::engine::{Engine, EngineKind, SearchLimits};
 use crate::game;
+use crate::game::eval::Style;
+
+/// Depth and time limit of the search for the move played on the user's
+/// behalf, see AutoMove::Engine. It's meant to be quick, since the user has
+/// run out of time already, e.g. on the clock.
+const AUTO_MOVE_DEPTH: usize = 4;
+const AUTO_MOVE_TIME_LIMIT: Duration = Duration::from_secs(1);
 
 /// Local player, which will request actual moves from the UI via the to_ui
 /// sender.
 pub struct PlayerLocal {
     /// Current player side, if any.
     side: Option<game::Side>,
+    /// Display name to let the GameManager know, if any, see set_name.
+    name: Option<String>,
+    /// Initial full game state to send to the GameManager, only for the
+    /// primary player.
+    initial_state: Option<FullGameState>,
 
     /// Channels for communicating with the GameManager.
     from_gm: mpsc::Receiver<GameManagerToPlayer>,
@@ -18,52 +40,96 @@ pub struct PlayerLocal {
     /// whenever it's our turn).
     to_ui: mpsc::Sender<PlayerLocalToUI>,
 
-    /// Channel to get the response back from the UI. The sender is here as
-    /// well, because this player will send that sender to the UI every time.
-    coords_from_ui_sender: mpsc::Sender<game::PoleCoords>,
-    coords_from_ui_receiver: mpsc::Receiver<game::PoleCoords>,
+    /// Channel to get the response back from the UI: the coords, or an undo
+    /// request, see UIToPlayerLocal. The sender is here as well, because this
+    /// player will send that sender to the UI every time.
+    from_ui_sender: mpsc::Sender<UIToPlayerLocal>,
+    from_ui_receiver: mpsc::Receiver<UIToPlayerLocal>,
+    /// Whether the UI has been asked for input, and hasn't sent the coords
+    /// yet; see cancel_input.
+    input_requested: bool,
+
+    /// Our own copy of the game, kept up to date with the moves of all
+    /// players, so that we can pick a move on the user's behalf; see
+    /// set_move_timeout.
+    game: game::Game,
+    /// Our move which was sent to the GameManager, but isn't on our board
+    /// yet: it's only put once the GameManager has accepted it.
+    pending_move: Option<game::PoleCoords>,
+    /// If set, the time the user has for every move, and what to play once
+    /// it passes, see set_move_timeout.
+    move_timeout: Option<(Duration, AutoMove)>,
+    /// Engine for AutoMove::Engine.
+    engine: Option<Arc<dyn Engine>>,
+    /// When the user runs out of time for the current move, if there's a move
+    /// timeout and the input is requested.
+    move_deadline: Option<time::Instant>,
 }
 
 impl PlayerLocal {
     /// Create a new local player. It will request actual moves from the UI via
     /// the to_ui sender.
     ///
-    /// If side is set, PlayerLocal will assume it's the primary player, and
-    /// will generate the initial update to set up an empty board. Why does a
-    /// player have to send messages like that - see
+    /// If initial_state is set, PlayerLocal will assume it's the primary
+    /// player, and will send this state as the initial update to set up the
+    /// board (normally an empty one, see FullGameState::new). Why does a player
+    /// have to send messages like that - see
     /// PlayerToGameManager::SetFullGameState.
     pub fn new(
-        side: Option<game::Side>,
+        initial_state: Option<FullGameState>,
         from_gm: mpsc::Receiver<GameManagerToPlayer>,
         to_gm: mpsc::Sender<PlayerToGameManager>,
         to_ui: mpsc::Sender<PlayerLocalToUI>,
     ) -> PlayerLocal {
         // Create the channel which we'll be asking UI to send the user-picked coords to.
-        let (coords_from_ui_sender, coords_from_ui_receiver) = mpsc::channel::<game::PoleCoords>(1);
+        let (from_ui_sender, from_ui_receiver) = mpsc::channel::<UIToPlayerLocal>(1);
 
         PlayerLocal {
-            side,
+            side: initial_state.as_ref().map(|v| v.primary_player_side),
+            name: None,
+            initial_state,
             from_gm,
             to_gm,
             to_ui,
-            coords_from_ui_sender,
-            coords_from_ui_receiver,
+            from_ui_sender,
+            from_ui_receiver,
+            input_requested: false,
+            game: game::Game::new(),
+            pending_move: None,
+            move_timeout: None,
+            engine: None,
+            move_deadline: None,
+        }
+    }
+
+    /// Sets the display name of the player, e.g. in a hot-seat game, so that
+    /// the UI shows who is who; see PlayerToGameManager::SetName. Must be
+    /// called before run.
+    pub fn set_name(&mut self, name: String) {
+        self.name = Some(name);
+    }
+
+    /// Gives the user the given time for every move: if the UI doesn't send
+    /// the coords in time, the player makes the move by itself, as given by
+    /// auto_move. The engine is only used for AutoMove::Engine. By default,
+    /// the user can think for as long as they like. Must be called before
+    /// run.
+    pub fn set_move_timeout(&mut self, timeout: Duration, auto_move: AutoMove, engine: EngineKind) {
+        self.move_timeout = Some((timeout, auto_move));
+        if auto_move == AutoMove::Engine {
+            self.engine = Some(engine.new_engine(1));
         }
     }
 
-    /// Event loop, runs forever, should be swapned by the client code as a separate task.
+    /// Event loop, runs until GameManagerToPlayer::Shutdown, should be swapned
+    /// by the client code as a separate task.
     pub async fn run(&mut self) -> Result<()> {
-        // If the PlayerLocal was constructed with the side right away (which
-        // has to be done if the player is a primary one), then set the initial
-        // game state to the GameManager, saying that it's our turn, with an
-        // empty board.
-        if let Some(side) = self.side {
+        // If the PlayerLocal was constructed with the initial state (which has
+        // to be done if the player is a primary one), then set it to the
+        // GameManager.
+        if let Some(initial_state) = self.initial_state.take() {
             self.to_gm
-                .send(PlayerToGameManager::SetFullGameState(FullGameState {
-                    game_state: GameState::WaitingFor(side),
-                    primary_player_side: side,
-                    board: game::BoardState::new(),
-                }))
+                .send(PlayerToGameManager::SetFullGameState(initial_state))
                 .await?;
         }
 
@@ -71,26 +137,112 @@ impl PlayerLocal {
         self.to_gm
             .send(PlayerToGameManager::StateChanged(PlayerState::Ready))
             .await?;
+        if let Some(name) = self.name.clone() {
+            self.to_gm.send(PlayerToGameManager::SetName(name)).await?;
+        }
 
         loop {
+            let move_timeout = self
+                .move_deadline
+                .map(|at| at.saturating_duration_since(time::Instant::now()));
+
             tokio::select! {
                 Some(val) = self.from_gm.recv() => {
                     //println!("player {:?}: received from GM: {:?}", self.side, val);
 
                     match val {
-                        GameManagerToPlayer::Reset(_board, new_side) => {
+                        GameManagerToPlayer::Reset(rules, board, new_side) => {
+                            // If it's still our turn, the input is requested
+                            // again with the game state which follows.
+                            self.cancel_input().await?;
                             self.side = Some(new_side);
+
+                            if self.game.get_rules() != rules {
+                                self.game = game::Game::with_rules(rules);
+                            }
+                            self.game.reset_board(&board);
+                            self.pending_move = None;
+                        },
+                        GameManagerToPlayer::OpponentPutToken(pcoords, _) => {
+                            if let Some(side) = self.side {
+                                self.game
+                                    .put_token(side.opposite(), pcoords)
+                                    .context("opponent's move")?;
+                            }
+                        },
+                        GameManagerToPlayer::OpponentResigned => {},
+                        GameManagerToPlayer::Configure(_) => {},
+                        // The UI gets the clocks on its own.
+                        GameManagerToPlayer::ClockUpdate(_) => {},
+                        // We never ask for it.
+                        GameManagerToPlayer::ProvideFullState(_) => {},
+                        // It's the local user who says it, to the remote one.
+                        GameManagerToPlayer::Chat(_) => {},
+                        // The UI has no way to accept it yet, so the user can
+                        // only decline it, by moving.
+                        GameManagerToPlayer::DrawOffered => {},
+                        // The UI has no way to ask the user either, so the
+                        // undo is accepted right away; in a local game, both
+                        // sides are at the same computer anyway.
+                        GameManagerToPlayer::UndoRequested(_) => {
+                            self.to_gm.send(PlayerToGameManager::AcceptUndo).await?;
+                        },
+                        GameManagerToPlayer::UndoApplied(board) => {
+                            self.game.reset_board(&board);
+                            self.pending_move = None;
+                        },
+                        // It's the user at this computer who asks for a new
+                        // game, so sure.
+                        GameManagerToPlayer::RematchOffered => {
+                            self.to_gm.send(PlayerToGameManager::Rematch).await?;
+                        }
+                        // The UI tells the user.
+                        GameManagerToPlayer::RematchDeclined => {},
+                        // Let the user know why nothing has happened.
+                        GameManagerToPlayer::MoveRejected(pcoords, reason) => {
+                            self.pending_move = None;
+                            self.to_ui.send(PlayerLocalToUI::MoveRejected(pcoords, reason)).await?;
+                        },
+                        // Only the initial state is sent, and it's made by
+                        // the app, so it's a bug; nothing the user can do.
+                        GameManagerToPlayer::StateRejected(reason) => {
+                            println!("player {:?}: the game state was rejected: {}", self.side, reason);
                         },
-                        GameManagerToPlayer::OpponentPutToken(_) => {},
-                        GameManagerToPlayer::GameStateChanged(state) => {
+                        GameManagerToPlayer::Shutdown => return Ok(()),
+                        GameManagerToPlayer::GameStateChanged(state, _) => {
+                            // Our move was accepted, if there was one. Our
+                            // board is only needed for the auto move, so if
+                            // it's off somehow, it's not worth failing over.
+                            if let (Some(pcoords), Some(side)) = (self.pending_move.take(), self.side) {
+                                if let Err(err) = self.game.put_token(side, pcoords) {
+                                    println!("player {:?}: failed to put our move {}: {}", self.side, pcoords, err);
+                                }
+                            }
                             self.handle_game_state(state).await?;
                         },
                     }
                 }
 
-                Some(pcoords) = self.coords_from_ui_receiver.recv() => {
-                    println!("got pole coords from UI: {:?}", &pcoords);
-                    self.to_gm.send(PlayerToGameManager::PutToken(pcoords)).await?;
+                Some(msg) = self.from_ui_receiver.recv() => {
+                    match msg {
+                        UIToPlayerLocal::PutToken(pcoords) => {
+                            println!("got pole coords from UI: {:?}", &pcoords);
+                            self.input_requested = false;
+                            self.move_deadline = None;
+                            self.pending_move = Some(pcoords);
+                            self.to_gm.send(PlayerToGameManager::PutToken(pcoords)).await?;
+                        },
+                        // The opponent is asked to accept it; once the moves
+                        // are taken back, it's our turn again, and the input
+                        // is requested anew.
+                        UIToPlayerLocal::RequestUndo => {
+                            self.to_gm.send(PlayerToGameManager::RequestUndo).await?;
+                        },
+                    }
+                }
+
+                _ = time::sleep(move_timeout.unwrap_or_default()), if move_timeout.is_some() => {
+                    self.auto_move().await?;
                 }
             }
         }
@@ -100,7 +252,7 @@ impl PlayerLocal {
     /// it's our turn now, it will request input from the UI.
     async fn handle_game_state(&mut self, state: GameState) -> Result<()> {
         match state {
-            GameState::WaitingFor(next_move_side) => {
+            GameState::WaitingFor(next_move_side, _) => {
                 let my_side = match self.side {
                     Some(side) => side,
                     None => {
@@ -109,7 +261,7 @@ impl PlayerLocal {
                 };
 
                 if my_side != next_move_side {
-                    return Ok(());
+                    return self.cancel_input().await;
                 }
 
                 // It's our turn, so request input from the UI, passing it a channel to send the
@@ -117,24 +269,156 @@ impl PlayerLocal {
                 self.to_ui
                     .send(PlayerLocalToUI::RequestInput(
                         self.side.unwrap(),
-                        self.coords_from_ui_sender.clone(),
+                        self.from_ui_sender.clone(),
                     ))
                     .await?;
+                self.input_requested = true;
+                self.move_deadline = self
+                    .move_timeout
+                    .map(|(timeout, _)| time::Instant::now() + timeout);
+            }
+
+            // Nobody is to move anymore, so if we've asked for input (e.g. the
+            // opponent has run out of time), it's stale. Still enumerating
+            // them all explicitly so that if the enum changes, we're forced by
+            // the compiler to revisit this logic.
+            GameState::WonBy(_, _) | GameState::Draw(_) => {
+                self.cancel_input().await?;
+            }
+        };
+
+        Ok(())
+    }
+
+    /// If the UI has been asked for input, lets it know that the request is
+    /// stale, e.g. because the game was reset or is over; and drops the coords
+    /// it might have sent meanwhile, since they're for the old position.
+    async fn cancel_input(&mut self) -> Result<()> {
+        self.move_deadline = None;
+        if !std::mem::take(&mut self.input_requested) {
+            return Ok(());
+        }
+
+        while self.from_ui_receiver.try_recv().is_ok() {}
+        self.to_ui
+            .send(PlayerLocalToUI::CancelInput(self.from_ui_sender.clone()))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Called once the user has run out of time for the move: takes the input
+    /// request back from the UI, and makes the move on the user's behalf, as
+    /// set with set_move_timeout.
+    async fn auto_move(&mut self) -> Result<()> {
+        self.cancel_input().await?;
+
+        let (side, auto_move) = match (self.side, self.move_timeout) {
+            (Some(side), Some((_, auto_move))) => (side, auto_move),
+            _ => return Ok(()),
+        };
+
+        let suggested = match (auto_move, &self.engine) {
+            (AutoMove::Engine, Some(engine)) => {
+                let engine = engine.clone();
+                let game = self.game.clone();
+                let limits = SearchLimits {
+                    depth: AUTO_MOVE_DEPTH,
+                    deadline: Some(Instant::now() + AUTO_MOVE_TIME_LIMIT),
+                    style: Style::Balanced,
+                };
+
+                tokio::task::spawn_blocking(move || engine.best_move(&game, side, &limits))
+                    .await?
+                    .map(|res| res.best_move)
             }
+            _ => None,
+        };
 
-            // We don't need to do anything special on any other game state, but
-            // still enumerating them all explicitly so that if the enum
-            // changes, we're forced by the compiler to revisit this logic.
-            GameState::WonBy(_) => {}
+        // Even the engine might have nothing to suggest, so then it's random
+        // as well.
+        let pcoords = match suggested.or_else(|| {
+            self.game
+                .legal_moves()
+                .choose(&mut rand::thread_rng())
+                .copied()
+        }) {
+            Some(pcoords) => pcoords,
+            None => {
+                println!("player {:?}: no legal moves to play", self.side);
+                return Ok(());
+            }
         };
 
+        self.to_gm
+            .send(PlayerToGameManager::Notice(format!(
+                "Out of time for the move, playing {}",
+                pcoords
+            )))
+            .await?;
+        self.pending_move = Some(pcoords);
+        self.to_gm
+            .send(PlayerToGameManager::PutToken(pcoords))
+            .await?;
+
         Ok(())
     }
 }
 
+/// What the PlayerLocal plays on the user's behalf once they run out of time
+/// for the move, see PlayerLocal::set_move_timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AutoMove {
+    /// The move the engine suggests, like a hint.
+    #[default]
+    Engine,
+    /// A random legal move.
+    Random,
+}
+
+impl FromStr for AutoMove {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "engine" => Ok(AutoMove::Engine),
+            "random" => Ok(AutoMove::Random),
+            _ => Err(anyhow!(
+                "invalid auto move {:?}; try 'engine' or 'random'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AutoMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutoMove::Engine => write!(f, "engine"),
+            AutoMove::Random => write!(f, "random"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum PlayerLocalToUI {
     // Lets UI know that we're waiting for the input, and when it's done,
     // the resulting coords should be sent via the provided sender.
-    RequestInput(game::Side, mpsc::Sender<game::PoleCoords>),
+    RequestInput(game::Side, mpsc::Sender<UIToPlayerLocal>),
+    // Lets UI know that the move on the given pole was refused, and why.
+    MoveRejected(game::PoleCoords, RejectReason),
+    // Lets UI know that the input requested with the given sender is not
+    // needed anymore, e.g. the game was reset; see RequestInput.
+    CancelInput(mpsc::Sender<UIToPlayerLocal>),
+}
+
+/// What the UI sends to the PlayerLocal which has requested input, see
+/// PlayerLocalToUI::RequestInput.
+#[derive(Debug)]
+pub enum UIToPlayerLocal {
+    // The user has picked the pole to put the token on.
+    PutToken(game::PoleCoords),
+    // The user asks to take back the last move instead, see
+    // PlayerToGameManager::RequestUndo.
+    RequestUndo,
 }
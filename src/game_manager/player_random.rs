@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use rand::seq::SliceRandom;
+use tokio::sync::mpsc;
+
+use super::{GameManagerToPlayer, GameState, PlayerState, PlayerToGameManager};
+use crate::game;
+
+/// Player which puts its tokens on random legal poles. It doesn't even try to
+/// play well; it's meant for testing the rest of the system (the GameManager,
+/// the server, the UI) without a human or an engine on the other side, e.g.
+/// for soak tests. It's always secondary, just like the AI by default.
+pub struct PlayerRandom {
+    /// Current player side, if any.
+    side: Option<game::Side>,
+    /// Our own copy of the game, kept up to date with the moves of both sides,
+    /// so that we know which moves are legal.
+    game: game::Game,
+    /// Board right after our last move, see PlayerAI::board_after_move.
+    board_after_move: Option<game::BoardState>,
+    /// If set, we wait for this long before every move, see
+    /// PlayerRandom::set_move_delay.
+    move_delay: Option<Duration>,
+
+    /// Channels for communicating with the GameManager.
+    from_gm: mpsc::Receiver<GameManagerToPlayer>,
+    to_gm: mpsc::Sender<PlayerToGameManager>,
+}
+
+impl PlayerRandom {
+    /// Create a new random player for the game with the given rules.
+    pub fn new(
+        rules: game::Rules,
+        from_gm: mpsc::Receiver<GameManagerToPlayer>,
+        to_gm: mpsc::Sender<PlayerToGameManager>,
+    ) -> PlayerRandom {
+        PlayerRandom {
+            side: None,
+            game: game::Game::with_rules(rules),
+            board_after_move: None,
+            move_delay: None,
+            from_gm,
+            to_gm,
+        }
+    }
+
+    /// Makes the player wait for the given time before every move, so that a
+    /// human can follow the game. By default, it moves right away.
+    pub fn set_move_delay(&mut self, move_delay: Duration) {
+        self.move_delay = Some(move_delay);
+    }
+
+    /// Event loop, runs until GameManagerToPlayer::Shutdown, should be swapned
+    /// by the client code as a separate task.
+    pub async fn run(&mut self) -> Result<()> {
+        self.to_gm
+            .send(PlayerToGameManager::StateChanged(PlayerState::Ready))
+            .await?;
+
+        while let Some(val) = self.from_gm.recv().await {
+            match val {
+                GameManagerToPlayer::Reset(rules, board, new_side) => {
+                    if self.game.get_rules() != rules {
+                        self.game = game::Game::with_rules(rules);
+                    }
+                    self.game.reset_board(&board);
+                    self.side = Some(new_side);
+                    self.board_after_move = None;
+                }
+                GameManagerToPlayer::OpponentPutToken(pcoords, _) => {
+                    if let Some(side) = self.side {
+                        self.game
+                            .put_token(side.opposite(), pcoords)
+                            .context("opponent's move")?;
+                    }
+                }
+                GameManagerToPlayer::GameStateChanged(state, _) => {
+                    self.handle_game_state(state).await?;
+                }
+                GameManagerToPlayer::UndoRequested(_) => {
+                    self.to_gm.send(PlayerToGameManager::AcceptUndo).await?;
+                }
+                GameManagerToPlayer::UndoApplied(board) => {
+                    self.game.reset_board(&board);
+                    self.board_after_move = None;
+                }
+                GameManagerToPlayer::RematchOffered => {
+                    self.to_gm.send(PlayerToGameManager::Rematch).await?;
+                }
+                // See PlayerAI::run.
+                GameManagerToPlayer::StateRejected(reason) => {
+                    println!("random player: the game state was rejected: {}", reason);
+                }
+                GameManagerToPlayer::MoveRejected(pcoords, reason) => {
+                    println!(
+                        "random player: the move {} was rejected: {}",
+                        pcoords, reason
+                    );
+                }
+                GameManagerToPlayer::Shutdown => break,
+                GameManagerToPlayer::Configure(_)
+                | GameManagerToPlayer::ClockUpdate(_)
+                | GameManagerToPlayer::Chat(_)
+                | GameManagerToPlayer::ProvideFullState(_)
+                | GameManagerToPlayer::DrawOffered
+                | GameManagerToPlayer::RematchDeclined
+                | GameManagerToPlayer::OpponentResigned => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Called whenever game state changes. Whenever the state changes so that
+    /// it's our turn now, it will make a random move.
+    async fn handle_game_state(&mut self, state: GameState) -> Result<()> {
+        match state {
+            GameState::WaitingFor(next_move_side, _) => {
+                if self.side != Some(next_move_side)
+                    || self.board_after_move.as_ref() == Some(self.game.get_board())
+                {
+                    return Ok(());
+                }
+
+                if let Some(move_delay) = self.move_delay {
+                    tokio::time::sleep(move_delay).await;
+                }
+
+                let pcoords = match self.game.legal_moves().choose(&mut rand::thread_rng()) {
+                    Some(pcoords) => *pcoords,
+                    None => {
+                        println!("random player: no legal moves");
+                        return Ok(());
+                    }
+                };
+
+                self.game.put_token(next_move_side, pcoords)?;
+                self.board_after_move = Some(self.game.get_board().clone());
+                self.to_gm
+                    .send(PlayerToGameManager::PutToken(pcoords))
+                    .await?;
+            }
+
+            // Nothing to do; see PlayerAI::handle_game_state.
+            GameState::WonBy(_, _) | GameState::Draw(_) => {}
+        };
+
+        Ok(())
+    }
+}
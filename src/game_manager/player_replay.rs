@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::sync::mpsc;
+use tokio::time;
+
+use super::{FullGameState, GameManagerToPlayer, GameState, PlayerState, PlayerToGameManager};
+use crate::game;
+use crate::game::record::GameRecord;
+
+/// Default pause before every move, see PlayerReplay::set_move_delay.
+const DEFAULT_MOVE_DELAY: Duration = Duration::from_secs(1);
+
+/// Player which replays its side's moves of a recorded game, so that a past
+/// game can be watched in the UI. Both sides are normally played by a
+/// PlayerReplay with the same record; one of them has to be primary, see
+/// PlayerReplay::set_initial_state.
+///
+/// The move to play is looked up by the number of moves made so far, so a new
+/// game (or an undo) just replays the record from that point again. Once the
+/// record is over, the player doesn't move anymore.
+pub struct PlayerReplay {
+    /// Current player side, if any.
+    side: Option<game::Side>,
+    /// Game to replay; it starts from an empty board.
+    record: GameRecord,
+    /// Pause before every move, see PlayerReplay::set_move_delay.
+    move_delay: Duration,
+    /// Initial full game state to send to the GameManager, only for the
+    /// primary player, see PlayerReplay::set_initial_state.
+    initial_state: Option<FullGameState>,
+    /// Our next move, and when to make it. It's dropped whenever the game
+    /// changes under us, e.g. on reset or undo.
+    next_move: Option<(time::Instant, game::PoleCoords)>,
+
+    /// Channels for communicating with the GameManager.
+    from_gm: mpsc::Receiver<GameManagerToPlayer>,
+    to_gm: mpsc::Sender<PlayerToGameManager>,
+}
+
+impl PlayerReplay {
+    /// Create a new player which replays the given record. It's secondary,
+    /// unless set_initial_state is called.
+    pub fn new(
+        record: GameRecord,
+        from_gm: mpsc::Receiver<GameManagerToPlayer>,
+        to_gm: mpsc::Sender<PlayerToGameManager>,
+    ) -> PlayerReplay {
+        PlayerReplay {
+            side: None,
+            record,
+            move_delay: DEFAULT_MOVE_DELAY,
+            initial_state: None,
+            next_move: None,
+            from_gm,
+            to_gm,
+        }
+    }
+
+    /// Makes the player primary: it will send the given state to the
+    /// GameManager to set up the board, just like the primary PlayerLocal.
+    /// Must be called before run.
+    pub fn set_initial_state(&mut self, initial_state: FullGameState) {
+        self.side = Some(initial_state.primary_player_side);
+        self.initial_state = Some(initial_state);
+    }
+
+    /// Sets the pause before every move, so that the game is easy to follow;
+    /// by default, it's a second.
+    pub fn set_move_delay(&mut self, move_delay: Duration) {
+        self.move_delay = move_delay;
+    }
+
+    /// Event loop, runs until GameManagerToPlayer::Shutdown, should be swapned
+    /// by the client code as a separate task.
+    pub async fn run(&mut self) -> Result<()> {
+        if let Some(initial_state) = self.initial_state.take() {
+            self.to_gm
+                .send(PlayerToGameManager::SetFullGameState(initial_state))
+                .await?;
+        }
+
+        self.to_gm
+            .send(PlayerToGameManager::StateChanged(PlayerState::Ready))
+            .await?;
+
+        loop {
+            let move_delay = self
+                .next_move
+                .map(|(at, _)| at.saturating_duration_since(time::Instant::now()));
+
+            tokio::select! {
+                val = self.from_gm.recv() => {
+                    let val = match val {
+                        Some(val) => val,
+                        None => return Ok(()),
+                    };
+
+                    match val {
+                        GameManagerToPlayer::Reset(_, _, new_side) => {
+                            self.side = Some(new_side);
+                            self.next_move = None;
+                        }
+                        GameManagerToPlayer::GameStateChanged(state, moves) => {
+                            self.handle_game_state(state, moves);
+                        }
+                        GameManagerToPlayer::UndoRequested(_) => {
+                            self.to_gm.send(PlayerToGameManager::AcceptUndo).await?;
+                        }
+                        GameManagerToPlayer::UndoApplied(_) => {
+                            self.next_move = None;
+                        }
+                        GameManagerToPlayer::RematchOffered => {
+                            self.to_gm.send(PlayerToGameManager::Rematch).await?;
+                        }
+                        // See PlayerAI::run.
+                        GameManagerToPlayer::StateRejected(reason) => {
+                            println!("replay player: the game state was rejected: {}", reason);
+                        }
+                        GameManagerToPlayer::MoveRejected(pcoords, reason) => {
+                            println!(
+                                "replay player: the move {} was rejected: {}",
+                                pcoords, reason
+                            );
+                        }
+                        GameManagerToPlayer::Shutdown => return Ok(()),
+                        GameManagerToPlayer::OpponentPutToken(..)
+                        | GameManagerToPlayer::Configure(_)
+                        | GameManagerToPlayer::ClockUpdate(_)
+                        | GameManagerToPlayer::Chat(_)
+                        | GameManagerToPlayer::ProvideFullState(_)
+                        | GameManagerToPlayer::DrawOffered
+                        | GameManagerToPlayer::RematchDeclined
+                        | GameManagerToPlayer::OpponentResigned => {}
+                    }
+                }
+
+                _ = time::sleep(move_delay.unwrap_or_default()), if move_delay.is_some() => {
+                    if let Some((_, pcoords)) = self.next_move.take() {
+                        self.to_gm.send(PlayerToGameManager::PutToken(pcoords)).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Called whenever game state changes. Whenever the state changes so that
+    /// it's our turn now, it schedules the next recorded move, if it's ours.
+    fn handle_game_state(&mut self, state: GameState, moves: usize) {
+        self.next_move = None;
+
+        match state {
+            GameState::WaitingFor(next_move_side, _) => {
+                if self.side != Some(next_move_side) {
+                    return;
+                }
+
+                let m = match self.record.moves.get(moves) {
+                    Some(m) => m,
+                    None => {
+                        println!("replay player: the record is over");
+                        return;
+                    }
+                };
+
+                // E.g. the GameManager has put some tokens on its own, with a
+                // handicap; the record doesn't match the game anymore.
+                if m.side != next_move_side {
+                    println!(
+                        "replay player: move {} of the record is {:?}'s, but it's {:?}'s turn",
+                        moves + 1,
+                        m.side,
+                        next_move_side
+                    );
+                    return;
+                }
+
+                self.next_move = Some((time::Instant::now() + self.move_delay, m.pole));
+            }
+
+            // Nothing to do; see PlayerAI::handle_game_state.
+            GameState::WonBy(_, _) | GameState::Draw(_) => {}
+        };
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use tokio::sync::mpsc;
+
+use super::{FullGameState, GameManagerToPlayer, GameState, PlayerState, PlayerToGameManager};
+use crate::game;
+
+/// Name which the scripted player shows in the UI, see
+/// PlayerToGameManager::SetName.
+const NAME: &str = "script";
+
+/// Moves of a whole game, of both sides, in the order they are to be made; see
+/// PlayerScripted. In the text form, the moves are in the pole notation, e.g.
+/// "B3 A1 B3", separated by whitespace or commas; "*" stands for any move of
+/// the opponent, e.g. when playing against the AI, and "#" starts a comment
+/// until the end of the line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    /// The moves; None is "*".
+    pub moves: Vec<Option<game::PoleCoords>>,
+}
+
+impl FromStr for Script {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut moves = Vec::new();
+
+        for line in s.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            for word in line.split(|c: char| c.is_whitespace() || c == ',') {
+                match word {
+                    "" => {}
+                    "*" => moves.push(None),
+                    _ => {
+                        let pcoords = word
+                            .parse()
+                            .with_context(|| format!("move {}", moves.len() + 1))?;
+                        moves.push(Some(pcoords));
+                    }
+                }
+            }
+        }
+
+        Ok(Script { moves })
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, m) in self.moves.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            match m {
+                Some(pcoords) => write!(f, "{}", pcoords)?,
+                None => write!(f, "*")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Player which plays its side's moves of a Script, meant for end-to-end
+/// tests of the GameManager and the network games, and for demo recordings.
+/// Unlike the other players, it doesn't put up with surprises: if the
+/// GameManager rejects a move or the game state, the opponent makes a move
+/// other than the scripted one, or the game is over before the script is, run
+/// returns an error. Once the script is over, the player doesn't move anymore.
+pub struct PlayerScripted {
+    /// Current player side, if any.
+    side: Option<game::Side>,
+    /// Moves of both sides.
+    script: Script,
+    /// Initial full game state to send to the GameManager, only for the
+    /// primary player, see PlayerScripted::set_initial_state.
+    initial_state: Option<FullGameState>,
+    /// If set, we wait for this long before every move, see
+    /// PlayerScripted::set_move_delay.
+    move_delay: Option<Duration>,
+
+    /// Channels for communicating with the GameManager.
+    from_gm: mpsc::Receiver<GameManagerToPlayer>,
+    to_gm: mpsc::Sender<PlayerToGameManager>,
+}
+
+impl PlayerScripted {
+    /// Create a new player which plays the given script. It's secondary,
+    /// unless set_initial_state is called.
+    pub fn new(
+        script: Script,
+        from_gm: mpsc::Receiver<GameManagerToPlayer>,
+        to_gm: mpsc::Sender<PlayerToGameManager>,
+    ) -> PlayerScripted {
+        PlayerScripted {
+            side: None,
+            script,
+            initial_state: None,
+            move_delay: None,
+            from_gm,
+            to_gm,
+        }
+    }
+
+    /// Makes the player primary: it will send the given state to the
+    /// GameManager to set up the board, just like the primary PlayerLocal.
+    /// The script is for a game from an empty board, so normally it's
+    /// FullGameState::new. Must be called before run.
+    pub fn set_initial_state(&mut self, initial_state: FullGameState) {
+        self.side = Some(initial_state.primary_player_side);
+        self.initial_state = Some(initial_state);
+    }
+
+    /// Makes the player wait for the given time before every move, e.g. so
+    /// that a demo is easy to follow. By default, it moves right away.
+    pub fn set_move_delay(&mut self, move_delay: Duration) {
+        self.move_delay = Some(move_delay);
+    }
+
+    /// Event loop, runs until GameManagerToPlayer::Shutdown, should be swapned
+    /// by the client code as a separate task. Returns an error as soon as the
+    /// GameManager does something the script doesn't expect.
+    pub async fn run(&mut self) -> Result<()> {
+        if let Some(initial_state) = self.initial_state.take() {
+            self.to_gm
+                .send(PlayerToGameManager::SetFullGameState(initial_state))
+                .await?;
+        }
+
+        self.to_gm
+            .send(PlayerToGameManager::StateChanged(PlayerState::Ready))
+            .await?;
+        self.to_gm
+            .send(PlayerToGameManager::SetName(NAME.to_string()))
+            .await?;
+
+        while let Some(val) = self.from_gm.recv().await {
+            match val {
+                GameManagerToPlayer::Reset(_, _, new_side) => {
+                    self.side = Some(new_side);
+                }
+                GameManagerToPlayer::OpponentPutToken(pcoords, move_num) => {
+                    self.check_opponent_move(pcoords, move_num)?;
+                }
+                GameManagerToPlayer::GameStateChanged(state, moves) => {
+                    self.handle_game_state(state, moves).await?;
+                }
+                GameManagerToPlayer::RematchOffered => {
+                    self.to_gm.send(PlayerToGameManager::Rematch).await?;
+                }
+                GameManagerToPlayer::StateRejected(reason) => {
+                    return Err(anyhow!("the game state was rejected: {}", reason));
+                }
+                GameManagerToPlayer::MoveRejected(pcoords, reason) => {
+                    return Err(anyhow!("the move {} was rejected: {}", pcoords, reason));
+                }
+                // The script can't be followed once the moves are taken
+                // back. The request itself is declined by moving, as usual.
+                GameManagerToPlayer::UndoApplied(_) => {
+                    return Err(anyhow!("the moves were taken back"));
+                }
+                GameManagerToPlayer::Shutdown => break,
+                GameManagerToPlayer::Configure(_)
+                | GameManagerToPlayer::ClockUpdate(_)
+                | GameManagerToPlayer::Chat(_)
+                | GameManagerToPlayer::ProvideFullState(_)
+                | GameManagerToPlayer::DrawOffered
+                | GameManagerToPlayer::UndoRequested(_)
+                | GameManagerToPlayer::RematchDeclined
+                | GameManagerToPlayer::OpponentResigned => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the opponent's move with the given number (see
+    /// PlacedToken::move_num) against the script.
+    fn check_opponent_move(&self, pcoords: game::PoleCoords, move_num: usize) -> Result<()> {
+        let expected = match self.script.moves.get(move_num.wrapping_sub(1)) {
+            Some(expected) => expected,
+            // Past the end of the script, anything goes.
+            None => return Ok(()),
+        };
+
+        match expected {
+            Some(expected) if *expected != pcoords => Err(anyhow!(
+                "move {}: expected the opponent to play {}, got {}",
+                move_num,
+                expected,
+                pcoords
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Called whenever game state changes. Whenever the state changes so that
+    /// it's our turn now, it will make the next scripted move.
+    async fn handle_game_state(&mut self, state: GameState, moves: usize) -> Result<()> {
+        match state {
+            GameState::WaitingFor(next_move_side, _) => {
+                if self.side != Some(next_move_side) {
+                    return Ok(());
+                }
+
+                let pcoords = match self.script.moves.get(moves) {
+                    Some(Some(pcoords)) => *pcoords,
+                    Some(None) => {
+                        return Err(anyhow!(
+                            "move {}: it's our turn, but the script has \"*\"",
+                            moves + 1
+                        ));
+                    }
+                    None => {
+                        println!("scripted player: the script is over");
+                        return Ok(());
+                    }
+                };
+
+                if let Some(move_delay) = self.move_delay {
+                    tokio::time::sleep(move_delay).await;
+                }
+
+                self.to_gm
+                    .send(PlayerToGameManager::PutToken(pcoords))
+                    .await?;
+            }
+
+            GameState::WonBy(_, _) | GameState::Draw(_) => {
+                if moves < self.script.moves.len() {
+                    return Err(anyhow!(
+                        "the game is over after {} moves ({:?}), but the script has {}",
+                        moves,
+                        state,
+                        self.script.moves.len()
+                    ));
+                }
+            }
+        };
+
+        Ok(())
+    }
+}
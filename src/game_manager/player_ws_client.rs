@@ -1,5 +1,10 @@
+use std::collections::VecDeque;
+
 use anyhow::{anyhow, Result};
 use futures_util::{SinkExt, StreamExt};
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use tokio::sync::mpsc;
 use tokio::time;
 use tokio::time::Duration;
@@ -8,13 +13,58 @@ use tokio_tungstenite::tungstenite;
 
 use super::{FullGameState, GameManagerToPlayer, GameState, PlayerState, PlayerToGameManager};
 use crate::game;
-use crate::{WSClientInfo, WSClientToServer, WSFullGameState, WSServerToClient};
+use crate::protocol::{
+    WSClientInfo, WSClientToServer, WSFullGameState, WSOpenGame, WSServerToClient, WireCodec,
+    WireFormat, HEARTBEAT_INTERVAL, PROTOCOL_VERSION,
+};
+
+/// How often, at most, to let the remote user know the local user's clock
+/// while it runs, see WSClientToServer::Clock; whenever it's started or
+/// stopped, they're told right away.
+const CLOCK_SYNC_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long the server may stay silent before the connection is considered
+/// dead and we reconnect. The server sends WSServerToClient::Ping every 5s, so
+/// that's a few pings missed in a row; without it, a connection which died
+/// without a word (e.g. the network went down) would hang until TCP gives up.
+/// It's also the time to connect in the first place.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How often to measure the round-trip time to the server, see
+/// WSClientToServer::Ping.
+const LATENCY_PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Delay before reconnecting to the server after the connection has failed:
+/// it starts with the min one, and doubles with every failure in a row, up to
+/// the max one. Every delay is randomized by the jitter (a fraction of it
+/// either way), so that when the server goes down, its clients don't all come
+/// back at the same moment.
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+const RECONNECT_JITTER: f64 = 0.2;
 
 /// WebSocket client player, which will get actual moves from the remote player
 /// via the server.
 pub struct PlayerWSClient {
     connect_url: url::Url,
     game_id: String,
+    /// Token skin to advertise to the server, see WSClientInfo::token_skin.
+    token_skin: Option<String>,
+    /// Name of the local user to show to the opponent, see
+    /// WSClientInfo::player_name.
+    player_name: Option<String>,
+    /// Name of the opponent, as last passed on to the GameManager.
+    opponent_name: Option<String>,
+    /// Encoding to ask the server for, see WSClientInfo::wire_format.
+    wire_format: WireFormat,
+    /// Whether to ask the server to compress the messages, see
+    /// WSClientInfo::deflate.
+    deflate: bool,
+    /// Whether we only watch the game, see PlayerWSClient::set_spectate.
+    spectate: bool,
+    /// Session token which the server gave us for this game, to present on
+    /// reconnect; see WSClientInfo::session_token.
+    session_token: Option<String>,
 
     /// Current player side, if any.
     side: Option<game::Side>,
@@ -28,6 +78,29 @@ pub struct PlayerWSClient {
     /// prepended with that message, until the status finally becomes
     /// PlayerState::Ready.
     server_msg: Option<String>,
+
+    /// Moves of the local user which the server hasn't confirmed yet, in
+    /// order, each with the number of tokens on the board after it. If the
+    /// connection drops before the server gets them, they're played again
+    /// once we're back, see resync_outbox.
+    outbox: VecDeque<(usize, game::PoleCoords)>,
+    /// Our copy of the server's game, to check the remote player's moves
+    /// against before they're passed on to the GameManager; None until the
+    /// server gives us a game.
+    shadow: Option<ShadowGame>,
+    /// Number of tokens on the board which the GameManager was last reset to;
+    /// its moves since then are numbered from there, see
+    /// PlacedToken::move_num.
+    reset_tokens: usize,
+
+    /// Whether the remote player has asked to take back moves, and we've
+    /// passed it on to the GameManager; once it applies the undo, we accept
+    /// it on the server on behalf of the local player.
+    undo_requested: bool,
+
+    /// Delay before the next reconnect, see RECONNECT_DELAY_MIN; it's reset
+    /// once the server gives us a game.
+    reconnect_delay: Duration,
 }
 
 impl PlayerWSClient {
@@ -45,135 +118,793 @@ impl PlayerWSClient {
         PlayerWSClient {
             connect_url,
             game_id,
+            token_skin: None,
+            player_name: None,
+            opponent_name: None,
+            wire_format: WireFormat::default(),
+            deflate: true,
+            spectate: false,
+            session_token: None,
             side: None,
             from_gm,
             to_gm,
             server_msg: None,
+            outbox: VecDeque::new(),
+            shadow: None,
+            reset_tokens: 0,
+            undo_requested: false,
+            reconnect_delay: RECONNECT_DELAY_MIN,
         }
     }
 
-    /// Event loop, runs forever, should be swapned by the client code as a
-    /// separate task.
+    /// Sets the token skin to advertise to the server, see
+    /// WSClientInfo::token_skin. Must be called before run.
+    pub fn set_token_skin(&mut self, token_skin: String) {
+        self.token_skin = Some(token_skin);
+    }
+
+    /// Sets the name of the local user to show to the opponent, see
+    /// WSClientInfo::player_name; by default, there's none. Must be called
+    /// before run.
+    pub fn set_player_name(&mut self, player_name: String) {
+        self.player_name = Some(player_name);
+    }
+
+    /// Sets the encoding to ask the server for, see WSClientInfo::wire_format;
+    /// by default, it's JSON. Must be called before run.
+    pub fn set_wire_format(&mut self, wire_format: WireFormat) {
+        self.wire_format = wire_format;
+    }
+
+    /// Sets whether to ask the server to compress the messages, see
+    /// WSClientInfo::deflate; by default, it does. Must be called before run.
+    pub fn set_deflate(&mut self, deflate: bool) {
+        self.deflate = deflate;
+    }
+
+    /// Makes the client only watch the game (see WSClientInfo::spectate):
+    /// nothing the GameManager sends goes to the server, and the moves of
+    /// both sides come to the GameManager as the full game state, so this
+    /// player has to be primary, and the other one must never move. Needs a
+    /// server which speaks protocol version 14 or newer. Must be called
+    /// before run.
+    pub fn set_spectate(&mut self, spectate: bool) {
+        self.spectate = spectate;
+    }
+
+    /// Event loop, runs until GameManagerToPlayer::Shutdown, should be swapned
+    /// by the client code as a separate task.
     pub async fn run(&mut self) -> Result<()> {
         loop {
-            match self.handle_ws_conn().await {
+            let err = match self.handle_ws_conn().await {
                 Ok(()) => {
-                    panic!("should never be ok");
-                }
-                Err(err) => {
-                    println!("ws conn error: {}", &err);
-                    self.upd_state_not_ready(&err.to_string()).await?;
+                    return Ok(());
                 }
+                Err(err) => err,
+            };
+            println!("ws conn error: {}", &err);
+
+            let jitter = rand::thread_rng().gen_range(-RECONNECT_JITTER..=RECONNECT_JITTER);
+            let delay = self.reconnect_delay.mul_f64(1.0 + jitter);
+            self.reconnect_delay = (self.reconnect_delay * 2).min(RECONNECT_DELAY_MAX);
+
+            if self.wait_to_reconnect(&err.to_string(), delay).await? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Waits for the given delay before reconnecting, counting it down in the
+    /// NotReady state, e.g. "reconnecting in 8s". Meanwhile, the messages from
+    /// the GameManager are handled like before the hello, see
+    /// handle_msg_offline. Returns whether it's time to shut down.
+    async fn wait_to_reconnect(&mut self, err: &str, delay: Duration) -> Result<bool> {
+        let deadline = time::Instant::now() + delay;
+        let mut shown_secs = None;
+
+        loop {
+            let left = deadline.saturating_duration_since(time::Instant::now());
+            if left.is_zero() {
+                return Ok(false);
             }
 
-            time::sleep(Duration::from_millis(1000)).await;
+            let secs = left.as_millis().div_ceil(1000) as u64;
+            if shown_secs != Some(secs) {
+                shown_secs = Some(secs);
+                self.upd_state_not_ready(&format!("{}; reconnecting in {}s", err, secs))
+                    .await?;
+            }
+
+            // Wake up whenever the countdown changes.
+            let tick = left.saturating_sub(Duration::from_secs(secs - 1));
+            tokio::select! {
+                _ = time::sleep(tick) => {}
+                msg = self.from_gm.recv() => {
+                    let msg = msg.ok_or(anyhow!("GameManager is gone"))?;
+                    if self.handle_msg_offline(msg) {
+                        return Ok(true);
+                    }
+                }
+            }
         }
     }
 
-    /// Tries to connect, and maintains this connection until it dies. Never
-    /// returns Ok.
+    /// Tries to connect, and maintains this connection until it dies; only
+    /// returns Ok on GameManagerToPlayer::Shutdown.
     pub async fn handle_ws_conn(&mut self) -> Result<()> {
         self.upd_state_not_ready("connecting to server...").await?;
 
-        let (ws_stream, _) = connect_async(&self.connect_url).await?;
+        let (ws_stream, _) = time::timeout(SERVER_TIMEOUT, connect_async(&self.connect_url))
+            .await
+            .map_err(|_| anyhow!("timed out connecting to server"))??;
 
         self.upd_state_not_ready("authenticating...").await?;
 
         let (mut to_ws, mut from_ws) = ws_stream.split();
 
+        // Send the actual current game, so that if the server doesn't have it
+        // anymore (e.g. it was restarted while both clients kept running),
+        // it's resumed from there.
+        let game_state = match self.current_game_state().await? {
+            Some(game_state) => game_state,
+            None => {
+                to_ws.send(tungstenite::Message::Close(None)).await?;
+                return Ok(());
+            }
+        };
+
         // Now that we connected, authenticate with the server.
         let hello = WSClientToServer::Hello(WSClientInfo {
             game_id: self.game_id.clone(),
 
-            // TODO: OS username (but it's actually not used by the server yet).
-            player_name: "me".to_string(),
-
-            // TODO: send actual current board state, instead of generating a
-            // brand new one. This way, the game can resume if server was
-            // rebooted while both clients kept running and eventually
-            // reconnected.
-            game_state: WSFullGameState {
-                game_state: GameState::WaitingFor(game::Side::White),
-                ws_player_side: game::Side::White,
-                board: game::BoardState::new(),
-            },
+            player_name: self.player_name.clone().unwrap_or_default(),
+
+            game_state,
+            protocol_version: PROTOCOL_VERSION,
+            token_skin: self.token_skin.clone(),
+            wire_format: self.wire_format,
+            deflate: self.deflate,
+            session_token: self.session_token.clone(),
+            spectate: self.spectate,
         });
 
-        let j = serde_json::to_string(&hello)?;
-        to_ws.send(tungstenite::Message::Text(j)).await?;
+        to_ws
+            .send(ws_message(WireCodec::default(), &hello)?)
+            .await?;
 
         self.upd_state_not_ready("connected, waiting for the opponent...")
             .await?;
 
+        // Protocol version of the server, from the welcome message; servers
+        // which are too old to send it don't speak any of the newer stuff.
+        let mut server_protocol_version = 0;
+
+        // Encoding of the messages; it's uncompressed JSON until the server
+        // confirms something else in the welcome.
+        let mut codec = WireCodec::default();
+
+        // Which clock was running when we last sent the local user's clock,
+        // and when.
+        let mut clock_sent: Option<(Option<game::Side>, time::Instant)> = None;
+
+        // Whether the outbox has been played on the server's game, see
+        // resync_outbox; it's only needed once, on the first game reset, and
+        // later on, the moves in the outbox are just the ones in flight.
+        let mut outbox_resynced = false;
+
+        // Whether we've asked the server for its game, see
+        // WSClientToServer::RequestFullState, and it hasn't come yet; there's
+        // no point asking again meanwhile.
+        let mut resync_requested = false;
+
+        // Whether either player has asked the server for a new game, so that
+        // it's going to reset the game once both have; see
+        // GameManagerToPlayer::Reset.
+        let mut rematch_pending = false;
+
+        // When we last heard from the server, see SERVER_TIMEOUT.
+        let mut last_recv = time::Instant::now();
+
+        // Our latest ping which the server hasn't responded to yet, and when
+        // it was sent; a ping which is never responded to is just replaced
+        // with the next one.
+        let mut latency_ping_interval = time::interval(LATENCY_PING_INTERVAL);
+        let mut ping_sent: Option<(u64, time::Instant)> = None;
+        let mut ping_nonce = 0;
+
+        // Lets the server know that we're still here, see
+        // WSClientToServer::Heartbeat.
+        let mut heartbeat_interval = time::interval(HEARTBEAT_INTERVAL);
+
         loop {
             tokio::select! {
                 v = from_ws.next() => {
                     let recv = v.ok_or(anyhow!("failed to read from ws"))??;
+                    last_recv = time::Instant::now();
 
-                    let msg: WSServerToClient = match serde_json::from_str(&recv.to_string()) {
+                    let msg: WSServerToClient = match parse_ws_message(codec, &recv) {
                         Ok(v) => v,
                         Err(err) => { return Err(anyhow!("failed to parse {:?}: {}", recv, err)); }
                     };
 
                     //println!("received: {:?}", msg);
 
+                    // The offers and requests are up to the players; watchers
+                    // only get the outcome, in the game reset.
+                    let offer = matches!(
+                        msg,
+                        WSServerToClient::UndoRequested(_)
+                            | WSServerToClient::RematchOffered
+                            | WSServerToClient::RematchDeclined
+                            | WSServerToClient::Resigned(_)
+                    );
+                    if self.spectate && offer {
+                        continue;
+                    }
+
                     match msg {
                         WSServerToClient::Ping => {},
+                        // We never ask for it here, see list_open_games.
+                        WSServerToClient::OpenGames(_) => {},
+                        WSServerToClient::Welcome(info) => {
+                            server_protocol_version = info.protocol_version;
+                            codec = WireCodec {
+                                format: info.wire_format,
+                                deflate: info.deflate,
+                            };
+
+                            // The server still serves us, but might stop doing
+                            // so after a few more updates, so let the user know.
+                            if info.protocol_version > PROTOCOL_VERSION {
+                                let notice = format!(
+                                    "The server speaks a newer protocol (version {}, and supports {} or newer), \
+                                    while this client only speaks version {}; consider updating it",
+                                    info.protocol_version, info.min_protocol_version, PROTOCOL_VERSION,
+                                );
+                                self.to_gm.send(PlayerToGameManager::Notice(notice)).await?;
+                            }
+                        }
                         WSServerToClient::Msg(s) => {
                             println!("got message from server: {}", s);
                             self.upd_state_not_ready(&s).await?;
                             self.server_msg = Some(s);
                         }
                         WSServerToClient::GameReset(v) => {
+                            // The older servers have just seated us as a
+                            // player, so get out of there before we're fed
+                            // the game.
+                            if self.spectate && server_protocol_version < 14 {
+                                to_ws.send(tungstenite::Message::Close(None)).await?;
+                                return Err(anyhow!("the server doesn't support watching the games"));
+                            }
+                            rematch_pending = false;
+                            resync_requested = false;
+
+                            self.undo_requested = false;
+                            // The server works, so if the connection drops
+                            // later, it's worth trying again soon.
+                            self.reconnect_delay = RECONNECT_DELAY_MIN;
+
+                            if v.session_token.is_some() {
+                                self.session_token = v.session_token;
+                            }
+
                             // Server reset the game, it means we're just meeting with the other
                             // player, and so we need to let GameManager know two things: that
                             // we're ready to play, and also send the full game state to it.
                             self.upd_state_ready().await?;
 
+                            // We play on behalf of the opponent, so it's our
+                            // name; the older servers don't know it.
+                            let name = Some(v.opponent_name).filter(|name| {
+                                server_protocol_version >= 9 && !name.is_empty()
+                            });
+                            if name.is_some() && name != self.opponent_name {
+                                self.opponent_name = name.clone();
+                                self.to_gm.send(PlayerToGameManager::SetName(name.unwrap_or_default())).await?;
+                            }
+
+                            // If the server has missed some of the local
+                            // user's moves, e.g. since the connection dropped
+                            // right as they were made, play them on top of its
+                            // game, here and on the server.
+                            let mut game_state = v.game_state;
+                            let mut flushed = Vec::new();
+                            if !outbox_resynced {
+                                outbox_resynced = true;
+                                flushed = self.resync_outbox(&mut game_state).await?;
+                            }
+                            self.reset_tokens = num_tokens(&game_state.board);
+                            self.shadow = Some(ShadowGame::new(&game_state));
+
                             self.to_gm
                                 .send(PlayerToGameManager::SetFullGameState(FullGameState{
-                                    game_state: v.game_state.game_state,
-                                    primary_player_side: v.game_state.ws_player_side,
-                                    board: v.game_state.board,
+                                    game_state: game_state.game_state,
+                                    primary_player_side: game_state.ws_player_side,
+                                    board: game_state.board,
+                                    // Handicaps are only supported in local games.
+                                    setup_tokens: 0,
                                 }))
                                 .await?;
+
+                            for pcoords in flushed {
+                                println!("resending move {}", pcoords);
+                                let msg = WSClientToServer::PutToken(pcoords);
+                                to_ws.send(ws_message(codec, &msg)?).await?;
+                                self.move_sent(pcoords, server_protocol_version).await?;
+                            }
                         }
                         WSServerToClient::PutToken(pcoords) => {
-                            // The remote player put token, so here we're communicating it to
-                            // our local GameManager on their behalf.
-                            self.to_gm.send(PlayerToGameManager::PutToken(pcoords)).await?;
+                            self.undo_requested = false;
+
+                            // If the move can't be played on our copy of the
+                            // game, we're out of sync with the server, and
+                            // the GameManager would refuse it too; so the move
+                            // is dropped, and we ask for the server's game,
+                            // which has it. The older servers can't do that,
+                            // but the reconnect gets us their game too.
+                            if let Some(shadow) = &mut self.shadow {
+                                // Watchers get the moves of both sides.
+                                let side = match shadow.state {
+                                    GameState::WaitingFor(side, _) if self.spectate => side,
+                                    _ => shadow.ws_side,
+                                };
+                                if let Err(err) = shadow.put_token(side, pcoords) {
+                                    let err = anyhow!(
+                                        "out of sync with the server, the opponent's move {} doesn't fit: {}",
+                                        pcoords, err
+                                    );
+                                    if server_protocol_version < 15 {
+                                        return Err(err);
+                                    }
+
+                                    if !resync_requested {
+                                        println!("{}; asking for the server's game", err);
+                                        resync_requested = true;
+                                        to_ws.send(ws_message(codec, &WSClientToServer::RequestFullState)?).await?;
+                                    }
+                                    continue;
+                                }
+                            }
+
+                            if self.spectate {
+                                // Nobody plays the other side locally, so
+                                // the whole game goes to the GameManager.
+                                if let Some(shadow) = &self.shadow {
+                                    let fgstate = shadow.full_game_state();
+                                    self.to_gm.send(PlayerToGameManager::SetFullGameState(fgstate)).await?;
+                                }
+                            } else {
+                                // The remote player put token, so here we're communicating it to
+                                // our local GameManager on their behalf.
+                                self.to_gm.send(PlayerToGameManager::PutToken(pcoords)).await?;
+                            }
+                        }
+                        WSServerToClient::MoveAccepted(pcoords) => {
+                            self.move_done(pcoords);
+                            self.to_gm.send(PlayerToGameManager::MoveConfirmed(pcoords)).await?;
+                        }
+                        WSServerToClient::MoveRejected(pcoords, reason) => {
+                            // The game reset with the server's state comes
+                            // next, and it'll roll the move back.
+                            self.move_done(pcoords);
+                            let notice = format!("The server rejected the move {}: {}", pcoords, reason);
+                            self.to_gm.send(PlayerToGameManager::Notice(notice)).await?;
                         }
                         WSServerToClient::OpponentIsGone => {
                             // Opponent is gone, so update our status.
                             self.upd_state_not_ready("opponent disconnected, waiting...").await?;
                         }
+                        WSServerToClient::Idle(idle) => {
+                            // Keep in mind that our side is the remote player's
+                            // side, so if it's idle, it's the opponent.
+                            let mut notice = if Some(idle.side) == self.side {
+                                format!("The opponent hasn't moved for {}s", idle.idle_secs)
+                            } else {
+                                format!("It's your turn! You haven't moved for {}s", idle.idle_secs)
+                            };
+                            if let Some(secs) = idle.abandon_in_secs {
+                                let s = format!("; the game will be abandoned in {}s", secs);
+                                notice.push_str(&s);
+                            }
+                            self.to_gm.send(PlayerToGameManager::Notice(notice)).await?;
+                        }
+                        WSServerToClient::GameAbandoned(side) => {
+                            // The final game state comes next, in the game reset.
+                            let notice = if Some(side) == self.side {
+                                "The opponent took too long to move, so you win"
+                            } else {
+                                "You took too long to move, so you lose"
+                            };
+                            self.to_gm.send(PlayerToGameManager::Notice(notice.to_string())).await?;
+                        }
+                        WSServerToClient::GameDrawn(reason) => {
+                            // The final game state comes next, in the game
+                            // reset; normally GameManager has adjudicated the
+                            // draw on its own already.
+                            println!("the server adjudicated a draw: {}", reason);
+                        }
+                        WSServerToClient::UndoRequested(_) => {
+                            // The GameManager figures out the number of moves
+                            // on its own, from the same game.
+                            self.undo_requested = true;
+                            self.to_gm.send(PlayerToGameManager::RequestUndo).await?;
+                        }
+                        WSServerToClient::Resigned(side) => {
+                            // Our side is the remote player's side; if it's
+                            // our local player who has resigned, the
+                            // GameManager knows already. The final game state
+                            // comes next, in the game reset.
+                            if Some(side) == self.side {
+                                self.to_gm.send(PlayerToGameManager::Resign).await?;
+                            }
+                        }
+                        WSServerToClient::RematchOffered => {
+                            rematch_pending = true;
+                            self.to_gm.send(PlayerToGameManager::Rematch).await?;
+                        }
+                        WSServerToClient::RematchDeclined => {
+                            rematch_pending = false;
+                            self.to_gm.send(PlayerToGameManager::DeclineRematch).await?;
+                        }
+                        WSServerToClient::OpponentClock(ms) => {
+                            // That's the remote user's clock, so it's ours.
+                            let remaining = Duration::from_millis(ms);
+                            self.to_gm.send(PlayerToGameManager::ClockSync(remaining)).await?;
+                        }
+                        WSServerToClient::Pong(nonce) => {
+                            if let Some((sent_nonce, sent_at)) = ping_sent {
+                                if sent_nonce == nonce {
+                                    ping_sent = None;
+                                    self.to_gm.send(PlayerToGameManager::Latency(sent_at.elapsed())).await?;
+                                }
+                            }
+                        }
+                        WSServerToClient::Chat { from, text } => {
+                            let from = if from.is_empty() { "Opponent".to_string() } else { from };
+                            self.to_gm.send(PlayerToGameManager::Chat { from, text }).await?;
+                        }
                     }
                 },
 
                 Some(val) = self.from_gm.recv() => {
                     //println!("ws player {:?}: received from GM: {:?}", self.side, val);
 
+                    // Watchers never say anything to the server, they just
+                    // leave when it's time.
+                    let needed = matches!(val, GameManagerToPlayer::Reset(..) | GameManagerToPlayer::Shutdown);
+                    if self.spectate && !needed {
+                        continue;
+                    }
+
                     match val {
-                        GameManagerToPlayer::Reset(_board, new_side) => {
+                        GameManagerToPlayer::Reset(_rules, board, new_side) => {
                             // Game manager lets us know our side. Actually that info originally
                             // came from the server, so we already could have remembered it when we
                             // received WSServerToClient::GameReset, but the protocol is that
                             // GameManager assigns it to the players, so we just play ball.
                             self.side = Some(new_side);
+                            self.reset_tokens = num_tokens(&board);
+
+                            // Normally it's the server's game which we've
+                            // passed on, or a new one which the server starts
+                            // too, once both players have asked. Otherwise,
+                            // the GameManager has reset the game on its own,
+                            // and the server must know: the hello of the
+                            // reconnect has the new game, which the server
+                            // takes if its own one is over, or sends its own
+                            // one back; either way, we agree again.
+                            let known = self.shadow.as_ref().is_none_or(|shadow| *shadow.game.get_board() == board);
+                            if !known && !rematch_pending && !self.spectate {
+                                return Err(anyhow!("the game was reset here, taking it to the server"));
+                            }
                         },
-                        GameManagerToPlayer::OpponentPutToken(pcoords) => {
-                            // Our local opponent put token, so send that info to the server.
+                        GameManagerToPlayer::OpponentPutToken(pcoords, move_num) => {
+                            // Our local opponent put token, so send that info
+                            // to the server; if it doesn't get there, it's in
+                            // the outbox until we're back.
+                            self.outbox.push_back((self.reset_tokens + move_num, pcoords));
+                            if let Some(shadow) = &mut self.shadow {
+                                // If it doesn't fit, the server rejects it,
+                                // and resets the game.
+                                if let Err(err) = shadow.put_token(shadow.ws_side.opposite(), pcoords) {
+                                    println!("the local move {} doesn't fit the server's game: {}", pcoords, err);
+                                }
+                            }
+
                             let msg = WSClientToServer::PutToken(pcoords);
-                            let j = serde_json::to_string(&msg)?;
-                            to_ws.send(tungstenite::Message::Text(j)).await?;
+                            to_ws.send(ws_message(codec, &msg)?).await?;
+                            self.move_sent(pcoords, server_protocol_version).await?;
+                        },
+                        GameManagerToPlayer::OpponentResigned => {
+                            // Our local opponent has resigned, so let the
+                            // server know, which lets the remote player know.
+                            if server_protocol_version >= 7 {
+                                to_ws.send(ws_message(codec, &WSClientToServer::Resign)?).await?;
+                            } else {
+                                let notice = "The server doesn't support resigning, so the opponent won't know".to_string();
+                                self.to_gm.send(PlayerToGameManager::Notice(notice)).await?;
+                            }
+                        },
+                        GameManagerToPlayer::GameStateChanged(..) => {},
+                        GameManagerToPlayer::MoveRejected(pcoords, reason) => {
+                            // The server has accepted the remote player's
+                            // move already, and so have we, so the
+                            // GameManager's game must be out of sync; the
+                            // server's game sets it right, see
+                            // WSServerToClient::PutToken.
+                            let notice = format!("The opponent's move {} was refused: {}", pcoords, reason);
+                            self.to_gm.send(PlayerToGameManager::Notice(notice)).await?;
+                            if server_protocol_version < 15 {
+                                return Err(anyhow!("out of sync with the server, resyncing"));
+                            }
+
+                            if !resync_requested {
+                                resync_requested = true;
+                                to_ws.send(ws_message(codec, &WSClientToServer::RequestFullState)?).await?;
+                            }
+                        },
+                        GameManagerToPlayer::StateRejected(reason) => {
+                            // Same, but with the server's game: the game here
+                            // goes on as it was, so it's out of sync.
+                            let notice = format!("The server's game state was refused: {}", reason);
+                            self.to_gm.send(PlayerToGameManager::Notice(notice)).await?;
+                        },
+                        GameManagerToPlayer::Configure(_) => {},
+                        // Only asked for before the hello, see
+                        // current_game_state.
+                        GameManagerToPlayer::ProvideFullState(_) => {},
+                        // The server doesn't support draw offers, so there's
+                        // nobody to pass it on to.
+                        GameManagerToPlayer::DrawOffered => {},
+                        GameManagerToPlayer::UndoRequested(moves) => {
+                            // Our local opponent asks to undo, so pass it on
+                            // to the remote player; if it's accepted, the
+                            // server resets the game to the new state.
+                            if server_protocol_version >= 5 {
+                                let msg = WSClientToServer::RequestUndo(moves);
+                                to_ws.send(ws_message(codec, &msg)?).await?;
+                            } else {
+                                let notice = "The server doesn't support taking moves back".to_string();
+                                self.to_gm.send(PlayerToGameManager::Notice(notice)).await?;
+                            }
+                        },
+                        GameManagerToPlayer::UndoApplied(_) => {
+                            // The local player has accepted the remote
+                            // player's request, so let the server know.
+                            if self.undo_requested {
+                                self.undo_requested = false;
+
+                                to_ws.send(ws_message(codec, &WSClientToServer::AcceptUndo)?).await?;
+                            }
+                        },
+                        GameManagerToPlayer::RematchOffered => {
+                            // Our local opponent asks for a new game, so pass
+                            // it on to the remote player; once both have asked
+                            // for it, the server resets the game.
+                            if server_protocol_version >= 6 {
+                                rematch_pending = true;
+                                to_ws.send(ws_message(codec, &WSClientToServer::OfferRematch)?).await?;
+                            } else {
+                                let notice = "The server doesn't support new games; reconnect to start one".to_string();
+                                self.to_gm.send(PlayerToGameManager::Notice(notice)).await?;
+                            }
+                        },
+                        GameManagerToPlayer::RematchDeclined => {
+                            // The local user calls off the new game, whoever
+                            // has asked for it; the older servers keep the
+                            // request until the next new game.
+                            if rematch_pending && server_protocol_version >= 18 {
+                                to_ws.send(ws_message(codec, &WSClientToServer::DeclineRematch)?).await?;
+                            }
+                            rematch_pending = false;
+                        },
+                        GameManagerToPlayer::ClockUpdate(times) => {
+                            // Our side is the remote user's side, so the local
+                            // user's clock is the other one.
+                            let side = match self.side {
+                                Some(side) => side.opposite(),
+                                None => continue,
+                            };
+                            let due = clock_sent.is_none_or(|(running, at)| {
+                                running != times.running || at.elapsed() >= CLOCK_SYNC_INTERVAL
+                            });
+
+                            if server_protocol_version >= 8 && due {
+                                clock_sent = Some((times.running, time::Instant::now()));
+
+                                let ms = times.remaining(side).as_millis() as u64;
+                                to_ws.send(ws_message(codec, &WSClientToServer::Clock(ms))?).await?;
+                            }
+                        },
+                        GameManagerToPlayer::Chat(text) => {
+                            // The local user says it to the remote one.
+                            if server_protocol_version >= 11 {
+                                to_ws.send(ws_message(codec, &WSClientToServer::Chat(text))?).await?;
+                            } else {
+                                let notice = "The server doesn't support the chat".to_string();
+                                self.to_gm.send(PlayerToGameManager::Notice(notice)).await?;
+                            }
+                        },
+                        GameManagerToPlayer::Shutdown => {
+                            // Close the connection properly, so that the
+                            // server tells the remote player right away.
+                            to_ws.send(tungstenite::Message::Close(None)).await?;
+                            return Ok(());
                         },
-                        GameManagerToPlayer::GameStateChanged(_) => {},
                     }
                 }
+
+                _ = latency_ping_interval.tick() => {
+                    // The older servers wouldn't understand it.
+                    if server_protocol_version >= 12 {
+                        ping_nonce += 1;
+                        ping_sent = Some((ping_nonce, time::Instant::now()));
+                        to_ws.send(ws_message(codec, &WSClientToServer::Ping(ping_nonce))?).await?;
+                    }
+                }
+
+                _ = heartbeat_interval.tick() => {
+                    // The older servers wouldn't understand it.
+                    if server_protocol_version >= 16 {
+                        to_ws.send(ws_message(codec, &WSClientToServer::Heartbeat)?).await?;
+                    }
+                }
+
+                _ = time::sleep_until(last_recv + SERVER_TIMEOUT) => {
+                    // The error goes to the user right away, as the NotReady
+                    // state, while we're waiting to reconnect.
+                    return Err(anyhow!(
+                        "connection lost: nothing from the server for {}s",
+                        SERVER_TIMEOUT.as_secs()
+                    ));
+                }
             }
         }
     }
 
+    /// Asks the GameManager for the current game, to send it to the server in
+    /// the hello. Whatever else the GameManager sends until it answers is
+    /// handled with handle_msg_offline. If there's no game yet, or it's over,
+    /// it's a brand new one. Returns None on GameManagerToPlayer::Shutdown.
+    async fn current_game_state(&mut self) -> Result<Option<WSFullGameState>> {
+        self.to_gm
+            .send(PlayerToGameManager::RequestFullState)
+            .await?;
+
+        loop {
+            let msg = self
+                .from_gm
+                .recv()
+                .await
+                .ok_or(anyhow!("GameManager is gone"))?;
+
+            match msg {
+                GameManagerToPlayer::ProvideFullState(fgstate) => {
+                    let fgstate =
+                        fgstate.filter(|v| matches!(v.game_state, GameState::WaitingFor(_, _)));
+
+                    return Ok(Some(match fgstate {
+                        Some(v) => WSFullGameState {
+                            game_state: v.game_state,
+                            ws_player_side: v.primary_player_side,
+                            board: v.board,
+                        },
+                        None => WSFullGameState {
+                            game_state: GameState::WaitingFor(
+                                game::Side::White,
+                                game::Scores::default(),
+                            ),
+                            ws_player_side: game::Side::White,
+                            board: game::BoardState::new(),
+                        },
+                    }));
+                }
+                msg => {
+                    if self.handle_msg_offline(msg) {
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handles the message from the GameManager while there's no connection
+    /// to the server: only the side, the moves and the shutdown matter, and the
+    /// rest is dropped, since there's nobody to pass it on to; once connected,
+    /// the hello has the whole game anyway, see current_game_state, and the
+    /// moves are only in case the server keeps its own game, see
+    /// resync_outbox. Returns whether it's GameManagerToPlayer::Shutdown.
+    fn handle_msg_offline(&mut self, msg: GameManagerToPlayer) -> bool {
+        match msg {
+            GameManagerToPlayer::Reset(_rules, board, new_side) => {
+                self.side = Some(new_side);
+                self.reset_tokens = num_tokens(&board);
+                false
+            }
+            GameManagerToPlayer::OpponentPutToken(pcoords, move_num) if !self.spectate => {
+                self.outbox
+                    .push_back((self.reset_tokens + move_num, pcoords));
+                false
+            }
+            GameManagerToPlayer::Shutdown => true,
+            _ => false,
+        }
+    }
+
+    /// Called once the local user's move is sent to the server: if the server
+    /// is going to confirm it, let the UI mark it as pending until then (see
+    /// move_done); the older servers don't, so it's done right away.
+    async fn move_sent(
+        &mut self,
+        pcoords: game::PoleCoords,
+        server_protocol_version: u32,
+    ) -> Result<()> {
+        if server_protocol_version >= 3 {
+            self.to_gm
+                .send(PlayerToGameManager::MovePending(pcoords))
+                .await?;
+        } else {
+            self.move_done(pcoords);
+        }
+
+        Ok(())
+    }
+
+    /// Called once the server has either accepted or rejected the local user's
+    /// move, so it's not in the outbox anymore.
+    fn move_done(&mut self, pcoords: game::PoleCoords) {
+        if matches!(self.outbox.front(), Some(&(_, v)) if v == pcoords) {
+            self.outbox.pop_front();
+        }
+    }
+
+    /// Called on the first game reset after connecting, before it's passed on
+    /// to the GameManager: drops the moves from the outbox which the server
+    /// has already, and plays the rest, in order, on top of the server's game,
+    /// so that it's our game again. Returns the moves to send to the server. If
+    /// some move can't be played anymore, e.g. since the opponent has moved
+    /// instead, it's dropped with the rest, and the user is told about it.
+    async fn resync_outbox(
+        &mut self,
+        gstate: &mut WSFullGameState,
+    ) -> Result<Vec<game::PoleCoords>> {
+        let tokens = num_tokens(&gstate.board);
+        self.outbox.retain(|&(num, _)| num > tokens);
+
+        // Our client plays the side opposite to the websocket player.
+        let side = gstate.ws_player_side.opposite();
+        let mut shadow = ShadowGame::new(gstate);
+        let mut flushed = Vec::new();
+
+        for &(num, pcoords) in &self.outbox {
+            let res = if num != tokens + flushed.len() + 1 {
+                Err(anyhow!("the game has moved on"))
+            } else {
+                shadow.put_token(side, pcoords)
+            };
+
+            match res {
+                Ok(()) => flushed.push(pcoords),
+                Err(err) => {
+                    let notice = format!(
+                        "The move {} didn't get to the server before the connection dropped, \
+                        and it's too late now: {}",
+                        pcoords, err
+                    );
+                    self.to_gm.send(PlayerToGameManager::Notice(notice)).await?;
+                    break;
+                }
+            }
+        }
+
+        self.outbox.truncate(flushed.len());
+        gstate.board = shadow.game.get_board().clone();
+        gstate.game_state = shadow.state;
+
+        Ok(flushed)
+    }
+
     /// Communicate the NotReady state to the GameManager. Other than just
     /// passing the given state string, it also prepends the state with whatever
     /// error message we last received from the server (WSServerToClient::Msg),
@@ -207,3 +938,127 @@ impl PlayerWSClient {
         Ok(())
     }
 }
+
+/// Copy of the server's game, as far as the client knows it; see
+/// PlayerWSClient::shadow. Just like the server, it plays by the default
+/// rules.
+struct ShadowGame {
+    game: game::Game,
+    state: GameState,
+    /// Side of the websocket player, i.e. the remote one.
+    ws_side: game::Side,
+}
+
+impl ShadowGame {
+    fn new(gstate: &WSFullGameState) -> ShadowGame {
+        let mut game = game::Game::new();
+        game.reset_board(&gstate.board);
+
+        ShadowGame {
+            game,
+            state: gstate.game_state,
+            ws_side: gstate.ws_player_side,
+        }
+    }
+
+    /// Returns the game as the full game state for the GameManager, with the
+    /// websocket player as the primary one.
+    fn full_game_state(&self) -> FullGameState {
+        FullGameState {
+            game_state: self.state,
+            primary_player_side: self.ws_side,
+            board: self.game.get_board().clone(),
+            // Handicaps are only supported in local games.
+            setup_tokens: 0,
+        }
+    }
+
+    /// Plays the move of the given side, or returns an error if it's not its
+    /// turn, or the move is illegal.
+    fn put_token(&mut self, side: game::Side, pcoords: game::PoleCoords) -> Result<()> {
+        if !self.state.is_waiting_for(side) {
+            return Err(anyhow!("it's not {:?}'s turn", side));
+        }
+
+        // Game::put_token panics on the poles which don't exist.
+        let row_size = self.game.get_rules().row_size();
+        if pcoords.x >= row_size || pcoords.z >= row_size {
+            return Err(anyhow!("there's no pole {}", pcoords));
+        }
+
+        let res = self.game.put_token(side, pcoords)?;
+        let scores = self.game.get_scores();
+        self.state = if let Some(winner) = res.winner {
+            GameState::WonBy(winner, scores)
+        } else if res.draw {
+            GameState::Draw(scores)
+        } else {
+            GameState::WaitingFor(side.opposite(), scores)
+        };
+
+        Ok(())
+    }
+}
+
+/// Asks the server at the given URL for the games which wait for the second
+/// player (see WSClientToServer::ListGames), so that the user can pick one to
+/// join with PlayerWSClient; it doesn't join anything itself.
+pub async fn list_open_games(connect_url: &url::Url) -> Result<Vec<WSOpenGame>> {
+    let (ws_stream, _) = time::timeout(SERVER_TIMEOUT, connect_async(connect_url))
+        .await
+        .map_err(|_| anyhow!("timed out connecting to server"))??;
+    let (mut to_ws, mut from_ws) = ws_stream.split();
+
+    to_ws
+        .send(ws_message(
+            WireCodec::default(),
+            &WSClientToServer::ListGames,
+        )?)
+        .await?;
+
+    let open_games = loop {
+        let recv = time::timeout(SERVER_TIMEOUT, from_ws.next())
+            .await
+            .map_err(|_| anyhow!("timed out waiting for the server"))?
+            .ok_or(anyhow!("failed to read from ws"))??;
+
+        match parse_ws_message(WireCodec::default(), &recv)? {
+            WSServerToClient::OpenGames(open_games) => break open_games,
+            // The servers which are too old to know the request.
+            WSServerToClient::Msg(s) => return Err(anyhow!("server: {}", s)),
+            _ => {}
+        }
+    };
+
+    let _ = to_ws.close().await;
+
+    Ok(open_games)
+}
+
+/// Returns the number of tokens on the board, of both sides.
+fn num_tokens(board: &game::BoardState) -> usize {
+    board.count(game::Side::White) + board.count(game::Side::Black)
+}
+
+/// Encodes the message to the server in the given format, compressed if the
+/// welcome has said so, see WireCodec.
+fn ws_message<T: Serialize>(codec: WireCodec, msg: &T) -> Result<tungstenite::Message> {
+    let data = codec.encode(msg)?;
+    if codec.is_binary() {
+        Ok(tungstenite::Message::Binary(data))
+    } else {
+        Ok(tungstenite::Message::Text(String::from_utf8(data)?))
+    }
+}
+
+/// Decodes the message from the server; the text messages are always JSON,
+/// and the binary ones are decoded as the codec says.
+fn parse_ws_message<T: DeserializeOwned>(
+    codec: WireCodec,
+    msg: &tungstenite::Message,
+) -> Result<T> {
+    match msg {
+        tungstenite::Message::Binary(data) => codec.decode_binary(data),
+        _ => WireFormat::Json.decode(msg.to_string().as_bytes()),
+    }
+}
@@ -0,0 +1,330 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context, Result};
+use tokio::sync::{mpsc, watch};
+
+use super::{
+    clock, save, GameManager, GameManagerConfig, GameManagerToPlayer, GameManagerToUI,
+    PlayerToGameManager, SearchDone, SideChoice, UIToGameManager,
+};
+use crate::game;
+
+/// Capacity of the channels used while replaying; they are drained after
+/// every input message, so it only needs to fit all the messages that
+/// GameManager sends in response to a single one.
+const REPLAY_CHAN_SIZE: usize = 1024;
+
+/// A single thing which happened to the GameManager.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum Event {
+    /// GameManager has started with the given config; always the first event.
+    Start(GameManagerConfig),
+    /// GameManager has started running, set up as given.
+    Setup(Setup),
+
+    /// Message received from the player with the given index.
+    FromPlayer(usize, PlayerToGameManager),
+    /// Message received from the UI.
+    FromUI(UIToGameManager),
+    /// Player with the given index has stopped, see
+    /// GameManager::handle_player_gone.
+    PlayerGone(usize),
+    /// Search for a hint or analysis has finished. While replaying, the
+    /// searches which GameManager starts itself are ignored, and these are
+    /// used instead, so that the replay is deterministic.
+    SearchDone(SearchDone),
+    /// Clock of the side to move was checked, see GameManager::set_time_control.
+    ClockTick,
+    /// Next game of the match is due, see GameManager::set_match.
+    NextMatchGame,
+
+    /// Message sent to the player with the given index.
+    ToPlayer(usize, GameManagerToPlayer),
+    /// Message sent to the UI.
+    ToUI(GameManagerToUI),
+}
+
+/// What the GameManager was set up with, beyond its config, by the time it
+/// started running: see GameManager::set_teammates, GameManager::set_time_control
+/// and the like.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Setup {
+    pub teams: bool,
+    pub side_choice: Option<SideChoice>,
+    pub alternate_sides: bool,
+    pub handicap: Option<game::handicap::Handicap>,
+    pub random_opening: usize,
+    pub time_control: Option<clock::TimeControl>,
+    pub peek_penalty: Duration,
+    pub best_of: Option<u32>,
+    pub resumed: Option<save::SavedGame>,
+    pub network_game: bool,
+    /// Wall clock time when the GameManager was created, which the time of
+    /// every move is derived from, see HistoryMove::time.
+    pub created_wall: SystemTime,
+}
+
+/// Recorded event, with its sequence number, and the time as of which the
+/// GameManager handled it, since it was created.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Entry {
+    pub seq: u64,
+    #[serde(default)]
+    pub at: Duration,
+    pub event: Event,
+}
+
+/// Recorder of all the GameManager traffic, see GameManager::set_recorder.
+/// Every event is written as a line of JSON, and flushed right away, so that
+/// the recording is complete even if the app crashes.
+pub struct Recorder {
+    out: Box<dyn Write + Send>,
+    next_seq: u64,
+}
+
+impl Recorder {
+    /// Creates a recorder writing to the given writer.
+    pub fn new(out: impl Write + Send + 'static) -> Recorder {
+        Recorder {
+            out: Box::new(out),
+            next_seq: 0,
+        }
+    }
+
+    /// Creates a recorder writing to the given file, which is overwritten if it
+    /// exists.
+    pub fn create(path: impl AsRef<Path>) -> Result<Recorder> {
+        let path = path.as_ref();
+        let file = File::create(path).context(format!("creating {}", path.display()))?;
+
+        Ok(Recorder::new(file))
+    }
+
+    pub fn record(&mut self, at: Duration, event: Event) -> Result<()> {
+        let entry = Entry {
+            seq: self.next_seq,
+            at,
+            event,
+        };
+        self.next_seq += 1;
+
+        serde_json::to_writer(&mut self.out, &entry)?;
+        self.out.write_all(b"\n")?;
+        self.out.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Loads a recording written by Recorder.
+pub fn load(r: impl BufRead) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+
+    for (i, line) in r.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry = serde_json::from_str(&line).context(format!("line {}", i + 1))?;
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Loads a recording from the given file, see load.
+pub fn load_file(path: impl AsRef<Path>) -> Result<Vec<Entry>> {
+    let path = path.as_ref();
+    let file = File::open(path).context(format!("opening {}", path.display()))?;
+
+    load(BufReader::new(file)).context(format!("loading {}", path.display()))
+}
+
+/// Drives a fresh GameManager with all the incoming messages from the
+/// recording, one by one, and checks that it sends exactly the same messages
+/// as recorded. Returns an error describing the first difference, if any.
+///
+/// Since the messages are fed one by one, and everything else the GameManager
+/// depends on is taken from the recording too (the setup, the seed of its
+/// random choices, the time of every event, the clock ticks and the other
+/// timers, and the results of the engine searches), the replay is
+/// deterministic; this makes it possible to turn an intermittent bug, once
+/// recorded, into a unit test.
+///
+/// Only the order of messages sent to the same receiver (a player or the UI)
+/// is checked, since that's the only order they can observe.
+pub async fn replay(entries: &[Entry]) -> Result<()> {
+    let config = match entries.first() {
+        Some(Entry {
+            event: Event::Start(config),
+            ..
+        }) => config.clone(),
+        Some(v) => return Err(anyhow!("expected the start event first, got {:?}", v)),
+        None => return Err(anyhow!("empty recording")),
+    };
+
+    let (to_ui, mut ui_rx) = mpsc::channel(REPLAY_CHAN_SIZE);
+    let (_ui_tx, from_ui) = mpsc::channel(1);
+    let (board_watch, _) = watch::channel(game::BoardState::new());
+    let (to_p0, p0_rx) = mpsc::channel(REPLAY_CHAN_SIZE);
+    let (_p0_tx, from_p0) = mpsc::channel(1);
+    let (to_p1, p1_rx) = mpsc::channel(REPLAY_CHAN_SIZE);
+    let (_p1_tx, from_p1) = mpsc::channel(1);
+
+    let mut gm = GameManager::new(
+        to_ui,
+        from_ui,
+        board_watch,
+        to_p0,
+        from_p0,
+        to_p1,
+        from_p1,
+        config,
+    );
+
+    let mut players_rx = vec![p0_rx, p1_rx];
+
+    for entry in &entries[1..] {
+        let seq = entry.seq;
+        gm.replay_now = Some(gm.created_at + entry.at);
+
+        match &entry.event {
+            Event::Start(_) => {
+                return Err(anyhow!("entry {}: unexpected second start", seq));
+            }
+            Event::Setup(setup) => {
+                if setup.teams {
+                    let (to_p2, p2_rx) = mpsc::channel(REPLAY_CHAN_SIZE);
+                    let (_p2_tx, from_p2) = mpsc::channel(1);
+                    let (to_p3, p3_rx) = mpsc::channel(REPLAY_CHAN_SIZE);
+                    let (_p3_tx, from_p3) = mpsc::channel(1);
+                    gm.set_teammates(to_p2, from_p2, to_p3, from_p3);
+                    players_rx.truncate(2);
+                    players_rx.extend([p2_rx, p3_rx]);
+                }
+                apply_setup(&mut gm, setup.clone());
+            }
+
+            Event::FromPlayer(i, msg) => {
+                expect_no_more(&mut ui_rx, &mut players_rx)
+                    .context(format!("before entry {}", seq))?;
+                gm.handle_player_msg(*i, msg.clone())
+                    .await
+                    .context(format!("entry {}", seq))?;
+            }
+            Event::FromUI(msg) => {
+                expect_no_more(&mut ui_rx, &mut players_rx)
+                    .context(format!("before entry {}", seq))?;
+                gm.handle_ui_msg(msg.clone())
+                    .await
+                    .context(format!("entry {}", seq))?;
+            }
+            Event::PlayerGone(i) => {
+                expect_no_more(&mut ui_rx, &mut players_rx)
+                    .context(format!("before entry {}", seq))?;
+                gm.handle_player_gone(*i)
+                    .await
+                    .context(format!("entry {}", seq))?;
+            }
+            Event::SearchDone(done) => {
+                expect_no_more(&mut ui_rx, &mut players_rx)
+                    .context(format!("before entry {}", seq))?;
+                gm.handle_search_done(done.clone())
+                    .await
+                    .context(format!("entry {}", seq))?;
+            }
+            Event::ClockTick => {
+                expect_no_more(&mut ui_rx, &mut players_rx)
+                    .context(format!("before entry {}", seq))?;
+                gm.handle_clock_tick()
+                    .await
+                    .context(format!("entry {}", seq))?;
+            }
+            Event::NextMatchGame => {
+                expect_no_more(&mut ui_rx, &mut players_rx)
+                    .context(format!("before entry {}", seq))?;
+                gm.handle_next_match_game()
+                    .await
+                    .context(format!("entry {}", seq))?;
+            }
+
+            Event::ToPlayer(i, expected) => {
+                let rx = players_rx.get_mut(*i).ok_or(anyhow!(
+                    "entry {}: invalid player index {}",
+                    seq,
+                    i
+                ))?;
+                let got = rx.try_recv().ok();
+                expect_same(expected, got.as_ref()).context(format!("entry {}", seq))?;
+            }
+            Event::ToUI(expected) => {
+                let got = ui_rx.try_recv().ok();
+                expect_same(expected, got.as_ref()).context(format!("entry {}", seq))?;
+            }
+        }
+    }
+
+    expect_no_more(&mut ui_rx, &mut players_rx).context("after the last entry")
+}
+
+/// Sets the GameManager up like the recorded one was, see Setup; except for
+/// the teammates, whose channels are up to the caller.
+fn apply_setup(gm: &mut GameManager, setup: Setup) {
+    gm.created_wall = setup.created_wall;
+    if let Some(side_choice) = setup.side_choice {
+        gm.set_side_choice(side_choice);
+    }
+    gm.set_alternate_sides(setup.alternate_sides);
+    if let Some(handicap) = setup.handicap {
+        gm.set_handicap(handicap);
+    }
+    gm.set_random_opening(setup.random_opening);
+    if let Some(time_control) = setup.time_control {
+        gm.set_time_control(time_control);
+    }
+    gm.set_peek_penalty(setup.peek_penalty);
+    if let Some(best_of) = setup.best_of {
+        gm.set_match(best_of);
+    }
+    if let Some(saved) = setup.resumed {
+        gm.resume(saved);
+    }
+    if setup.network_game {
+        gm.set_network_game();
+    }
+}
+
+/// Checks that the message sent by the GameManager is the same as recorded.
+/// The messages are compared in their serialized form, just like they're
+/// stored in the recording.
+fn expect_same<T: serde::Serialize + std::fmt::Debug>(expected: &T, got: Option<&T>) -> Result<()> {
+    let got = got.ok_or(anyhow!("expected {:?}, got nothing", expected))?;
+
+    if serde_json::to_value(expected)? != serde_json::to_value(got)? {
+        return Err(anyhow!("expected {:?}, got {:?}", expected, got));
+    }
+
+    Ok(())
+}
+
+/// Checks that the GameManager didn't send anything which wasn't recorded.
+fn expect_no_more(
+    ui_rx: &mut mpsc::Receiver<GameManagerToUI>,
+    players_rx: &mut [mpsc::Receiver<GameManagerToPlayer>],
+) -> Result<()> {
+    if let Ok(msg) = ui_rx.try_recv() {
+        return Err(anyhow!("unexpected message to the UI: {:?}", msg));
+    }
+
+    for (i, rx) in players_rx.iter_mut().enumerate() {
+        if let Ok(msg) = rx.try_recv() {
+            return Err(anyhow!("unexpected message to player {}: {:?}", i, msg));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use super::{FullGameState, HistoryMove};
+use crate::game;
+
+/// Everything needed to resume a game later, e.g. after the app was closed in
+/// the middle of it: the full state of the game, and the moves leading to it.
+/// See GameManager::set_save_path and GameManager::resume.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SavedGame {
+    pub state: FullGameState,
+    /// Moves since the board was last reset, and the board at that moment,
+    /// see GameManagerToUI::MoveHistory.
+    pub history: Vec<HistoryMove>,
+    pub history_start: game::BoardState,
+}
+
+impl SavedGame {
+    /// Load the saved game from a JSON file.
+    pub fn load(path: &Path) -> Result<SavedGame> {
+        let data = fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+        let saved = serde_json::from_str(&data).with_context(|| format!("parsing {:?}", path))?;
+
+        Ok(saved)
+    }
+
+    /// Save the game to a JSON file, creating its dir if needed. The file is
+    /// replaced atomically: it's written next to the target first, and then
+    /// renamed, so that if the app is killed in the middle, the previous save
+    /// is still there.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("creating {:?}", dir))?;
+        }
+
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        let data = serde_json::to_string_pretty(self)?;
+        fs::write(&tmp_path, data).with_context(|| format!("writing {:?}", tmp_path))?;
+        fs::rename(&tmp_path, path).with_context(|| format!("renaming to {:?}", path))?;
+
+        Ok(())
+    }
+}
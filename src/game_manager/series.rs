@@ -0,0 +1,90 @@
+/// Score of a match: a series of games between the same two players, won by
+/// whoever wins more than half of them, e.g. 3 games in a best-of-5 match.
+/// Drawn games don't count towards either player, so a match can take more
+/// games than it's "best of". The score is kept per player, not per side,
+/// since the players can swap sides between the games (see
+/// GameManager::set_alternate_sides).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MatchScore {
+    pub best_of: u32,
+    /// Games won by the primary and the secondary player.
+    pub wins: [u32; 2],
+    pub draws: u32,
+}
+
+impl MatchScore {
+    /// Creates the score of a best-of-N match which hasn't started yet.
+    pub fn new(best_of: u32) -> MatchScore {
+        MatchScore {
+            best_of,
+            wins: [0; 2],
+            draws: 0,
+        }
+    }
+
+    /// Games a player has to win to win the match.
+    pub fn wins_needed(&self) -> u32 {
+        self.best_of / 2 + 1
+    }
+
+    /// Index of the player who has won the match, if any.
+    pub fn winner(&self) -> Option<usize> {
+        self.wins.iter().position(|&w| w >= self.wins_needed())
+    }
+
+    /// Counts the result of a game: the index of the player who has won it,
+    /// or None for a draw. Once the match is won, the results aren't counted
+    /// anymore.
+    pub fn add_result(&mut self, winner: Option<usize>) {
+        if self.winner().is_some() {
+            return;
+        }
+
+        match winner {
+            Some(i) => self.wins[i] += 1,
+            None => self.draws += 1,
+        }
+    }
+}
+
+/// Running tally of the games between the same two players in one session,
+/// i.e. while the app runs, across the rematches. Unlike MatchScore, it's
+/// never over; like it, it's kept per player, not per side.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SessionStats {
+    /// Games won by the primary and the secondary player.
+    pub wins: [u32; 2],
+    pub draws: u32,
+}
+
+impl SessionStats {
+    /// Number of games played so far.
+    pub fn games(&self) -> u32 {
+        self.wins[0] + self.wins[1] + self.draws
+    }
+
+    /// Games lost by the player with the given index: the ones won by the
+    /// other player.
+    pub fn losses(&self, i: usize) -> u32 {
+        self.wins[1 - i]
+    }
+
+    /// Counts the result of a game: the index of the player who has won it,
+    /// or None for a draw.
+    pub fn add_result(&mut self, winner: Option<usize>) {
+        match winner {
+            Some(i) => self.wins[i] += 1,
+            None => self.draws += 1,
+        }
+    }
+
+    /// Takes back the result counted by add_result, e.g. when the end of the
+    /// game is undone.
+    pub fn remove_result(&mut self, winner: Option<usize>) {
+        let count = match winner {
+            Some(i) => &mut self.wins[i],
+            None => &mut self.draws,
+        };
+        *count = count.saturating_sub(1);
+    }
+}
@@ -0,0 +1,325 @@
+//! Test harness for the GameManager: it runs a real GameManager, with
+//! in-memory stubs in place of the UI and both players, which the test drives
+//! by hand. Tests are meant to run with the paused tokio time (see
+//! Harness::advance), so that they don't depend on how fast the machine is.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use super::{
+    FullGameState, GameManager, GameManagerConfig, GameManagerToPlayer, GameManagerToUI, GameState,
+    PlayerState, PlayerToGameManager, UIToGameManager,
+};
+use crate::game;
+
+/// Plenty for any test, so that the GameManager never blocks on a stub whose
+/// messages aren't collected yet.
+const CHANNEL_SIZE: usize = 1024;
+
+/// How long to let the GameManager run after poking it, see Harness::settle.
+/// The time is paused, so it's just a way to wait until it's idle.
+const SETTLE_TIME: Duration = Duration::from_millis(1);
+
+/// How long to wait for the GameManager to stop, see Harness::finish.
+const FINISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Stand-in for a player: the test sends the messages on its behalf, and
+/// whatever the GameManager sends to it is collected.
+pub struct StubPlayer {
+    /// Channels to and from the GameManager; None once disconnected, see
+    /// Harness::disconnect.
+    to_gm: Option<mpsc::Sender<PlayerToGameManager>>,
+    from_gm: Option<mpsc::Receiver<GameManagerToPlayer>>,
+    /// Messages from the GameManager which the test hasn't taken yet, see
+    /// Harness::take_player.
+    log: Vec<GameManagerToPlayer>,
+    /// Side of the player, as last reset by the GameManager.
+    pub side: Option<game::Side>,
+}
+
+/// GameManager under test, with the stubs around it.
+pub struct Harness {
+    to_gm: mpsc::Sender<UIToGameManager>,
+    from_gm: mpsc::Receiver<GameManagerToUI>,
+    board_watch: watch::Receiver<game::BoardState>,
+    /// Messages to the UI which the test hasn't taken yet, see
+    /// Harness::take_ui.
+    ui_log: Vec<GameManagerToUI>,
+    /// Game state, and in a team game, the seat to move, as last sent to
+    /// the UI.
+    game_state: Option<GameState>,
+    seat_to_move: Option<usize>,
+    /// Both players, and in a team game, their teammates.
+    pub players: Vec<StubPlayer>,
+
+    gm: JoinHandle<Result<()>>,
+}
+
+impl StubPlayer {
+    fn new(
+        to_gm: mpsc::Sender<PlayerToGameManager>,
+        from_gm: mpsc::Receiver<GameManagerToPlayer>,
+    ) -> StubPlayer {
+        StubPlayer {
+            to_gm: Some(to_gm),
+            from_gm: Some(from_gm),
+            log: Vec::new(),
+            side: None,
+        }
+    }
+
+    /// Collects everything the GameManager has sent so far.
+    fn collect(&mut self) {
+        let from_gm = match &mut self.from_gm {
+            Some(from_gm) => from_gm,
+            None => return,
+        };
+
+        while let Ok(msg) = from_gm.try_recv() {
+            if let GameManagerToPlayer::Reset(_, _, side) = &msg {
+                self.side = Some(*side);
+            }
+
+            self.log.push(msg);
+        }
+    }
+}
+
+impl Harness {
+    /// Spawns the GameManager with the given config; before it runs, the
+    /// test can set it up further, e.g. with set_time_control. The game
+    /// doesn't start until Harness::start.
+    pub fn new(config: GameManagerConfig, setup: impl FnOnce(&mut GameManager)) -> Harness {
+        Self::spawn(config, false, setup)
+    }
+
+    /// Like new, but for a team game, with four players, see
+    /// GameManager::set_teammates.
+    pub fn with_teams(config: GameManagerConfig, setup: impl FnOnce(&mut GameManager)) -> Harness {
+        Self::spawn(config, true, setup)
+    }
+
+    fn spawn(
+        config: GameManagerConfig,
+        teams: bool,
+        setup: impl FnOnce(&mut GameManager),
+    ) -> Harness {
+        let (to_ui, from_gm) = mpsc::channel(CHANNEL_SIZE);
+        let (to_gm, from_ui) = mpsc::channel(CHANNEL_SIZE);
+        let (board_watch_tx, board_watch) = watch::channel(game::BoardState::new());
+
+        // Channels to and from every player, and the stubs at their other ends.
+        let num_players = if teams { 4 } else { 2 };
+        let mut players = Vec::new();
+        let mut channels = Vec::new();
+        for _ in 0..num_players {
+            let (gm_to_p_tx, gm_to_p_rx) = mpsc::channel(CHANNEL_SIZE);
+            let (p_to_gm_tx, p_to_gm_rx) = mpsc::channel(CHANNEL_SIZE);
+            players.push(StubPlayer::new(p_to_gm_tx, gm_to_p_rx));
+            channels.push((gm_to_p_tx, p_to_gm_rx));
+        }
+        let mut channels = channels.into_iter();
+
+        let (to_p0, from_p0) = channels.next().unwrap();
+        let (to_p1, from_p1) = channels.next().unwrap();
+        let mut gm = GameManager::new(
+            to_ui,
+            from_ui,
+            board_watch_tx,
+            to_p0,
+            from_p0,
+            to_p1,
+            from_p1,
+            config,
+        );
+        if teams {
+            let (to_p2, from_p2) = channels.next().unwrap();
+            let (to_p3, from_p3) = channels.next().unwrap();
+            gm.set_teammates(to_p2, from_p2, to_p3, from_p3);
+        }
+        setup(&mut gm);
+
+        Harness {
+            to_gm,
+            from_gm,
+            board_watch,
+            ui_log: Vec::new(),
+            game_state: None,
+            seat_to_move: None,
+            players,
+
+            gm: tokio::spawn(async move { gm.run().await }),
+        }
+    }
+
+    /// Starts the game like the binary does: both players get ready, and the
+    /// primary one sets up a new game where it plays the given side.
+    pub async fn start(&mut self, primary_side: game::Side) {
+        for i in 0..self.players.len() {
+            self.send_player(i, PlayerToGameManager::StateChanged(PlayerState::Ready))
+                .await;
+        }
+
+        let fgstate = FullGameState::new(primary_side);
+        self.send_player(0, PlayerToGameManager::SetFullGameState(fgstate))
+            .await;
+    }
+
+    /// Sends the message from the UI, and waits until it's handled.
+    pub async fn send_ui(&mut self, msg: UIToGameManager) {
+        self.to_gm.send(msg).await.expect("GameManager is gone");
+        self.settle().await;
+    }
+
+    /// Sends the message from the given player, and waits until it's handled.
+    pub async fn send_player(&mut self, i: usize, msg: PlayerToGameManager) {
+        self.players[i]
+            .to_gm
+            .as_ref()
+            .expect("player is disconnected")
+            .send(msg)
+            .await
+            .expect("GameManager is gone");
+        self.settle().await;
+    }
+
+    /// Makes the given player put a token on the given pole, in the pole
+    /// notation, e.g. "B3".
+    pub async fn put(&mut self, i: usize, pole: &str) {
+        let pcoords = pole.parse().expect("invalid pole");
+        self.send_player(i, PlayerToGameManager::PutToken(pcoords))
+            .await;
+    }
+
+    /// Plays the given moves, in the pole notation, as a script: every move
+    /// is made by the player whose turn it is (in a team game, the one who
+    /// was last told so). Fails if it's nobody's turn, e.g. the game is over
+    /// before the script is.
+    pub async fn play(&mut self, moves: &[&str]) -> Result<()> {
+        for pole in moves {
+            let side = match self.game_state {
+                Some(GameState::WaitingFor(side, _)) => side,
+                state => return Err(anyhow!("can't play {}: game state {:?}", pole, state)),
+            };
+
+            let i = match self.seat_to_move {
+                Some(i) => i,
+                None => self
+                    .players
+                    .iter()
+                    .position(|p| p.side == Some(side))
+                    .ok_or_else(|| anyhow!("can't play {}: nobody plays {:?}", pole, side))?,
+            };
+
+            self.put(i, pole).await;
+        }
+
+        Ok(())
+    }
+
+    /// Takes the channels of the given stub, so that a real player can be run
+    /// on them instead, e.g. PlayerScripted. It's then up to that player to
+    /// get ready and, if it's the primary one, to start the game.
+    pub fn detach(
+        &mut self,
+        i: usize,
+    ) -> (
+        mpsc::Receiver<GameManagerToPlayer>,
+        mpsc::Sender<PlayerToGameManager>,
+    ) {
+        let p = &mut self.players[i];
+        let from_gm = p.from_gm.take().expect("player is detached");
+        let to_gm = p.to_gm.take().expect("player is detached");
+
+        (from_gm, to_gm)
+    }
+
+    /// Drops both channels of the given player, like a player task which has
+    /// stopped would.
+    pub async fn disconnect(&mut self, i: usize) {
+        self.players[i].collect();
+        self.players[i].to_gm = None;
+        self.players[i].from_gm = None;
+        self.settle().await;
+    }
+
+    /// Lets the given time pass; with the paused time, it's instant.
+    pub async fn advance(&mut self, duration: Duration) {
+        time::sleep(duration).await;
+        self.settle().await;
+    }
+
+    /// Lets the GameManager handle everything sent to it so far, and collects
+    /// everything it has sent meanwhile.
+    pub async fn settle(&mut self) {
+        // With the paused time, the sleep is only over once every task is
+        // idle, i.e. the GameManager is waiting for something again.
+        time::sleep(SETTLE_TIME).await;
+
+        while let Ok(msg) = self.from_gm.try_recv() {
+            match &msg {
+                GameManagerToUI::GameStateChanged(state, _) => {
+                    self.game_state = Some(*state);
+                    self.seat_to_move = None;
+                }
+                GameManagerToUI::SeatToMove(i) => self.seat_to_move = Some(*i),
+                _ => {}
+            }
+
+            self.ui_log.push(msg);
+        }
+
+        for p in &mut self.players {
+            p.collect();
+        }
+    }
+
+    /// Game state, as last sent to the UI.
+    pub fn game_state(&self) -> Option<GameState> {
+        self.game_state
+    }
+
+    /// Current board, see GameManager::new.
+    pub fn board(&self) -> game::BoardState {
+        self.board_watch.borrow().clone()
+    }
+
+    /// Takes the messages sent to the UI since the last time.
+    pub fn take_ui(&mut self) -> Vec<GameManagerToUI> {
+        std::mem::take(&mut self.ui_log)
+    }
+
+    /// Takes the messages sent to the given player since the last time.
+    pub fn take_player(&mut self, i: usize) -> Vec<GameManagerToPlayer> {
+        std::mem::take(&mut self.players[i].log)
+    }
+
+    /// Whether the GameManager still runs.
+    pub fn is_running(&self) -> bool {
+        !self.gm.is_finished()
+    }
+
+    /// Waits for the GameManager to stop, and returns the result of its run.
+    pub async fn finish(self) -> Result<()> {
+        time::timeout(FINISH_TIMEOUT, self.gm)
+            .await
+            .map_err(|_| anyhow!("GameManager hasn't stopped"))?
+            .map_err(|err| anyhow!("GameManager has panicked: {}", err))?
+    }
+
+    /// Shuts the GameManager down like the UI does, and waits for it to stop;
+    /// the players stop once they get GameManagerToPlayer::Shutdown, like the
+    /// real ones do.
+    pub async fn shutdown(mut self) -> Result<()> {
+        self.send_ui(UIToGameManager::Shutdown).await;
+        for i in 0..self.players.len() {
+            self.disconnect(i).await;
+        }
+
+        self.finish().await
+    }
+}
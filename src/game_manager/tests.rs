@@ -0,0 +1,1186 @@
+use std::time::Duration;
+
+use super::player_scripted::{PlayerScripted, Script};
+use super::testing::Harness;
+use super::*;
+use crate::game::Side;
+
+/// Moves which make White win with a vertical row on A1, while Black stacks
+/// its tokens on B1.
+const WHITE_WINS: [&str; 7] = ["A1", "B1", "A1", "B1", "A1", "B1", "A1"];
+
+/// Scores of every game in the classic rules, see game::Rules::scoring.
+const NO_SCORES: game::Scores = game::Scores { white: 0, black: 0 };
+
+fn tokens(board: &game::BoardState) -> usize {
+    board.count(Side::White) + board.count(Side::Black)
+}
+
+#[tokio::test(start_paused = true)]
+async fn starts_the_game() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::Black).await;
+
+    assert_eq!(h.players[0].side, Some(Side::Black));
+    assert_eq!(h.players[1].side, Some(Side::White));
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::White, NO_SCORES))
+    );
+    assert_eq!(tokens(&h.board()), 0);
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn passes_the_rules_on_reset() {
+    let rules = game::Rules {
+        misere: true,
+        scoring: false,
+    };
+    let config = GameManagerConfig {
+        rules,
+        ..GameManagerConfig::default()
+    };
+    let mut h = Harness::new(config, |_| {});
+    h.start(Side::White).await;
+
+    for i in 0..2 {
+        assert!(h
+            .take_player(i)
+            .iter()
+            .any(|msg| matches!(msg, GameManagerToPlayer::Reset(r, _, _) if *r == rules)));
+    }
+    assert!(h
+        .take_ui()
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToUI::ResetBoard(r, _) if *r == rules)));
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn carries_the_scores_in_the_game_state() {
+    let config = GameManagerConfig {
+        rules: game::Rules {
+            misere: false,
+            scoring: true,
+        },
+        ..GameManagerConfig::default()
+    };
+    let mut h = Harness::new(config, |_| {});
+    h.start(Side::White).await;
+    h.play(&WHITE_WINS[..6]).await.unwrap();
+    h.take_ui();
+
+    // The row is completed, but the game goes on.
+    h.play(&WHITE_WINS[6..]).await.unwrap();
+    let scores = game::Scores { white: 1, black: 0 };
+    assert!(h.take_ui().iter().any(|msg| matches!(
+        msg,
+        GameManagerToUI::GameStateChanged(GameState::WaitingFor(Side::Black, s), _) if *s == scores
+    )));
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::Black, scores))
+    );
+    for i in 0..2 {
+        assert!(h.take_player(i).iter().any(|msg| matches!(
+            msg,
+            GameManagerToPlayer::GameStateChanged(gs, _) if gs.scores() == scores
+        )));
+    }
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn plays_moves() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+    h.take_player(1);
+
+    h.put(0, "B3").await;
+
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::Black, NO_SCORES))
+    );
+    let b3: game::PoleCoords = "B3".parse().unwrap();
+    assert_eq!(h.board().get(b3.token_coords(0)), Some(Side::White));
+    assert!(h
+        .take_player(1)
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToPlayer::OpponentPutToken(p, 1) if *p == b3)));
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn rejects_moves_out_of_turn() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+
+    h.put(1, "A1").await;
+
+    assert!(h.take_player(1).iter().any(|msg| matches!(
+        msg,
+        GameManagerToPlayer::MoveRejected(_, RejectReason::WrongTurn(Side::White))
+    )));
+    assert_eq!(tokens(&h.board()), 0);
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::White, NO_SCORES))
+    );
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn rejects_moves_before_the_game_starts() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.send_player(0, PlayerToGameManager::StateChanged(PlayerState::Ready))
+        .await;
+
+    h.put(0, "A1").await;
+
+    assert!(h.take_player(0).iter().any(|msg| matches!(
+        msg,
+        GameManagerToPlayer::MoveRejected(_, RejectReason::NoSide)
+    )));
+    assert_eq!(tokens(&h.board()), 0);
+    assert_eq!(h.game_state(), None);
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn ends_the_game_on_a_win() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+
+    h.play(&WHITE_WINS).await.unwrap();
+
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WonBy(Side::White, NO_SCORES))
+    );
+    let ui = h.take_ui();
+    assert!(ui
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToUI::GameOver(GameOverReason::Move))));
+    assert!(ui
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToUI::WinRow(_))));
+
+    // Nobody can move anymore.
+    h.put(1, "C1").await;
+    assert!(h.take_player(1).iter().any(|msg| matches!(
+        msg,
+        GameManagerToPlayer::MoveRejected(_, RejectReason::GameOver)
+    )));
+    assert_eq!(tokens(&h.board()), WHITE_WINS.len());
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn resets_mid_game() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+    h.play(&["A1", "B2", "C3"]).await.unwrap();
+
+    // E.g. the server has started a new game, with the sides swapped.
+    let fgstate = FullGameState::new(Side::Black);
+    h.send_player(0, PlayerToGameManager::SetFullGameState(fgstate))
+        .await;
+
+    assert_eq!(tokens(&h.board()), 0);
+    assert_eq!(h.players[0].side, Some(Side::Black));
+    assert_eq!(h.players[1].side, Some(Side::White));
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::White, NO_SCORES))
+    );
+
+    // The game goes on with the new sides.
+    h.put(1, "D4").await;
+    assert_eq!(tokens(&h.board()), 1);
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::Black, NO_SCORES))
+    );
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn flags_on_timeout() {
+    let mut h = Harness::new(GameManagerConfig::default(), |gm| {
+        gm.set_time_control("1+0".parse().unwrap());
+    });
+    h.start(Side::White).await;
+    h.put(0, "A1").await;
+
+    // White has moved right away, so Black is the one to run out of time.
+    h.advance(Duration::from_secs(59)).await;
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::Black, NO_SCORES))
+    );
+
+    h.advance(Duration::from_secs(2)).await;
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WonBy(Side::White, NO_SCORES))
+    );
+    assert!(h.take_ui().iter().any(|msg| matches!(
+        msg,
+        GameManagerToUI::GameOver(GameOverReason::Timeout(Side::Black))
+    )));
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn charges_the_peek_penalty() {
+    let mut h = Harness::new(GameManagerConfig::default(), |gm| {
+        gm.set_time_control("1+0".parse().unwrap());
+        gm.set_peek_penalty(Duration::from_secs(10));
+    });
+    h.start(Side::White).await;
+    h.take_ui();
+
+    h.send_ui(UIToGameManager::Peek(Side::White)).await;
+    let ui = h.take_ui();
+    assert!(ui
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToUI::Peeks(1))));
+    let times = ui
+        .iter()
+        .rev()
+        .find_map(|msg| match msg {
+            GameManagerToUI::ClockUpdate(times) => Some(*times),
+            _ => None,
+        })
+        .unwrap();
+    // The clock has been running for a few milliseconds too.
+    assert!(times.white <= Duration::from_secs(50));
+    assert!(times.white > Duration::from_secs(49));
+    assert_eq!(times.black, Duration::from_secs(60));
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn resets_the_peeks_on_a_new_game() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+    h.send_ui(UIToGameManager::Peek(Side::White)).await;
+    h.send_ui(UIToGameManager::Peek(Side::White)).await;
+    assert!(h
+        .take_ui()
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToUI::Peeks(2))));
+
+    h.send_player(0, PlayerToGameManager::Rematch).await;
+    h.send_player(1, PlayerToGameManager::Rematch).await;
+    assert!(h
+        .take_ui()
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToUI::Peeks(0))));
+
+    h.send_ui(UIToGameManager::Peek(Side::White)).await;
+    assert!(h
+        .take_ui()
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToUI::Peeks(1))));
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn pauses_the_game() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+
+    h.send_ui(UIToGameManager::Pause).await;
+    assert!(h
+        .take_ui()
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToUI::Paused(true))));
+
+    h.put(0, "A1").await;
+    assert!(h.take_player(0).iter().any(|msg| matches!(
+        msg,
+        GameManagerToPlayer::MoveRejected(_, RejectReason::Paused)
+    )));
+    assert_eq!(tokens(&h.board()), 0);
+
+    h.send_ui(UIToGameManager::Resume).await;
+    h.put(0, "A1").await;
+    assert_eq!(tokens(&h.board()), 1);
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn refuses_to_pause_a_network_game() {
+    let mut h = Harness::new(GameManagerConfig::default(), |gm| {
+        gm.set_network_game();
+    });
+    h.start(Side::White).await;
+
+    h.send_ui(UIToGameManager::Pause).await;
+    assert!(!h
+        .take_ui()
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToUI::Paused(true))));
+
+    // The remote user's move still counts.
+    h.put(0, "A1").await;
+    assert_eq!(tokens(&h.board()), 1);
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn goes_on_when_a_player_is_gone() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+    h.take_ui();
+
+    h.disconnect(1).await;
+
+    assert!(h.is_running());
+    assert!(h.take_ui().iter().any(|msg| matches!(
+        msg,
+        GameManagerToUI::PlayerStateChanged(1, PlayerState::Gone)
+    )));
+
+    // Sending to the gone player is not an error.
+    h.put(0, "A1").await;
+    assert!(h.is_running());
+    assert_eq!(tokens(&h.board()), 1);
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn stops_when_both_players_are_gone() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+
+    h.disconnect(0).await;
+    h.disconnect(1).await;
+
+    h.finish().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn shuts_the_players_down() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+
+    h.send_ui(UIToGameManager::Shutdown).await;
+
+    for i in 0..2 {
+        assert!(h
+            .take_player(i)
+            .iter()
+            .any(|msg| matches!(msg, GameManagerToPlayer::Shutdown)));
+    }
+
+    // Once the players have wrapped up, the GameManager stops.
+    h.disconnect(0).await;
+    h.disconnect(1).await;
+    h.finish().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn takes_turns_in_a_team_game() {
+    let mut h = Harness::with_teams(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+
+    assert_eq!(h.players[2].side, Some(Side::White));
+    assert_eq!(h.players[3].side, Some(Side::Black));
+
+    // A1, then B1; A2 and B2 sit their side's first move out.
+    h.put(0, "A1").await;
+    h.put(3, "B1").await;
+    assert!(h.take_player(3).iter().any(|msg| matches!(
+        msg,
+        GameManagerToPlayer::MoveRejected(_, RejectReason::TeammatesTurn)
+    )));
+    h.put(1, "B1").await;
+
+    // A2 has got the board with A1's move, and now it's their turn.
+    let a2 = h.take_player(2);
+    assert!(a2
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToPlayer::Reset(_, board, _) if tokens(board) == 1)));
+    assert!(matches!(
+        a2.last(),
+        Some(GameManagerToPlayer::GameStateChanged(
+            GameState::WaitingFor(Side::White, _),
+            2
+        ))
+    ));
+    assert!(h
+        .take_ui()
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToUI::SeatToMove(2))));
+
+    h.put(0, "C1").await;
+    assert!(h.take_player(0).iter().any(|msg| matches!(
+        msg,
+        GameManagerToPlayer::MoveRejected(_, RejectReason::TeammatesTurn)
+    )));
+    h.put(2, "C1").await;
+    assert_eq!(tokens(&h.board()), 3);
+
+    // The script follows the seats: B2, then A1 again.
+    h.play(&["D1", "A2"]).await.unwrap();
+    assert_eq!(tokens(&h.board()), 5);
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::Black, NO_SCORES))
+    );
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn takes_moves_back_in_a_team_game() {
+    let mut h = Harness::with_teams(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+    h.play(&["A1", "B1"]).await.unwrap();
+    for i in 0..4 {
+        h.take_player(i);
+    }
+
+    // A2 asks for A1's move back, along with B1's reply; both B1 and B2
+    // are asked, and either of them can agree.
+    h.send_player(2, PlayerToGameManager::RequestUndo).await;
+    for i in [1, 3] {
+        assert!(h
+            .take_player(i)
+            .iter()
+            .any(|msg| matches!(msg, GameManagerToPlayer::UndoRequested(2))));
+    }
+    assert!(!h
+        .take_player(0)
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToPlayer::UndoRequested(_))));
+
+    h.send_player(3, PlayerToGameManager::AcceptUndo).await;
+    assert_eq!(tokens(&h.board()), 0);
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::White, NO_SCORES))
+    );
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn resigns_in_a_team_game() {
+    let mut h = Harness::with_teams(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+    h.play(&["A1", "B1"]).await.unwrap();
+
+    h.send_player(3, PlayerToGameManager::Resign).await;
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WonBy(Side::White, NO_SCORES))
+    );
+    for i in [0, 2] {
+        assert!(h
+            .take_player(i)
+            .iter()
+            .any(|msg| matches!(msg, GameManagerToPlayer::OpponentResigned)));
+    }
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn agrees_to_a_draw_in_a_team_game() {
+    let mut h = Harness::with_teams(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+    h.play(&["A1", "B1"]).await.unwrap();
+
+    h.send_player(2, PlayerToGameManager::OfferDraw).await;
+    for i in [1, 3] {
+        assert!(h
+            .take_player(i)
+            .iter()
+            .any(|msg| matches!(msg, GameManagerToPlayer::DrawOffered)));
+    }
+
+    h.send_player(1, PlayerToGameManager::AcceptDraw).await;
+    assert_eq!(h.game_state(), Some(GameState::Draw(NO_SCORES)));
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn places_the_free_handicap_tokens_first() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+
+    let handicap = "black:3:free".parse().unwrap();
+    let fgstate = FullGameState::from_handicap(&handicap, Side::White).unwrap();
+    h.send_player(0, PlayerToGameManager::SetFullGameState(fgstate))
+        .await;
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::Black, NO_SCORES))
+    );
+
+    h.play(&["A1", "B2"]).await.unwrap();
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::Black, NO_SCORES))
+    );
+
+    h.play(&["C3"]).await.unwrap();
+    assert_eq!(tokens(&h.board()), 3);
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::White, NO_SCORES))
+    );
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn refuses_a_row_in_the_handicap_setup() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+
+    // Handicap::board doesn't allow that many free tokens, but a primary
+    // player could send anything.
+    let fgstate = FullGameState {
+        setup_tokens: 4,
+        ..FullGameState::new(Side::White)
+    };
+    h.send_player(0, PlayerToGameManager::SetFullGameState(fgstate))
+        .await;
+    h.play(&["A1", "A1", "A1", "A1"]).await.unwrap();
+
+    assert!(h.take_player(0).iter().any(|msg| matches!(
+        msg,
+        GameManagerToPlayer::MoveRejected(_, RejectReason::InvalidSetup)
+    )));
+    assert_eq!(tokens(&h.board()), 3);
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::White, NO_SCORES))
+    );
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn rejects_moves_after_the_state_is_rejected() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    for i in 0..2 {
+        h.send_player(i, PlayerToGameManager::StateChanged(PlayerState::Ready))
+            .await;
+    }
+
+    // Black can't move first on the empty board.
+    let fgstate = FullGameState {
+        game_state: GameState::WaitingFor(Side::Black, NO_SCORES),
+        ..FullGameState::new(Side::White)
+    };
+    h.send_player(0, PlayerToGameManager::SetFullGameState(fgstate))
+        .await;
+    assert!(h
+        .take_player(0)
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToPlayer::StateRejected(_))));
+    assert_eq!(h.game_state(), None);
+
+    // E.g. the remote opponent keeps moving on its own copy of the game.
+    for i in 0..2 {
+        h.put(i, "A1").await;
+        let msgs = h.take_player(i);
+        assert!(
+            matches!(
+                msgs.as_slice(),
+                [GameManagerToPlayer::MoveRejected(_, RejectReason::NoSide)]
+            ),
+            "{:?}",
+            msgs
+        );
+    }
+    assert_eq!(h.game_state(), None);
+    assert_eq!(tokens(&h.board()), 0);
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn plays_the_random_opening() {
+    let mut h = Harness::new(GameManagerConfig::default(), |gm| {
+        gm.set_random_opening(4);
+    });
+    h.start(Side::White).await;
+
+    assert_eq!(tokens(&h.board()), 4);
+    assert_eq!(h.board().count(Side::White), 2);
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::White, NO_SCORES))
+    );
+    assert!(h
+        .take_player(0)
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToPlayer::Reset(_, board, _) if tokens(board) == 4)));
+
+    // The opening moves are in the history.
+    h.send_ui(UIToGameManager::RequestMoveHistory).await;
+    assert!(h
+        .take_ui()
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToUI::MoveHistory(moves) if moves.len() == 4)));
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn keeps_the_session_stats() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+    h.play(&WHITE_WINS).await.unwrap();
+
+    // A rematch with the same sides, which Black resigns.
+    h.send_player(0, PlayerToGameManager::Rematch).await;
+    h.send_player(1, PlayerToGameManager::Rematch).await;
+    h.send_player(1, PlayerToGameManager::Resign).await;
+
+    let stats: Vec<_> = h
+        .take_ui()
+        .into_iter()
+        .filter_map(|msg| match msg {
+            GameManagerToUI::SessionStats(stats) => Some(stats),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(stats.len(), 2);
+    assert_eq!(stats[1].wins, [2, 0]);
+    assert_eq!(stats[1].losses(1), 2);
+    assert_eq!(stats[1].games(), 2);
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn emits_the_game_events_in_order() {
+    let mut events = None;
+    let mut h = Harness::new(GameManagerConfig::default(), |gm| {
+        events = Some(gm.subscribe_events());
+    });
+    let mut events = events.unwrap();
+    h.start(Side::White).await;
+
+    // White wins, and then a rematch resets the game.
+    h.play(&WHITE_WINS).await.unwrap();
+    h.send_player(0, PlayerToGameManager::Rematch).await;
+    h.send_player(1, PlayerToGameManager::Rematch).await;
+
+    // The scores are always zero in the classic rules, so they're left out.
+    let name = |state: GameState| match state {
+        GameState::WaitingFor(side, _) => format!("WaitingFor({:?})", side),
+        GameState::WonBy(side, _) => format!("WonBy({:?})", side),
+        GameState::Draw(_) => "Draw".to_string(),
+    };
+    let mut got = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        got.push(match event {
+            GameEvent::GameStarted(fgstate) => format!("started {}", name(fgstate.game_state)),
+            GameEvent::MovePlayed(side, _, move_num) => format!("move {} {:?}", move_num, side),
+            GameEvent::MovesTakenBack(n) => format!("taken back {}", n),
+            GameEvent::StateChanged(state) => format!("state {}", name(state)),
+            GameEvent::GameEnded(state, reason) => format!("ended {} {:?}", name(state), reason),
+        });
+    }
+
+    let mut want = vec![
+        "started WaitingFor(White)".to_string(),
+        "state WaitingFor(White)".to_string(),
+    ];
+    for (i, side) in [Side::White, Side::Black]
+        .iter()
+        .cycle()
+        .take(6)
+        .enumerate()
+    {
+        want.push(format!("move {} {:?}", i + 1, side));
+        want.push(format!("state WaitingFor({:?})", side.opposite()));
+    }
+    want.extend(
+        [
+            "move 7 White",
+            "ended WonBy(White) Move",
+            "state WonBy(White)",
+            "started WaitingFor(White)",
+            "state WaitingFor(White)",
+        ]
+        .map(String::from),
+    );
+    assert_eq!(got, want);
+
+    h.shutdown().await.unwrap();
+}
+
+/// Sides of the primary player in the given number of new games, picked at
+/// random by the GameManager with the given seed.
+async fn random_sides(seed: u64, games: usize) -> Vec<Option<Side>> {
+    let config = GameManagerConfig {
+        seed: Some(seed),
+        ..GameManagerConfig::default()
+    };
+    let mut h = Harness::new(config, |gm| gm.set_side_choice(SideChoice::Random));
+    h.start(Side::White).await;
+
+    let mut sides = Vec::new();
+    for _ in 0..games {
+        h.send_player(0, PlayerToGameManager::Rematch).await;
+        h.send_player(1, PlayerToGameManager::Rematch).await;
+        sides.push(h.players[0].side);
+    }
+    h.shutdown().await.unwrap();
+
+    sides
+}
+
+#[tokio::test(start_paused = true)]
+async fn picks_random_sides_from_the_seed() {
+    let sides = random_sides(42, 16).await;
+    assert!(sides.contains(&Some(Side::White)));
+    assert!(sides.contains(&Some(Side::Black)));
+
+    assert_eq!(random_sides(42, 16).await, sides);
+}
+
+#[tokio::test(start_paused = true)]
+async fn declines_a_rematch() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+    h.play(&WHITE_WINS).await.unwrap();
+    h.take_player(1);
+
+    // Black asks for a new game, and White declines, so that White asking
+    // for one later doesn't start it.
+    h.send_player(1, PlayerToGameManager::Rematch).await;
+    h.send_player(0, PlayerToGameManager::DeclineRematch).await;
+    assert!(h
+        .take_player(1)
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToPlayer::RematchDeclined)));
+    assert!(h.take_ui().iter().any(
+        |msg| matches!(msg, GameManagerToUI::Notice(text) if text == "White declines a new game")
+    ));
+
+    h.send_player(0, PlayerToGameManager::Rematch).await;
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WonBy(Side::White, NO_SCORES))
+    );
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn declines_a_rematch_from_the_ui() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+    h.play(&WHITE_WINS).await.unwrap();
+    h.take_player(0);
+    h.take_player(1);
+
+    h.send_player(1, PlayerToGameManager::Rematch).await;
+    h.take_ui();
+    h.send_ui(UIToGameManager::DeclineNewGame).await;
+
+    // Both players hear about it, and so does the user.
+    for i in 0..2 {
+        assert!(h
+            .take_player(i)
+            .iter()
+            .any(|msg| matches!(msg, GameManagerToPlayer::RematchDeclined)));
+    }
+    let ui = h.take_ui();
+    let called_off = |msg: &GameManagerToUI| match msg {
+        GameManagerToUI::Notice(text) | GameManagerToUI::Announcement(text) => {
+            text == "The new game is called off"
+        }
+        _ => false,
+    };
+    assert_eq!(ui.iter().filter(|msg| called_off(msg)).count(), 2);
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn refuses_inconsistent_game_states() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+    h.play(&["A1"]).await.unwrap();
+
+    let b1: game::PoleCoords = "B1".parse().unwrap();
+    let mut black_first = game::BoardState::new();
+    black_first.set(Side::Black, b1.token_coords(0));
+
+    let mut won = game::BoardState::new();
+    for (i, pole) in WHITE_WINS.iter().enumerate() {
+        let pcoords: game::PoleCoords = pole.parse().unwrap();
+        let side = [Side::White, Side::Black][i % 2];
+        won.set(side, pcoords.token_coords(i / 2));
+    }
+
+    let invalid = [
+        // Black can't have moved first.
+        (GameState::WaitingFor(Side::White, NO_SCORES), black_first),
+        // It's Black's turn after White's move.
+        (GameState::WaitingFor(Side::White, NO_SCORES), {
+            let mut board = game::BoardState::new();
+            board.set(Side::White, b1.token_coords(0));
+            board
+        }),
+        // White has won on this board.
+        (GameState::WaitingFor(Side::Black, NO_SCORES), won.clone()),
+        (GameState::WonBy(Side::Black, NO_SCORES), won),
+    ];
+    for (game_state, board) in invalid {
+        let fgstate = FullGameState {
+            game_state,
+            board,
+            ..FullGameState::new(Side::White)
+        };
+        h.send_player(0, PlayerToGameManager::SetFullGameState(fgstate))
+            .await;
+
+        assert!(h
+            .take_player(0)
+            .iter()
+            .any(|msg| matches!(msg, GameManagerToPlayer::StateRejected(_))));
+    }
+
+    // The game goes on as it was.
+    assert_eq!(tokens(&h.board()), 1);
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WaitingFor(Side::Black, NO_SCORES))
+    );
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn passes_the_player_names_on() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+
+    h.send_player(1, PlayerToGameManager::SetName("Bob".to_string()))
+        .await;
+
+    assert!(h
+        .take_ui()
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToUI::PlayerNameChanged(1, name) if name == "Bob")));
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn scripted_players_follow_the_script() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    let script: Script = WHITE_WINS.join(" ").parse().unwrap();
+
+    let (from_gm, to_gm) = h.detach(0);
+    let mut white = PlayerScripted::new(script.clone(), from_gm, to_gm);
+    white.set_initial_state(FullGameState::new(Side::White));
+    let white = tokio::spawn(async move { white.run().await });
+
+    // Black doesn't care what White plays, as long as it's White's turn.
+    let mut black_script = script;
+    for m in black_script.moves.iter_mut().step_by(2) {
+        *m = None;
+    }
+    let (from_gm, to_gm) = h.detach(1);
+    let mut black = PlayerScripted::new(black_script, from_gm, to_gm);
+    let black = tokio::spawn(async move { black.run().await });
+
+    h.settle().await;
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WonBy(Side::White, NO_SCORES))
+    );
+
+    h.shutdown().await.unwrap();
+    white.await.unwrap().unwrap();
+    black.await.unwrap().unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn scripted_player_fails_on_unexpected_moves() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+
+    let script: Script = "A1 B1 A1".parse().unwrap();
+    let (from_gm, to_gm) = h.detach(1);
+    let mut black = PlayerScripted::new(script, from_gm, to_gm);
+    let black = tokio::spawn(async move { black.run().await });
+    h.settle().await;
+
+    h.put(0, "C3").await;
+    assert!(black.await.unwrap().is_err());
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn provides_the_full_state() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::Black).await;
+    h.play(&["A1", "B2"]).await.unwrap();
+    h.take_player(1);
+
+    h.send_player(1, PlayerToGameManager::RequestFullState)
+        .await;
+
+    let fgstate = h.take_player(1).into_iter().find_map(|msg| match msg {
+        GameManagerToPlayer::ProvideFullState(fgstate) => fgstate,
+        _ => None,
+    });
+    let fgstate = fgstate.expect("no full state provided");
+    assert_eq!(fgstate.primary_player_side, Side::White);
+    assert_eq!(
+        fgstate.game_state,
+        GameState::WaitingFor(Side::White, NO_SCORES)
+    );
+    assert_eq!(fgstate.board, h.board());
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn passes_the_chat_on() {
+    let mut h = Harness::new(GameManagerConfig::default(), |_| {});
+    h.start(Side::White).await;
+
+    h.send_ui(UIToGameManager::Chat("hi".to_string())).await;
+    for i in 0..2 {
+        assert!(h
+            .take_player(i)
+            .iter()
+            .any(|msg| matches!(msg, GameManagerToPlayer::Chat(text) if text == "hi")));
+    }
+
+    let chat = PlayerToGameManager::Chat {
+        from: "Bob".to_string(),
+        text: "hello".to_string(),
+    };
+    h.send_player(1, chat).await;
+    assert!(h.take_ui().iter().any(|msg| matches!(
+        msg,
+        GameManagerToUI::Chat { from, text } if from == "Bob" && text == "hello"
+    )));
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn brings_a_new_ui_subscriber_up_to_date() {
+    let mut subscriptions = None;
+    let mut h = Harness::new(GameManagerConfig::default(), |gm| {
+        subscriptions = Some(gm.ui_subscriptions());
+    });
+    h.start(Side::White).await;
+    h.send_player(1, PlayerToGameManager::SetName("Bob".to_string()))
+        .await;
+    h.play(&["A1", "B2", "C3"]).await.unwrap();
+
+    let (to_ui, mut from_gm) = mpsc::channel(256);
+    subscriptions.unwrap().send(to_ui).await.unwrap();
+    h.settle().await;
+
+    // The board reset is a diff, so the subscriber applies it to the empty
+    // board, which is all it has.
+    let mut board = game::BoardState::new();
+    let mut game_state = None;
+    let mut name = None;
+    while let Ok(msg) = from_gm.try_recv() {
+        match msg {
+            GameManagerToUI::ResetBoard(_, diff) => {
+                for (side, tcoords) in diff.set {
+                    board.set(side, tcoords);
+                }
+            }
+            GameManagerToUI::GameStateChanged(state, moves) => game_state = Some((state, moves)),
+            GameManagerToUI::PlayerNameChanged(1, v) => name = Some(v),
+            _ => {}
+        }
+    }
+    assert_eq!(board, h.board());
+    assert_eq!(
+        game_state,
+        Some((GameState::WaitingFor(Side::Black, NO_SCORES), 3))
+    );
+    assert_eq!(name.as_deref(), Some("Bob"));
+
+    // From now on, it gets the game as it goes.
+    h.play(&["D4"]).await.unwrap();
+    let msgs: Vec<_> = std::iter::from_fn(|| from_gm.try_recv().ok()).collect();
+    assert!(msgs
+        .iter()
+        .any(|msg| matches!(msg, GameManagerToUI::SetToken(placed) if placed.move_num == 4)));
+
+    h.shutdown().await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn drops_a_lagging_ui_subscriber() {
+    // Enough for the snapshot of the players before the game, but not for
+    // the game itself.
+    let (to_ui, mut from_gm) = mpsc::channel(4);
+    let mut h = Harness::new(GameManagerConfig::default(), |gm| {
+        gm.add_ui_subscriber(to_ui);
+    });
+    h.start(Side::White).await;
+
+    // Nobody reads the subscriber's channel, but the game goes on.
+    h.play(&WHITE_WINS).await.unwrap();
+    assert_eq!(
+        h.game_state(),
+        Some(GameState::WonBy(Side::White, NO_SCORES))
+    );
+
+    // And the subscriber is dropped, once its channel is full.
+    let mut received = 0;
+    loop {
+        match from_gm.try_recv() {
+            Ok(_) => received += 1,
+            Err(mpsc::error::TryRecvError::Disconnected) => break,
+            Err(mpsc::error::TryRecvError::Empty) => panic!("the subscriber is still there"),
+        }
+    }
+    assert_eq!(received, 4);
+
+    h.shutdown().await.unwrap();
+}
+
+/// Writer into a buffer which the test keeps too, to read the recording back.
+#[cfg(feature = "record")]
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(feature = "record")]
+impl std::io::Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "record")]
+#[tokio::test(start_paused = true)]
+async fn replays_a_timed_game() {
+    let buf = SharedBuf::default();
+    let recorder = record::Recorder::new(buf.clone());
+    let mut h = Harness::new(GameManagerConfig::default(), |gm| {
+        gm.set_recorder(recorder);
+        gm.set_time_control("1+2".parse().unwrap());
+        gm.set_random_opening(2);
+    });
+    h.start(Side::White).await;
+
+    // Every move takes a while, so that the clocks show something else on
+    // the ticks in between.
+    for pole in ["A1", "B1", "A2", "B2"] {
+        h.advance(Duration::from_millis(1500)).await;
+        h.play(&[pole]).await.unwrap();
+    }
+    // The moves are timed too.
+    h.send_ui(UIToGameManager::RequestMoveHistory).await;
+    h.shutdown().await.unwrap();
+
+    let entries = record::load(&buf.0.lock().unwrap()[..]).unwrap();
+    assert!(entries
+        .iter()
+        .any(|entry| matches!(entry.event, record::Event::ClockTick)));
+    record::replay(&entries).await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn ai_resyncs_when_the_opponents_move_does_not_fit() {
+    use super::player_ai::{Difficulty, PlayerAI};
+
+    let rules = game::Rules::default();
+    let a1 = "A1".parse().unwrap();
+    let mut game = game::Game::with_rules(rules);
+    for side in [Side::White, Side::Black, Side::White, Side::Black] {
+        game.put_token(side, a1).unwrap();
+    }
+
+    let (to_ai, from_gm) = mpsc::channel(16);
+    let (to_gm, mut from_ai) = mpsc::channel(16);
+    let engine = crate::engine::EngineKind::Minimax.new_engine(1);
+    let mut ai = PlayerAI::new(rules, engine, Difficulty::EASY, None, from_gm, to_gm);
+    let ai = tokio::spawn(async move { ai.run().await });
+
+    assert!(matches!(
+        from_ai.recv().await,
+        Some(PlayerToGameManager::StateChanged(PlayerState::Ready))
+    ));
+
+    // A1 is full already, so White's move there doesn't fit the AI's copy of
+    // the game.
+    let board = game.get_board().clone();
+    to_ai
+        .send(GameManagerToPlayer::Reset(rules, board, Side::Black))
+        .await
+        .unwrap();
+    to_ai
+        .send(GameManagerToPlayer::OpponentPutToken(a1, 4))
+        .await
+        .unwrap();
+    let state = GameState::WaitingFor(Side::Black, NO_SCORES);
+    to_ai
+        .send(GameManagerToPlayer::GameStateChanged(state, 5))
+        .await
+        .unwrap();
+    assert!(matches!(
+        from_ai.recv().await,
+        Some(PlayerToGameManager::RequestFullState)
+    ));
+
+    game.put_token(Side::White, "B1".parse().unwrap()).unwrap();
+    let fgstate = FullGameState {
+        game_state: state,
+        primary_player_side: Side::Black,
+        board: game.get_board().clone(),
+        setup_tokens: 0,
+    };
+    to_ai
+        .send(GameManagerToPlayer::ProvideFullState(Some(fgstate)))
+        .await
+        .unwrap();
+    match from_ai.recv().await {
+        Some(PlayerToGameManager::PutToken(pcoords)) => {
+            game.put_token(Side::Black, pcoords).unwrap();
+        }
+        other => panic!("expected a move, got {:?}", other),
+    }
+
+    to_ai.send(GameManagerToPlayer::Shutdown).await.unwrap();
+    ai.await.unwrap().unwrap();
+}
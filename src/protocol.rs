@@ -0,0 +1,706 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::game::{self, GameState};
+
+/// Version of the protocol described here. It's bumped whenever the protocol
+/// changes in a way which older clients or servers can't cope with; see
+/// WSClientInfo::protocol_version and WSServerInfo::min_protocol_version.
+///
+/// - 1: WSServerToClient::Welcome.
+/// - 2: WSServerToClient::Idle and WSServerToClient::GameAbandoned.
+/// - 3: WSServerToClient::MoveAccepted and WSServerToClient::MoveRejected.
+/// - 4: WSServerToClient::GameDrawn and GameState::Draw.
+/// - 5: WSClientToServer::RequestUndo, WSClientToServer::AcceptUndo and
+///   WSServerToClient::UndoRequested.
+/// - 6: WSClientToServer::OfferRematch and WSServerToClient::RematchOffered.
+/// - 7: WSClientToServer::Resign and WSServerToClient::Resigned.
+/// - 8: WSClientToServer::Clock and WSServerToClient::OpponentClock.
+/// - 9: actual names in WSClientInfo::player_name and
+///   WSGameReset::opponent_name.
+/// - 10: WSClientInfo::wire_format and WSServerInfo::wire_format.
+/// - 11: WSClientToServer::Chat and WSServerToClient::Chat.
+/// - 12: WSClientToServer::Ping and WSServerToClient::Pong.
+/// - 13: WSGameReset::session_token and WSClientInfo::session_token.
+/// - 14: WSClientInfo::spectate.
+/// - 15: WSClientToServer::RequestFullState.
+/// - 16: WSClientToServer::Heartbeat.
+/// - 17: WSClientToServer::ListGames and WSServerToClient::OpenGames.
+/// - 18: WSClientToServer::DeclineRematch and WSServerToClient::RematchDeclined.
+/// - 19: WSClientInfo::deflate and WSServerInfo::deflate.
+pub const PROTOCOL_VERSION: u32 = 19;
+
+/// How often the client sends WSClientToServer::Heartbeat.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Message that WS client (PlayerWSClient) can send to the server.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum WSClientToServer {
+    /// Authentication message, must be the first one that the client sends.
+    Hello(WSClientInfo),
+    /// Put token at the given pole.
+    PutToken(game::PoleCoords),
+    /// Ask the opponent to take back the given number of the last moves, so
+    /// that it's our turn again; the opponent gets
+    /// WSServerToClient::UndoRequested. The request stands until the opponent
+    /// accepts it, or anyone moves.
+    RequestUndo(usize),
+    /// Accept the undo requested by the opponent. The server takes the moves
+    /// back, and sends the GameReset with the new state to both players.
+    AcceptUndo,
+    /// Ask for a new game in place of the current one, which can be over or
+    /// not. The opponent gets WSServerToClient::RematchOffered, and once it
+    /// asks for a new game too, the server resets the game to the empty
+    /// board, with the same sides, and sends the GameReset to both players.
+    OfferRematch,
+    /// Call off the new game: either decline the one the opponent has asked
+    /// for, or withdraw our own request. The opponent gets
+    /// WSServerToClient::RematchDeclined, and the current game goes on; if
+    /// nobody has asked for a new game, it's ignored.
+    DeclineRematch,
+    /// Concede the game, so the opponent wins; the server sends
+    /// WSServerToClient::Resigned to both players.
+    Resign,
+    /// Time remaining on our own clock, in milliseconds, in a timed game. The
+    /// server doesn't keep the time itself, it only passes it on to the
+    /// opponent as WSServerToClient::OpponentClock; every client keeps both
+    /// clocks, and corrects the opponent's one with it. Zero means we've lost
+    /// on time.
+    Clock(u64),
+    /// Say the given text in the chat; the server passes it on to the
+    /// opponent (and to the spectators) as WSServerToClient::Chat. The server
+    /// drops the messages which are empty or over its length limit, and the
+    /// ones which come too fast.
+    Chat(String),
+    /// Ask the server to respond with WSServerToClient::Pong with the same
+    /// nonce right away, so that the client can measure the round-trip time.
+    Ping(u64),
+    /// Ask the server for its game, e.g. once the client has noticed that
+    /// it's out of sync; the server responds with WSServerToClient::GameReset.
+    /// Until the opponent is here, there's no game yet, and the request is
+    /// ignored: the game reset comes once the game starts anyway.
+    RequestFullState,
+    /// Sent every HEARTBEAT_INTERVAL, so that the server can tell a client
+    /// which has nothing to say from a dead connection (e.g. a NAT mapping
+    /// which has expired); the server drops the clients which miss a few in
+    /// a row. There's no response.
+    Heartbeat,
+    /// Ask for the games which wait for the second player, so that the user
+    /// can pick one to join. Unlike everything else, it's sent instead of the
+    /// hello, any number of times; the server responds with
+    /// WSServerToClient::OpenGames, in JSON, and keeps waiting for the hello.
+    /// The servers which speak a protocol older than 17 just respond with an
+    /// error message, and disconnect.
+    ListGames,
+}
+
+/// Message that server can send to WS clients (PlayerWSClient).
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum WSServerToClient {
+    /// Ping is sent every few seconds.
+    Ping,
+    /// Sent right after the hello, but only to the clients which speak
+    /// protocol version 1 or newer (older ones wouldn't understand it).
+    Welcome(WSServerInfo),
+    /// Msg is any human readable message that can be useful to show on the UI
+    /// as part of the player state.
+    Msg(String),
+    /// Full game reset; it's sent to both players whenever two of them meet
+    /// each other at a game.
+    GameReset(WSGameReset),
+    /// Opponent put token at the given pole.
+    PutToken(game::PoleCoords),
+    /// The server has accepted our move at the given pole.
+    MoveAccepted(game::PoleCoords),
+    /// The server has rejected our move at the given pole (e.g. because it's
+    /// not our turn according to the server), for the given reason. It's
+    /// followed by the GameReset with the actual game state, so that the
+    /// client can roll the move back.
+    MoveRejected(game::PoleCoords, String),
+    /// Opponent has disconnected from the server. It might still come back
+    /// later though, and the game can continue then.
+    OpponentIsGone,
+    /// The player whose turn it is hasn't moved for a while; sent to both
+    /// players.
+    Idle(WSIdle),
+    /// The given side hasn't moved for too long, so the game is abandoned, and
+    /// it counts as a loss of that side. Sent to both players, followed by the
+    /// GameReset with the final game state (the clients which don't speak
+    /// protocol version 2 only get the latter).
+    GameAbandoned(game::Side),
+    /// The server has adjudicated the game as a draw for the given reason
+    /// (e.g. nobody can complete any row anymore, see Game::is_dead_draw).
+    /// Sent to both players, followed by the GameReset with the final game
+    /// state.
+    GameDrawn(String),
+    /// Opponent asks to take back the given number of the last moves, see
+    /// WSClientToServer::RequestUndo. Only sent to the clients which speak
+    /// protocol version 5; for older ones, the request is just never
+    /// accepted.
+    UndoRequested(usize),
+    /// Opponent asks for a new game, see WSClientToServer::OfferRematch. Only
+    /// sent to the clients which speak protocol version 6.
+    RematchOffered,
+    /// Opponent has called off the new game, see
+    /// WSClientToServer::DeclineRematch. Only sent to the clients which speak
+    /// protocol version 18.
+    RematchDeclined,
+    /// The given side has resigned, see WSClientToServer::Resign. Sent to
+    /// both players, followed by the GameReset with the final game state (the
+    /// clients which don't speak protocol version 7 only get the latter).
+    Resigned(game::Side),
+    /// Time remaining on the opponent's clock, in milliseconds, see
+    /// WSClientToServer::Clock. Only sent to the clients which speak protocol
+    /// version 8.
+    OpponentClock(u64),
+    /// Chat message from the given player, see WSClientToServer::Chat; from
+    /// is their WSClientInfo::player_name, which can be empty. Only sent to
+    /// the clients which speak protocol version 11.
+    Chat { from: String, text: String },
+    /// Response to WSClientToServer::Ping, with its nonce.
+    Pong(u64),
+    /// Response to WSClientToServer::ListGames: the games which wait for the
+    /// second player, the ones which have waited the longest first.
+    OpenGames(Vec<WSOpenGame>),
+}
+
+/// Authentication message that the client sends right after connecting to the server.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct WSClientInfo {
+    /// ID of the game to play. When two players connect with the same game ID,
+    /// the players are introduced to each other, and the game starts. When more
+    /// players try to connect with the same game ID, the server responds with
+    /// an error message (WSServerToClient::Msg), and disconnects the client;
+    /// unless they only watch the game, see spectate.
+    pub game_id: String,
+    /// Player name to show to the opponent, see WSGameReset::opponent_name;
+    /// it can be empty. The clients which speak a protocol older than 9 always
+    /// send "me", so the server ignores it.
+    pub player_name: String,
+
+    /// Full game state that the client currently has. Players send this state
+    /// so that if the server restarts, while at least one of the players is
+    /// still running and trying to connect, then on the server will pick up the
+    /// game state from where it left off.
+    pub game_state: WSFullGameState,
+
+    /// Protocol version which the client speaks, see PROTOCOL_VERSION. The
+    /// clients which predate the versioning don't send it, so it's 0 for them.
+    #[serde(default)]
+    pub protocol_version: u32,
+
+    /// Style of the tokens which the player prefers, e.g. "marble"; it's
+    /// purely cosmetic, so it's a free-form string which nobody has to
+    /// understand. As of now, the server only logs it.
+    #[serde(default)]
+    pub token_skin: Option<String>,
+
+    /// Encoding which the client would like the server to use from now on,
+    /// see WireFormat; the server confirms it in WSServerInfo::wire_format.
+    #[serde(default)]
+    pub wire_format: WireFormat,
+
+    /// Whether the client would like the messages compressed from now on,
+    /// see WireCodec::deflate; the server confirms it in
+    /// WSServerInfo::deflate.
+    #[serde(default)]
+    pub deflate: bool,
+
+    /// Session token from the last WSGameReset of this game, if any. If both
+    /// players are there already, but one of them has this token, the client
+    /// takes that player's seat over, instead of being refused: it's the same
+    /// human reconnecting, e.g. from another network, before the server has
+    /// noticed that the old connection is dead.
+    #[serde(default)]
+    pub session_token: Option<String>,
+
+    /// Whether the client only wants to watch the game, and never moves. The
+    /// game has to exist already; the spectator gets the game reset right
+    /// away, and then every move (WSServerToClient::PutToken, of either side)
+    /// and every game reset, and nothing else. Spectators don't take the
+    /// seats, so there can be any number of them. The servers which speak a
+    /// protocol older than 14 don't know about it, and would seat the client
+    /// as a player, so the client has to check the version in the welcome.
+    #[serde(default)]
+    pub spectate: bool,
+}
+
+/// Info about the server, see WSServerToClient::Welcome.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct WSServerInfo {
+    /// Protocol version which the server speaks. If it's newer than the one
+    /// of the client, it's a hint that it's time to update the client.
+    pub protocol_version: u32,
+    /// Oldest protocol version which the server still supports; the clients
+    /// which speak an older one are refused.
+    pub min_protocol_version: u32,
+    /// Encoding of all the further messages from the server, normally the one
+    /// from WSClientInfo::wire_format; the servers which predate it only
+    /// speak JSON.
+    #[serde(default)]
+    pub wire_format: WireFormat,
+    /// Whether all the further messages, both ways, are compressed, normally
+    /// as asked in WSClientInfo::deflate; the servers which predate it, or
+    /// which have it turned off, never compress.
+    #[serde(default)]
+    pub deflate: bool,
+}
+
+/// Game which waits for the second player, see WSServerToClient::OpenGames.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct WSOpenGame {
+    /// ID to join the game with, see WSClientInfo::game_id.
+    pub game_id: String,
+    /// Name of the player who waits, see WSClientInfo::player_name; it can be
+    /// empty.
+    pub host_name: String,
+    /// For how long the player has waited, in seconds.
+    pub waiting_secs: u64,
+}
+
+/// See WSServerToClient::Idle.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct WSIdle {
+    /// Side which hasn't moved.
+    pub side: game::Side,
+    /// For how long it hasn't moved, in seconds.
+    pub idle_secs: u64,
+    /// In how many seconds the game is going to be abandoned, unless the side
+    /// moves; None if the server doesn't abandon games.
+    pub abandon_in_secs: Option<u64>,
+}
+
+/// Full game reset, server sends it to both clients whenever two of them meet
+/// each other to play a game.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct WSGameReset {
+    /// Opponent name, as sent in their WSClientInfo::player_name; empty if
+    /// the opponent hasn't given one. The servers which speak a protocol
+    /// older than 9 always send "my opponent".
+    pub opponent_name: String,
+
+    /// Actual state of the game.
+    pub game_state: WSFullGameState,
+
+    /// Secret which identifies the receiving player's seat in this game, see
+    /// WSClientInfo::session_token. The servers which speak a protocol older
+    /// than 13 don't send it.
+    #[serde(default)]
+    pub session_token: Option<String>,
+}
+
+/// Full game state, server sends it to both clients whenever two of them meet
+/// each other to play a game.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct WSFullGameState {
+    pub game_state: GameState,
+
+    /// Side of the websocket player (the one who sends or receives this update).
+    pub ws_player_side: game::Side,
+
+    /// Full board state.
+    pub board: game::BoardState,
+}
+
+/// Encoding of the messages on the wire. The hello and the welcome are always
+/// JSON, in text frames, since nobody knows yet what the other end speaks; the
+/// rest of the messages from the server are in the format it has confirmed in
+/// WSServerInfo::wire_format. Both ends decode every message by its frame
+/// type: JSON comes in text frames, and MessagePack in binary ones; unless the
+/// messages are compressed, see WireCodec.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum WireFormat {
+    /// Human readable, handy for debugging, but a full BoardState takes a few
+    /// times more bytes in it.
+    #[default]
+    Json,
+    /// Binary, for the clients which care about the bandwidth.
+    MessagePack,
+}
+
+impl WireFormat {
+    /// Whether the messages in this format go in binary WebSocket frames;
+    /// otherwise, they're UTF-8 text.
+    pub fn is_binary(self) -> bool {
+        self != WireFormat::Json
+    }
+
+    /// Encodes the message in this format.
+    pub fn encode<T: Serialize>(self, msg: &T) -> Result<Vec<u8>> {
+        match self {
+            WireFormat::Json => Ok(serde_json::to_vec(msg)?),
+            // With the field names, so that the optional fields can be added
+            // just like in JSON.
+            WireFormat::MessagePack => Ok(rmp_serde::to_vec_named(msg)?),
+        }
+    }
+
+    /// Decodes the message from this format.
+    pub fn decode<T: DeserializeOwned>(self, data: &[u8]) -> Result<T> {
+        match self {
+            WireFormat::Json => Ok(serde_json::from_slice(data)?),
+            WireFormat::MessagePack => Ok(rmp_serde::from_slice(data)?),
+        }
+    }
+}
+
+/// Max size of a compressed message once it's inflated; anything bigger is
+/// refused, so that a tiny message can't blow up into gigabytes. A full game
+/// reset is a few kilobytes even in JSON.
+const MAX_INFLATED_LEN: u64 = 1024 * 1024;
+
+/// How the messages are put on the wire, once the hello and the welcome have
+/// settled it: the format (see WireFormat), and whether the messages are
+/// compressed. The compression is negotiated in the hello just like the
+/// format, instead of the permessage-deflate WebSocket extension, which
+/// tungstenite doesn't implement.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WireCodec {
+    pub format: WireFormat,
+    /// Whether every message is compressed with DEFLATE (RFC 1951), after
+    /// it's encoded in the format. Compressed messages always go in binary
+    /// frames, so with the compression on, the text frames are still JSON,
+    /// but the binary ones are compressed, whatever the format. A full game
+    /// reset shrinks a few times, since the board is mostly the same bytes
+    /// over and over.
+    pub deflate: bool,
+}
+
+impl WireCodec {
+    /// Whether the messages go in binary WebSocket frames; otherwise, they're
+    /// UTF-8 text.
+    pub fn is_binary(self) -> bool {
+        self.deflate || self.format.is_binary()
+    }
+
+    /// Encodes the message in the format, and compresses it, if needed.
+    pub fn encode<T: Serialize>(self, msg: &T) -> Result<Vec<u8>> {
+        let data = self.format.encode(msg)?;
+        if !self.deflate {
+            return Ok(data);
+        }
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data)?;
+
+        Ok(encoder.finish()?)
+    }
+
+    /// Decodes the message which has come in a binary frame; the text frames
+    /// are always just JSON.
+    pub fn decode_binary<T: DeserializeOwned>(self, data: &[u8]) -> Result<T> {
+        if !self.deflate {
+            return WireFormat::MessagePack.decode(data);
+        }
+
+        let mut inflated = Vec::new();
+        DeflateDecoder::new(data)
+            .take(MAX_INFLATED_LEN + 1)
+            .read_to_end(&mut inflated)?;
+        if inflated.len() as u64 > MAX_INFLATED_LEN {
+            return Err(anyhow!(
+                "the message is over {} bytes once inflated",
+                MAX_INFLATED_LEN
+            ));
+        }
+
+        self.format.decode(&inflated)
+    }
+}
+
+impl FromStr for WireFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(WireFormat::Json),
+            "msgpack" => Ok(WireFormat::MessagePack),
+            _ => Err(anyhow!(
+                "invalid wire format {:?}; try 'json' or 'msgpack'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for WireFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireFormat::Json => write!(f, "json"),
+            WireFormat::MessagePack => write!(f, "msgpack"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{BoardState, Game, PoleCoords, Scores, Side, TokenCoords, ROW_SIZE};
+
+    const CODECS: [WireCodec; 4] = [
+        WireCodec {
+            format: WireFormat::Json,
+            deflate: false,
+        },
+        WireCodec {
+            format: WireFormat::MessagePack,
+            deflate: false,
+        },
+        WireCodec {
+            format: WireFormat::Json,
+            deflate: true,
+        },
+        WireCodec {
+            format: WireFormat::MessagePack,
+            deflate: true,
+        },
+    ];
+
+    /// Encodes the message, and decodes it back the way the other end does,
+    /// by the frame type.
+    fn round_trip<T: Serialize + DeserializeOwned>(codec: WireCodec, msg: &T) -> T {
+        let data = codec.encode(msg).unwrap();
+        if codec.is_binary() {
+            codec.decode_binary(&data).unwrap()
+        } else {
+            WireFormat::Json.decode(&data).unwrap()
+        }
+    }
+
+    fn pole(s: &str) -> PoleCoords {
+        s.parse().unwrap()
+    }
+
+    /// Game states with a few boards: the empty one, one after a few moves,
+    /// and a full one, where every token is there.
+    fn game_states() -> Vec<WSFullGameState> {
+        let mut game = Game::new();
+        for (i, m) in ["A1", "B2", "A1", "D4", "C3"].iter().enumerate() {
+            let side = if i % 2 == 0 { Side::White } else { Side::Black };
+            game.put_token(side, pole(m)).unwrap();
+        }
+
+        let mut full = BoardState::new();
+        for x in 0..ROW_SIZE {
+            for y in 0..ROW_SIZE {
+                for z in 0..ROW_SIZE {
+                    let side = if (x + y * 2 + z) % 3 == 0 {
+                        Side::White
+                    } else {
+                        Side::Black
+                    };
+                    full.set(side, TokenCoords::new(x, y, z));
+                }
+            }
+        }
+
+        vec![
+            WSFullGameState {
+                game_state: GameState::WaitingFor(Side::White, Scores::default()),
+                ws_player_side: Side::Black,
+                board: BoardState::new(),
+            },
+            WSFullGameState {
+                game_state: GameState::WaitingFor(Side::Black, Scores::default()),
+                ws_player_side: Side::White,
+                board: game.get_board().clone(),
+            },
+            WSFullGameState {
+                game_state: GameState::WonBy(Side::Black, Scores { white: 2, black: 3 }),
+                ws_player_side: Side::Black,
+                board: full.clone(),
+            },
+            WSFullGameState {
+                game_state: GameState::Draw(Scores::default()),
+                ws_player_side: Side::White,
+                board: full,
+            },
+        ]
+    }
+
+    fn client_messages() -> Vec<WSClientToServer> {
+        let mut msgs: Vec<_> = game_states()
+            .into_iter()
+            .map(|game_state| {
+                WSClientToServer::Hello(WSClientInfo {
+                    game_id: "some game".to_string(),
+                    player_name: "Ann \"the\" Player".to_string(),
+                    game_state,
+                    protocol_version: PROTOCOL_VERSION,
+                    token_skin: Some("marble".to_string()),
+                    wire_format: WireFormat::MessagePack,
+                    deflate: true,
+                    session_token: Some("secret".to_string()),
+                    spectate: false,
+                })
+            })
+            .collect();
+
+        msgs.extend([
+            WSClientToServer::Hello(WSClientInfo {
+                game_id: String::new(),
+                player_name: String::new(),
+                game_state: game_states().remove(0),
+                protocol_version: 0,
+                token_skin: None,
+                wire_format: WireFormat::Json,
+                deflate: false,
+                session_token: None,
+                spectate: true,
+            }),
+            WSClientToServer::PutToken(pole("D3")),
+            WSClientToServer::RequestUndo(2),
+            WSClientToServer::AcceptUndo,
+            WSClientToServer::OfferRematch,
+            WSClientToServer::DeclineRematch,
+            WSClientToServer::Resign,
+            WSClientToServer::Clock(61_500),
+            WSClientToServer::Chat("hi there,\nglhf ü".to_string()),
+            WSClientToServer::Ping(u64::MAX),
+            WSClientToServer::RequestFullState,
+            WSClientToServer::Heartbeat,
+            WSClientToServer::ListGames,
+        ]);
+
+        msgs
+    }
+
+    fn server_messages() -> Vec<WSServerToClient> {
+        let mut msgs: Vec<_> = game_states()
+            .into_iter()
+            .map(|game_state| {
+                WSServerToClient::GameReset(WSGameReset {
+                    opponent_name: "Bob".to_string(),
+                    game_state,
+                    session_token: Some("secret".to_string()),
+                })
+            })
+            .collect();
+
+        msgs.extend([
+            WSServerToClient::GameReset(WSGameReset {
+                opponent_name: String::new(),
+                game_state: game_states().remove(1),
+                session_token: None,
+            }),
+            WSServerToClient::Ping,
+            WSServerToClient::Welcome(WSServerInfo {
+                protocol_version: PROTOCOL_VERSION,
+                min_protocol_version: 1,
+                wire_format: WireFormat::MessagePack,
+                deflate: true,
+            }),
+            WSServerToClient::Msg("game is full".to_string()),
+            WSServerToClient::PutToken(pole("A1")),
+            WSServerToClient::MoveAccepted(pole("B4")),
+            WSServerToClient::MoveRejected(pole("C2"), "the pole is full".to_string()),
+            WSServerToClient::OpponentIsGone,
+            WSServerToClient::Idle(WSIdle {
+                side: Side::White,
+                idle_secs: 30,
+                abandon_in_secs: Some(90),
+            }),
+            WSServerToClient::Idle(WSIdle {
+                side: Side::Black,
+                idle_secs: 0,
+                abandon_in_secs: None,
+            }),
+            WSServerToClient::GameAbandoned(Side::Black),
+            WSServerToClient::GameDrawn("agreed".to_string()),
+            WSServerToClient::UndoRequested(1),
+            WSServerToClient::RematchOffered,
+            WSServerToClient::RematchDeclined,
+            WSServerToClient::Resigned(Side::White),
+            WSServerToClient::OpponentClock(0),
+            WSServerToClient::Chat {
+                from: "Bob".to_string(),
+                text: "gg\r\n".to_string(),
+            },
+            WSServerToClient::Pong(42),
+            WSServerToClient::OpenGames(vec![]),
+            WSServerToClient::OpenGames(vec![
+                WSOpenGame {
+                    game_id: "one".to_string(),
+                    host_name: "Ann".to_string(),
+                    waiting_secs: 120,
+                },
+                WSOpenGame {
+                    game_id: "two".to_string(),
+                    host_name: String::new(),
+                    waiting_secs: 0,
+                },
+            ]),
+        ]);
+
+        msgs
+    }
+
+    #[test]
+    fn round_trips_client_messages() {
+        for codec in CODECS {
+            for msg in client_messages() {
+                assert_eq!(round_trip(codec, &msg), msg, "{:?}", codec);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_server_messages() {
+        for codec in CODECS {
+            for msg in server_messages() {
+                assert_eq!(round_trip(codec, &msg), msg, "{:?}", codec);
+            }
+        }
+    }
+
+    #[test]
+    fn compresses_game_resets() {
+        let msg = server_messages().remove(2);
+        for format in [WireFormat::Json, WireFormat::MessagePack] {
+            let plain = WireCodec {
+                format,
+                deflate: false,
+            };
+            let deflated = WireCodec {
+                format,
+                deflate: true,
+            };
+            let plain_len = plain.encode(&msg).unwrap().len();
+            let deflated_len = deflated.encode(&msg).unwrap().len();
+            assert!(
+                deflated_len < plain_len,
+                "{}: {} vs {}",
+                format,
+                deflated_len,
+                plain_len
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_to_inflate_huge_messages() {
+        let codec = WireCodec {
+            format: WireFormat::Json,
+            deflate: true,
+        };
+
+        // A valid JSON string, just too long.
+        let huge = "x".repeat(MAX_INFLATED_LEN as usize + 1);
+        let data = codec.encode(&huge).unwrap();
+        assert!(data.len() < 10 * 1024);
+        assert!(codec.decode_binary::<String>(&data).is_err());
+
+        let fine = "x".repeat(MAX_INFLATED_LEN as usize - 2);
+        let data = codec.encode(&fine).unwrap();
+        assert_eq!(codec.decode_binary::<String>(&data).unwrap(), fine);
+    }
+}